@@ -2,18 +2,21 @@ use std::time::Duration;
 
 use clap::Parser;
 use helyim::{
-    directory::{DirectoryServer, Sequencer, SequencerType},
+    cache::CacheServer,
+    directory::{DirectoryServer, Sequencer},
     storage::{NeedleMapType, VolumeServer},
     util::{
-        args::{Command, LogOptions, MasterOptions, Opts, VolumeOptions},
+        args::{CacheOptions, Command, MasterOptions, Opts, ServerOptions, VolumeOptions},
+        check::{check_cache, check_master, check_server, check_volume},
+        log,
         sys::shutdown_signal,
     },
 };
-use tracing::{info, Level};
-use tracing_subscriber::EnvFilter;
+use tracing::{error, info, Level};
+use tracing_appender::non_blocking::WorkerGuard;
 
 async fn start_master(master_opts: MasterOptions) -> Result<(), Box<dyn std::error::Error>> {
-    let sequencer = Sequencer::new(SequencerType::Memory)?;
+    let sequencer = Sequencer::from_options(&master_opts.sequencer).await?;
     let mut directory = DirectoryServer::new(master_opts, 0.3, sequencer).await?;
 
     directory.start().await?;
@@ -36,59 +39,106 @@ async fn start_volume(volume_opts: VolumeOptions) -> Result<(), Box<dyn std::err
     Ok(())
 }
 
-fn log_init(
-    level: Level,
-    _opts: &LogOptions,
-    _log_prefix: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    std::env::set_var("RUST_LOG", "none");
-    let helyim = env!("CARGO_PKG_NAME");
-    let filter = EnvFilter::from_default_env().add_directive(format!("{helyim}={level}").parse()?);
-    // let file_appender = tracing_appender::rolling::daily(
-    //     opts.log_path.as_str(),
-    //     format!("helyim-{}.log", log_prefix),
-    // );
-    let subscriber = tracing_subscriber::fmt()
-        // .with_writer(file_appender)
-        .with_env_filter(filter)
-        .with_target(true)
-        .with_level(true)
-        .with_max_level(level)
-        // .with_max_level(level)
-        .with_ansi(true)
-        .with_line_number(true)
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber)?;
+async fn start_cache(cache_opts: CacheOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mut server = CacheServer::new(cache_opts).await?;
+
+    server.start().await?;
+    shutdown_signal().await;
+    server.stop().await?;
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let level = Level::INFO;
+/// the master and the volume server of `helyim server` share the runtime and the log but not
+/// their clients, the volume server heartbeats to the master over grpc like a remote one. it is
+/// stopped first so its last heartbeat reaches the master
+async fn start_server(server_opts: ServerOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let master_opts = server_opts.master_options()?;
+    let volume_opts = server_opts.volume_options()?;
+
+    let sequencer = Sequencer::from_options(&master_opts.sequencer).await?;
+    let mut directory = DirectoryServer::new(master_opts, 0.3, sequencer).await?;
+    directory.start().await?;
+    let mut server =
+        VolumeServer::new(NeedleMapType::NeedleMapInMemory, volume_opts, false).await?;
+    server.start().await?;
+
+    shutdown_signal().await;
+    server.stop().await?;
+    directory.stop().await?;
+
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    Ok(())
+}
+
+/// exit before starting a server whose options are invalid, the log guard is dropped first so
+/// the problems are flushed to the log file
+fn fail_fast(
+    result: helyim::errors::Result<()>,
+    guard: Option<WorkerGuard>,
+) -> Option<WorkerGuard> {
+    if let Err(err) = result {
+        error!("{err}");
+        drop(guard);
+        std::process::exit(1);
+    }
+    guard
+}
 
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opts = Opts::parse();
+    // every role sizes its own runtime
+    let runtime = match &opts.command {
+        Command::Master(master) => master.runtime.build("master")?,
+        Command::Volume(volume) => volume.runtime.build("volume")?,
+        Command::Server(server) => server.runtime.build("server")?,
+        Command::Cache(cache) => cache.runtime.build("cache")?,
+    };
+    runtime.block_on(run(opts))
+}
+
+async fn run(opts: Opts) -> Result<(), Box<dyn std::error::Error>> {
+    let level = Level::INFO;
     info!("opts: {:?}", opts);
 
     let log_opts = opts.log.clone();
     match opts.command {
         Command::Master(mut master) => {
-            log_init(level, &log_opts, "master")?;
+            let guard = log::init(level, &log_opts, "master")?;
 
             master.check_raft_peers();
+            let _guard = fail_fast(check_master(&master), guard);
 
             info!("starting master server....");
             start_master(master).await
         }
         Command::Volume(volume) => {
-            log_init(
+            let guard = log::init(
                 level,
                 &log_opts,
                 &format!("volume-{}-{}", volume.ip, volume.port),
             )?;
+            let _guard = fail_fast(check_volume(&volume), guard);
 
             info!("starting volume....");
             start_volume(volume).await
         }
+        Command::Server(server) => {
+            let guard = log::init(level, &log_opts, "server")?;
+            let _guard = fail_fast(check_server(&server), guard);
+
+            info!("starting master and volume server....");
+            start_server(server).await
+        }
+        Command::Cache(cache) => {
+            let guard = log::init(
+                level,
+                &log_opts,
+                &format!("cache-{}-{}", cache.ip, cache.port),
+            )?;
+            let _guard = fail_fast(check_cache(&cache), guard);
+
+            info!("starting cache....");
+            start_cache(cache).await
+        }
     }
 }
@@ -0,0 +1,260 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{RawQuery, State},
+    http::{
+        header::{
+            CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, HOST, LAST_MODIFIED, RANGE,
+            TRANSFER_ENCODING,
+        },
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use faststr::FastStr;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+    cache::{
+        disk::{CacheStats, CachedNeedle, DiskCache},
+        offline::{assign, is_unreachable, upload, OfflineQueue, Submission},
+    },
+    errors::{Error, Result},
+    operation::Looker,
+    storage::{spawn_io, IoClass, VolumeError, VolumeId},
+    util::{
+        http::{extractor::FormOrJson, HTTP_CLIENT},
+        parser::parse_url_path,
+        time::now,
+    },
+};
+
+/// whether a read was served from the disk cache, `HIT` or `MISS`
+pub const X_HELYIM_CACHE: HeaderName = HeaderName::from_static("x-helyim-cache");
+
+#[derive(Clone)]
+pub struct CacheState {
+    pub cache: Arc<DiskCache>,
+    pub looker: Arc<Looker>,
+    /// master of the remote cluster
+    pub master: FastStr,
+    /// writes submitted while the remote cluster is unreachable
+    pub offline: Option<Arc<OfflineQueue>>,
+}
+
+impl CacheState {
+    /// a volume server of the remote cluster holding `vid`
+    async fn volume_server(&self, vid: VolumeId) -> Result<String> {
+        self.looker
+            .lookup(vec![vid], &self.master)
+            .await?
+            .into_iter()
+            .flat_map(|location| location.locations)
+            .map(|location| location.url)
+            .next()
+            .ok_or_else(|| VolumeError::NotFound(vid).into())
+    }
+}
+
+fn cache_key(vid: VolumeId, fid: &str) -> String {
+    format!("{vid},{fid}")
+}
+
+fn header_value(headers: &reqwest::header::HeaderMap, name: &str) -> FastStr {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(FastStr::new)
+        .unwrap_or_default()
+}
+
+fn needle_response(needle: &CachedNeedle, data: Bytes, cache: &'static str) -> Response {
+    let mut headers = HeaderMap::new();
+    for (name, value) in [
+        (CONTENT_TYPE, &needle.content_type),
+        (ETAG, &needle.etag),
+        (LAST_MODIFIED, &needle.last_modified),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            if !value.is_empty() {
+                headers.insert(name, value);
+            }
+        }
+    }
+    headers.insert(X_HELYIM_CACHE, HeaderValue::from_static(cache));
+    (headers, data).into_response()
+}
+
+/// send the request of the client to `url`, the response is passed back as it is
+async fn proxy(method: Method, url: String, headers: &HeaderMap, body: Bytes) -> Result<Response> {
+    let method = reqwest::Method::from_bytes(method.as_str().as_bytes())
+        .map_err(|err| Error::String(err.to_string()))?;
+    let mut request = HTTP_CLIENT.request(method, url).body(body);
+    for (name, value) in headers.iter() {
+        if name != HOST && name != CONTENT_LENGTH {
+            request = request.header(name.as_str(), value.as_bytes());
+        }
+    }
+    let response = request.send().await?;
+
+    let mut builder = Response::builder().status(response.status().as_u16());
+    for (name, value) in response.headers() {
+        // the body is passed back in one piece
+        if name.as_str() != TRANSFER_ENCODING.as_str() && name.as_str() != CONNECTION.as_str() {
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+    }
+    Ok(builder.body(Body::from(response.bytes().await?))?)
+}
+
+fn path_and_query(uri: &Uri) -> &str {
+    uri.path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or(uri.path())
+}
+
+/// serve a needle from the disk cache, a miss is read from the remote cluster and cached. HEAD
+/// and range requests are passed to the remote cluster, they neither fill nor count as reads of
+/// the cache.
+pub async fn get_or_head_handler(
+    State(state): State<CacheState>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let (vid, fid, _filename, _ext) = parse_url_path(uri.path())?;
+    if method == Method::HEAD || headers.contains_key(RANGE) {
+        let url = format!(
+            "http://{}{}",
+            state.volume_server(vid).await?,
+            path_and_query(&uri)
+        );
+        return proxy(method, url, &headers, Bytes::new()).await;
+    }
+
+    let key = FastStr::new(cache_key(vid, fid));
+    let cache = state.cache.clone();
+    let cached = {
+        let key = key.clone();
+        spawn_io(IoClass::Foreground, move || cache.get(&key)).await??
+    };
+    if let Some((needle, data)) = cached {
+        return Ok(needle_response(&needle, data, "HIT"));
+    }
+    // taken before the read, a write removing the key meanwhile keeps the stale data out
+    let generation = state.cache.generation(&key);
+
+    let url = format!("http://{}{}", state.volume_server(vid).await?, uri.path());
+    let response = HTTP_CLIENT.get(url).send().await?;
+    if response.status() != reqwest::StatusCode::OK {
+        let status =
+            StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+        return Ok((status, response.bytes().await?).into_response());
+    }
+    let needle = CachedNeedle {
+        content_type: header_value(response.headers(), CONTENT_TYPE.as_str()),
+        etag: header_value(response.headers(), ETAG.as_str()),
+        last_modified: header_value(response.headers(), LAST_MODIFIED.as_str()),
+        stored_at: now().as_secs(),
+    };
+    let data = response.bytes().await?;
+    // the read is answered even if the needle can not be cached
+    let cache = state.cache.clone();
+    let (cached, data) = {
+        let needle = needle.clone();
+        spawn_io(IoClass::Foreground, move || {
+            (cache.put(&key, generation, &needle, &data), data)
+        })
+        .await?
+    };
+    if let Err(err) = cached {
+        warn!("cache needle {} failed: {err}", cache_key(vid, fid));
+    }
+    Ok(needle_response(&needle, data, "MISS"))
+}
+
+/// writes and deletes go to the remote cluster, the cached copy is dropped
+pub async fn forward_handler(
+    State(state): State<CacheState>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let (vid, fid, _filename, _ext) = parse_url_path(uri.path())?;
+    let url = format!(
+        "http://{}{}",
+        state.volume_server(vid).await?,
+        path_and_query(&uri)
+    );
+    let response = proxy(method, url, &headers, body).await;
+    let cache = state.cache.clone();
+    let key = cache_key(vid, fid);
+    spawn_io(IoClass::Foreground, move || cache.remove(&key)).await?;
+    response
+}
+
+/// assignments and lookups are answered by the master of the remote cluster
+pub async fn master_handler(
+    State(state): State<CacheState>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let url = format!("http://{}{}", state.master, path_and_query(&uri));
+    proxy(method, url, &headers, body).await
+}
+
+pub async fn status_handler(State(state): State<CacheState>) -> Json<CacheStats> {
+    Json(state.cache.stats())
+}
+
+/// upload a file to the remote cluster, a write the cluster can not take for now is queued and
+/// answered with `202 Accepted` and the id to ask for its fid later
+pub async fn submit_handler(
+    State(state): State<CacheState>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let query = query.unwrap_or_default();
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream");
+    // only a failed assign is queued, a failed upload may have been written already
+    match assign(&state.master, &query).await {
+        Ok(assignment) => Ok(Json(upload(&assignment, content_type, body).await?).into_response()),
+        Err(err) if is_unreachable(&err) => match &state.offline {
+            Some(offline) => {
+                warn!("remote cluster is unreachable, queue the write: {err}");
+                let submission = offline.push(content_type, &query, body).await?;
+                Ok((StatusCode::ACCEPTED, Json(submission)).into_response())
+            }
+            None => Err(err),
+        },
+        Err(err) => Err(err),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmissionRequest {
+    pub id: u64,
+}
+
+/// the state of a queued write, its fid once it is uploaded
+pub async fn submission_handler(
+    State(state): State<CacheState>,
+    FormOrJson(SubmissionRequest { id }): FormOrJson<SubmissionRequest>,
+) -> Result<Json<Submission>> {
+    state
+        .offline
+        .as_ref()
+        .and_then(|offline| offline.get(id))
+        .map(Json)
+        .ok_or_else(|| Error::String(format!("queued write {id} is not found")))
+}
@@ -0,0 +1,357 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bytes::Bytes;
+use faststr::FastStr;
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{errors::Result, util::time::now};
+
+const TMP_SUFFIX: &str = ".tmp";
+/// keys share the generation of their slot, a removal only makes reads of a few other keys skip
+/// caching
+const GENERATION_SLOTS: usize = 1024;
+
+/// what is kept of the response of the volume server besides the needle data
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedNeedle {
+    pub content_type: FastStr,
+    pub etag: FastStr,
+    pub last_modified: FastStr,
+    /// unix seconds the needle was fetched from the remote cluster
+    pub stored_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub entries: usize,
+    pub used_bytes: u64,
+    pub capacity_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct Entry {
+    size: u64,
+    stored_at: u64,
+}
+
+struct Index {
+    entries: LruCache<FastStr, Entry>,
+    used: u64,
+    /// bumped whenever a key of the slot is removed
+    generations: Vec<u64>,
+}
+
+fn generation_slot(key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize % GENERATION_SLOTS
+}
+
+/// Needles of a remote cluster kept on the local disk, one file per fid. The least recently read
+/// needles are evicted once the capacity is exceeded, and a needle older than the ttl is fetched
+/// again.
+///
+/// The methods do blocking file io, callers run them with `spawn_io`.
+pub struct DiskCache {
+    dir: PathBuf,
+    capacity: u64,
+    ttl: u64,
+    index: Mutex<Index>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// the big endian length of the json of `meta`, the json and `data`
+pub(super) fn encode<T: Serialize>(meta: &T, data: &[u8]) -> Result<Vec<u8>> {
+    let meta = serde_json::to_vec(meta)?;
+    let mut buf = Vec::with_capacity(4 + meta.len() + data.len());
+    buf.extend_from_slice(&(meta.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&meta);
+    buf.extend_from_slice(data);
+    Ok(buf)
+}
+
+pub(super) fn decode<T: DeserializeOwned>(buf: &[u8]) -> Result<(T, &[u8])> {
+    let corrupted = || format!("corrupted file of {} bytes", buf.len());
+    let len = buf
+        .get(..4)
+        .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+        .ok_or_else(corrupted)?;
+    let meta = buf.get(4..4 + len).ok_or_else(corrupted)?;
+    Ok((serde_json::from_slice(meta)?, &buf[4 + len..]))
+}
+
+impl DiskCache {
+    /// open the cache in `dir`, the needles cached by a previous run are kept
+    pub fn open<P: AsRef<Path>>(dir: P, capacity: u64, ttl: u64) -> Result<DiskCache> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let cache = DiskCache {
+            dir,
+            capacity,
+            ttl,
+            index: Mutex::new(Index {
+                entries: LruCache::unbounded(),
+                used: 0,
+                generations: vec![0; GENERATION_SLOTS],
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        };
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&cache.dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if name.ends_with(TMP_SUFFIX) {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            match fs::read(&path).map_err(Into::into).and_then(|buf| {
+                decode::<CachedNeedle>(&buf).map(|(needle, data)| (needle, data.len()))
+            }) {
+                Ok((needle, size)) => files.push((FastStr::new(name), needle.stored_at, size)),
+                Err(err) => {
+                    warn!("drop cache file {}: {err}", path.display());
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+        // the oldest needles are the first to go
+        files.sort_by_key(|(_, stored_at, _)| *stored_at);
+        for (key, stored_at, size) in files {
+            cache.insert(key, None, stored_at, size as u64);
+        }
+        Ok(cache)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+
+    fn expired(&self, stored_at: u64, now: u64) -> bool {
+        self.ttl > 0 && now.saturating_sub(stored_at) > self.ttl
+    }
+
+    /// index a stored needle and evict until the capacity is kept, the needle is not indexed if
+    /// its key was removed since `generation`
+    fn insert(&self, key: FastStr, generation: Option<u64>, stored_at: u64, size: u64) -> bool {
+        let mut evicted = Vec::new();
+        {
+            let mut index = self.index.lock();
+            if let Some(generation) = generation {
+                if index.generations[generation_slot(&key)] != generation {
+                    return false;
+                }
+            }
+            if let Some(old) = index.entries.put(key, Entry { size, stored_at }) {
+                index.used -= old.size;
+            }
+            index.used += size;
+            while index.used > self.capacity {
+                match index.entries.pop_lru() {
+                    Some((key, entry)) => {
+                        index.used -= entry.size;
+                        evicted.push(key);
+                    }
+                    None => break,
+                }
+            }
+        }
+        for key in evicted {
+            debug!("evict {key} from cache");
+            let _ = fs::remove_file(self.path(&key));
+        }
+        true
+    }
+
+    /// the generation of `key`, taken before its needle is read from the remote cluster and
+    /// passed to `put`
+    pub fn generation(&self, key: &str) -> u64 {
+        self.index.lock().generations[generation_slot(key)]
+    }
+
+    /// the cached needle of `key`, a miss if it is absent or expired
+    pub fn get(&self, key: &str) -> Result<Option<(CachedNeedle, Bytes)>> {
+        let stored_at = self
+            .index
+            .lock()
+            .entries
+            .get(key)
+            .map(|entry| entry.stored_at);
+        let Some(stored_at) = stored_at else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        };
+        if self.expired(stored_at, now().as_secs()) {
+            self.remove(key);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        }
+        let buf = match fs::read(self.path(key)) {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                self.remove(key);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let (needle, data) = decode(&buf)?;
+        let data = Bytes::copy_from_slice(data);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Ok(Some((needle, data)))
+    }
+
+    /// store a needle fetched from the remote cluster, needles larger than the capacity are not
+    /// cached. a needle written or deleted through the cache since `generation` is stale and is
+    /// not cached either.
+    pub fn put(
+        &self,
+        key: &str,
+        generation: u64,
+        needle: &CachedNeedle,
+        data: &[u8],
+    ) -> Result<()> {
+        let size = data.len() as u64;
+        if size > self.capacity {
+            return Ok(());
+        }
+        let path = self.path(key);
+        let tmp = self.path(&format!("{key}{TMP_SUFFIX}"));
+        fs::write(&tmp, encode(needle, data)?)?;
+        fs::rename(&tmp, &path)?;
+        if !self.insert(FastStr::new(key), Some(generation), needle.stored_at, size) {
+            debug!("{key} is removed while it is read, do not cache it");
+            let _ = fs::remove_file(&path);
+        }
+        Ok(())
+    }
+
+    /// drop the cached needle of `key`, after it is written or deleted through the cache
+    pub fn remove(&self, key: &str) {
+        let removed = {
+            let mut index = self.index.lock();
+            index.generations[generation_slot(key)] += 1;
+            match index.entries.pop(key) {
+                Some(entry) => {
+                    index.used -= entry.size;
+                    true
+                }
+                None => false,
+            }
+        };
+        if removed {
+            let _ = fs::remove_file(self.path(key));
+        }
+    }
+
+    /// drop the expired needles, returns how many were dropped
+    pub fn expire(&self) -> usize {
+        if self.ttl == 0 {
+            return 0;
+        }
+        let now = now().as_secs();
+        let expired: Vec<FastStr> = self
+            .index
+            .lock()
+            .entries
+            .iter()
+            .filter(|(_, entry)| self.expired(entry.stored_at, now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired.iter() {
+            self.remove(key);
+        }
+        expired.len()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let index = self.index.lock();
+        CacheStats {
+            entries: index.entries.len(),
+            used_bytes: index.used,
+            capacity_bytes: self.capacity,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use faststr::FastStr;
+
+    use crate::{
+        cache::disk::{CachedNeedle, DiskCache},
+        util::time::now,
+    };
+
+    #[test]
+    fn test_disk_cache() {
+        let dir = tempfile::Builder::new()
+            .prefix("cache")
+            .tempdir_in(".")
+            .unwrap();
+        let needle = CachedNeedle {
+            content_type: FastStr::new("text/plain"),
+            stored_at: now().as_secs(),
+            ..Default::default()
+        };
+
+        let cache = DiskCache::open(dir.path(), 10, 60).unwrap();
+        cache.put("1,01", 0, &needle, b"hello").unwrap();
+        cache.put("1,02", 0, &needle, b"world").unwrap();
+        let (cached, data) = cache.get("1,01").unwrap().unwrap();
+        assert_eq!(cached, needle);
+        assert_eq!(&data[..], b"hello");
+
+        // 1,02 is the least recently read
+        cache.put("1,03", 0, &needle, b"!").unwrap();
+        assert!(cache.get("1,02").unwrap().is_none());
+        assert_eq!(cache.stats().used_bytes, 6);
+        // larger than the whole cache
+        cache.put("1,04", 0, &needle, b"hello world").unwrap();
+        assert!(cache.get("1,04").unwrap().is_none());
+
+        cache.remove("1,03");
+        drop(cache);
+        let cache = DiskCache::open(dir.path(), 10, 60).unwrap();
+        assert_eq!(cache.stats().entries, 1);
+        assert!(cache.get("1,01").unwrap().is_some());
+
+        let stale = CachedNeedle {
+            stored_at: now().as_secs() - 120,
+            ..needle
+        };
+        cache
+            .put("1,05", cache.generation("1,05"), &stale, b"old")
+            .unwrap();
+        assert_eq!(cache.expire(), 1);
+        assert!(cache.get("1,05").unwrap().is_none());
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+
+        // a read started before a write is not cached after the write removed the key
+        let generation = cache.generation("1,06");
+        cache.remove("1,06");
+        cache.put("1,06", generation, &needle, b"old").unwrap();
+        assert!(cache.get("1,06").unwrap().is_none());
+        assert!(!dir.path().join("1,06").exists());
+    }
+}
@@ -0,0 +1,18 @@
+//! An edge cache in front of a remote cluster.
+//!
+//! Reads are served from needles cached on the local disk, a miss is read from the volume server
+//! of the remote cluster and cached. Writes, deletes, assignments and lookups are forwarded to the
+//! remote cluster. With an offline queue, a write submitted while the remote cluster is
+//! unreachable is kept on the local disk and uploaded once the cluster is back.
+
+mod api;
+pub use api::{CacheState, X_HELYIM_CACHE};
+
+mod disk;
+pub use disk::{CacheStats, CachedNeedle, DiskCache};
+
+mod offline;
+pub use offline::{OfflineQueue, QueuedWrite, Submission, SubmissionState};
+
+mod server;
+pub use server::CacheServer;
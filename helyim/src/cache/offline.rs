@@ -0,0 +1,432 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use faststr::FastStr;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{
+    cache::disk::{decode, encode},
+    errors::{Error, ErrorCode, Result},
+    operation::{Assignment, Upload},
+    storage::{spawn_io, IoClass},
+    util::{
+        http::{get, post_with_content_type},
+        time::now,
+    },
+};
+
+const WRITE_SUFFIX: &str = ".write";
+const DONE_SUFFIX: &str = ".done";
+const TMP_SUFFIX: &str = ".tmp";
+
+/// seconds the outcome of a queued write is kept for its client to ask
+pub const OUTCOME_RETENTION: u64 = 24 * 60 * 60;
+
+/// a write accepted while the remote cluster was unreachable
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedWrite {
+    pub id: u64,
+    pub content_type: FastStr,
+    /// query of the write, passed on to the assign, like `collection=pics&replication=001`
+    pub query: FastStr,
+    /// unix seconds the write was accepted
+    pub accepted_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubmissionState {
+    Queued,
+    Uploaded,
+    Rejected,
+}
+
+/// where a write submitted through the cache stands
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Submission {
+    /// id of a queued write, the fid is only known once it is uploaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    pub state: SubmissionState,
+    #[serde(default, skip_serializing_if = "FastStr::is_empty")]
+    pub fid: FastStr,
+    #[serde(default, skip_serializing_if = "FastStr::is_empty")]
+    pub url: FastStr,
+    #[serde(default, skip_serializing_if = "FastStr::is_empty")]
+    pub error: FastStr,
+    /// unix seconds the state was reached
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+impl Submission {
+    fn queued(id: u64) -> Self {
+        Self {
+            id: Some(id),
+            state: SubmissionState::Queued,
+            fid: FastStr::empty(),
+            url: FastStr::empty(),
+            error: FastStr::empty(),
+            updated_at: now().as_secs(),
+        }
+    }
+}
+
+/// whether `err` means the remote cluster can not take writes for now, the write is queued
+/// instead of failed
+pub fn is_unreachable(err: &Error) -> bool {
+    match err {
+        Error::Reqwest(_) | Error::Timeout => true,
+        Error::Api(body) => body.retryable,
+        _ => false,
+    }
+}
+
+/// whether the remote cluster refused `err`'s write for good, it would be refused again the same
+/// way. Every other error, like a full or read only volume, keeps the write queued.
+fn is_refused(err: &Error) -> bool {
+    match err {
+        Error::Api(body) => matches!(
+            body.code,
+            ErrorCode::BadRequest
+                | ErrorCode::ChecksumMismatch
+                | ErrorCode::PreconditionFailed
+                | ErrorCode::Conflict
+        ),
+        _ => false,
+    }
+}
+
+/// assign a fid from `master`
+pub async fn assign(master: &str, query: &str) -> Result<Assignment> {
+    let mut url = format!("http://{master}/dir/assign");
+    if !query.is_empty() {
+        url = format!("{url}?{query}");
+    }
+    let assignment: Assignment = serde_json::from_slice(&get(url, &[]).await?)?;
+    if !assignment.error.is_empty() {
+        return Err(Error::String(assignment.error));
+    }
+    Ok(assignment)
+}
+
+/// upload `body` to the fid of `assignment`
+pub async fn upload(
+    assignment: &Assignment,
+    content_type: &str,
+    body: Bytes,
+) -> Result<Submission> {
+    let upload: Upload = serde_json::from_slice(
+        &post_with_content_type(
+            format!("http://{}/{}", assignment.url, assignment.fid),
+            content_type,
+            body,
+        )
+        .await?,
+    )?;
+    if !upload.error.is_empty() {
+        return Err(Error::String(upload.error));
+    }
+    Ok(Submission {
+        id: None,
+        state: SubmissionState::Uploaded,
+        fid: FastStr::new(&assignment.fid),
+        url: FastStr::new(&assignment.url),
+        error: FastStr::empty(),
+        updated_at: now().as_secs(),
+    })
+}
+
+/// Writes accepted while the remote cluster is unreachable, one file per write which is synced
+/// before the write is acknowledged. The writes are uploaded in the order they were accepted once
+/// the cluster is back, the fid each one got is kept for its client to ask.
+pub struct OfflineQueue {
+    dir: PathBuf,
+    next_id: AtomicU64,
+    pending: Mutex<BTreeSet<u64>>,
+    outcomes: DashMap<u64, Submission>,
+}
+
+fn sync_write(path: &Path, tmp: &Path, buf: &[u8]) -> Result<()> {
+    let mut file = File::create(tmp)?;
+    file.write_all(buf)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)?;
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+impl OfflineQueue {
+    /// open the queue in `dir`, the writes queued by a previous run are kept
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<OfflineQueue> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut pending = BTreeSet::new();
+        let outcomes = DashMap::new();
+        let mut max_id = 0;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if name.ends_with(TMP_SUFFIX) {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            if let Some(id) = name.strip_suffix(WRITE_SUFFIX) {
+                if let Ok(id) = id.parse::<u64>() {
+                    pending.insert(id);
+                    max_id = max_id.max(id);
+                }
+            } else if let Some(id) = name.strip_suffix(DONE_SUFFIX) {
+                match fs::read(&path)
+                    .map_err(Error::from)
+                    .and_then(|buf| Ok(serde_json::from_slice::<Submission>(&buf)?))
+                {
+                    Ok(submission) => {
+                        if let Ok(id) = id.parse::<u64>() {
+                            max_id = max_id.max(id);
+                            outcomes.insert(id, submission);
+                        }
+                    }
+                    Err(err) => {
+                        warn!("drop outcome file {}: {err}", path.display());
+                        let _ = fs::remove_file(&path);
+                    }
+                }
+            }
+        }
+        // ids of writes whose files are gone are not handed out again
+        let next_id = (max_id + 1).max(now().as_millis() as u64);
+        Ok(OfflineQueue {
+            dir,
+            next_id: AtomicU64::new(next_id),
+            pending: Mutex::new(pending),
+            outcomes,
+        })
+    }
+
+    fn path(&self, id: u64, suffix: &str) -> PathBuf {
+        self.dir.join(format!("{id}{suffix}"))
+    }
+
+    /// queue a write, it is on disk once this returns
+    pub async fn push(&self, content_type: &str, query: &str, body: Bytes) -> Result<Submission> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let write = QueuedWrite {
+            id,
+            content_type: FastStr::new(content_type),
+            query: FastStr::new(query),
+            accepted_at: now().as_secs(),
+        };
+        let buf = encode(&write, &body)?;
+        let (path, tmp) = (self.path(id, WRITE_SUFFIX), self.path(id, TMP_SUFFIX));
+        spawn_io(IoClass::Fsync, move || sync_write(&path, &tmp, &buf)).await??;
+        self.pending.lock().insert(id);
+        Ok(Submission::queued(id))
+    }
+
+    fn read(&self, id: u64) -> Result<(QueuedWrite, Bytes)> {
+        let buf = fs::read(self.path(id, WRITE_SUFFIX))?;
+        let (write, body) = decode::<QueuedWrite>(&buf)?;
+        Ok((write, Bytes::copy_from_slice(body)))
+    }
+
+    /// record the outcome of a queued write, the write itself is dropped
+    fn finish(&self, id: u64, submission: Submission) -> Result<()> {
+        let submission = Submission {
+            id: Some(id),
+            ..submission
+        };
+        sync_write(
+            &self.path(id, DONE_SUFFIX),
+            &self.path(id, TMP_SUFFIX),
+            &serde_json::to_vec(&submission)?,
+        )?;
+        self.outcomes.insert(id, submission);
+        self.pending.lock().remove(&id);
+        let _ = fs::remove_file(self.path(id, WRITE_SUFFIX));
+        Ok(())
+    }
+
+    /// the state of the queued write `id`, `None` if it is unknown or its outcome is forgotten
+    pub fn get(&self, id: u64) -> Option<Submission> {
+        if self.pending.lock().contains(&id) {
+            return Some(Submission::queued(id));
+        }
+        self.outcomes.get(&id).map(|outcome| outcome.clone())
+    }
+
+    /// number of writes waiting for the remote cluster
+    pub fn len(&self) -> usize {
+        self.pending.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// upload the queued writes through `master` in the order they were accepted, stops at the
+    /// first write the remote cluster can not take yet. Only a write the cluster refuses for good
+    /// is dropped as rejected. Returns how many writes were uploaded.
+    pub async fn reconcile(&self, master: &str) -> usize {
+        let ids: Vec<u64> = self.pending.lock().iter().copied().collect();
+        let mut uploaded = 0;
+        for id in ids {
+            let (write, body) = match self.read(id) {
+                Ok(write) => write,
+                Err(err) => {
+                    warn!("read queued write {id} failed: {err}");
+                    continue;
+                }
+            };
+            let uploaded_as = match assign(master, &write.query).await {
+                Ok(assignment) => upload(&assignment, &write.content_type, body).await,
+                Err(err) => Err(err),
+            };
+            let submission = match uploaded_as {
+                Ok(submission) => {
+                    debug!("queued write {id} uploaded as {}", submission.fid);
+                    uploaded += 1;
+                    submission
+                }
+                Err(err) if is_refused(&err) => {
+                    warn!("queued write {id} is rejected: {err}");
+                    Submission {
+                        state: SubmissionState::Rejected,
+                        error: FastStr::new(err.to_string()),
+                        updated_at: now().as_secs(),
+                        ..Submission::queued(id)
+                    }
+                }
+                Err(err) => {
+                    // kept for the next pass, the later writes wait to keep the order
+                    warn!("upload queued write {id} failed, retry later: {err}");
+                    break;
+                }
+            };
+            if let Err(err) = self.finish(id, submission) {
+                warn!("record outcome of queued write {id} failed: {err}");
+                break;
+            }
+        }
+        if uploaded > 0 {
+            info!("{uploaded} queued writes uploaded, {} left", self.len());
+        }
+        uploaded
+    }
+
+    /// forget the outcomes older than `OUTCOME_RETENTION`
+    pub fn expire(&self) -> usize {
+        let now = now().as_secs();
+        let expired: Vec<u64> = self
+            .outcomes
+            .iter()
+            .filter(|outcome| now.saturating_sub(outcome.updated_at) > OUTCOME_RETENTION)
+            .map(|outcome| *outcome.key())
+            .collect();
+        for id in expired.iter() {
+            self.outcomes.remove(id);
+            let _ = fs::remove_file(self.path(*id, DONE_SUFFIX));
+        }
+        expired.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use faststr::FastStr;
+
+    use crate::{
+        cache::offline::{is_refused, OfflineQueue, Submission, SubmissionState},
+        errors::{Error, ErrorBody, ErrorCode},
+    };
+
+    #[tokio::test]
+    async fn test_offline_queue() {
+        let dir = tempfile::Builder::new()
+            .prefix("offline")
+            .tempdir_in(".")
+            .unwrap();
+        let queue = OfflineQueue::open(dir.path()).unwrap();
+        let first = queue
+            .push(
+                "text/plain",
+                "collection=pics",
+                Bytes::from_static(b"hello"),
+            )
+            .await
+            .unwrap();
+        let second = queue
+            .push("text/plain", "", Bytes::from_static(b"world"))
+            .await
+            .unwrap();
+        let (first, second) = (first.id.unwrap(), second.id.unwrap());
+        assert!(second > first);
+        assert_eq!(queue.len(), 2);
+
+        // nothing listens on the master port, both writes stay queued
+        assert_eq!(queue.reconcile("127.0.0.1:1").await, 0);
+        assert_eq!(queue.get(first).unwrap().state, SubmissionState::Queued);
+
+        queue
+            .finish(
+                first,
+                Submission {
+                    state: SubmissionState::Uploaded,
+                    fid: FastStr::new("3,01637037d6"),
+                    ..Submission::queued(first)
+                },
+            )
+            .unwrap();
+        drop(queue);
+
+        let queue = OfflineQueue::open(dir.path()).unwrap();
+        assert_eq!(queue.len(), 1);
+        let uploaded = queue.get(first).unwrap();
+        assert_eq!(uploaded.state, SubmissionState::Uploaded);
+        assert_eq!(uploaded.fid, "3,01637037d6");
+        let (write, body) = queue.read(second).unwrap();
+        assert_eq!(write.query, "");
+        assert_eq!(&body[..], b"world");
+        let third = queue
+            .push("text/plain", "", Bytes::from_static(b"!"))
+            .await
+            .unwrap();
+        assert!(third.id.unwrap() > second);
+        assert!(queue.get(0).is_none());
+        assert_eq!(queue.expire(), 0);
+    }
+
+    #[test]
+    fn test_is_refused() {
+        assert!(is_refused(&Error::Api(ErrorBody::new(
+            ErrorCode::BadRequest,
+            "bad replication"
+        ))));
+        for code in [
+            ErrorCode::Internal,
+            ErrorCode::ReadOnly,
+            ErrorCode::Quarantined,
+            ErrorCode::NoFreeSpace,
+            ErrorCode::VolumeNotFound,
+        ] {
+            assert!(!is_refused(&Error::Api(ErrorBody::new(code, "oops"))));
+        }
+        assert!(!is_refused(&Error::String("bad gateway".to_string())));
+    }
+}
@@ -0,0 +1,211 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware::from_fn,
+    routing::{get, post},
+    Router,
+};
+use faststr::FastStr;
+use tokio::net::TcpListener;
+use tower_http::{catch_panic::CatchPanicLayer, timeout::TimeoutLayer};
+use tracing::{debug, error, info};
+
+use crate::{
+    cache::{
+        api::{
+            forward_handler, get_or_head_handler, master_handler, status_handler,
+            submission_handler, submit_handler, CacheState,
+        },
+        disk::DiskCache,
+        offline::OfflineQueue,
+    },
+    errors::Result,
+    operation::Looker,
+    storage::{spawn_io, IoClass},
+    util::{
+        args::CacheOptions,
+        http::{
+            default_handler, favicon_handler, health::healthz_handler, panic_response,
+            request_id::request_id,
+        },
+        retry::set_retry_policy,
+        sign::set_cluster_secret,
+        sys::exit,
+    },
+};
+
+/// seconds between two sweeps of the expired needles
+const EXPIRE_INTERVAL: u64 = 60;
+/// seconds between two attempts to upload the queued writes
+const RECONCILE_INTERVAL: u64 = 5;
+
+pub struct CacheServer {
+    pub options: Arc<CacheOptions>,
+    pub cache: Arc<DiskCache>,
+    pub offline: Option<Arc<OfflineQueue>>,
+
+    shutdown: async_broadcast::Sender<()>,
+}
+
+impl CacheServer {
+    pub async fn new(options: CacheOptions) -> Result<CacheServer> {
+        let options = Arc::new(options);
+        set_retry_policy(options.retry.policy());
+        set_cluster_secret(options.cluster_secret.clone());
+
+        let (dir, capacity, ttl) = (
+            options.dir.clone(),
+            options.capacity_mb * 1024 * 1024,
+            options.ttl,
+        );
+        let cache = Arc::new(
+            spawn_io(IoClass::Background, move || {
+                DiskCache::open(dir.as_str(), capacity, ttl)
+            })
+            .await??,
+        );
+        let stats = cache.stats();
+        info!(
+            "cache opened with {} needles of {} bytes",
+            stats.entries, stats.used_bytes
+        );
+
+        let (shutdown, shutdown_rx) = async_broadcast::broadcast(16);
+        let offline = match &options.offline_dir {
+            Some(dir) => {
+                let offline = Arc::new(OfflineQueue::open(dir.as_str())?);
+                info!("offline queue opened with {} queued writes", offline.len());
+                tokio::spawn(reconcile_loop(
+                    offline.clone(),
+                    options.master_server.clone(),
+                    shutdown.new_receiver(),
+                ));
+                Some(offline)
+            }
+            None => None,
+        };
+        tokio::spawn(expire_loop(cache.clone(), shutdown_rx));
+        Ok(CacheServer {
+            options,
+            cache,
+            offline,
+            shutdown,
+        })
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        let state = CacheState {
+            cache: self.cache.clone(),
+            looker: Arc::new(Looker::new()),
+            master: self.options.master_server.clone(),
+            offline: self.offline.clone(),
+        };
+        let addr = format!("{}:{}", self.options.ip, self.options.port).parse()?;
+        tokio::spawn(start_cache_server(
+            state,
+            self.options.timeout.request_timeout,
+            addr,
+            self.shutdown.new_receiver(),
+        ));
+        Ok(())
+    }
+
+    pub async fn stop(self) -> Result<()> {
+        self.shutdown.broadcast(()).await?;
+        Ok(())
+    }
+}
+
+async fn expire_loop(cache: Arc<DiskCache>, mut shutdown: async_broadcast::Receiver<()>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(EXPIRE_INTERVAL));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let cache = cache.clone();
+                match spawn_io(IoClass::Background, move || cache.expire()).await {
+                    Ok(0) => {}
+                    Ok(expired) => debug!("{expired} expired needles dropped from cache"),
+                    Err(err) => error!("expire cached needles error: {err}"),
+                }
+            }
+            _ = shutdown.recv() => {
+                break;
+            }
+        }
+    }
+}
+
+async fn reconcile_loop(
+    offline: Arc<OfflineQueue>,
+    master: FastStr,
+    mut shutdown: async_broadcast::Receiver<()>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(RECONCILE_INTERVAL));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if !offline.is_empty() {
+                    offline.reconcile(&master).await;
+                }
+                offline.expire();
+            }
+            _ = shutdown.recv() => {
+                break;
+            }
+        }
+    }
+}
+
+async fn start_cache_server(
+    state: CacheState,
+    request_timeout: u64,
+    addr: SocketAddr,
+    mut shutdown: async_broadcast::Receiver<()>,
+) {
+    let app = Router::new()
+        .route("/", get(default_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/favicon.ico", get(favicon_handler))
+        .route("/cache/status", get(status_handler))
+        .route("/cache/submit", post(submit_handler))
+        .route(
+            "/cache/submission",
+            get(submission_handler).post(submission_handler),
+        )
+        .route("/dir/assign", get(master_handler).post(master_handler))
+        .route("/dir/lookup", get(master_handler).post(master_handler))
+        .fallback_service(
+            get(get_or_head_handler)
+                .head(get_or_head_handler)
+                .post(forward_handler)
+                .put(forward_handler)
+                .delete(forward_handler)
+                .fallback(default_handler)
+                .with_state(state.clone()),
+        )
+        .layer((
+            from_fn(request_id),
+            CatchPanicLayer::custom(panic_response),
+            DefaultBodyLimit::max(1024 * 1024 * 50),
+            TimeoutLayer::new(Duration::from_secs(request_timeout)),
+        ))
+        .with_state(state);
+
+    info!("cache api server is starting up. binding addr: {addr}");
+    match TcpListener::bind(addr).await {
+        Ok(listener) => {
+            if let Err(err) = axum::serve(listener, app.into_make_service())
+                .with_graceful_shutdown(async move {
+                    let _ = shutdown.recv().await;
+                    info!("cache api server shutting down gracefully.");
+                })
+                .await
+            {
+                error!("starting cache api server failed, error: {err}");
+                exit();
+            }
+        }
+        Err(err) => error!("binding cache api address {addr} failed, error: {err}"),
+    }
+}
@@ -4,7 +4,11 @@ use dashmap::{mapref::one::Ref, DashMap};
 use faststr::FastStr;
 use rand::Rng;
 
-use crate::{client::ClientError, storage::VolumeId, util::parser::parse_vid_fid};
+use crate::{
+    client::ClientError,
+    storage::VolumeId,
+    util::{http::pool::host_available, parser::parse_vid_fid},
+};
 
 #[derive(Clone)]
 pub struct Location {
@@ -24,9 +28,17 @@ impl LocationMap {
             .map_err(|_| ClientError::UnknownVolumeId(vid.to_string()))?;
         let locations = self.get_locations_by_vid(vid);
         if let Some(locations) = locations {
-            if !locations.is_empty() {
-                let idx = rand::thread_rng().gen_range(0..locations.len());
-                let url = locations[idx].url.clone();
+            // skip ejected volume servers, unless all of them are ejected
+            let mut available: Vec<&Location> = locations
+                .iter()
+                .filter(|location| host_available(&location.url))
+                .collect();
+            if available.is_empty() {
+                available = locations.iter().collect();
+            }
+            if !available.is_empty() {
+                let idx = rand::thread_rng().gen_range(0..available.len());
+                let url = available[idx].url.clone();
                 return Ok(url);
             }
         }
@@ -1,20 +1,28 @@
 mod location;
+mod stream;
 
 use std::{ops::Deref, time::Duration};
 
 use async_stream::stream;
 use faststr::FastStr;
-use helyim_proto::directory::KeepConnectedRequest;
+use helyim_proto::{
+    directory::KeepConnectedRequest,
+    volume::{ReadNeedleBlobRequest, ReadNeedleBlobResponse},
+};
 use nom::error::Error as NomError;
+pub use stream::{list_entries, watch_volume_locations, DEFAULT_LIST_PAGE_SIZE};
 use tokio::sync::{RwLock, RwLockReadGuard};
 use tokio_stream::StreamExt;
-use tonic::Status;
+use tonic::{Status, Streaming};
 use tracing::{error, info, warn};
 
 use crate::{
     client::location::{Location, LocationMap},
-    storage::{VolumeError, VolumeId},
-    util::grpc::helyim_client,
+    storage::{NeedleId, VolumeError, VolumeId},
+    util::{
+        capability::PROTOCOL_VERSION,
+        grpc::{helyim_client, volume_server_client},
+    },
 };
 
 pub struct MasterClient {
@@ -40,6 +48,16 @@ impl MasterClient {
         self.current_master.read().await
     }
 
+    /// read the stored record of a needle in frames from a volume server holding its volume
+    pub async fn read_needle_blob(
+        &self,
+        volume_id: VolumeId,
+        needle_id: NeedleId,
+    ) -> Result<Streaming<ReadNeedleBlobResponse>, ClientError> {
+        let volume_server = self.lookup_volume_server_url(&volume_id.to_string())?;
+        read_needle_blob(&volume_server, volume_id, needle_id).await
+    }
+
     pub async fn keep_connected_to_master(&self) {
         let mut interval = tokio::time::interval(Duration::from_secs(3));
         loop {
@@ -71,6 +89,7 @@ impl MasterClient {
             loop {
                 yield KeepConnectedRequest {
                     name: client_name.to_string(),
+                    protocol_version: PROTOCOL_VERSION,
                 };
                 interval.tick().await;
             }
@@ -121,6 +140,27 @@ impl MasterClient {
     }
 }
 
+/// read the stored record of a needle from `volume_server` in frames, a large needle is never
+/// held in memory at once
+pub async fn read_needle_blob(
+    volume_server: &str,
+    volume_id: VolumeId,
+    needle_id: NeedleId,
+) -> Result<Streaming<ReadNeedleBlobResponse>, ClientError> {
+    let client = volume_server_client(volume_server)?;
+    let request = ReadNeedleBlobRequest {
+        volume_id,
+        needle_id,
+    };
+    match client.read_needle_blob(request).await {
+        Ok(response) => Ok(response.into_inner()),
+        Err(status) => Err(ClientError::ReadNeedleBlob(
+            FastStr::new(volume_server),
+            status,
+        )),
+    }
+}
+
 impl Deref for MasterClient {
     type Target = LocationMap;
 
@@ -145,6 +185,10 @@ pub enum ClientError {
 
     #[error("Keep connected to {0} error: {1}")]
     KeepConnected(FastStr, Status),
+    #[error("Read needle blob from {0} error: {1}")]
+    ReadNeedleBlob(FastStr, Status),
+    #[error("List entries from {0} error: {1}")]
+    ListEntries(FastStr, Status),
 }
 
 impl From<nom::Err<NomError<&str>>> for ClientError {
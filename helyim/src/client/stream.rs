@@ -0,0 +1,416 @@
+use std::time::Duration;
+
+use async_stream::{stream, try_stream};
+use faststr::FastStr;
+use futures::Stream;
+use helyim_proto::{
+    directory::{KeepConnectedRequest, VolumeLocation},
+    filer::{Entry, ListEntriesRequest},
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    client::ClientError,
+    util::{
+        capability::PROTOCOL_VERSION,
+        grpc::{filer_client, helyim_client},
+    },
+};
+
+/// entries of a page when the caller does not choose
+pub const DEFAULT_LIST_PAGE_SIZE: u32 = 1024;
+
+/// the entries of `directory` whose names start with `prefix`, in name order
+///
+/// the listing is fetched page by page, the next page is only requested once the consumer has
+/// taken every entry of the current one, so a large directory is never held in memory.
+pub fn list_entries(
+    filer: FastStr,
+    directory: FastStr,
+    prefix: FastStr,
+    page_size: u32,
+) -> impl Stream<Item = Result<Entry, ClientError>> {
+    let page_size = if page_size == 0 {
+        DEFAULT_LIST_PAGE_SIZE
+    } else {
+        page_size
+    };
+    try_stream! {
+        let mut start_from = String::new();
+        loop {
+            let request = ListEntriesRequest {
+                directory: directory.to_string(),
+                prefix: prefix.to_string(),
+                start_from_file_name: start_from.clone(),
+                inclusive_start_from: false,
+                limit: page_size,
+            };
+            let mut client = filer_client(&filer)?;
+            let mut page = client
+                .list_entries(request)
+                .await
+                .map_err(|status| ClientError::ListEntries(filer.clone(), status))?
+                .into_inner();
+
+            let mut entries = 0;
+            while let Some(response) = page.next().await {
+                let response =
+                    response.map_err(|status| ClientError::ListEntries(filer.clone(), status))?;
+                if let Some(entry) = response.entry {
+                    entries += 1;
+                    start_from.clone_from(&entry.name);
+                    yield entry;
+                }
+            }
+            if entries < page_size {
+                break;
+            }
+        }
+    }
+}
+
+/// watch the volume location changes announced by `master`, a hint to another leader is
+/// followed. The stream ends with an error when the master goes away, the caller decides whether
+/// to watch again.
+pub fn watch_volume_locations(
+    name: FastStr,
+    master: FastStr,
+) -> impl Stream<Item = Result<VolumeLocation, ClientError>> {
+    try_stream! {
+        let mut master = master;
+        loop {
+            let client_name = name.to_string();
+            let requests = stream! {
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                    yield KeepConnectedRequest {
+                        name: client_name.clone(),
+                        protocol_version: PROTOCOL_VERSION,
+                    };
+                }
+            };
+            let client = helyim_client(&master)?;
+            let mut locations = client
+                .keep_connected(requests)
+                .await
+                .map_err(|status| ClientError::KeepConnected(master.clone(), status))?
+                .into_inner();
+
+            let mut leader = None;
+            while let Some(location) = locations.next().await {
+                let location =
+                    location.map_err(|status| ClientError::KeepConnected(master.clone(), status))?;
+                if let Some(hinted) = location.leader {
+                    leader = Some(FastStr::new(hinted));
+                    break;
+                }
+                yield location;
+            }
+            master = leader
+                .ok_or_else(|| ClientError::String(format!("{master} closed the watch")))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, TcpListener},
+        pin::Pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use faststr::FastStr;
+    use futures::{Stream, StreamExt};
+    use helyim_proto::{
+        directory::{
+            helyim_server::{Helyim, HelyimServer},
+            HeartbeatRequest, HeartbeatResponse, KeepConnectedRequest, LookupEcVolumeRequest,
+            LookupEcVolumeResponse, LookupVolumeRequest, LookupVolumeResponse, TopologyEvent,
+            VolumeLocation, WatchTopologyRequest,
+        },
+        filer::{
+            helyim_filer_server::{HelyimFiler, HelyimFilerServer},
+            AppendToEntryRequest, AppendToEntryResponse, AssignVolumeRequest, AssignVolumeResponse,
+            CollectionListRequest, CollectionListResponse, CreateEntryRequest, CreateEntryResponse,
+            DeleteCollectionRequest, DeleteCollectionResponse, DeleteEntryRequest,
+            DeleteEntryResponse, Entry, KvGetRequest, KvGetResponse, KvPutRequest, KvPutResponse,
+            ListEntriesRequest, ListEntriesResponse, LookupDirectoryEntryRequest,
+            LookupDirectoryEntryResponse, PingRequest, PingResponse, UpdateEntryRequest,
+            UpdateEntryResponse,
+        },
+    };
+    use tonic::{transport::Server, Request, Response, Status, Streaming};
+
+    use crate::client::stream::{list_entries, watch_volume_locations};
+
+    type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+    /// the address whose grpc port is free, the grpc port is the http port plus 10000
+    fn free_addr() -> (FastStr, SocketAddr) {
+        let grpc_addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let addr = format!("127.0.0.1:{}", grpc_addr.port() - 10000);
+        (FastStr::new(addr), grpc_addr)
+    }
+
+    struct Filer {
+        names: Vec<&'static str>,
+        pages: Arc<AtomicUsize>,
+    }
+
+    #[tonic::async_trait]
+    impl HelyimFiler for Filer {
+        type ListEntriesStream = ResponseStream<ListEntriesResponse>;
+
+        async fn lookup_directory_entry(
+            &self,
+            _: Request<LookupDirectoryEntryRequest>,
+        ) -> Result<Response<LookupDirectoryEntryResponse>, Status> {
+            Err(Status::unimplemented("lookup directory entry"))
+        }
+
+        async fn list_entries(
+            &self,
+            request: Request<ListEntriesRequest>,
+        ) -> Result<Response<Self::ListEntriesStream>, Status> {
+            self.pages.fetch_add(1, Ordering::Relaxed);
+            let request = request.into_inner();
+            let entries: Vec<_> = self
+                .names
+                .iter()
+                .filter(|name| {
+                    name.starts_with(&request.prefix)
+                        && **name > request.start_from_file_name.as_str()
+                })
+                .take(request.limit as usize)
+                .map(|name| {
+                    Ok(ListEntriesResponse {
+                        entry: Some(Entry {
+                            name: name.to_string(),
+                            ..Default::default()
+                        }),
+                    })
+                })
+                .collect();
+            Ok(Response::new(Box::pin(futures::stream::iter(entries))))
+        }
+
+        async fn create_entry(
+            &self,
+            _: Request<CreateEntryRequest>,
+        ) -> Result<Response<CreateEntryResponse>, Status> {
+            Err(Status::unimplemented("create entry"))
+        }
+
+        async fn update_entry(
+            &self,
+            _: Request<UpdateEntryRequest>,
+        ) -> Result<Response<UpdateEntryResponse>, Status> {
+            Err(Status::unimplemented("update entry"))
+        }
+
+        async fn append_to_entry(
+            &self,
+            _: Request<AppendToEntryRequest>,
+        ) -> Result<Response<AppendToEntryResponse>, Status> {
+            Err(Status::unimplemented("append to entry"))
+        }
+
+        async fn delete_entry(
+            &self,
+            _: Request<DeleteEntryRequest>,
+        ) -> Result<Response<DeleteEntryResponse>, Status> {
+            Err(Status::unimplemented("delete entry"))
+        }
+
+        async fn assign_volume(
+            &self,
+            _: Request<AssignVolumeRequest>,
+        ) -> Result<Response<AssignVolumeResponse>, Status> {
+            Err(Status::unimplemented("assign volume"))
+        }
+
+        async fn lookup_volume(
+            &self,
+            _: Request<helyim_proto::filer::LookupVolumeRequest>,
+        ) -> Result<Response<helyim_proto::filer::LookupVolumeResponse>, Status> {
+            Err(Status::unimplemented("lookup volume"))
+        }
+
+        async fn collection_list(
+            &self,
+            _: Request<CollectionListRequest>,
+        ) -> Result<Response<CollectionListResponse>, Status> {
+            Err(Status::unimplemented("collection list"))
+        }
+
+        async fn delete_collection(
+            &self,
+            _: Request<DeleteCollectionRequest>,
+        ) -> Result<Response<DeleteCollectionResponse>, Status> {
+            Err(Status::unimplemented("delete collection"))
+        }
+
+        async fn ping(&self, _: Request<PingRequest>) -> Result<Response<PingResponse>, Status> {
+            Err(Status::unimplemented("ping"))
+        }
+
+        async fn kv_get(
+            &self,
+            _: Request<KvGetRequest>,
+        ) -> Result<Response<KvGetResponse>, Status> {
+            Err(Status::unimplemented("kv get"))
+        }
+
+        async fn kv_put(
+            &self,
+            _: Request<KvPutRequest>,
+        ) -> Result<Response<KvPutResponse>, Status> {
+            Err(Status::unimplemented("kv put"))
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_list_entries() {
+        let (addr, grpc_addr) = free_addr();
+        let pages = Arc::new(AtomicUsize::new(0));
+        let filer = Filer {
+            names: vec!["a.jpg", "b.jpg", "b.png", "c.jpg", "d.jpg", "e.jpg"],
+            pages: pages.clone(),
+        };
+        tokio::spawn(
+            Server::builder()
+                .add_service(HelyimFilerServer::new(filer))
+                .serve(grpc_addr),
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let names: Vec<String> =
+            list_entries(addr.clone(), FastStr::new("/photos"), FastStr::empty(), 2)
+                .map(|entry| entry.unwrap().name)
+                .collect()
+                .await;
+        assert_eq!(
+            names,
+            vec!["a.jpg", "b.jpg", "b.png", "c.jpg", "d.jpg", "e.jpg"]
+        );
+        // the last page is full, an empty page ends the listing
+        assert_eq!(pages.load(Ordering::Relaxed), 4);
+
+        let names: Vec<String> = list_entries(addr, FastStr::new("/photos"), FastStr::new("b"), 0)
+            .map(|entry| entry.unwrap().name)
+            .collect()
+            .await;
+        assert_eq!(names, vec!["b.jpg", "b.png"]);
+    }
+
+    /// a master sending `locations` to every watcher, then closing the watch
+    struct Master {
+        locations: Vec<VolumeLocation>,
+    }
+
+    #[tonic::async_trait]
+    impl Helyim for Master {
+        type HeartbeatStream = ResponseStream<HeartbeatResponse>;
+        type KeepConnectedStream = ResponseStream<VolumeLocation>;
+        type WatchTopologyStream = ResponseStream<TopologyEvent>;
+
+        async fn heartbeat(
+            &self,
+            _: Request<Streaming<HeartbeatRequest>>,
+        ) -> Result<Response<Self::HeartbeatStream>, Status> {
+            Err(Status::unimplemented("heartbeat"))
+        }
+
+        async fn keep_connected(
+            &self,
+            _: Request<Streaming<KeepConnectedRequest>>,
+        ) -> Result<Response<Self::KeepConnectedStream>, Status> {
+            let locations: Vec<_> = self.locations.iter().cloned().map(Ok).collect();
+            Ok(Response::new(Box::pin(futures::stream::iter(locations))))
+        }
+
+        async fn lookup_volume(
+            &self,
+            _: Request<LookupVolumeRequest>,
+        ) -> Result<Response<LookupVolumeResponse>, Status> {
+            Err(Status::unimplemented("lookup volume"))
+        }
+
+        async fn lookup_ec_volume(
+            &self,
+            _: Request<LookupEcVolumeRequest>,
+        ) -> Result<Response<LookupEcVolumeResponse>, Status> {
+            Err(Status::unimplemented("lookup ec volume"))
+        }
+
+        async fn watch_topology(
+            &self,
+            _: Request<WatchTopologyRequest>,
+        ) -> Result<Response<Self::WatchTopologyStream>, Status> {
+            Err(Status::unimplemented("watch topology"))
+        }
+    }
+
+    fn location(url: &str, new_vids: Vec<u32>) -> VolumeLocation {
+        VolumeLocation {
+            url: url.to_string(),
+            new_vids,
+            ..VolumeLocation::new()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_watch_volume_locations() {
+        let (follower, follower_grpc) = free_addr();
+        let (leader, leader_grpc) = free_addr();
+        // the follower hints at the leader, which announces a volume and goes away
+        let hint = VolumeLocation {
+            leader: Some(leader.to_string()),
+            ..VolumeLocation::new()
+        };
+        tokio::spawn(
+            Server::builder()
+                .add_service(HelyimServer::new(Master {
+                    locations: vec![location("127.0.0.1:8080", vec![1]), hint],
+                }))
+                .serve(follower_grpc),
+        );
+        tokio::spawn(
+            Server::builder()
+                .add_service(HelyimServer::new(Master {
+                    locations: vec![location("127.0.0.1:8081", vec![2])],
+                }))
+                .serve(leader_grpc),
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let results: Vec<_> = watch_volume_locations(FastStr::new("test"), follower)
+            .collect()
+            .await;
+        assert_eq!(results.len(), 3);
+        let first = results[0].as_ref().unwrap();
+        assert_eq!(
+            (first.url.as_str(), &first.new_vids[..]),
+            ("127.0.0.1:8080", &[1][..])
+        );
+        let second = results[1].as_ref().unwrap();
+        assert_eq!(
+            (second.url.as_str(), &second.new_vids[..]),
+            ("127.0.0.1:8081", &[2][..])
+        );
+        assert!(results[2]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("closed the watch"));
+    }
+}
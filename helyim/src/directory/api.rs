@@ -1,16 +1,35 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
-use axum::{extract::State, Json};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::header::CONTENT_TYPE,
+    response::IntoResponse,
+    Json,
+};
 use faststr::FastStr;
+use tracing::info;
 
 use crate::{
+    directory::federation::Federation,
     operation::{
-        lookup::{Location, Lookup, LookupRequest},
-        AssignRequest, Assignment, ClusterStatus,
+        lookup::{Location, Lookup, LookupRequest, ReadPreference},
+        sequence::{SequenceRequest, SequenceStatus},
+        usage_metrics, AssignAlternative, AssignRequest, Assignment, ClusterStatus,
+        CollectionUsage, DataNodeStatus, DecommissionRequest, JobControlRequest, QuarantinedVolume,
+        CLUSTER_USAGE_PREFIX, MAX_ASSIGN_ALTERNATIVES,
     },
     storage::VolumeError,
-    topology::{node::Node, volume_grow::VolumeGrowth, Topology, TopologyRef},
-    util::{args::MasterOptions, http::extractor::FormOrJson},
+    topology::{
+        node::Node, start_decommission, volume_grow::VolumeGrowth, CollectionDeleteAction,
+        CollectionDeletion, DataNodeRef, DecommissionProgress, JobStatus, PlacementChange,
+        SimulationReport, Topology, TopologyError, TopologyRef,
+    },
+    util::{
+        args::MasterOptions,
+        capability::PROTOCOL_VERSION,
+        cidr::DataCenterRanges,
+        http::{extractor::FormOrJson, health::Readiness, PROMETHEUS_TEXT_FORMAT},
+    },
 };
 
 #[derive(Clone)]
@@ -18,19 +37,37 @@ pub struct DirectoryState {
     pub topology: TopologyRef,
     pub volume_grow: VolumeGrowth,
     pub options: Arc<MasterOptions>,
+    pub data_center_ranges: Arc<DataCenterRanges>,
+    pub federation: Arc<Federation>,
 }
 
 pub async fn assign_handler(
     State(state): State<DirectoryState>,
-    FormOrJson(request): FormOrJson<AssignRequest>,
+    FormOrJson(mut request): FormOrJson<AssignRequest>,
 ) -> Result<Json<Assignment>, VolumeError> {
     let count = match request.count {
         Some(n) if n > 1 => n,
         _ => 1,
     };
+    let path = request.path.take();
+    let alternatives = request
+        .alternatives
+        .take()
+        .unwrap_or_default()
+        .min(MAX_ASSIGN_ALTERNATIVES);
     let option = request.volume_grow_option(&state.options.default_replication)?;
+    if state.topology.is_collection_marked(&option.collection) {
+        return Err(VolumeError::String(format!(
+            "collection {} is marked for deletion",
+            option.collection
+        )));
+    }
 
     if !state.topology.has_writable_volume(&option).await {
+        let bootstrapping = state.topology.bootstrapping_nodes();
+        if !bootstrapping.is_empty() {
+            return Err(VolumeError::Bootstrapping(bootstrapping));
+        }
         if state.topology.free_space() <= 0 {
             return Err(VolumeError::NoFreeSpace("no free volumes".to_string()));
         }
@@ -39,44 +76,83 @@ pub async fn assign_handler(
             .grow_by_type(&option, state.topology.as_ref())
             .await?;
     }
-    let (fid, count, node) = state.topology.pick_for_write(count, &option).await?;
+    let (count, mut picked) = match path {
+        Some(path) if !path.is_empty() => {
+            let (fid, count, nodes) = state
+                .topology
+                .pick_for_write_by_path(&path, &option)
+                .await?;
+            (count, vec![(fid, nodes)])
+        }
+        _ => {
+            state
+                .topology
+                .pick_for_write(count, &option, alternatives + 1)
+                .await?
+        }
+    };
+    let (fid, nodes) = picked.remove(0);
+    let primary = &nodes[0];
     let assignment = Assignment {
         fid: fid.to_string(),
-        url: node.url(),
-        public_url: node.public_url.clone(),
+        url: primary.url(),
+        public_url: primary.public_url.clone(),
         count,
+        replicas: nodes.iter().map(location).collect(),
+        alternatives: picked
+            .into_iter()
+            .map(|(fid, nodes)| AssignAlternative {
+                fid: fid.to_string(),
+                url: nodes[0].url(),
+                public_url: nodes[0].public_url.clone(),
+                replicas: nodes.iter().map(location).collect(),
+            })
+            .collect(),
         error: String::default(),
+        protocol_version: PROTOCOL_VERSION,
     };
     Ok(Json(assignment))
 }
 
 pub async fn lookup_handler(
     State(state): State<DirectoryState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     FormOrJson(request): FormOrJson<LookupRequest>,
 ) -> Result<Json<Lookup>, VolumeError> {
     if request.volume_id.is_empty() {
-        return Err(VolumeError::String("volume_id can't be empty".to_string()));
+        return Err(VolumeError::InvalidVolumeId(request.volume_id));
     }
-    let mut volume_id = request.volume_id;
+    let collection = request.collection.unwrap_or_default();
+    let mut volume_id = match state.federation.split(&request.volume_id) {
+        (Some(region), volume_id) => {
+            let lookup = state
+                .federation
+                .lookup(region, volume_id, &collection)
+                .await?;
+            return Ok(Json(lookup));
+        }
+        (None, volume_id) => volume_id.to_string(),
+    };
     if let Some(idx) = volume_id.rfind(',') {
         volume_id = volume_id[..idx].to_string();
     }
-    let mut locations = vec![];
-    let data_nodes = state
-        .topology
-        .lookup(
-            &request.collection.unwrap_or_default(),
-            volume_id.parse::<u32>()?,
-        )
-        .await;
+    let vid = volume_id
+        .parse::<u32>()
+        .map_err(|_| VolumeError::InvalidVolumeId(volume_id.clone()))?;
+    let data_nodes = state.topology.lookup(&collection, vid).await;
     match data_nodes {
         Some(nodes) => {
-            for dn in nodes.iter() {
-                locations.push(Location {
-                    url: dn.url(),
-                    public_url: dn.public_url.clone(),
+            // the data center passed by the client takes precedence over the one of its ip
+            let data_center = request
+                .data_center
+                .filter(|data_center| !data_center.is_empty())
+                .or_else(|| {
+                    connect_info.and_then(|ConnectInfo(addr)| {
+                        state.data_center_ranges.data_center(addr.ip())
+                    })
                 });
-            }
+            let nodes = order_locations(nodes, request.read_preference, data_center).await;
+            let locations = nodes.iter().map(location).collect();
 
             let lookup = Lookup {
                 volume_id,
@@ -85,10 +161,41 @@ pub async fn lookup_handler(
             };
             Ok(Json(lookup))
         }
-        None => Err(VolumeError::String("cannot find any locations".to_string())),
+        None => Err(VolumeError::NotFound(vid)),
     }
 }
 
+fn location(data_node: &DataNodeRef) -> Location {
+    Location {
+        url: data_node.url(),
+        public_url: data_node.public_url.clone(),
+    }
+}
+
+/// the primary is the first location of a volume, the order of the other replicas is kept. the
+/// locations are ordered by proximity to `data_center` unless the primary is preferred explicitly.
+pub async fn order_locations(
+    nodes: Vec<DataNodeRef>,
+    preference: Option<ReadPreference>,
+    data_center: Option<FastStr>,
+) -> Vec<DataNodeRef> {
+    let data_center = match data_center {
+        Some(data_center) if preference != Some(ReadPreference::Primary) => data_center,
+        _ => return nodes,
+    };
+    let mut nearest = Vec::with_capacity(nodes.len());
+    let mut remote = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        if node.data_center_id().await == data_center {
+            nearest.push(node);
+        } else {
+            remote.push(node);
+        }
+    }
+    nearest.append(&mut remote);
+    nearest
+}
+
 pub async fn dir_status_handler(State(state): State<DirectoryState>) -> Json<Topology> {
     let topology = state.topology.topology();
     Json(topology)
@@ -111,6 +218,131 @@ pub async fn cluster_status_handler(State(state): State<DirectoryState>) -> Json
     Json(status)
 }
 
+/// ready when a raft leader is elected, followers forward requests to it
+pub async fn readyz_handler(State(state): State<DirectoryState>) -> Readiness {
+    let mut readiness = Readiness::default();
+    readiness.check("leader", state.topology.current_leader().await.map(|_| ()));
+    readiness
+}
+
+/// inspect the sequencer
+pub async fn sequence_status_handler(State(state): State<DirectoryState>) -> Json<SequenceStatus> {
+    Json(state.topology.sequence_status())
+}
+
+/// bump the sequencer after restoring data from backup
+pub async fn sequence_handler(
+    State(state): State<DirectoryState>,
+    FormOrJson(request): FormOrJson<SequenceRequest>,
+) -> Json<SequenceStatus> {
+    info!("bump sequencer to {}", request.max);
+    state.topology.set_max_sequence(request.max);
+    Json(state.topology.sequence_status())
+}
+
+/// volumes quarantined on any of their replicas
+pub async fn quarantined_volumes_handler(
+    State(state): State<DirectoryState>,
+) -> Json<Vec<QuarantinedVolume>> {
+    Json(state.topology.quarantined_volumes())
+}
+
+/// stored bytes and requests per collection, the requests are counted by the volume servers
+/// since they started and reported with every full heartbeat
+pub async fn cluster_usage_handler(
+    State(state): State<DirectoryState>,
+) -> Json<Vec<CollectionUsage>> {
+    Json(state.topology.collection_usages())
+}
+
+/// the collection usages of the cluster for prometheus to scrape
+pub async fn metrics_handler(State(state): State<DirectoryState>) -> impl IntoResponse {
+    let metrics = usage_metrics(CLUSTER_USAGE_PREFIX, &state.topology.collection_usages());
+    ([(CONTENT_TYPE, PROMETHEUS_TEXT_FORMAT)], metrics)
+}
+
+pub async fn cluster_nodes_handler(
+    State(state): State<DirectoryState>,
+) -> Json<Vec<DataNodeStatus>> {
+    Json(state.topology.data_node_statuses().await)
+}
+
+/// evacuate all volumes of a volume server so it can be removed from the cluster
+pub async fn decommission_handler(
+    State(state): State<DirectoryState>,
+    FormOrJson(request): FormOrJson<DecommissionRequest>,
+) -> Result<Json<DecommissionProgress>, VolumeError> {
+    info!("decommission volume server {}", request.node);
+    let progress = start_decommission(state.topology.clone(), &request.node).await?;
+    Ok(Json(progress))
+}
+
+pub async fn decommission_status_handler(
+    State(state): State<DirectoryState>,
+) -> Json<Vec<DecommissionProgress>> {
+    Json(state.topology.decommissions())
+}
+
+/// the collections marked for deletion
+pub async fn collection_deletions_handler(
+    State(state): State<DirectoryState>,
+) -> Json<Vec<CollectionDeletion>> {
+    Json(state.topology.collection_deletions())
+}
+
+/// delete a collection in two steps, it is marked first and its volumes are deleted by a purge
+/// after the delay and before the mark expires
+pub async fn collection_delete_handler(
+    State(state): State<DirectoryState>,
+    FormOrJson(request): FormOrJson<CollectionDeleteRequest>,
+) -> Result<Json<CollectionDeletion>, TopologyError> {
+    let topology = &state.topology;
+    let deletion = match request.action {
+        CollectionDeleteAction::Mark => {
+            topology
+                .mark_collection_deletion(
+                    &request.collection,
+                    state.options.collection_delete_delay,
+                    state.options.collection_delete_ttl,
+                    &state.options.protected_collections,
+                )
+                .await?
+        }
+        CollectionDeleteAction::Purge => topology.purge_collection(&request.collection).await?,
+        CollectionDeleteAction::Cancel => {
+            topology
+                .cancel_collection_deletion(&request.collection)
+                .await?
+        }
+    };
+    Ok(Json(deletion))
+}
+
+/// the admin jobs of the master, running and recently finished
+pub async fn jobs_handler(State(state): State<DirectoryState>) -> Json<Vec<JobStatus>> {
+    Json(state.topology.jobs.list())
+}
+
+/// pause, resume or cancel an admin job
+pub async fn job_control_handler(
+    State(state): State<DirectoryState>,
+    FormOrJson(request): FormOrJson<JobControlRequest>,
+) -> Result<Json<JobStatus>, TopologyError> {
+    info!("{:?} job {}", request.action, request.id);
+    let status = state.topology.jobs.control(request.id, request.action)?;
+    Ok(Json(status))
+}
+
+/// the replications and moves a hypothetical change of the cluster would cause, for capacity
+/// planning, nothing is executed
+pub async fn simulate_handler(
+    State(state): State<DirectoryState>,
+    FormOrJson(change): FormOrJson<PlacementChange>,
+) -> Result<Json<SimulationReport>, VolumeError> {
+    let report = state.topology.simulate(&change).await?;
+    Ok(Json(report))
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -139,9 +371,12 @@ mod tests {
             DirectoryState,
         },
         operation::Assignment,
-        topology::volume_grow::VolumeGrowth,
+        topology::{volume_grow::VolumeGrowth, volume_layout::AssignStrategy},
         util::{
-            args::{MasterOptions, RaftOptions},
+            args::{
+                MasterOptions, MetricsPushOptions, RaftOptions, ReplicationOptions, RetryOptions,
+                RuntimeOptions, SequencerOptions, TimeoutOptions,
+            },
             connector,
             http::default_handler,
         },
@@ -163,9 +398,28 @@ mod tests {
             port: 9333,
             meta_path: FastStr::new("./"),
             pulse: 5,
+            max_missed_heartbeats: 3,
             volume_size_limit_mb: 30000,
             default_replication: FastStr::new("000"),
+            topology_file: None,
+            topology_bootstrap_timeout: 300,
+            policy_file: None,
+            lookup_miss_ttl: 5,
+            assign_strategy: AssignStrategy::Random,
             raft: RaftOptions { peers: vec![] },
+            sequencer: SequencerOptions::default(),
+            replication: ReplicationOptions::default(),
+            timeout: TimeoutOptions::default(),
+            retry: RetryOptions::default(),
+            metrics_push: MetricsPushOptions::default(),
+            runtime: RuntimeOptions::default(),
+            data_center_ranges: vec![],
+            region: FastStr::empty(),
+            federation: vec![],
+            cluster_secret: None,
+            collection_delete_delay: 300,
+            collection_delete_ttl: 3600,
+            protected_collections: vec![],
         };
         let options = Arc::new(options);
 
@@ -173,6 +427,8 @@ mod tests {
             topology: topo,
             volume_grow: VolumeGrowth {},
             options,
+            data_center_ranges: Arc::default(),
+            federation: Arc::default(),
         };
 
         let http_router = Router::new()
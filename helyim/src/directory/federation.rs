@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use faststr::FastStr;
+
+use crate::{
+    errors::{Error, Result},
+    operation::lookup::Lookup,
+    storage::VolumeError,
+    util::http::get,
+};
+
+/// Independent clusters which share one namespace, a fid or volume id prefixed with
+/// `<region>/` is resolved by the master of that region.
+#[derive(Debug, Clone, Default)]
+pub struct Federation {
+    region: FastStr,
+    masters: HashMap<FastStr, FastStr>,
+}
+
+impl Federation {
+    /// every entry is `<region>=<master>`
+    pub fn parse(region: FastStr, entries: &[FastStr]) -> Result<Self> {
+        let mut masters = HashMap::new();
+        for entry in entries {
+            let (region, master) = entry.split_once('=').ok_or_else(|| {
+                Error::String(format!(
+                    "federated cluster {entry} should be <region>=<master>"
+                ))
+            })?;
+            masters.insert(FastStr::new(region), FastStr::new(master));
+        }
+        Ok(Self { region, masters })
+    }
+
+    /// split the region prefix from `volume_id`, a prefix naming this cluster is dropped
+    pub fn split<'a>(&self, volume_id: &'a str) -> (Option<&'a str>, &'a str) {
+        match volume_id.split_once('/') {
+            Some((region, volume_id)) if region != self.region => (Some(region), volume_id),
+            Some((_, volume_id)) => (None, volume_id),
+            None => (None, volume_id),
+        }
+    }
+
+    /// resolve `volume_id` by the master of `region`
+    pub async fn lookup(
+        &self,
+        region: &str,
+        volume_id: &str,
+        collection: &str,
+    ) -> std::result::Result<Lookup, VolumeError> {
+        let master = self
+            .masters
+            .get(region)
+            .ok_or_else(|| VolumeError::String(format!("unknown region {region}")))?;
+        let body = get(
+            format!("http://{master}/dir/lookup"),
+            &[("volumeId", volume_id), ("collection", collection)],
+        )
+        .await
+        .map_err(|err| VolumeError::String(format!("lookup in region {region} failed: {err}")))?;
+        let mut lookup: Lookup = serde_json::from_slice(&body).map_err(|err| {
+            VolumeError::String(format!("lookup in region {region} failed: {err}"))
+        })?;
+        lookup.volume_id = format!("{region}/{}", lookup.volume_id);
+        Ok(lookup)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use faststr::FastStr;
+
+    use crate::directory::federation::Federation;
+
+    #[test]
+    fn test_split_region() {
+        let federation =
+            Federation::parse(FastStr::new("us"), &[FastStr::new("eu=10.0.0.1:9333")]).unwrap();
+        assert_eq!(federation.split("3,01637037d6"), (None, "3,01637037d6"));
+        assert_eq!(federation.split("us/3,01637037d6"), (None, "3,01637037d6"));
+        assert_eq!(
+            federation.split("eu/3,01637037d6"),
+            (Some("eu"), "3,01637037d6")
+        );
+        assert!(Federation::parse(FastStr::empty(), &[FastStr::new("eu")]).is_err());
+    }
+}
@@ -1,4 +1,6 @@
 mod api;
+
+mod federation;
 pub use api::DirectoryState;
 
 pub use crate::sequence::{Sequence, Sequencer, SequencerType};
@@ -1,6 +1,17 @@
-use std::{net::SocketAddr, pin::Pin, result::Result as StdResult, sync::Arc, time::Duration};
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    result::Result as StdResult,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
 
-use axum::{extract::DefaultBodyLimit, middleware::from_fn_with_state, routing::get, Router};
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware::{from_fn, from_fn_with_state},
+    routing::{get, post},
+    Router,
+};
 use dashmap::DashMap;
 use faststr::FastStr;
 use futures::{
@@ -12,33 +23,58 @@ use helyim_proto::directory::{
     lookup_ec_volume_response::EcShardIdLocation,
     lookup_volume_response::VolumeIdLocation,
     HeartbeatRequest, HeartbeatResponse, KeepConnectedRequest, Location, LookupEcVolumeRequest,
-    LookupEcVolumeResponse, LookupVolumeRequest, LookupVolumeResponse, VolumeLocation,
+    LookupEcVolumeResponse, LookupVolumeRequest, LookupVolumeResponse, TopologyEvent,
+    TopologyEventKind, VolumeLocation, WatchTopologyRequest,
 };
-use tokio::net::TcpListener;
+use tokio::{net::TcpListener, sync::broadcast::error::RecvError};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tonic::{transport::Server as TonicServer, Request, Response, Status, Streaming};
-use tower_http::{compression::CompressionLayer, timeout::TimeoutLayer};
-use tracing::{error, info};
+use tower_http::{
+    catch_panic::CatchPanicLayer, compression::CompressionLayer, timeout::TimeoutLayer,
+};
+use tracing::{error, info, warn};
 
 use crate::{
     client::MasterClient,
-    directory::api::{
-        assign_handler, cluster_status_handler, dir_status_handler, lookup_handler, DirectoryState,
+    directory::{
+        api::{
+            assign_handler, cluster_nodes_handler, cluster_status_handler, cluster_usage_handler,
+            collection_delete_handler, collection_deletions_handler, decommission_handler,
+            decommission_status_handler, dir_status_handler, job_control_handler, jobs_handler,
+            lookup_handler, metrics_handler, order_locations, quarantined_volumes_handler,
+            readyz_handler, sequence_handler, sequence_status_handler, simulate_handler,
+            DirectoryState,
+        },
+        federation::Federation,
     },
     errors::Result,
+    operation::{usage_metrics, CLUSTER_USAGE_PREFIX},
     raft::{create_raft_router, RaftServer},
     sequence::Sequencer,
     storage::VolumeError,
     topology::{
-        node::Node, topology_vacuum_loop, volume_grow::VolumeGrowth, DataNodeRef, Topology,
-        TopologyError, TopologyRef,
+        new_event, node::Node, topology_maintenance_loop, topology_replication_loop,
+        topology_vacuum_loop, volume_grow::VolumeGrowth, DataNodeRef, MaintenancePolicy,
+        StaticTopology, Topology, TopologyError, TopologyRef,
     },
     util::{
         args::MasterOptions,
+        capability::{check_protocol_version, Capabilities, PROTOCOL_VERSION},
+        cidr::DataCenterRanges,
         get_or_default,
-        grpc::grpc_port,
-        http::{default_handler, extractor::require_leader},
+        grpc::{grpc_port, standard_services},
+        http::{
+            default_handler,
+            extractor::require_leader,
+            health::healthz_handler,
+            panic_response, pool_stats_handler,
+            request_id::{request_id, GrpcRequestIdLayer},
+        },
+        log::{log_levels_handler, set_log_level_handler},
         parser::parse_vid_fid,
+        pushgateway::push_loop,
+        retry::set_retry_policy,
+        sign::{set_cluster_secret, verify_signature, VerifyGrpc},
         sys::exit,
     },
 };
@@ -46,6 +82,8 @@ use crate::{
 pub struct DirectoryServer {
     pub options: Arc<MasterOptions>,
     pub garbage_threshold: f64,
+    pub data_center_ranges: Arc<DataCenterRanges>,
+    pub federation: Arc<Federation>,
     pub topology: TopologyRef,
     pub volume_grow: VolumeGrowth,
     pub master_client: Arc<MasterClient>,
@@ -60,26 +98,91 @@ impl DirectoryServer {
         sequencer: Sequencer,
     ) -> Result<DirectoryServer> {
         let master_opts = Arc::new(options);
+        set_retry_policy(master_opts.retry.policy());
+        set_cluster_secret(master_opts.cluster_secret.clone());
 
         let (shutdown, mut shutdown_rx) = async_broadcast::broadcast(16);
         let volume_size_limit_mb = master_opts.volume_size_limit_mb;
 
-        let topology = Arc::new(Topology::new(
-            sequencer,
-            volume_size_limit_mb * 1024 * 1024,
-            master_opts.pulse,
-        ));
+        let topology = Arc::new(
+            Topology::new(
+                sequencer,
+                volume_size_limit_mb * 1024 * 1024,
+                master_opts.pulse,
+            )
+            .with_lookup_miss_ttl(master_opts.lookup_miss_ttl)
+            .with_file_key_conflict(master_opts.sequencer.file_key_conflict)
+            .with_assign_strategy(master_opts.assign_strategy)
+            .with_max_missed_heartbeats(master_opts.max_missed_heartbeats),
+        );
 
-        tokio::spawn(topology_vacuum_loop(
+        if let Some(path) = master_opts.topology_file.as_ref() {
+            let declared = StaticTopology::load(path.as_str())?;
+            topology
+                .bootstrap(&declared, master_opts.topology_bootstrap_timeout)
+                .await?;
+        }
+
+        match master_opts.policy_file.as_ref() {
+            Some(path) => {
+                let policy = MaintenancePolicy::load(path.as_str())?;
+                tokio::spawn(topology_maintenance_loop(
+                    topology.clone(),
+                    path.clone(),
+                    policy,
+                    volume_size_limit_mb * (1 << 20),
+                    shutdown_rx.clone(),
+                ));
+            }
+            None => {
+                tokio::spawn(topology_vacuum_loop(
+                    topology.clone(),
+                    garbage_threshold,
+                    volume_size_limit_mb * (1 << 20),
+                    shutdown_rx.clone(),
+                ));
+            }
+        }
+        tokio::spawn(topology_replication_loop(
             topology.clone(),
-            garbage_threshold,
-            volume_size_limit_mb * (1 << 20),
+            master_opts.replication.clone(),
+            shutdown_rx.clone(),
+        ));
+        let metrics_topology = topology.clone();
+        tokio::spawn(push_loop(
+            master_opts.metrics_push.clone(),
+            FastStr::new(format!("{}:{}", master_opts.ip, master_opts.port)),
+            "master",
+            // only the leader knows the usages of the cluster
+            move || {
+                let topology = metrics_topology.clone();
+                async move {
+                    if topology.is_leader().await {
+                        Some(usage_metrics(
+                            CLUSTER_USAGE_PREFIX,
+                            &topology.collection_usages(),
+                        ))
+                    } else {
+                        None
+                    }
+                }
+            },
             shutdown_rx.clone(),
         ));
 
+        let data_center_ranges =
+            Arc::new(DataCenterRanges::parse(&master_opts.data_center_ranges)?);
+
+        let federation = Arc::new(Federation::parse(
+            master_opts.region.clone(),
+            &master_opts.federation,
+        )?);
+
         let master_client = MasterClient::new("master", master_opts.raft.peers.clone());
         let master = DirectoryServer {
             options: master_opts,
+            data_center_ranges: data_center_ranges.clone(),
+            federation,
             garbage_threshold,
             volume_grow: VolumeGrowth,
             topology: topology.clone(),
@@ -88,14 +191,19 @@ impl DirectoryServer {
         };
 
         let addr = format!("{}:{}", master.options.ip, grpc_port(master.options.port)).parse()?;
+        let (health, reflection) = standard_services::<HelyimServer<DirectoryGrpcServer>>().await?;
         tokio::spawn(async move {
             info!("directory grpc server starting up. binding addr: {addr}");
             if let Err(err) = TonicServer::builder()
-                .add_service(HelyimServer::new(DirectoryGrpcServer {
+                .layer(GrpcRequestIdLayer)
+                .add_service(health)
+                .add_service(reflection)
+                .add_service(VerifyGrpc::new(HelyimServer::new(DirectoryGrpcServer {
                     volume_size_limit_mb,
                     topology,
                     client_chans: Arc::new(DashMap::new()),
-                }))
+                    data_center_ranges,
+                })))
                 .serve_with_shutdown(addr, async {
                     let _ = shutdown_rx.recv().await;
                 })
@@ -126,6 +234,8 @@ impl DirectoryServer {
             topology: self.topology.clone(),
             volume_grow: self.volume_grow,
             options: self.options.clone(),
+            data_center_ranges: self.data_center_ranges.clone(),
+            federation: self.federation.clone(),
         };
         let addr = format!("{}:{}", self.options.ip, self.options.port).parse()?;
         let shutdown_rx = self.shutdown.new_receiver();
@@ -156,6 +266,42 @@ async fn start_directory_server(
     mut shutdown: async_broadcast::Receiver<()>,
     raft_router: Router,
 ) {
+    // the routes changing the cluster only take calls signed with the cluster secret
+    let admin_router = Router::new()
+        .route(
+            "/admin/sequence",
+            get(sequence_status_handler)
+                .post(sequence_handler)
+                .layer(from_fn_with_state(state.clone(), require_leader)),
+        )
+        .route(
+            "/cluster/decommission",
+            get(decommission_status_handler)
+                .post(decommission_handler)
+                .layer(from_fn_with_state(state.clone(), require_leader)),
+        )
+        .route(
+            "/col/delete",
+            get(collection_deletions_handler)
+                .post(collection_delete_handler)
+                .layer(from_fn_with_state(state.clone(), require_leader)),
+        )
+        .route(
+            "/admin/jobs",
+            get(jobs_handler)
+                .post(job_control_handler)
+                .layer(from_fn_with_state(state.clone(), require_leader)),
+        )
+        .route(
+            "/admin/simulate",
+            post(simulate_handler).layer(from_fn_with_state(state.clone(), require_leader)),
+        )
+        .route(
+            "/admin/log-level",
+            get(log_levels_handler).put(set_log_level_handler),
+        )
+        .layer(from_fn(verify_signature));
+
     let http_router = Router::new()
         .route(
             "/dir/assign",
@@ -177,26 +323,53 @@ async fn start_directory_server(
             "/cluster/status",
             get(cluster_status_handler).post(cluster_status_handler),
         )
+        .route(
+            "/cluster/nodes",
+            get(cluster_nodes_handler).layer(from_fn_with_state(state.clone(), require_leader)),
+        )
+        .route(
+            "/cluster/quarantined",
+            get(quarantined_volumes_handler)
+                .layer(from_fn_with_state(state.clone(), require_leader)),
+        )
+        .route(
+            "/cluster/usage",
+            get(cluster_usage_handler).layer(from_fn_with_state(state.clone(), require_leader)),
+        )
+        .route(
+            "/metrics",
+            get(metrics_handler).layer(from_fn_with_state(state.clone(), require_leader)),
+        )
+        .merge(admin_router)
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .route("/stats/pool", get(pool_stats_handler))
         .fallback(default_handler)
         .layer((
+            CatchPanicLayer::custom(panic_response),
             CompressionLayer::new(),
             DefaultBodyLimit::max(1024 * 1024),
-            TimeoutLayer::new(Duration::from_secs(10)),
+            TimeoutLayer::new(Duration::from_secs(state.options.timeout.request_timeout)),
         ))
         .with_state(state);
 
-    let app = http_router.merge(Router::new().nest("/raft", raft_router));
+    let app = http_router
+        .merge(Router::new().nest("/raft", raft_router))
+        .layer(from_fn(request_id));
 
     info!("directory api server is starting up. binding addr: {addr}");
     match TcpListener::bind(addr).await {
         Ok(listener) => {
             // will blocking current thread
-            if let Err(err) = axum::serve(listener, app.into_make_service())
-                .with_graceful_shutdown(async move {
-                    let _ = shutdown.recv().await;
-                    info!("directory api server shutting down gracefully.");
-                })
-                .await
+            if let Err(err) = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(async move {
+                let _ = shutdown.recv().await;
+                info!("directory api server shutting down gracefully.");
+            })
+            .await
             {
                 error!("starting directory api server failed, error: {err}");
                 exit();
@@ -211,6 +384,7 @@ struct DirectoryGrpcServer {
     pub volume_size_limit_mb: u64,
     pub topology: TopologyRef,
     pub client_chans: Arc<DashMap<FastStr, UnboundedSender<VolumeLocation>>>,
+    pub data_center_ranges: Arc<DataCenterRanges>,
 }
 
 #[tonic::async_trait]
@@ -246,10 +420,18 @@ impl Helyim for DirectoryGrpcServer {
                                 }
                             }
                         }
-                        topology.set_max_sequence(heartbeat.max_file_key);
+                        topology.observe_max_file_key(&addr.to_string(), heartbeat.max_file_key);
 
                         match data_node_opt.as_ref() {
                             Some(data_node) => {
+                                if data_node.is_expired() {
+                                    // let the volume server reconnect and register again
+                                    let _ = tx.send(Err(Status::unavailable(format!(
+                                        "data node {} missed heartbeats and was removed",
+                                        data_node.id()
+                                    ))));
+                                    break;
+                                }
                                 if let Err(err) = update_volume_layout(
                                     &heartbeat,
                                     &topology,
@@ -262,6 +444,12 @@ impl Helyim for DirectoryGrpcServer {
                                 }
                             }
                             None => {
+                                if let Err(err) = check_protocol_version(heartbeat.protocol_version)
+                                {
+                                    error!("reject heartbeat from {addr}: {err}");
+                                    let _ = tx.send(Err(Status::failed_precondition(err)));
+                                    break;
+                                }
                                 if let Ok(data_node) =
                                     update_topology(&heartbeat, &topology, addr).await
                                 {
@@ -269,11 +457,18 @@ impl Helyim for DirectoryGrpcServer {
                                         "register connected volume server: {}:{}",
                                         data_node.ip, data_node.port
                                     );
+                                    topology.publish_event(TopologyEvent {
+                                        node: data_node.url(),
+                                        ..new_event(TopologyEventKind::NodeJoined)
+                                    });
 
                                     data_node_opt = Some(data_node);
                                 }
                             }
                         }
+                        if let Some(data_node) = data_node_opt.as_ref() {
+                            data_node.touch();
+                        }
 
                         match heartbeat_response(volume_size_limit, &topology).await {
                             Ok(response) => {
@@ -325,8 +520,13 @@ impl Helyim for DirectoryGrpcServer {
                     topology.inform_new_leader(&location_tx).await;
                     return Err(Status::internal("current node is not raft leader"));
                 }
+                check_protocol_version(request.protocol_version)
+                    .map_err(Status::failed_precondition)?;
                 let client_name = FastStr::new(format!("{}.{addr}", request.name));
-                info!("add client: {client_name}");
+                info!(
+                    "add client: {client_name}, protocol version: {}",
+                    request.protocol_version
+                );
 
                 clients.insert(client_name.clone(), message_tx);
 
@@ -391,15 +591,60 @@ impl Helyim for DirectoryGrpcServer {
         ))
     }
 
+    type WatchTopologyStream = Pin<Box<dyn Stream<Item = StdResult<TopologyEvent, Status>> + Send>>;
+    async fn watch_topology(
+        &self,
+        request: Request<WatchTopologyRequest>,
+    ) -> StdResult<Response<Self::WatchTopologyStream>, Status> {
+        // only the leader sees the heartbeats the events come from
+        if !self.topology.is_leader().await {
+            let leader = self.topology.current_leader_address().await;
+            return Err(Status::failed_precondition(format!(
+                "this node is not raft leader, watch the leader {}",
+                leader.as_deref().unwrap_or("unknown")
+            )));
+        }
+        let kinds = request.into_inner().kinds;
+        let mut events = self.topology.subscribe_events();
+        let out_stream = async_stream::stream! {
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        if kinds.is_empty() || kinds.contains(&event.kind) {
+                            yield Ok(event);
+                        }
+                    }
+                    // the observer has to watch again and resync from the status api
+                    Err(RecvError::Lagged(dropped)) => {
+                        warn!("topology watcher fell behind, {dropped} events dropped");
+                        yield Err(Status::data_loss(format!("{dropped} topology events dropped")));
+                        break;
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        };
+        Ok(Response::new(
+            Box::pin(out_stream) as Self::WatchTopologyStream
+        ))
+    }
+
     async fn lookup_volume(
         &self,
         request: Request<LookupVolumeRequest>,
     ) -> StdResult<Response<LookupVolumeResponse>, Status> {
+        let remote_addr = request.remote_addr();
         let request = request.into_inner();
         if request.volume_or_file_ids.is_empty() {
             return Err(Status::invalid_argument("volumes can't be empty"));
         }
 
+        let data_center = if request.data_center.is_empty() {
+            remote_addr.and_then(|addr| self.data_center_ranges.data_center(addr.ip()))
+        } else {
+            Some(FastStr::new(&request.data_center))
+        };
+
         let mut volume_id_locations = vec![];
         for volume_id in request.volume_or_file_ids {
             let (_, (vid, _fid)) = parse_vid_fid(&volume_id)
@@ -412,6 +657,7 @@ impl Helyim for DirectoryGrpcServer {
             let mut error = String::default();
             match self.topology.lookup(&request.collection, vid).await {
                 Some(nodes) => {
+                    let nodes = order_locations(nodes, None, data_center.clone()).await;
                     for dn in nodes.iter() {
                         let public_url = dn.public_url.to_string();
                         locations.push(Location {
@@ -484,7 +730,6 @@ async fn update_topology(
     topology: &TopologyRef,
     addr: SocketAddr,
 ) -> StdResult<DataNodeRef, VolumeError> {
-    topology.set_max_sequence(heartbeat.max_file_key);
     let mut ip = heartbeat.ip.clone();
     if heartbeat.ip.is_empty() {
         ip = addr.ip().to_string();
@@ -510,6 +755,30 @@ async fn update_topology(
         )
         .await;
     data_node.set_parent(Some(rack.clone())).await;
+    // declared data nodes get their capacity from the first heartbeat
+    let mut max_volume_count = heartbeat.max_volume_count as i64;
+    // a reconnecting data node which is decommissioned keeps draining, it gets no free slots back
+    if topology.decommission_progress(data_node.id()).is_some() {
+        data_node.decommissioning.store(true, Ordering::Relaxed);
+        max_volume_count = max_volume_count.min(heartbeat.volumes.len() as i64);
+    }
+    if data_node.max_volume_count() != max_volume_count {
+        data_node
+            .adjust_max_volume_count(max_volume_count - data_node.max_volume_count())
+            .await;
+    }
+    if !heartbeat.max_volume_counts.is_empty() {
+        data_node.set_max_volume_counts(&heartbeat.max_volume_counts);
+    }
+
+    let capabilities = Capabilities::new(&heartbeat.version, &heartbeat.features);
+    info!(
+        "data node {} version: {}, features: {:?}",
+        data_node.id(),
+        capabilities.version,
+        capabilities.features
+    );
+    data_node.set_capabilities(capabilities);
     Ok(data_node)
 }
 
@@ -560,6 +829,7 @@ async fn update_volume_layout(
         let (new_volumes, deleted_volumes) = topology
             .sync_data_node_registration(&heartbeat.volumes, data_node)
             .await;
+        data_node.set_usages(heartbeat.usages.clone());
         for volume in new_volumes {
             volume_location.new_vids.push(volume.id);
         }
@@ -635,6 +905,22 @@ async fn update_volume_layout(
         }
     }
 
+    for (vids, kind) in [
+        (&volume_location.new_vids, TopologyEventKind::VolumeCreated),
+        (
+            &volume_location.deleted_vids,
+            TopologyEventKind::VolumeDeleted,
+        ),
+    ] {
+        for vid in vids {
+            topology.publish_event(TopologyEvent {
+                node: data_node.url(),
+                volume_id: *vid,
+                ..new_event(kind)
+            });
+        }
+    }
+
     if !volume_location.new_vids.is_empty()
         || !volume_location.deleted_vids.is_empty()
         || !volume_location.new_ec_vids.is_empty()
@@ -660,6 +946,10 @@ async fn remove_data_node(
         );
 
         topology.unregister_data_node(&data_node).await;
+        topology.publish_event(TopologyEvent {
+            node: data_node.url(),
+            ..new_event(TopologyEventKind::NodeLeft)
+        });
 
         let mut volume_location = VolumeLocation::new();
         volume_location.url = data_node.url();
@@ -685,9 +975,13 @@ async fn heartbeat_response(
     topology: &TopologyRef,
 ) -> StdResult<HeartbeatResponse, TopologyError> {
     let leader = topology.current_leader().await?;
+    let capabilities = Capabilities::local();
     Ok(HeartbeatResponse {
         volume_size_limit,
         leader: leader.to_string(),
+        version: capabilities.version.to_string(),
+        features: capabilities.feature_list(),
+        protocol_version: PROTOCOL_VERSION,
         ..Default::default()
     })
 }
@@ -2,20 +2,25 @@ use std::net::AddrParseError;
 
 use axum::{
     http::{
-        header::{InvalidHeaderName, InvalidHeaderValue, ToStrError},
+        header::{InvalidHeaderName, InvalidHeaderValue, ToStrError, RETRY_AFTER},
         StatusCode,
     },
     response::{IntoResponse, Response},
     Json,
 };
+use faststr::FastStr;
 use futures::channel::mpsc::TrySendError;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
 use tracing::error;
 
 use crate::{
     raft::types::RaftError,
-    storage::{erasure_coding::EcVolumeError, NeedleError, TtlError, VolumeError},
+    storage::{
+        erasure_coding::EcVolumeError, NeedleError, NeedleId, TtlError, VolumeError, VolumeId,
+        WRITE_QUEUE_RETRY_AFTER_SECS,
+    },
     topology::TopologyError,
+    util::{http::request_id::current_request_id, sign::SignatureError},
 };
 
 #[derive(thiserror::Error, Debug)]
@@ -32,6 +37,8 @@ pub enum Error {
     Raft(#[from] RaftError),
     #[error("Topology error: {0}")]
     Topology(#[from] TopologyError),
+    #[error("Signature error: {0}")]
+    Signature(#[from] SignatureError),
 
     /// other errors
     #[error("Io error: {0}")]
@@ -46,6 +53,8 @@ pub enum Error {
     SerdeJson(#[from] serde_json::Error),
     #[error("{0}")]
     String(String),
+    #[error("Invalid options:\n  {}", .0.join("\n  "))]
+    InvalidOptions(Vec<String>),
     #[error("Utf8 error: {0}")]
     Utf8(#[from] std::string::FromUtf8Error),
     #[error("Addr parse error: {0}")]
@@ -57,11 +66,14 @@ pub enum Error {
     #[error("Chrono parse error: {0}")]
     ChronoParse(#[from] chrono::ParseError),
 
+    #[cfg(unix)]
     #[error("Errno: {0}")]
     Errno(#[from] rustix::io::Errno),
 
     #[error("Snowflake error: {0}")]
     Snowflake(#[from] sonyflake::Error),
+    #[error("Etcd error: {0}")]
+    Etcd(#[from] etcd_client::Error),
 
     // http
     #[error("Invalid header value: {0}")]
@@ -80,6 +92,9 @@ pub enum Error {
     AxumHttp(#[from] axum::http::Error),
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
+    /// the host failed too often, requests are not sent until the eject period is over
+    #[error("Host {0} is ejected")]
+    HostEjected(FastStr),
 
     // tonic
     #[error("Tonic status: {0}")]
@@ -89,6 +104,10 @@ pub enum Error {
 
     #[error("Broadcast channel closed")]
     BroadcastSend(#[from] async_broadcast::SendError<()>),
+
+    /// error response of a helyim http api
+    #[error("Api error: {0}")]
+    Api(ErrorBody),
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -99,14 +118,214 @@ impl From<String> for Error {
     }
 }
 
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Volume(err) => err.code(),
+            Error::EcVolume(err) => err.code(),
+            Error::Needle(err) => err.code(),
+            Error::Raft(err) => err.code(),
+            Error::Topology(err) => err.code(),
+            Error::Api(body) => body.code,
+            Error::Ttl(_)
+            | Error::ParseInt(_)
+            | Error::SerdeJson(_)
+            | Error::String(_)
+            | Error::InvalidOptions(_)
+            | Error::Utf8(_)
+            | Error::AddrParse(_)
+            | Error::Nom(_)
+            | Error::Multer(_)
+            | Error::ChronoParse(_)
+            | Error::InvalidHeaderValue(_)
+            | Error::InvalidHeaderName(_)
+            | Error::ToStr(_)
+            | Error::UrlParse(_) => ErrorCode::BadRequest,
+            Error::Signature(_) => ErrorCode::Unauthorized,
+            Error::Timeout => ErrorCode::Timeout,
+            Error::Io(_)
+            | Error::Bincode(_)
+            | Error::Box(_)
+            | Error::Snowflake(_)
+            | Error::Etcd(_)
+            | Error::Hyper(_)
+            | Error::AxumHttp(_)
+            | Error::Reqwest(_)
+            | Error::HostEjected(_)
+            | Error::TonicStatus(_)
+            | Error::TonicTransport(_)
+            | Error::BroadcastSend(_) => ErrorCode::Internal,
+            #[cfg(unix)]
+            Error::Errno(_) => ErrorCode::Internal,
+        }
+    }
+
+    pub fn volume_id(&self) -> Option<VolumeId> {
+        match self {
+            Error::Volume(err) => err.volume_id(),
+            Error::EcVolume(err) => err.volume_id(),
+            Error::Needle(err) => err.volume_id(),
+            Error::Api(body) => body.volume_id,
+            _ => None,
+        }
+    }
+
+    pub fn needle_id(&self) -> Option<NeedleId> {
+        match self {
+            Error::Volume(err) => err.needle_id(),
+            Error::Needle(err) => err.needle_id(),
+            Error::Api(body) => body.needle_id,
+            _ => None,
+        }
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        let error = self.to_string();
-        let error = json!({
-            "error": error
-        });
-        let response = (StatusCode::BAD_REQUEST, Json(error));
-        response.into_response()
+        let body = match self {
+            Error::Api(body) => body,
+            err => ErrorBody::new(err.code(), &err)
+                .with_volume_id(err.volume_id())
+                .with_needle_id(err.needle_id()),
+        };
+        body.into_response()
+    }
+}
+
+/// Machine readable category of an api error, clients should match on it instead of the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    VolumeNotFound,
+    NeedleNotFound,
+    NeedleDeleted,
+    NeedleExpired,
+    CookieMismatch,
+    ChecksumMismatch,
+    PreconditionFailed,
+    /// the needle exists with other content and the write does not replace it
+    Conflict,
+    ReadOnly,
+    Quarantined,
+    Compacting,
+    WriteQueueFull,
+    NoWritableVolumes,
+    NoFreeSpace,
+    DataCorrupted,
+    NotLeader,
+    Bootstrapping,
+    JobNotFound,
+    CollectionNotFound,
+    /// the write did not reach the copies its replica sync asks for
+    ReplicationIncomplete,
+    /// an internal call without a valid signature of the cluster secret
+    Unauthorized,
+    Timeout,
+    Internal,
+    /// a code added by a newer server
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::VolumeNotFound
+            | ErrorCode::NeedleNotFound
+            | ErrorCode::NeedleDeleted
+            | ErrorCode::NeedleExpired
+            | ErrorCode::JobNotFound
+            | ErrorCode::CollectionNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::WriteQueueFull => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::NotLeader
+            | ErrorCode::Bootstrapping
+            | ErrorCode::NoWritableVolumes
+            | ErrorCode::ReplicationIncomplete => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCode::DataCorrupted | ErrorCode::Internal | ErrorCode::Unknown => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// whether the same request may succeed later without being changed
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorCode::Compacting
+                | ErrorCode::WriteQueueFull
+                | ErrorCode::NoWritableVolumes
+                | ErrorCode::NotLeader
+                | ErrorCode::Bootstrapping
+                | ErrorCode::ReplicationIncomplete
+                | ErrorCode::Timeout
+        )
+    }
+}
+
+/// Json body of every error response of the master and volume http apis.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    /// the message, named like the error field of the other api responses
+    pub error: String,
+    pub retryable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume_id: Option<VolumeId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub needle_id: Option<NeedleId>,
+    /// id of the failed request, to quote when reporting it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<FastStr>,
+}
+
+impl ErrorBody {
+    pub fn new<E: ToString>(code: ErrorCode, error: E) -> Self {
+        Self {
+            code,
+            error: error.to_string(),
+            retryable: code.retryable(),
+            volume_id: None,
+            needle_id: None,
+            request_id: None,
+        }
+    }
+
+    pub fn with_volume_id(mut self, volume_id: Option<VolumeId>) -> Self {
+        self.volume_id = volume_id;
+        self
+    }
+
+    pub fn with_needle_id(mut self, needle_id: Option<NeedleId>) -> Self {
+        self.needle_id = needle_id;
+        self
+    }
+}
+
+impl std::fmt::Display for ErrorBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}, {}", self.code, self.error)
+    }
+}
+
+impl IntoResponse for ErrorBody {
+    fn into_response(mut self) -> Response {
+        // the error of another server answers this request, it is reported under its id
+        if let Some(id) = current_request_id() {
+            self.request_id = Some(id);
+        }
+        let status = self.code.status();
+        if self.code == ErrorCode::WriteQueueFull {
+            let retry_after = [(RETRY_AFTER, WRITE_QUEUE_RETRY_AFTER_SECS)];
+            return (status, retry_after, Json(self)).into_response();
+        }
+        (status, Json(self)).into_response()
     }
 }
 
@@ -127,3 +346,42 @@ impl From<nom::Err<nom::error::Error<&str>>> for Error {
         Self::String(value.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use crate::{
+        errors::{Error, ErrorBody, ErrorCode},
+        storage::{NeedleError, VolumeError},
+    };
+
+    #[test]
+    fn test_error_code() {
+        let err = Error::Volume(VolumeError::Needle(NeedleError::Deleted(3, 7)));
+        assert_eq!(err.code(), ErrorCode::NeedleDeleted);
+        assert_eq!(err.code().status(), StatusCode::NOT_FOUND);
+        assert_eq!(err.volume_id(), Some(3));
+        assert_eq!(err.needle_id(), Some(7));
+
+        let err = Error::Volume(VolumeError::WriteQueueFull(5));
+        assert_eq!(err.code().status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(err.code().retryable());
+        assert_eq!(Error::String("bad".into()).code(), ErrorCode::BadRequest);
+
+        let err = Error::Volume(VolumeError::Replication(3, 1, 2));
+        assert_eq!(err.code().status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(err.code().retryable());
+
+        let body = ErrorBody::new(ErrorCode::VolumeNotFound, "volume 3 is not found")
+            .with_volume_id(Some(3));
+        let json = serde_json::to_string(&body).unwrap();
+        assert_eq!(
+            json,
+            r#"{"code":"volume_not_found","error":"volume 3 is not found","retryable":false,"volumeId":3}"#
+        );
+        let body: ErrorBody =
+            serde_json::from_str(r#"{"code":"brand_new","error":"x","retryable":false}"#).unwrap();
+        assert_eq!(body.code, ErrorCode::Unknown);
+    }
+}
@@ -4,6 +4,7 @@
 #![allow(clippy::module_inception)]
 #![deny(unused_qualifications)]
 
+pub mod cache;
 pub mod client;
 pub mod directory;
 
@@ -18,6 +19,8 @@ pub mod raft;
 pub mod storage;
 
 mod sequence;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod topology;
 pub mod util;
 
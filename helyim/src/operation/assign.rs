@@ -2,18 +2,40 @@ use faststr::FastStr;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    storage::{ReplicaPlacement, Ttl, VolumeError},
+    operation::lookup::Location,
+    storage::{check_needle_alignment, DiskType, ReplicaPlacement, Ttl, VolumeError},
     topology::volume_grow::VolumeGrowOption,
+    util::capability::FEATURE_TTL,
 };
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+/// alternatives an assign returns at most
+pub const MAX_ASSIGN_ALTERNATIVES: usize = 4;
+
+// fields missing from older masters are filled with defaults
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
 pub struct Assignment {
     pub fid: String,
     pub url: String,
     pub public_url: FastStr,
     pub count: u64,
+    /// all replicas of the assigned volume, the primary which `url` refers to is first
+    pub replicas: Vec<Location>,
+    /// other writable volumes for the same file, to write to if the assigned one fails
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<AssignAlternative>,
     pub error: String,
+    pub protocol_version: u32,
+}
+
+/// A writable volume to fall back to, its fid has the key and cookie of the assigned fid.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssignAlternative {
+    pub fid: String,
+    pub url: String,
+    pub public_url: FastStr,
+    pub replicas: Vec<Location>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,6 +49,19 @@ pub struct AssignRequest {
     pub data_center: Option<FastStr>,
     pub rack: Option<FastStr>,
     pub data_node: Option<FastStr>,
+    /// derive the file key from this path instead of the sequencer
+    pub path: Option<FastStr>,
+    /// `hdd` or `ssd`, the disk type of the volume to write to
+    pub disk: Option<FastStr>,
+    /// writable volumes returned besides the assigned one, at most `MAX_ASSIGN_ALTERNATIVES`,
+    /// ignored for path assigns whose volume is derived from the path
+    pub alternatives: Option<usize>,
+    /// comma separated features the volume servers of a new volume must support, like
+    /// `conditional_write`
+    pub features: Option<FastStr>,
+    /// alignment of the needles of volumes grown for this assign, volumes already writable keep
+    /// theirs
+    pub needle_alignment: Option<u32>,
 }
 
 impl AssignRequest {
@@ -48,6 +83,12 @@ impl AssignRequest {
         }
         if let Some(ttl) = self.ttl {
             option.ttl = Ttl::new(&ttl)?;
+            // a volume server without ttl support would keep the needles forever
+            if option.ttl.to_u32() != 0 {
+                option
+                    .required_features
+                    .push(FastStr::from_static_str(FEATURE_TTL));
+            }
         }
         if let Some(preallocate) = self.preallocate {
             option.preallocate = preallocate;
@@ -64,6 +105,46 @@ impl AssignRequest {
         if let Some(data_node) = self.data_node {
             option.data_node = data_node;
         }
+        if let Some(disk) = self.disk {
+            option.disk_type = DiskType::new(&disk)?;
+        }
+        if let Some(features) = self.features {
+            for feature in features.split(',').map(str::trim) {
+                if !feature.is_empty() && !option.required_features.iter().any(|f| f == feature) {
+                    option.required_features.push(FastStr::new(feature));
+                }
+            }
+        }
+        if let Some(alignment) = self.needle_alignment {
+            check_needle_alignment(alignment)?;
+            option.needle_alignment = alignment;
+        }
         Ok(option)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use faststr::FastStr;
+
+    use crate::operation::AssignRequest;
+
+    #[test]
+    fn test_volume_grow_option_features() {
+        let request: AssignRequest =
+            serde_json::from_str(r#"{"ttl":"3m","features":"conditional_write, ttl,"}"#).unwrap();
+        let option = request.volume_grow_option(&FastStr::new("000")).unwrap();
+        assert_eq!(option.required_features, vec!["ttl", "conditional_write"]);
+
+        let request: AssignRequest = serde_json::from_str("{}").unwrap();
+        let option = request.volume_grow_option(&FastStr::new("000")).unwrap();
+        assert!(option.required_features.is_empty());
+        assert_eq!(option.needle_alignment, 0);
+
+        let request: AssignRequest = serde_json::from_str(r#"{"needleAlignment":32}"#).unwrap();
+        let option = request.volume_grow_option(&FastStr::new("000")).unwrap();
+        assert_eq!(option.needle_alignment, 32);
+        let request: AssignRequest = serde_json::from_str(r#"{"needleAlignment":12}"#).unwrap();
+        assert!(request.volume_grow_option(&FastStr::new("000")).is_err());
+    }
+}
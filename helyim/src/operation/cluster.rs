@@ -3,7 +3,12 @@ use std::{collections::BTreeMap, time::Duration};
 use faststr::FastStr;
 use serde::{Deserialize, Serialize};
 
-use crate::{raft::types::NodeId, storage::VolumeError, util::http::HTTP_CLIENT};
+use crate::{
+    raft::types::NodeId,
+    storage::{VolumeError, VolumeId},
+    topology::{CollectionDeleteAction, JobAction},
+    util::http::HTTP_CLIENT,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct ClusterStatus {
@@ -12,6 +17,55 @@ pub struct ClusterStatus {
     pub peers: BTreeMap<NodeId, FastStr>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataNodeStatus {
+    pub id: FastStr,
+    pub url: String,
+    pub public_url: FastStr,
+    pub data_center: FastStr,
+    pub rack: FastStr,
+    /// unix seconds of the last heartbeat
+    pub last_seen: i64,
+    /// false for declared data nodes which never sent a heartbeat
+    pub connected: bool,
+    pub alive: bool,
+    pub volumes: i64,
+    pub ec_shards: i64,
+    pub max_volumes: i64,
+    pub free_space: i64,
+    pub version: FastStr,
+    pub features: Vec<FastStr>,
+    pub decommissioning: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantinedVolume {
+    pub id: VolumeId,
+    pub collection: FastStr,
+    /// data nodes holding a quarantined replica
+    pub nodes: Vec<FastStr>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecommissionRequest {
+    pub node: FastStr,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CollectionDeleteRequest {
+    pub collection: FastStr,
+    #[serde(default)]
+    pub action: CollectionDeleteAction,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobControlRequest {
+    pub id: u64,
+    pub action: JobAction,
+}
+
 pub async fn list_master(addr: &str) -> Result<ClusterStatus, VolumeError> {
     for _ in 0..3 {
         let cluster_status: ClusterStatus = HTTP_CLIENT
@@ -9,9 +9,9 @@ use serde::{Deserialize, Serialize};
 use tonic::Status;
 
 use crate::{
-    errors::Result,
+    errors::{Error, Result},
     storage::VolumeId,
-    util::{grpc::helyim_client, parser::parse_vid_fid},
+    util::{grpc::helyim_client, parser::parse_vid_fid, retry::retry},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +19,23 @@ use crate::{
 pub struct LookupRequest {
     pub volume_id: String,
     pub collection: Option<String>,
+    /// data center of the client, inferred from the client ip if it is not set
+    pub data_center: Option<FastStr>,
+    pub read_preference: Option<ReadPreference>,
+}
+
+/// Order of the locations in a lookup response.
+///
+/// The primary of a volume is the replica which assigned writes are sent to, it is always listed
+/// first for `Primary`. `Nearest` lists the replicas in the data center of the client first, and
+/// keeps the primary first among them, it is used if no preference is set and the data center of
+/// the client is known.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadPreference {
+    #[default]
+    Primary,
+    Nearest,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,10 +70,14 @@ impl Looker {
         let request = LookupVolumeRequest {
             volume_or_file_ids: vids.iter().map(|vid| vid.to_string()).collect(),
             collection: String::default(),
+            data_center: String::default(),
         };
 
-        let client = helyim_client(master)?;
-        let response = client.lookup_volume(request).await?;
+        let response = retry("lookup volume", || async {
+            let client = helyim_client(master)?;
+            Ok::<_, Error>(client.lookup_volume(request.clone()).await?)
+        })
+        .await?;
         Ok(response.into_inner())
     }
 
@@ -1,11 +1,19 @@
 mod assign;
-pub use assign::{AssignRequest, Assignment};
+pub use assign::{AssignAlternative, AssignRequest, Assignment, MAX_ASSIGN_ALTERNATIVES};
 
 mod cluster;
-pub use cluster::{list_master, ClusterStatus};
+pub use cluster::{
+    list_master, ClusterStatus, CollectionDeleteRequest, DataNodeStatus, DecommissionRequest,
+    JobControlRequest, QuarantinedVolume,
+};
 
 pub mod lookup;
 pub use lookup::Looker;
 
+pub mod sequence;
+
 mod upload;
-pub use upload::{ParseUpload, Upload};
+pub use upload::{ParseUpload, ReplicaSync, Upload};
+
+mod usage;
+pub use usage::{usage_metrics, CollectionUsage, CLUSTER_USAGE_PREFIX, SERVER_USAGE_PREFIX};
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+use crate::storage::VolumeId;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SequenceRequest {
+    /// bump the sequencer so that no id less than or equal to `max` is handed out again
+    pub max: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SequenceStatus {
+    pub sequencer: String,
+    /// the next file id this master would hand out, snowflake ids are not counted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_file_id: Option<u64>,
+    /// the largest file key a volume server reported beyond the sequencer, 0 if none
+    pub conflicting_file_key: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaxFileKeyQuery {
+    pub volume: VolumeId,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaxFileKeyRequest {
+    pub volume: VolumeId,
+    /// raise the max file key of the volume, it never goes down
+    pub max: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaxFileKey {
+    pub volume: VolumeId,
+    pub max_file_key: u64,
+}
@@ -1,5 +1,6 @@
 use std::{collections::HashMap, fmt::Display};
 
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
 use crate::storage::Ttl;
@@ -9,11 +10,16 @@ pub struct Upload {
     pub name: String,
     pub size: usize,
     pub error: String,
+    /// checksums of the request body, which is the file itself unless it is a multipart form
+    #[serde(rename = "contentMd5", skip_serializing_if = "Option::is_none")]
+    pub content_md5: Option<String>,
+    #[serde(rename = "contentSha256", skip_serializing_if = "Option::is_none")]
+    pub content_sha256: Option<String>,
 }
 
 pub struct ParseUpload {
     pub filename: String,
-    pub data: Vec<u8>,
+    pub data: Bytes,
     pub mime_type: String,
     pub pair_map: HashMap<String, String>,
     pub modified_time: u64,
@@ -34,3 +40,38 @@ impl Display for ParseUpload {
         )
     }
 }
+
+/// How many copies of a write, the one on the receiving volume server included, are written
+/// before the write is acknowledged, the other replicas are written in the background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicaSync {
+    All,
+    Quorum,
+    One,
+}
+
+impl ReplicaSync {
+    /// copies to wait for out of `copies`
+    pub fn required(&self, copies: usize) -> usize {
+        match self {
+            ReplicaSync::All => copies,
+            ReplicaSync::Quorum => copies / 2 + 1,
+            ReplicaSync::One => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operation::ReplicaSync;
+
+    #[test]
+    fn test_replica_sync_required() {
+        assert_eq!(ReplicaSync::All.required(3), 3);
+        assert_eq!(ReplicaSync::Quorum.required(3), 2);
+        assert_eq!(ReplicaSync::Quorum.required(2), 2);
+        assert_eq!(ReplicaSync::Quorum.required(1), 1);
+        assert_eq!(ReplicaSync::One.required(3), 1);
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt::Write;
+
+use faststr::FastStr;
+use helyim_proto::directory::CollectionUsageMessage;
+use serde::{Deserialize, Serialize};
+
+/// stored bytes and requests of a collection, a volume server reports its own share and the
+/// master the sum over all volume servers
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionUsage {
+    pub collection: FastStr,
+    pub volumes: u64,
+    /// live files, replicas are counted once
+    pub files: u64,
+    /// bytes of the data files, replicas included
+    pub size: u64,
+    pub deleted_bytes: u64,
+    pub reads: u64,
+    pub writes: u64,
+    pub deletes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+impl CollectionUsage {
+    pub fn new(collection: FastStr) -> Self {
+        Self {
+            collection,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_requests(&mut self, message: &CollectionUsageMessage) {
+        self.reads += message.reads;
+        self.writes += message.writes;
+        self.deletes += message.deletes;
+        self.bytes_read += message.bytes_read;
+        self.bytes_written += message.bytes_written;
+    }
+}
+
+/// prefix of the metrics of a volume server, its own share of the collections
+pub const SERVER_USAGE_PREFIX: &str = "helyim_collection";
+/// prefix of the metrics of the master, the sum over all volume servers. it differs from the
+/// prefix of the volume servers so a sum over every scraped target does not count twice.
+pub const CLUSTER_USAGE_PREFIX: &str = "helyim_cluster_collection";
+
+/// the usages in the prometheus text format, every sample is labeled by its collection
+pub fn usage_metrics(prefix: &str, usages: &[CollectionUsage]) -> String {
+    let mut metrics = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, value: fn(&CollectionUsage) -> u64| {
+        let name = format!("{prefix}_{name}");
+        let _ = writeln!(metrics, "# HELP {name} {help}");
+        let _ = writeln!(metrics, "# TYPE {name} {kind}");
+        for usage in usages {
+            let _ = writeln!(
+                metrics,
+                "{name}{{collection=\"{}\"}} {}",
+                escape_label(&usage.collection),
+                value(usage)
+            );
+        }
+    };
+    family("volumes", "gauge", "volumes of the collection", |usage| {
+        usage.volumes
+    });
+    family("files", "gauge", "live files of the collection", |usage| {
+        usage.files
+    });
+    family(
+        "size_bytes",
+        "gauge",
+        "bytes of the data files of the collection, replicas included",
+        |usage| usage.size,
+    );
+    family(
+        "deleted_bytes",
+        "gauge",
+        "bytes of deleted files not vacuumed yet",
+        |usage| usage.deleted_bytes,
+    );
+    family(
+        "reads_total",
+        "counter",
+        "reads of files of the collection",
+        |usage| usage.reads,
+    );
+    family(
+        "writes_total",
+        "counter",
+        "writes of files of the collection",
+        |usage| usage.writes,
+    );
+    family(
+        "deletes_total",
+        "counter",
+        "deletes of files of the collection",
+        |usage| usage.deletes,
+    );
+    family(
+        "read_bytes_total",
+        "counter",
+        "bytes of files of the collection sent to clients",
+        |usage| usage.bytes_read,
+    );
+    family(
+        "written_bytes_total",
+        "counter",
+        "bytes of files of the collection received from clients",
+        |usage| usage.bytes_written,
+    );
+    metrics
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use faststr::FastStr;
+    use helyim_proto::directory::CollectionUsageMessage;
+
+    use crate::operation::usage::{
+        usage_metrics, CollectionUsage, CLUSTER_USAGE_PREFIX, SERVER_USAGE_PREFIX,
+    };
+
+    #[test]
+    pub fn test_usage_metrics() {
+        let mut usage = CollectionUsage::new(FastStr::new("pic\"s"));
+        usage.volumes = 2;
+        usage.size = 4096;
+        let message = CollectionUsageMessage {
+            reads: 3,
+            bytes_read: 300,
+            ..Default::default()
+        };
+        usage.add_requests(&message);
+        usage.add_requests(&message);
+
+        let metrics = usage_metrics(
+            SERVER_USAGE_PREFIX,
+            &[usage.clone(), CollectionUsage::default()],
+        );
+        assert!(metrics.contains("# TYPE helyim_collection_reads_total counter\n"));
+        assert!(metrics.contains("helyim_collection_volumes{collection=\"pic\\\"s\"} 2\n"));
+        assert!(metrics.contains("helyim_collection_size_bytes{collection=\"pic\\\"s\"} 4096\n"));
+        assert!(metrics.contains("helyim_collection_reads_total{collection=\"pic\\\"s\"} 6\n"));
+        assert!(
+            metrics.contains("helyim_collection_read_bytes_total{collection=\"pic\\\"s\"} 600\n")
+        );
+        assert!(metrics.contains("helyim_collection_reads_total{collection=\"\"} 0\n"));
+
+        let metrics = usage_metrics(CLUSTER_USAGE_PREFIX, &[usage]);
+        assert!(metrics.contains("helyim_cluster_collection_volumes{collection=\"pic\\\"s\"} 2\n"));
+        assert!(!metrics.contains("\nhelyim_collection_"));
+    }
+}
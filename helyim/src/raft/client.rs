@@ -19,6 +19,7 @@ use crate::{
         RaftRequest, RpcError,
     },
     storage::VolumeId,
+    util::sign::sign_http,
 };
 
 #[derive(Clone)]
@@ -130,7 +131,7 @@ impl RaftClient {
             )
         };
 
-        let fut = if let Some(r) = req {
+        let request = if let Some(r) = req {
             debug!(
                 ">>> client send request to {}: {}",
                 url,
@@ -140,8 +141,12 @@ impl RaftClient {
         } else {
             debug!(">>> client send request to {}", url);
             self.inner.get(&url)
-        }
-        .send();
+        };
+        let mut request = request
+            .build()
+            .map_err(|e| RpcError::Network(NetworkError::new(&e)))?;
+        sign_http(&mut request);
+        let fut = self.inner.execute(request);
 
         let timeout_fut = timeout(Duration::from_millis(3_000), fut).await;
         let response = match timeout_fut {
@@ -6,6 +6,7 @@ use std::{
 
 use axum::{
     extract::DefaultBodyLimit,
+    middleware::from_fn,
     routing::{get, post},
     Router,
 };
@@ -37,6 +38,7 @@ use crate::{
     },
     storage::VolumeId,
     topology::TopologyRef,
+    util::sign::verify_signature,
 };
 
 pub mod client;
@@ -116,6 +118,21 @@ impl RaftServer {
             .client_write(RaftRequest::max_volume_id(max_volume_id))
             .await
     }
+
+    pub async fn set_collection_deletion(
+        &self,
+        collection: FastStr,
+        purge_after: u64,
+        expires_at: u64,
+    ) -> Result<ClientWriteResponse, OpenRaftError<ClientWriteError>> {
+        self.raft
+            .client_write(RaftRequest::collection_deletion(
+                collection,
+                purge_after,
+                expires_at,
+            ))
+            .await
+    }
 }
 
 impl RaftServer {
@@ -251,6 +268,7 @@ pub fn create_raft_router(raft_state: RaftServer) -> Router {
         .route("/raft-snapshot", post(install_snapshot_handler))
         .route("/raft-append", post(append_entries_handler))
         .layer((
+            from_fn(verify_signature),
             CompressionLayer::new(),
             DefaultBodyLimit::max(1024 * 1024 * 50),
             TimeoutLayer::new(Duration::from_secs(10)),
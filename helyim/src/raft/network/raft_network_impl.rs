@@ -12,9 +12,12 @@ use openraft::{
 use serde::{de::DeserializeOwned, Serialize};
 use tracing::{error, info};
 
-use crate::raft::{
-    client::RaftClient,
-    types::{ClientWriteResponse, NodeId, RpcError, TypeConfig},
+use crate::{
+    raft::{
+        client::RaftClient,
+        types::{ClientWriteResponse, NodeId, RpcError, TypeConfig},
+    },
+    util::sign::sign_http,
 };
 
 #[derive(Clone)]
@@ -66,10 +69,14 @@ impl NetworkFactory {
         let url = format!("http://{}/raft/{}", target_node.addr, uri);
 
         let client = reqwest::Client::new();
-        let resp = client
+        let mut request = client
             .post(url)
             .json(&req)
-            .send()
+            .build()
+            .map_err(|e| openraft::error::RPCError::Unreachable(Unreachable::new(&e)))?;
+        sign_http(&mut request);
+        let resp = client
+            .execute(request)
             .await
             .map_err(|e| openraft::error::RPCError::Unreachable(Unreachable::new(&e)))?;
 
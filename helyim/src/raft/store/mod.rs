@@ -164,6 +164,22 @@ impl RaftStateMachine<TypeConfig> for Arc<StateMachineStore> {
                         sm.topology().adjust_max_volume_id(*max_volume_id).await;
                         res.push(RaftResponse)
                     }
+                    RaftRequest::CollectionDeletion {
+                        collection,
+                        purge_after,
+                        expires_at,
+                    } => {
+                        debug!(
+                            "apply deletion mark of collection {collection}: {purge_after} - \
+                             {expires_at}"
+                        );
+                        sm.topology().apply_collection_deletion(
+                            collection.clone(),
+                            *purge_after,
+                            *expires_at,
+                        );
+                        res.push(RaftResponse)
+                    }
                 },
                 EntryPayload::Membership(ref mem) => {
                     sm.last_membership = StoredMembership::new(Some(entry.log_id), mem.clone());
@@ -1,15 +1,14 @@
 use std::{io::Cursor, net::AddrParseError};
 
-use axum::{
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    Json,
-};
+use axum::response::{IntoResponse, Response};
+use faststr::FastStr;
 use openraft::{error::InstallSnapshotError, BasicNode, TokioRuntime};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 
-use crate::storage::VolumeId;
+use crate::{
+    errors::{Error, ErrorCode},
+    storage::VolumeId,
+};
 
 pub type NodeId = u64;
 
@@ -37,13 +36,31 @@ pub type ClientWriteResponse = openraft::raft::ClientWriteResponse<TypeConfig>;
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum RaftRequest {
-    MaxVolumeId { max_volume_id: VolumeId },
+    MaxVolumeId {
+        max_volume_id: VolumeId,
+    },
+    /// a mark of a collection about to be deleted, it is dropped if `expires_at` is 0
+    CollectionDeletion {
+        collection: FastStr,
+        /// marks written before the purge delay have none
+        #[serde(default)]
+        purge_after: u64,
+        expires_at: u64,
+    },
 }
 
 impl RaftRequest {
     pub fn max_volume_id(max_volume_id: VolumeId) -> Self {
         Self::MaxVolumeId { max_volume_id }
     }
+
+    pub fn collection_deletion(collection: FastStr, purge_after: u64, expires_at: u64) -> Self {
+        Self::CollectionDeletion {
+            collection,
+            purge_after,
+            expires_at,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -84,13 +101,33 @@ impl From<nom::Err<nom::error::Error<&str>>> for RaftError {
     }
 }
 
+impl RaftError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            RaftError::ClientWrite(openraft::error::RaftError::APIError(
+                openraft::error::ClientWriteError::ForwardToLeader(_),
+            ))
+            | RaftError::CheckIsLeader(openraft::error::RaftError::APIError(
+                openraft::error::CheckIsLeaderError::ForwardToLeader(_),
+            )) => ErrorCode::NotLeader,
+            RaftError::String(_) | RaftError::ParseInt(_) | RaftError::AddrParse(_) => {
+                ErrorCode::BadRequest
+            }
+            RaftError::Io(_)
+            | RaftError::Box(_)
+            | RaftError::Raft(_)
+            | RaftError::Rpc(_)
+            | RaftError::InstallSnapshot(_)
+            | RaftError::InitializeRaftCluster(_)
+            | RaftError::ClientWrite(_)
+            | RaftError::CheckIsLeader(_)
+            | RaftError::Fatal(_) => ErrorCode::Internal,
+        }
+    }
+}
+
 impl IntoResponse for RaftError {
     fn into_response(self) -> Response {
-        let error = self.to_string();
-        let error = json!({
-            "error": error
-        });
-        let response = (StatusCode::BAD_REQUEST, Json(error));
-        response.into_response()
+        Error::Raft(self).into_response()
     }
 }
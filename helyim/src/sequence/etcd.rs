@@ -0,0 +1,191 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use async_trait::async_trait;
+use etcd_client::{Client, Compare, CompareOp, Txn, TxnOp};
+use faststr::FastStr;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::{
+    directory::Sequence,
+    errors::Result,
+    sequence::{is_path_key, sequence_end},
+};
+
+/// Allocates file ids from a counter stored in etcd. Each master reserves a range of `batch_size`
+/// ids with a compare-and-swap and hands them out locally, the whole range is persisted before any
+/// id in it is used, so a restart never reuses an id.
+#[derive(Clone)]
+pub struct EtcdSequencer {
+    client: Client,
+    key: FastStr,
+    batch_size: u64,
+    // [next, end) of the reserved range
+    range: Arc<Mutex<(u64, u64)>>,
+    seen: Arc<AtomicU64>,
+}
+
+impl EtcdSequencer {
+    pub async fn new(endpoints: &[FastStr], key: FastStr, batch_size: u64) -> Result<Self> {
+        let client = Client::connect(endpoints, None).await?;
+        info!("etcd sequencer connected to {endpoints:?}, key: {key}");
+        Ok(Self {
+            client,
+            key,
+            batch_size: batch_size.max(1),
+            range: Arc::new(Mutex::new((0, 0))),
+            seen: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// reserve `[start, start + count)` in etcd, retry until the compare-and-swap succeeds
+    async fn reserve(&self, count: u64) -> Result<u64> {
+        let mut client = self.client.clone();
+        loop {
+            let response = client.get(self.key.as_str(), None).await?;
+            let (current, compare) = match response.kvs().first() {
+                Some(kv) => (
+                    kv.value_str()?.parse::<u64>()?,
+                    Compare::mod_revision(self.key.as_str(), CompareOp::Equal, kv.mod_revision()),
+                ),
+                None => (
+                    1,
+                    Compare::create_revision(self.key.as_str(), CompareOp::Equal, 0),
+                ),
+            };
+            let start = current.max(self.seen.load(Ordering::Relaxed) + 1);
+            let end = sequence_end(start, count)?;
+
+            let txn = Txn::new().when([compare]).and_then([TxnOp::put(
+                self.key.as_str(),
+                end.to_string(),
+                None,
+            )]);
+            if client.txn(txn).await?.succeeded() {
+                debug!("etcd sequencer reserved [{start}, {end})");
+                return Ok(start);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Sequence for EtcdSequencer {
+    async fn next_file_id(&self, count: u64) -> Result<u64> {
+        let mut range = self.range.lock().await;
+        let seen = self.seen.load(Ordering::Relaxed);
+        if range.0 <= seen {
+            range.0 = seen + 1;
+        }
+        if sequence_end(range.0, count)? > range.1 {
+            let reserved = count.max(self.batch_size);
+            let start = self.reserve(reserved).await?;
+            *range = (start, start + reserved);
+        }
+        let file_id = range.0;
+        range.0 = sequence_end(file_id, count)?;
+        Ok(file_id)
+    }
+
+    fn set_max(&self, seen_value: u64) {
+        if is_path_key(seen_value) {
+            return;
+        }
+        // the next reservation starts after the seen value
+        self.seen.fetch_max(seen_value, Ordering::Relaxed);
+    }
+
+    fn peek(&self) -> Option<u64> {
+        let seen = self.seen.load(Ordering::Relaxed);
+        match self.range.try_lock() {
+            Ok(range) => Some(range.0.max(seen + 1)),
+            Err(_) => Some(seen + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use etcd_client::Client;
+    use faststr::FastStr;
+
+    use crate::{
+        sequence::{EtcdSequencer, Sequence},
+        util::time::now,
+    };
+
+    // these tests need an etcd server, run them with `cargo test -- --ignored`
+    fn etcd_endpoints() -> [FastStr; 1] {
+        let endpoint =
+            std::env::var("ETCD_ENDPOINT").unwrap_or_else(|_| "http://127.0.0.1:2379".to_string());
+        [FastStr::new(endpoint)]
+    }
+
+    async fn etcd_sequencer(name: &str, batch_size: u64) -> (EtcdSequencer, Client) {
+        let endpoints = etcd_endpoints();
+        let key = FastStr::new(format!("/helyim/test/{name}/{}", now().as_nanos()));
+        let sequencer = EtcdSequencer::new(&endpoints, key, batch_size)
+            .await
+            .unwrap();
+        let client = Client::connect(&endpoints, None).await.unwrap();
+        (sequencer, client)
+    }
+
+    async fn reserved_end(client: &mut Client, key: &str) -> u64 {
+        let response = client.get(key, None).await.unwrap();
+        response.kvs()[0].value_str().unwrap().parse().unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_etcd_sequencer_batch() {
+        let (sequencer, mut client) = etcd_sequencer("batch", 10).await;
+        assert_eq!(sequencer.next_file_id(1).await.unwrap(), 1);
+        // the whole batch is reserved before the first id is used
+        assert_eq!(reserved_end(&mut client, &sequencer.key).await, 11);
+
+        // ids of the batch are handed out locally
+        assert_eq!(sequencer.next_file_id(3).await.unwrap(), 2);
+        assert_eq!(sequencer.next_file_id(5).await.unwrap(), 5);
+        assert_eq!(sequencer.next_file_id(1).await.unwrap(), 10);
+        assert_eq!(sequencer.peek(), Some(11));
+        assert_eq!(reserved_end(&mut client, &sequencer.key).await, 11);
+
+        // the batch is used up, the next one starts where it ends
+        assert_eq!(sequencer.next_file_id(2).await.unwrap(), 11);
+        assert_eq!(reserved_end(&mut client, &sequencer.key).await, 21);
+        // a count crossing the end of the batch reserves a new one, the rest of the old one is
+        // skipped
+        assert_eq!(sequencer.next_file_id(9).await.unwrap(), 21);
+        assert_eq!(reserved_end(&mut client, &sequencer.key).await, 31);
+        // a count larger than the batch reserves all of it at once
+        assert_eq!(sequencer.next_file_id(25).await.unwrap(), 31);
+        assert_eq!(reserved_end(&mut client, &sequencer.key).await, 56);
+        assert_eq!(sequencer.next_file_id(1).await.unwrap(), 56);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_etcd_sequencer_restart() {
+        let (sequencer, _) = etcd_sequencer("restart", 10).await;
+        assert_eq!(sequencer.next_file_id(1).await.unwrap(), 1);
+        assert_eq!(sequencer.next_file_id(1).await.unwrap(), 2);
+
+        // a restarted master does not know which ids of the batch were used, it starts after it
+        let restarted = EtcdSequencer::new(&etcd_endpoints(), sequencer.key.clone(), 10)
+            .await
+            .unwrap();
+        assert_eq!(restarted.next_file_id(1).await.unwrap(), 11);
+
+        // masters sharing the key never hand out the same id
+        assert_eq!(sequencer.next_file_id(8).await.unwrap(), 3);
+        assert_eq!(sequencer.next_file_id(1).await.unwrap(), 21);
+
+        // the file keys seen in heartbeats are skipped by the next reservation
+        restarted.set_max(100);
+        assert_eq!(restarted.next_file_id(1).await.unwrap(), 101);
+    }
+}
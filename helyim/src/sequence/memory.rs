@@ -1,8 +1,13 @@
 use std::sync::Arc;
 
+use async_trait::async_trait;
 use parking_lot::Mutex;
 
-use crate::{directory::Sequence, errors::Result};
+use crate::{
+    directory::Sequence,
+    errors::Result,
+    sequence::{is_path_key, sequence_end},
+};
 
 #[derive(Clone)]
 pub struct MemorySequencer {
@@ -23,18 +28,52 @@ impl Default for MemorySequencer {
     }
 }
 
+#[async_trait]
 impl Sequence for MemorySequencer {
-    fn next_file_id(&self, count: u64) -> Result<u64> {
+    async fn next_file_id(&self, count: u64) -> Result<u64> {
         let mut counter = self.counter.lock();
         let file_id = *counter;
-        *counter += count;
+        *counter = sequence_end(file_id, count)?;
         Ok(file_id)
     }
 
     fn set_max(&self, seen_value: u64) {
+        if is_path_key(seen_value) {
+            return;
+        }
         let mut counter = self.counter.lock();
+        // the seen value is already used
         if *counter <= seen_value {
-            *counter = seen_value;
+            *counter = seen_value + 1;
         }
     }
+
+    fn peek(&self) -> Option<u64> {
+        Some(*self.counter.lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sequence::{MemorySequencer, Sequence, PATH_KEY_BIT};
+
+    #[tokio::test]
+    async fn test_memory_sequencer_set_max() {
+        let sequencer = MemorySequencer::new();
+        assert_eq!(sequencer.next_file_id(10).await.unwrap(), 1);
+        assert_eq!(sequencer.peek(), Some(11));
+
+        sequencer.set_max(100);
+        assert_eq!(sequencer.peek(), Some(101));
+        // never goes down
+        sequencer.set_max(50);
+        assert_eq!(sequencer.next_file_id(1).await.unwrap(), 101);
+
+        // path keys are not allocated by the sequencer
+        sequencer.set_max(PATH_KEY_BIT + 10);
+        assert_eq!(sequencer.peek(), Some(102));
+        sequencer.set_max(PATH_KEY_BIT - 2);
+        assert_eq!(sequencer.next_file_id(1).await.unwrap(), PATH_KEY_BIT - 1);
+        assert!(sequencer.next_file_id(1).await.is_err());
+    }
 }
@@ -1,26 +1,59 @@
-use crate::errors::Result;
+use async_trait::async_trait;
+
+use crate::{errors::Result, util::args::SequencerOptions};
+
+mod etcd;
+pub use etcd::EtcdSequencer;
 
 mod memory;
 pub use memory::MemorySequencer;
 
+mod path_hash;
+pub use path_hash::{is_path_key, path_file_key, PATH_KEY_BIT};
+
 mod snowflake;
 pub use snowflake::SnowflakeSequencer;
 
+/// the end of the sequence `[start, start + count)`, an error if it reaches into the path keys
+fn sequence_end(start: u64, count: u64) -> Result<u64> {
+    start
+        .checked_add(count)
+        .filter(|end| *end <= PATH_KEY_BIT)
+        .ok_or_else(|| format!("file ids from {start} by {count} exhaust the sequence").into())
+}
+
+#[async_trait]
 pub trait Sequence {
-    fn next_file_id(&self, count: u64) -> Result<u64>;
+    async fn next_file_id(&self, count: u64) -> Result<u64>;
     fn set_max(&self, value: u64);
+    /// the next file id would be handed out, without allocating it. `None` if the ids are not
+    /// counted
+    fn peek(&self) -> Option<u64>;
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
 pub enum SequencerType {
     Memory,
     Snowflake,
+    Etcd,
+}
+
+/// what the master does when a volume server holds a file key the sequencer may hand out again,
+/// e.g. after old volumes are restored
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FileKeyConflict {
+    /// alert and bump the sequencer past the key
+    #[default]
+    Bump,
+    /// only alert, the operator bumps the sequencer through `/admin/sequence`
+    Alert,
 }
 
 #[derive(Clone)]
 pub enum Sequencer {
     Memory(MemorySequencer),
     Snowflake(SnowflakeSequencer),
+    Etcd(EtcdSequencer),
 }
 
 impl Sequencer {
@@ -28,15 +61,35 @@ impl Sequencer {
         match typ {
             SequencerType::Snowflake => Ok(Sequencer::Snowflake(SnowflakeSequencer::new()?)),
             SequencerType::Memory => Ok(Sequencer::Memory(MemorySequencer::new())),
+            SequencerType::Etcd => Err("etcd sequencer requires endpoints, use \
+                                        `Sequencer::from_options`"
+                .to_string()
+                .into()),
+        }
+    }
+
+    pub async fn from_options(options: &SequencerOptions) -> Result<Self> {
+        match options.sequencer {
+            SequencerType::Etcd => Ok(Sequencer::Etcd(
+                EtcdSequencer::new(
+                    &options.etcd_endpoints,
+                    options.etcd_sequence_key.clone(),
+                    options.etcd_sequence_batch,
+                )
+                .await?,
+            )),
+            typ => Self::new(typ),
         }
     }
 }
 
+#[async_trait]
 impl Sequence for Sequencer {
-    fn next_file_id(&self, count: u64) -> Result<u64> {
+    async fn next_file_id(&self, count: u64) -> Result<u64> {
         match self {
-            Sequencer::Memory(memory) => memory.next_file_id(count),
-            Sequencer::Snowflake(snowflake) => snowflake.next_file_id(count),
+            Sequencer::Memory(memory) => memory.next_file_id(count).await,
+            Sequencer::Snowflake(snowflake) => snowflake.next_file_id(count).await,
+            Sequencer::Etcd(etcd) => etcd.next_file_id(count).await,
         }
     }
 
@@ -44,6 +97,25 @@ impl Sequence for Sequencer {
         match self {
             Sequencer::Memory(memory) => memory.set_max(value),
             Sequencer::Snowflake(snowflake) => snowflake.set_max(value),
+            Sequencer::Etcd(etcd) => etcd.set_max(value),
+        }
+    }
+
+    fn peek(&self) -> Option<u64> {
+        match self {
+            Sequencer::Memory(memory) => memory.peek(),
+            Sequencer::Snowflake(snowflake) => snowflake.peek(),
+            Sequencer::Etcd(etcd) => etcd.peek(),
+        }
+    }
+}
+
+impl Sequencer {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sequencer::Memory(_) => "memory",
+            Sequencer::Snowflake(_) => "snowflake",
+            Sequencer::Etcd(_) => "etcd",
         }
     }
 }
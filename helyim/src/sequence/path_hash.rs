@@ -0,0 +1,39 @@
+use sha2::{Digest, Sha256};
+
+use crate::storage::NeedleId;
+
+/// Set on every key derived from a path. The sequencers hand out keys below it, so a path key never
+/// collides with an allocated key and is not mistaken for the progress of the sequencer.
+pub const PATH_KEY_BIT: NeedleId = 1 << 63;
+
+pub fn is_path_key(key: NeedleId) -> bool {
+    key & PATH_KEY_BIT != 0
+}
+
+/// Derive the needle key and cookie from a file path, the same path always maps to the same key,
+/// so re-uploading a path overwrites the previous needle instead of allocating a new one.
+pub fn path_file_key(path: &str) -> (NeedleId, u32) {
+    let digest = Sha256::digest(path.trim_start_matches('/').as_bytes());
+    let mut key = [0u8; 8];
+    key.copy_from_slice(&digest[..8]);
+    let mut cookie = [0u8; 4];
+    cookie.copy_from_slice(&digest[8..12]);
+    (
+        u64::from_be_bytes(key) | PATH_KEY_BIT,
+        u32::from_be_bytes(cookie),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sequence::path_hash::{is_path_key, path_file_key};
+
+    #[test]
+    fn test_path_file_key() {
+        let (key, cookie) = path_file_key("/backup/2024/01/a.log");
+        assert_eq!(path_file_key("backup/2024/01/a.log"), (key, cookie));
+        assert_ne!(path_file_key("/backup/2024/01/b.log").0, key);
+        assert!(is_path_key(key));
+        assert!(!is_path_key(1));
+    }
+}
@@ -1,3 +1,5 @@
+use async_trait::async_trait;
+
 use crate::{directory::Sequence, errors::Result};
 
 #[derive(Clone)]
@@ -13,12 +15,18 @@ impl SnowflakeSequencer {
     }
 }
 
+#[async_trait]
 impl Sequence for SnowflakeSequencer {
-    fn next_file_id(&self, _count: u64) -> Result<u64> {
+    async fn next_file_id(&self, _count: u64) -> Result<u64> {
         Ok(self.flake.next_id()?)
     }
 
     fn set_max(&self, _seen_value: u64) {
         // ignore set max as we are snowflake
     }
+
+    fn peek(&self) -> Option<u64> {
+        // snowflake ids are time based, there is no counter to inspect
+        None
+    }
 }
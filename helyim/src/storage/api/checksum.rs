@@ -0,0 +1,101 @@
+use axum::http::{HeaderMap, HeaderName};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::{Digest, Md5};
+use sha2::Sha256;
+
+use crate::storage::NeedleError;
+
+pub const CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
+pub const AMZ_CONTENT_SHA256: HeaderName = HeaderName::from_static("x-amz-content-sha256");
+
+/// sha256 values which do not describe the payload itself
+const SHA256_PLACEHOLDERS: [&str; 2] = ["UNSIGNED-PAYLOAD", "STREAMING-"];
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ContentChecksum {
+    /// base64 encoded md5 digest
+    pub md5: String,
+    /// hex encoded sha256 digest, only set when the client supplied one
+    pub sha256: Option<String>,
+}
+
+/// Verify the client supplied checksums against the request body, like `Content-MD5` is defined
+/// by http. The body is the file itself unless it is a multipart form. The md5 of the body is
+/// always returned for the answer of the upload, none of them is stored with the needle.
+pub fn verify_content_checksum(
+    headers: &HeaderMap,
+    data: &[u8],
+) -> Result<ContentChecksum, NeedleError> {
+    let md5 = STANDARD.encode(Md5::digest(data));
+    if let Some(expected) = headers.get(CONTENT_MD5) {
+        let expected = expected.to_str().unwrap_or_default().trim();
+        if expected != md5 {
+            return Err(NeedleError::ContentChecksumMismatch(
+                CONTENT_MD5.to_string(),
+                expected.to_string(),
+                md5,
+            ));
+        }
+    }
+
+    let mut sha256 = None;
+    if let Some(expected) = headers.get(AMZ_CONTENT_SHA256) {
+        let expected = expected.to_str().unwrap_or_default().trim();
+        if !SHA256_PLACEHOLDERS.iter().any(|p| expected.starts_with(p)) {
+            let actual = hex::encode(Sha256::digest(data));
+            if !expected.eq_ignore_ascii_case(&actual) {
+                return Err(NeedleError::ContentChecksumMismatch(
+                    AMZ_CONTENT_SHA256.to_string(),
+                    expected.to_string(),
+                    actual,
+                ));
+            }
+            sha256 = Some(actual);
+        }
+    }
+
+    Ok(ContentChecksum { md5, sha256 })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use crate::storage::{
+        api::checksum::{verify_content_checksum, AMZ_CONTENT_SHA256, CONTENT_MD5},
+        NeedleError,
+    };
+
+    #[test]
+    fn test_verify_content_checksum() {
+        let data = b"hello world";
+        let md5 = "XrY7u+Ae7tCTyyK7j1rNww==";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        let checksum = verify_content_checksum(&HeaderMap::new(), data).unwrap();
+        assert_eq!(checksum.md5, md5);
+        assert_eq!(checksum.sha256, None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_MD5, HeaderValue::from_static(md5));
+        headers.insert(AMZ_CONTENT_SHA256, HeaderValue::from_static(sha256));
+        let checksum = verify_content_checksum(&headers, data).unwrap();
+        assert_eq!(checksum.sha256.as_deref(), Some(sha256));
+
+        headers.insert(
+            AMZ_CONTENT_SHA256,
+            HeaderValue::from_static("UNSIGNED-PAYLOAD"),
+        );
+        let checksum = verify_content_checksum(&headers, data).unwrap();
+        assert_eq!(checksum.sha256, None);
+
+        headers.insert(
+            CONTENT_MD5,
+            HeaderValue::from_static("1B2M2Y8AsgTpgAmY7PhCfg=="),
+        );
+        assert!(matches!(
+            verify_content_checksum(&headers, data),
+            Err(NeedleError::ContentChecksumMismatch(..))
+        ));
+    }
+}
@@ -1,52 +1,85 @@
 use std::{
-    collections::HashMap, convert::Infallible, io::Read, result::Result as StdResult, str::FromStr,
-    sync::Arc,
+    collections::HashMap, convert::Infallible, fs::File, io::Read, result::Result as StdResult,
+    str::FromStr, sync::Arc,
 };
 
+use async_stream::stream;
 use axum::{
     body::Body,
-    extract::State,
+    extract::{Query, State},
     http::{
         header::{
-            HeaderName, HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING,
-            CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+            HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING,
+            CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+            LAST_MODIFIED,
         },
-        Response, StatusCode,
+        Method, Response, StatusCode,
     },
+    response::IntoResponse,
     Json,
 };
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use chrono::{DateTime, Utc};
-use futures::stream::once;
+use futures::{
+    future::join_all,
+    stream::{once, FuturesUnordered},
+    Stream, StreamExt,
+};
 use libflate::gzip::Decoder;
 use mime_guess::mime;
 use multer::Multipart;
+use serde::Deserialize;
 use serde_json::{json, Value};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{
-    anyhow,
+    anyhow, client,
     errors::Result,
-    operation::{Looker, ParseUpload, Upload},
+    operation::{
+        sequence::{MaxFileKey, MaxFileKeyQuery, MaxFileKeyRequest},
+        usage_metrics, CollectionUsage, Looker, ParseUpload, ReplicaSync, Upload,
+        SERVER_USAGE_PREFIX,
+    },
     storage::{
+        api::checksum::{verify_content_checksum, ContentChecksum},
         crc,
-        needle::{Needle, NeedleMapType, PAIR_NAME_PREFIX},
+        io_class::{background_io_pending, spawn_io, IoClass},
+        needle::{IndexCompaction, Needle, NeedleMapType, NEEDLE_HEADER_SIZE, PAIR_NAME_PREFIX},
         store::StoreRef,
-        NeedleError, Ttl, VolumeId, VolumeInfo,
+        version::Version,
+        NeedleError, NeedleId, Ttl, UsageKind, VolumeError, VolumeId, VolumeInfo,
+        BUFFER_SIZE_LIMIT,
     },
     util,
     util::{
+        file::FileExt,
         http::{
             extractor::{DeleteExtractor, GetOrHeadExtractor, PostExtractor},
-            HTTP_DATE_FORMAT,
+            health::Readiness,
+            HTTP_DATE_FORMAT, PROMETHEUS_TEXT_FORMAT,
         },
         parser::parse_url_path,
+        sign::verify_http,
         time::now,
     },
 };
 
+mod checksum;
 pub mod erasure_coding;
 
+/// set on reads from a quarantined volume, the data may be corrupted
+pub const HELYIM_QUARANTINED: HeaderName = HeaderName::from_static("x-helyim-quarantined");
+
+/// opt-in header of reads, the needle is verified against its index entry and the data on disk
+pub const X_VERIFY: HeaderName = HeaderName::from_static("x-verify");
+pub const X_VERIFY_INDEX: HeaderName = HeaderName::from_static("x-verify-index");
+pub const X_VERIFY_CHECKSUM: HeaderName = HeaderName::from_static("x-verify-checksum");
+pub const X_VERIFY_OFFSET: HeaderName = HeaderName::from_static("x-verify-offset");
+
+/// needles larger than this are streamed from the data file instead of being read into memory
+const STREAM_READ_THRESHOLD: u64 = BUFFER_SIZE_LIMIT as u64;
+const STREAM_READ_CHUNK_SIZE: u64 = 256 * 1024;
+
 #[derive(Clone)]
 pub struct StorageState {
     pub store: StoreRef,
@@ -56,28 +89,187 @@ pub struct StorageState {
     pub looker: Arc<Looker>,
 }
 
+/// ready when the master is known and every disk location is writable
+pub async fn readyz_handler(State(state): State<StorageState>) -> Readiness {
+    let mut readiness = Readiness::default();
+    let master = state.store.current_master.read().await.clone();
+    let master = if master.is_empty() {
+        Err("no master leader connected")
+    } else {
+        Ok(())
+    };
+    readiness.check("master", master);
+    for location in state.store.locations() {
+        readiness.check(
+            format!("disk:{}", location.directory),
+            location.check_writable(),
+        );
+    }
+    readiness
+}
+
 pub async fn status_handler(State(state): State<StorageState>) -> Result<Json<Value>> {
     let mut infos: Vec<VolumeInfo> = vec![];
     for location in state.store.locations().iter() {
         for volume in location.volumes.iter() {
-            infos.push(volume.get_volume_info());
+            let mut info = volume.get_volume_info();
+            info.disk_type = location.disk_type;
+            infos.push(info);
         }
     }
 
     let stat = json!({
         "version": "0.1",
         "volumes": &infos,
+        "backgroundIoPending": background_io_pending(),
     });
 
     Ok(Json(stat))
 }
 
+/// stored bytes and requests of the collections on this volume server
+pub async fn usage_handler(State(state): State<StorageState>) -> Json<Vec<CollectionUsage>> {
+    Json(state.store.collection_usages())
+}
+
+/// the collection usages for prometheus to scrape
+pub async fn metrics_handler(State(state): State<StorageState>) -> impl IntoResponse {
+    let metrics = usage_metrics(SERVER_USAGE_PREFIX, &state.store.collection_usages());
+    ([(CONTENT_TYPE, PROMETHEUS_TEXT_FORMAT)], metrics)
+}
+
+/// inspect the max file key of a volume
+pub async fn max_file_key_handler(
+    State(state): State<StorageState>,
+    Query(request): Query<MaxFileKeyQuery>,
+) -> Result<Json<MaxFileKey>> {
+    let volume = state
+        .store
+        .find_volume(request.volume)
+        .ok_or(VolumeError::NotFound(request.volume))?;
+    Ok(Json(MaxFileKey {
+        volume: request.volume,
+        max_file_key: volume.max_file_key(),
+    }))
+}
+
+/// raise the max file key of a volume, used to repair sequences after a restore
+pub async fn bump_max_file_key_handler(
+    State(state): State<StorageState>,
+    Query(request): Query<MaxFileKeyRequest>,
+) -> Result<Json<MaxFileKey>> {
+    let volume = state
+        .store
+        .find_volume(request.volume)
+        .ok_or(VolumeError::NotFound(request.volume))?;
+    info!(
+        "bump max file key of volume {} to {}",
+        request.volume, request.max
+    );
+    volume.bump_max_file_key(request.max)?;
+    Ok(Json(MaxFileKey {
+        volume: request.volume,
+        max_file_key: volume.max_file_key(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VacuumRequest {
+    pub volume: VolumeId,
+}
+
+/// vacuum a volume on demand, tombstones and superseded entries are dropped from the index too
+pub async fn vacuum_volume_handler(
+    State(state): State<StorageState>,
+    Query(request): Query<VacuumRequest>,
+) -> Result<Json<IndexCompaction>> {
+    info!("vacuum volume {} on demand", request.volume);
+    let store = state.store.clone();
+    let compaction = spawn_io(IoClass::Background, move || {
+        store.vacuum_volume(request.volume)
+    })
+    .await??;
+    Ok(Json(compaction))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuarantineRequest {
+    pub volume: VolumeId,
+    /// false lifts the quarantine
+    pub enable: Option<bool>,
+}
+
+/// quarantine a volume suspected to be corrupted, it is still readable but is not written to,
+/// vacuumed or used to re-create replicas
+pub async fn quarantine_volume_handler(
+    State(state): State<StorageState>,
+    Query(request): Query<QuarantineRequest>,
+) -> Result<Json<Value>> {
+    let quarantined = request.enable.unwrap_or(true);
+    state
+        .store
+        .quarantine_volume(request.volume, quarantined)
+        .await?;
+    Ok(Json(json!({
+        "volume": request.volume,
+        "quarantined": quarantined,
+    })))
+}
+
+/// add the verification of a needle to `headers`, returns whether the needle is intact
+fn verify_needle(
+    state: &StorageState,
+    vid: VolumeId,
+    key: NeedleId,
+    headers: &mut HeaderMap,
+) -> Result<bool> {
+    let verification = state.store.verify_volume_needle(vid, key)?;
+    let index_ok = verification.index_ok(key);
+    let checksum_ok = verification.checksum_ok();
+
+    let index = if index_ok {
+        "ok".to_string()
+    } else {
+        format!(
+            "mismatch, index size {}, header id {} size {}",
+            verification.index_size, verification.header_id, verification.header_size
+        )
+    };
+    let checksum = match verification.computed_checksum {
+        Some(_) if checksum_ok => "ok".to_string(),
+        Some(computed) => format!(
+            "mismatch, stored {:08x} computed {computed:08x}",
+            verification.stored_checksum
+        ),
+        None => "mismatch, data size out of range".to_string(),
+    };
+    headers.insert(X_VERIFY_INDEX, HeaderValue::from_str(&index)?);
+    headers.insert(X_VERIFY_CHECKSUM, HeaderValue::from_str(&checksum)?);
+    headers.insert(X_VERIFY_OFFSET, HeaderValue::from(verification.offset));
+
+    if !index_ok || !checksum_ok {
+        warn!(
+            "volume {vid}: needle {key} verification failed, index: {index}, checksum: {checksum}"
+        );
+    }
+    Ok(index_ok && checksum_ok)
+}
+
 pub async fn delete_handler(
     State(state): State<StorageState>,
     extractor: DeleteExtractor,
 ) -> Result<Json<Value>> {
     let (vid, fid, _, _) = parse_url_path(extractor.uri.path())?;
     let is_replicate = extractor.query.r#type == Some("replicate".into());
+    if is_replicate {
+        verify_http(
+            &extractor.headers,
+            "DELETE",
+            extractor.uri.path(),
+            extractor.uri.query(),
+            &[],
+        )?;
+    }
 
     let mut needle = Needle::new_with_fid(fid)?;
 
@@ -90,9 +282,24 @@ pub async fn delete_handler(
         );
         return Err(NeedleError::CookieNotMatch(needle.cookie, cookie).into());
     }
+    let if_match = match extractor.headers.get(IF_MATCH) {
+        Some(if_match) if !is_replicate => Some(if_match.to_str()?),
+        _ => None,
+    };
 
-    let size =
-        replicate_delete(&state, extractor.uri.path(), vid, &mut needle, is_replicate).await?;
+    let size = replicate_delete(
+        &state,
+        extractor.uri.path(),
+        vid,
+        &mut needle,
+        is_replicate,
+        if_match,
+    )
+    .await?;
+    // the primary counts the delete once for all replicas
+    if !is_replicate {
+        state.store.record_usage(vid, UsageKind::Delete, 0);
+    }
     let size = json!({ "size": size });
 
     Ok(Json(size))
@@ -104,9 +311,13 @@ async fn replicate_delete(
     vid: VolumeId,
     needle: &mut Needle,
     is_replicate: bool,
+    if_match: Option<&str>,
 ) -> Result<usize> {
     let local_url = format!("{}:{}", state.store.ip, state.store.port);
-    let size = state.store.delete_volume_needle(vid, needle).await?;
+    let size = state
+        .store
+        .delete_volume_needle_if_match(vid, needle, if_match)
+        .await?;
     if is_replicate {
         return Ok(size);
     }
@@ -123,28 +334,27 @@ async fn replicate_delete(
         .lookup(vec![vid], &state.store.current_master.read().await)
         .await?;
 
+    // the fanout is polled by the request, it is aborted when the client goes away
     if let Some(volume_location) = volume_locations.pop() {
-        async_scoped::TokioScope::scope_and_block(|s| {
-            for location in volume_location.locations.iter() {
-                if location.url == local_url {
-                    continue;
-                }
-                s.spawn(async {
-                    let url = format!("http://{}{}", &location.url, path);
-                    if let Err(err) = util::http::delete(&url, &params).await.and_then(|body| {
-                        let value: Value = serde_json::from_slice(&body)?;
-                        if let Some(err) = value["error"].as_str() {
-                            if !err.is_empty() {
-                                return Err(anyhow!("delete {} err: {err}", location.url));
-                            }
+        let replicas = volume_location
+            .locations
+            .iter()
+            .filter(|location| location.url != local_url)
+            .map(|location| async {
+                let url = format!("http://{}{}", &location.url, path);
+                if let Err(err) = util::http::delete(&url, &params).await.and_then(|body| {
+                    let value: Value = serde_json::from_slice(&body)?;
+                    if let Some(err) = value["error"].as_str() {
+                        if !err.is_empty() {
+                            return Err(anyhow!("delete {} err: {err}", location.url));
                         }
-                        Ok(())
-                    }) {
-                        error!("replicate delete failed, error: {err}");
                     }
-                });
-            }
-        });
+                    Ok(())
+                }) {
+                    error!("replicate delete failed, error: {err}");
+                }
+            });
+        join_all(replicas).await;
     }
     Ok(size)
 }
@@ -153,17 +363,52 @@ pub async fn post_handler(
     State(state): State<StorageState>,
     extractor: PostExtractor,
 ) -> Result<Json<Upload>> {
-    let (vid, _, _, _) = parse_url_path(extractor.uri.path())?;
+    let (vid, fid, _, _) = parse_url_path(extractor.uri.path())?;
     let is_replicate = extractor.query.r#type == Some("replicate".into());
+    // replicas are written by the other volume servers only
+    if is_replicate {
+        verify_http(
+            &extractor.headers,
+            "POST",
+            extractor.uri.path(),
+            extractor.uri.query(),
+            &extractor.body,
+        )?;
+    }
 
-    let mut needle = if is_replicate {
-        bincode::deserialize(&extractor.body)?
+    let (mut needle, checksum) = if is_replicate {
+        let needle = match extractor.query.source.as_deref() {
+            Some(source) => pull_replica_needle(&state, source, vid, fid).await?,
+            None => bincode::deserialize(&extractor.body)?,
+        };
+        (needle, None)
     } else {
-        new_needle_from_request(&extractor).await?
+        let (needle, checksum) = new_needle_from_request(&extractor).await?;
+        (needle, Some(checksum))
     };
 
-    let size =
-        replicate_write(&state, extractor.uri.path(), vid, &mut needle, is_replicate).await?;
+    let if_match = match extractor.headers.get(IF_MATCH) {
+        Some(if_match) if !is_replicate => Some(if_match.to_str()?),
+        _ => None,
+    };
+    // the primary has checked the overwrite, replicas follow it
+    let replace = is_replicate || extractor.query.op.as_deref() == Some("replace");
+    let size = replicate_write(
+        &state,
+        extractor.uri.path(),
+        vid,
+        &mut needle,
+        is_replicate,
+        if_match,
+        replace,
+        extractor.query.sync,
+    )
+    .await?;
+    if !is_replicate {
+        state
+            .store
+            .record_usage(vid, UsageKind::Write, needle.data.len() as u64);
+    }
     let mut upload = Upload {
         size,
         ..Default::default()
@@ -171,74 +416,152 @@ pub async fn post_handler(
     if needle.has_name() {
         upload.name = String::from_utf8(needle.name.to_vec())?;
     }
+    if let Some(checksum) = checksum {
+        upload.content_md5 = Some(checksum.md5);
+        upload.content_sha256 = checksum.sha256;
+    }
 
     // TODO: add etag support
     Ok(Json(upload))
 }
 
+/// read the needle a replica is asked to write from `source` with ReadNeedleBlob
+async fn pull_replica_needle(
+    state: &StorageState,
+    source: &str,
+    vid: VolumeId,
+    fid: &str,
+) -> Result<Needle> {
+    let version = match state.store.find_volume(vid) {
+        Some(volume) => volume.version(),
+        None => return Err(VolumeError::NotFound(vid).into()),
+    };
+    let mut needle = Needle::new_with_fid(fid)?;
+    let cookie = needle.cookie;
+
+    let mut frames = client::read_needle_blob(source, vid, needle.id)
+        .await
+        .map_err(|err| anyhow!(err.to_string()))?;
+    let mut record = BytesMut::new();
+    while let Some(frame) = frames.next().await {
+        record.extend_from_slice(&frame?.needle_blob);
+    }
+    if record.len() < NEEDLE_HEADER_SIZE as usize {
+        return Err(NeedleError::Truncated(NEEDLE_HEADER_SIZE as usize, record.len()).into());
+    }
+    needle.parse_needle_header(&record);
+    let size = needle.size;
+    needle.read_bytes(record.freeze(), size, version)?;
+    if needle.cookie != cookie {
+        return Err(NeedleError::CookieNotMatch(needle.cookie, cookie).into());
+    }
+    Ok(needle)
+}
+
 async fn replicate_write(
     state: &StorageState,
     path: &str,
     vid: VolumeId,
     needle: &mut Needle,
     is_replicate: bool,
+    if_match: Option<&str>,
+    replace: bool,
+    sync: Option<ReplicaSync>,
 ) -> Result<usize> {
     let local_url = format!("{}:{}", state.store.ip, state.store.port);
-    let size = state.store.write_volume_needle(vid, needle).await?;
+    let size = state
+        .store
+        .write_volume_needle_if_match(vid, needle, if_match, replace)
+        .await?;
     // if the volume is replica, it will return needle directly.
     if is_replicate {
         return Ok(size);
     }
 
-    if let Some(volume) = state.store.find_volume(vid) {
-        if !volume.need_to_replicate() {
-            return Ok(size);
-        }
-    }
+    // the copies the placement asks for, the lookup may miss a replica which is down
+    let copies = match state.store.find_volume(vid) {
+        Some(volume) if !volume.need_to_replicate() => return Ok(size),
+        Some(volume) => volume.replica_placement.copy_count(),
+        None => 1,
+    };
 
-    let params = vec![("type", "replicate")];
-    let data = Bytes::from(bincode::serialize(&needle)?);
+    // replicas pull a large needle from this server with ReadNeedleBlob instead of receiving it
+    // serialized in every request
+    let pull = needle.data.len() as u64 >= STREAM_READ_THRESHOLD;
+    let data = if pull {
+        Bytes::new()
+    } else {
+        Bytes::from(bincode::serialize(&needle)?)
+    };
+    let mut params = vec![("type", "replicate")];
+    if pull {
+        params.push(("source", local_url.as_str()));
+    }
+    let params = &params;
 
     let mut volume_locations = state
         .looker
         .lookup(vec![vid], &state.store.current_master.read().await)
         .await?;
 
+    // the fanout is polled by the request until enough copies are written, so it is aborted when
+    // the client goes away before that
     if let Some(volume_location) = volume_locations.pop() {
-        async_scoped::TokioScope::scope_and_block(|s| {
-            for location in volume_location.locations.iter() {
-                if location.url == local_url {
-                    continue;
-                }
-                s.spawn(async {
-                    let url = format!("http://{}{}", location.url, path);
-                    if let Err(err) = util::http::post(&url, &params, data.clone())
-                        .await
-                        .and_then(|body| {
-                            let value: Value = serde_json::from_slice(&body)?;
-                            if let Some(err) = value["error"].as_str() {
-                                if !err.is_empty() {
-                                    return Err(anyhow!("write {} err: {err}", location.url));
-                                }
+        let mut replicas: FuturesUnordered<_> = volume_location
+            .locations
+            .into_iter()
+            .filter(|location| location.url != local_url)
+            .map(|location| {
+                let url = format!("http://{}{}", location.url, path);
+                let data = data.clone();
+                async move {
+                    let result = util::http::post(&url, params, data).await.and_then(|body| {
+                        let value: Value = serde_json::from_slice(&body)?;
+                        if let Some(err) = value["error"].as_str() {
+                            if !err.is_empty() {
+                                return Err(anyhow!("write {} err: {err}", location.url));
                             }
-                            Ok(())
-                        })
-                    {
+                        }
+                        Ok(())
+                    });
+                    if let Err(err) = &result {
                         error!("replicate write failed, error: {err}");
                     }
-                });
+                    result.is_ok()
+                }
+            })
+            .collect();
+
+        // without a sync mode every replica is waited for and failures are only logged
+        let required = sync.map_or(copies, |sync| sync.required(copies));
+        let mut written = 1;
+        while written < required {
+            match replicas.next().await {
+                Some(true) => written += 1,
+                Some(false) => {}
+                None => break,
             }
-        });
+        }
+        if !replicas.is_empty() {
+            // the client is answered, the remaining replicas are written in the background
+            tokio::spawn(async move { while replicas.next().await.is_some() {} });
+        }
+        if sync.is_some() && written < required {
+            return Err(VolumeError::Replication(vid, written, required).into());
+        }
     }
 
     Ok(size)
 }
 
-async fn new_needle_from_request(extractor: &PostExtractor) -> Result<Needle> {
+async fn new_needle_from_request(extractor: &PostExtractor) -> Result<(Needle, ContentChecksum)> {
+    // reject a corrupted payload before it is persisted, the checksum headers describe the body
+    // as it was sent, a multipart form included
+    let checksum = verify_content_checksum(&extractor.headers, &extractor.body)?;
     let mut parse_upload = parse_upload(extractor).await?;
 
     let mut needle = Needle {
-        data: Bytes::from(parse_upload.data),
+        data: parse_upload.data,
         ..Default::default()
     };
 
@@ -279,7 +602,7 @@ async fn new_needle_from_request(extractor: &PostExtractor) -> Result<Needle> {
     let end = path.rfind('.').unwrap_or(path.len());
     needle.parse_path(&path[start..end])?;
 
-    Ok(needle)
+    Ok((needle, checksum))
 }
 
 fn get_boundary(extractor: &PostExtractor) -> Result<String> {
@@ -315,7 +638,7 @@ async fn parse_upload(extractor: &PostExtractor) -> Result<ParseUpload> {
 
     // get first file with filename
     let mut filename = String::new();
-    let mut data = vec![];
+    let mut data = Bytes::new();
     let mut post_mtype = String::new();
     while let Ok(Some(field)) = mpart.next_field().await {
         if let Some(name) = field.file_name() {
@@ -326,7 +649,7 @@ async fn parse_upload(extractor: &PostExtractor) -> Result<ParseUpload> {
                     post_mtype.push('/');
                     post_mtype.push_str(content_type.subtype().as_str());
                 }
-                data.extend(field.bytes().await?);
+                data = field.bytes().await?;
                 break;
             }
         }
@@ -393,9 +716,26 @@ pub async fn get_or_head_handler(
     let mut needle = Needle::new_with_fid(fid)?;
     let cookie = needle.cookie;
 
+    if has_volume && extractor.headers.contains_key(X_VERIFY) {
+        let verified = verify_needle(&state, vid, needle.id, response.headers_mut())?;
+        // a corrupted needle is reported by the verification headers only
+        if !verified {
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(response);
+        }
+    }
+
     let mut count = 0;
+    let mut data_location = None;
     if has_volume {
-        count = state.store.read_volume_needle(vid, &mut needle).await?;
+        data_location = state
+            .store
+            .locate_volume_needle_data(vid, &mut needle, STREAM_READ_THRESHOLD)
+            .await?;
+        count = match data_location {
+            Some(_) => needle.data_size as usize,
+            None => state.store.read_volume_needle(vid, &mut needle).await?,
+        };
     } else if has_ec_volume {
         count = state.store.read_ec_shard_needle(vid, &mut needle).await?;
     }
@@ -409,6 +749,13 @@ pub async fn get_or_head_handler(
         return Err(NeedleError::CookieNotMatch(needle.cookie, cookie).into());
     }
 
+    if has_volume && state.store.is_volume_quarantined(vid) {
+        warn!("read needle {} from quarantined volume {vid}", needle.id);
+        response
+            .headers_mut()
+            .insert(HELYIM_QUARANTINED, HeaderValue::from_static("true"));
+    }
+
     // TODO: ignore datetime parsing error
     if needle.last_modified != 0 {
         let datetime: DateTime<Utc> = DateTime::from_timestamp_millis(needle.last_modified as i64)
@@ -456,6 +803,31 @@ pub async fn get_or_head_handler(
         }
     }
 
+    // a HEAD request sends no data
+    let bytes_read = match extractor.method {
+        Method::HEAD => 0,
+        _ => needle.data_size as u64,
+    };
+    state.store.record_usage(vid, UsageKind::Read, bytes_read);
+
+    if let Some((data_file, data_offset, version)) = data_location {
+        response
+            .headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from(needle.data_size));
+        response
+            .headers_mut()
+            .insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        *response.body_mut() = Body::from_stream(needle_data_stream(
+            data_file,
+            data_offset,
+            needle.data_size as u64,
+            version,
+            needle.checksum,
+        ));
+        *response.status_mut() = StatusCode::ACCEPTED;
+        return Ok(response);
+    }
+
     if needle.is_gzipped() {
         match extractor.headers.get(ACCEPT_ENCODING) {
             Some(value) => {
@@ -487,3 +859,59 @@ pub async fn get_or_head_handler(
 
     Ok(response)
 }
+
+/// stream the needle data from data file chunk by chunk, the whole needle is never held in memory,
+/// the chunks are read on the foreground io pool like the other user reads.
+/// the crc is checked before the last chunk is sent, a mismatch aborts the response so the client
+/// never receives a complete body of corrupted data.
+fn needle_data_stream(
+    data_file: File,
+    mut offset: u64,
+    len: u64,
+    version: Version,
+    expected: u32,
+) -> impl Stream<Item = std::io::Result<Bytes>> {
+    let data_file = Arc::new(data_file);
+    let end = offset + len;
+    let mut hasher = Some(crc::NeedleHasher::new(version));
+    stream! {
+        while offset < end {
+            let chunk_size = (end - offset).min(STREAM_READ_CHUNK_SIZE) as usize;
+            let data_file = data_file.clone();
+            let chunk = spawn_io(IoClass::Foreground, move || {
+                let mut buf = vec![0u8; chunk_size];
+                data_file.read_exact_at(&mut buf, offset).map(|_| Bytes::from(buf))
+            })
+            .await;
+            match chunk {
+                Ok(Ok(chunk)) => {
+                    offset += chunk.len() as u64;
+                    if let Some(hasher) = hasher.as_mut() {
+                        hasher.update(&chunk);
+                    }
+                    if offset >= end {
+                        if let Some(hasher) = hasher.take() {
+                            let checksum = hasher.finalize();
+                            if !crc::checksum_matches(version, expected, checksum) {
+                                let err = NeedleError::Crc(expected, checksum);
+                                error!("stream needle data from data file error: {err}");
+                                yield Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err));
+                                break;
+                            }
+                        }
+                    }
+                    yield Ok(chunk);
+                }
+                Ok(Err(err)) => {
+                    error!("read needle data from data file error: {err}");
+                    yield Err(err);
+                    break;
+                }
+                Err(err) => {
+                    yield Err(std::io::Error::other(err));
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -1,3 +1,217 @@
+use crate::storage::version::{Version, VERSION3};
+
+/// the reversed polynomial of crc32 (Castagnoli)
+const CASTAGNOLI: u32 = 0x82f6_3b78;
+/// added to the rotated checksum by the masking of older SeaweedFS releases
+const CASTAGNOLI_MASK_DELTA: u32 = 0xa282_ead8;
+
+static CASTAGNOLI_TABLE: [u32; 256] = castagnoli_table();
+
+const fn castagnoli_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CASTAGNOLI
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// crc32 (IEEE) of the needle data, the checksum of data files before version 3. `crc32fast`
+/// picks the fastest implementation at runtime, carry-less multiplication on x86_64 and the crc
+/// instructions on aarch64, and falls back to a table based one.
 pub fn checksum(bytes: &[u8]) -> u32 {
     crc32fast::hash(bytes)
 }
+
+/// crc32 (Castagnoli) of the needle data, the checksum of version 3 data files. the crc32c
+/// instructions of sse4.2 on x86_64 and of the crc extension on aarch64 are used when the cpu
+/// has them, otherwise a table based implementation.
+pub fn castagnoli(bytes: &[u8]) -> u32 {
+    !castagnoli_update(!0u32, bytes)
+}
+
+fn castagnoli_update(crc: u32, bytes: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    if std::arch::is_x86_feature_detected!("sse4.2") {
+        // SAFETY: the cpu supports sse4.2
+        return unsafe { castagnoli_sse42(crc, bytes) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("crc") {
+        // SAFETY: the cpu supports the crc extension
+        return unsafe { castagnoli_aarch64(crc, bytes) };
+    }
+    castagnoli_bytewise(crc, bytes)
+}
+
+fn castagnoli_bytewise(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, &b| {
+        CASTAGNOLI_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn castagnoli_sse42(crc: u32, bytes: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut chunks = bytes.chunks_exact(8);
+    let mut crc = crc as u64;
+    for chunk in &mut chunks {
+        let word = u64::from_le_bytes(chunk.try_into().expect("chunk of 8 bytes"));
+        crc = _mm_crc32_u64(crc, word);
+    }
+    chunks
+        .remainder()
+        .iter()
+        .fold(crc as u32, |crc, &b| _mm_crc32_u8(crc, b))
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "crc")]
+unsafe fn castagnoli_aarch64(crc: u32, bytes: &[u8]) -> u32 {
+    use std::arch::aarch64::{__crc32cb, __crc32cd};
+
+    let mut chunks = bytes.chunks_exact(8);
+    let mut crc = crc;
+    for chunk in &mut chunks {
+        let word = u64::from_le_bytes(chunk.try_into().expect("chunk of 8 bytes"));
+        crc = __crc32cd(crc, word);
+    }
+    chunks
+        .remainder()
+        .iter()
+        .fold(crc, |crc, &b| __crc32cb(crc, b))
+}
+
+/// the masked checksum older SeaweedFS releases stored instead of the plain one
+pub fn castagnoli_masked(crc: u32) -> u32 {
+    crc.rotate_right(15).wrapping_add(CASTAGNOLI_MASK_DELTA)
+}
+
+/// the checksum stored in the needles of a data file of `version`
+pub fn needle_checksum(version: Version, bytes: &[u8]) -> u32 {
+    if version == VERSION3 {
+        castagnoli(bytes)
+    } else {
+        checksum(bytes)
+    }
+}
+
+/// whether `stored` is the checksum of needle data whose `needle_checksum` is `actual`, older
+/// SeaweedFS releases stored the masked checksum in version 3 data files
+pub fn checksum_matches(version: Version, stored: u32, actual: u32) -> bool {
+    stored == actual || (version == VERSION3 && stored == castagnoli_masked(actual))
+}
+
+/// `needle_checksum` of needle data fed chunk by chunk
+pub enum NeedleHasher {
+    Ieee(crc32fast::Hasher),
+    Castagnoli(u32),
+}
+
+impl NeedleHasher {
+    pub fn new(version: Version) -> Self {
+        if version == VERSION3 {
+            NeedleHasher::Castagnoli(!0u32)
+        } else {
+            NeedleHasher::Ieee(crc32fast::Hasher::new())
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            NeedleHasher::Ieee(hasher) => hasher.update(bytes),
+            NeedleHasher::Castagnoli(crc) => *crc = castagnoli_update(*crc, bytes),
+        }
+    }
+
+    pub fn finalize(self) -> u32 {
+        match self {
+            NeedleHasher::Ieee(hasher) => hasher.finalize(),
+            NeedleHasher::Castagnoli(crc) => !crc,
+        }
+    }
+}
+
+/// the cpu features used by `checksum` and `castagnoli` on this machine
+pub fn acceleration() -> &'static str {
+    #[cfg(target_arch = "x86_64")]
+    {
+        let clmul = std::arch::is_x86_feature_detected!("pclmulqdq")
+            && std::arch::is_x86_feature_detected!("sse4.1");
+        let sse42 = std::arch::is_x86_feature_detected!("sse4.2");
+        match (clmul, sse42) {
+            (true, true) => return "pclmulqdq, sse4.2",
+            (true, false) => return "pclmulqdq",
+            (false, true) => return "sse4.2",
+            (false, false) => {}
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("crc") {
+        return "aarch64 crc";
+    }
+    "none"
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{
+        crc::{
+            castagnoli, castagnoli_bytewise, castagnoli_masked, checksum, needle_checksum,
+            NeedleHasher,
+        },
+        version::{VERSION2, VERSION3},
+    };
+
+    #[test]
+    fn test_checksum() {
+        // the check value of crc32 (IEEE), whichever implementation is picked
+        assert_eq!(checksum(b"123456789"), 0xCBF43926);
+        assert_eq!(checksum(&[]), 0);
+
+        // long inputs take the accelerated path, it must agree with the bytewise result
+        let data: Vec<u8> = (0..4096u32).map(|i| (i * 31 % 251) as u8).collect();
+        let mut hasher = crc32fast::Hasher::new();
+        for chunk in data.chunks(1) {
+            hasher.update(chunk);
+        }
+        assert_eq!(checksum(&data), hasher.finalize());
+    }
+
+    #[test]
+    fn test_castagnoli() {
+        // the check value of crc32 (Castagnoli)
+        assert_eq!(castagnoli(b"123456789"), 0xE3069283);
+        assert_eq!(castagnoli(&[]), 0);
+        assert_eq!(castagnoli_masked(0), 0xa282ead8);
+
+        // the crc32c instructions must agree with the table, the length leaves a remainder
+        let data: Vec<u8> = (0..4099u32).map(|i| (i * 31 % 251) as u8).collect();
+        assert_eq!(castagnoli(&data), !castagnoli_bytewise(!0, &data));
+    }
+
+    #[test]
+    fn test_needle_hasher() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 17 % 253) as u8).collect();
+        for version in [VERSION2, VERSION3] {
+            let mut hasher = NeedleHasher::new(version);
+            for chunk in data.chunks(97) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finalize(), needle_checksum(version, &data));
+        }
+    }
+}
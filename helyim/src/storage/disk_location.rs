@@ -1,4 +1,4 @@
-use std::{fs, path::Path};
+use std::{fs, io::Write, path::Path};
 
 use dashmap::{
     mapref::one::{Ref, RefMut},
@@ -14,34 +14,47 @@ use crate::{
     anyhow,
     storage::{
         erasure_coding::EcVolume,
-        needle::NeedleMapType,
+        needle::{NeedleMapType, NEEDLE_PADDING_SIZE},
         ttl::Ttl,
         volume::{ReplicaPlacement, Volume, DATA_FILE_SUFFIX},
-        VolumeError, VolumeId,
+        DiskType, VolumeError, VolumeId,
     },
 };
 
 pub struct DiskLocation {
     pub directory: FastStr,
     pub max_volume_count: i64,
+    pub disk_type: DiskType,
     pub volumes: DashMap<VolumeId, Volume>,
     pub ec_volumes: DashMap<VolumeId, EcVolume>,
 }
 
 impl DiskLocation {
-    pub fn new(dir: &str, max_volume_count: i64) -> DiskLocation {
+    pub fn new(dir: &str, max_volume_count: i64, disk_type: DiskType) -> DiskLocation {
         DiskLocation {
             directory: FastStr::new(dir),
             max_volume_count,
+            disk_type,
             volumes: DashMap::new(),
             ec_volumes: DashMap::new(),
         }
     }
 
+    /// write and remove a probe file to make sure the directory is still writable
+    pub fn check_writable(&self) -> Result<(), VolumeError> {
+        let probe = Path::new(self.directory.as_str()).join(".writable");
+        let mut file = fs::File::create(&probe)?;
+        file.write_all(b"ok")?;
+        file.sync_all()?;
+        fs::remove_file(probe)?;
+        Ok(())
+    }
+
     /// concurrent loading volumes
     pub async fn load_existing_volumes(
         &self,
         needle_map_type: NeedleMapType,
+        read_bloom_filter: bool,
     ) -> Result<(), VolumeError> {
         let dir = self.directory.to_string();
         let dir = Path::new(&dir);
@@ -60,6 +73,7 @@ impl DiskLocation {
                     let collection = FastStr::new(collection);
 
                     let handle = tokio::spawn(async move {
+                        // the super block of the data file has the alignment
                         let volume = Volume::new(
                             dir,
                             collection,
@@ -68,7 +82,9 @@ impl DiskLocation {
                             ReplicaPlacement::default(),
                             Ttl::default(),
                             0,
-                        )?;
+                            NEEDLE_PADDING_SIZE,
+                        )?
+                        .with_read_bloom_filter(read_bloom_filter);
 
                         Ok((vid, volume))
                     });
@@ -138,7 +154,7 @@ fn parse_volume_id_from_path(path: &Path) -> Result<(VolumeId, &str), VolumeErro
 
 #[cfg(test)]
 mod tests {
-    use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
+    use std::path::Path;
 
     use crate::storage::disk_location::parse_volume_id_from_path;
 
@@ -163,8 +179,13 @@ mod tests {
         let parse = parse_volume_id_from_path(path);
         assert!(parse.is_err());
 
-        let path = Path::new(OsStr::from_bytes(&[0xC3, 0x28, 0x20, 0xC2, 0x29]));
-        let parse = parse_volume_id_from_path(path);
-        assert!(parse.is_err());
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+            let path = Path::new(OsStr::from_bytes(&[0xC3, 0x28, 0x20, 0xC2, 0x29]));
+            let parse = parse_volume_id_from_path(path);
+            assert!(parse.is_err());
+        }
     }
 }
@@ -2,7 +2,6 @@ use std::{
     cmp::min,
     fs,
     io::{copy, ErrorKind, Read, Write},
-    os::unix::fs::{FileExt, OpenOptionsExt},
     result::Result as StdResult,
 };
 
@@ -18,11 +17,10 @@ use crate::{
         needle::{NEEDLE_ENTRY_SIZE, NEEDLE_ID_SIZE},
         read_index_entry,
         types::{Offset, Size},
-        version::Version,
         volume::{SuperBlock, SUPER_BLOCK_SIZE},
         NeedleId, NeedleValue,
     },
-    util::file::file_exists,
+    util::file::{file_exists, FileExt, OpenOptionsExt},
 };
 
 pub fn write_index_file_from_ec_index(base_filename: &str) -> Result<()> {
@@ -50,8 +48,8 @@ pub fn write_index_file_from_ec_index(base_filename: &str) -> Result<()> {
 }
 
 pub fn find_data_filesize(base_filename: &str) -> Result<u64> {
-    // TODO: handle version
-    let version = read_ec_volume_version(base_filename)?;
+    let super_block = read_ec_super_block(base_filename)?;
+    let alignment = super_block.alignment;
     let mut data_filesize = 0;
     iterate_ecx_file(
         base_filename,
@@ -60,7 +58,8 @@ pub fn find_data_filesize(base_filename: &str) -> Result<u64> {
                 if size.is_deleted() {
                     return Ok(());
                 }
-                let entry_stop_offset = offset.actual_offset() + size.actual_size();
+                let entry_stop_offset =
+                    offset.actual_offset(alignment) + size.actual_size(alignment);
                 if data_filesize < entry_stop_offset {
                     data_filesize = entry_stop_offset;
                 }
@@ -71,15 +70,14 @@ pub fn find_data_filesize(base_filename: &str) -> Result<u64> {
     Ok(data_filesize)
 }
 
-fn read_ec_volume_version(base_filename: &str) -> Result<Version> {
+fn read_ec_super_block(base_filename: &str) -> Result<SuperBlock> {
     let data_file = fs::OpenOptions::new()
         .read(true)
         .mode(0o644)
         .open(format!("{}.ec00", base_filename))?;
     let mut super_block = [0u8; SUPER_BLOCK_SIZE];
     data_file.read_exact_at(&mut super_block, 0)?;
-    let super_block = SuperBlock::parse(super_block)?;
-    Ok(super_block.version)
+    Ok(SuperBlock::parse(super_block)?)
 }
 
 fn iterate_ecx_file<F>(base_filename: &str, mut process_needle: Option<F>) -> Result<()>
@@ -2,7 +2,6 @@ use std::{
     fs,
     fs::File,
     io::{ErrorKind, Write},
-    os::unix::fs::{FileExt, OpenOptionsExt},
 };
 
 use reed_solomon_erasure::{galois_8::Field, ReedSolomon};
@@ -17,7 +16,7 @@ use crate::{
         needle::SortedIndexMap,
         NeedleError,
     },
-    util::file::file_exists,
+    util::file::{file_exists, FileExt, OpenOptionsExt},
 };
 
 /// generates .ecx file from existing .idx file all keys are sorted in ascending order
@@ -30,7 +29,7 @@ pub fn write_sorted_file_from_index(base_filename: &str, ext: &str) -> Result<()
         .mode(0o644)
         .open(format!("{}{}", base_filename, ext))?;
 
-    nm.ascending_visit(|key, value| -> Result<(), NeedleError> {
+    nm.key_ascending_visit(|key, value| -> Result<(), NeedleError> {
         let buf = value.as_bytes(*key);
         ecx_file.write_all(&buf)?;
         Ok(())
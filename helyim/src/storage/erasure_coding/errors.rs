@@ -1,11 +1,9 @@
-use axum::{
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    Json,
-};
-use serde_json::json;
+use axum::response::{IntoResponse, Response};
 
-use crate::storage::{erasure_coding::ShardId, NeedleError, VolumeError, VolumeId};
+use crate::{
+    errors::{Error, ErrorCode},
+    storage::{erasure_coding::ShardId, NeedleError, VolumeError, VolumeId},
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum EcVolumeError {
@@ -56,14 +54,39 @@ impl From<EcVolumeError> for tonic::Status {
     }
 }
 
+impl EcVolumeError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            EcVolumeError::Volume(err) => err.code(),
+            EcVolumeError::Needle(err) => err.code(),
+            EcVolumeError::ShardNotFound(..) => ErrorCode::VolumeNotFound,
+            EcVolumeError::EcShard(EcShardError::Underflow(..))
+            | EcVolumeError::ErasureCoding(_) => ErrorCode::DataCorrupted,
+            EcVolumeError::String(_) | EcVolumeError::ParseInt(_) => ErrorCode::BadRequest,
+            EcVolumeError::Io(_)
+            | EcVolumeError::BoxError(_)
+            | EcVolumeError::EcShard(_)
+            | EcVolumeError::TokioTaskJoin(_)
+            | EcVolumeError::FutureSendError(_)
+            | EcVolumeError::FutureTrySendError(_)
+            | EcVolumeError::TonicTransport(_)
+            | EcVolumeError::TonicStatus(_) => ErrorCode::Internal,
+        }
+    }
+
+    pub fn volume_id(&self) -> Option<VolumeId> {
+        match self {
+            EcVolumeError::Volume(err) => err.volume_id(),
+            EcVolumeError::Needle(err) => err.volume_id(),
+            EcVolumeError::ShardNotFound(vid, _) => Some(*vid),
+            _ => None,
+        }
+    }
+}
+
 impl IntoResponse for EcVolumeError {
     fn into_response(self) -> Response {
-        let error = self.to_string();
-        let error = json!({
-            "error": error
-        });
-        let response = (StatusCode::BAD_REQUEST, Json(error));
-        response.into_response()
+        Error::EcVolume(self).into_response()
     }
 }
 
@@ -1,9 +1,4 @@
-use std::{
-    fs,
-    fs::File,
-    io::Read,
-    os::unix::fs::{FileExt, OpenOptionsExt},
-};
+use std::{fs, fs::File, io::Read};
 
 use bytes::{Buf, BufMut};
 
@@ -15,7 +10,7 @@ use crate::{
         },
         read_index_entry, NeedleError, NeedleId, NeedleValue,
     },
-    util::file::file_exists,
+    util::file::{file_exists, FileExt, OpenOptionsExt},
 };
 
 mod decoder;
@@ -69,7 +64,7 @@ fn search_needle_from_sorted_index(
             if let Some(mut process_needle) = process_needle {
                 process_needle(ecx_file, middle * NEEDLE_HEADER_SIZE as u64)?;
             }
-            return Ok(NeedleValue { offset, size });
+            return Ok(NeedleValue::new(offset, size));
         }
         if key < needle_id {
             low = middle + 1;
@@ -1,10 +1,13 @@
-use std::{fs, fs::File, os::unix::fs::OpenOptionsExt};
+use std::{fs, fs::File};
 
 use faststr::FastStr;
 
-use crate::storage::{
-    erasure_coding::{errors::EcShardError, to_ext, ShardId},
-    VolumeId,
+use crate::{
+    storage::{
+        erasure_coding::{errors::EcShardError, to_ext, ShardId},
+        VolumeId,
+    },
+    util::file::OpenOptionsExt,
 };
 
 pub struct EcVolumeShard {
@@ -1,7 +1,6 @@
 use std::{
     io::ErrorKind,
     ops::Add,
-    os::unix::fs::FileExt,
     sync::Arc,
     time::{Duration, SystemTime},
 };
@@ -27,7 +26,10 @@ use crate::{
         store::Store,
         Needle, NeedleError, NeedleId, VolumeId,
     },
-    util::grpc::{helyim_client, volume_server_client},
+    util::{
+        file::FileExt,
+        grpc::{helyim_client, volume_server_client},
+    },
 };
 
 impl Store {
@@ -143,8 +145,8 @@ impl Store {
                 info!(
                     "locate needle within ec shard success, ec volume: {vid}, offset: {}, size: \
                      {}, interval len: {}",
-                    index.offset.actual_offset(),
-                    index.size.actual_size(),
+                    index.offset.actual_offset(volume.alignment),
+                    index.size.actual_size(volume.alignment),
                     intervals.len()
                 );
 
@@ -163,7 +165,7 @@ impl Store {
                     needle.id
                 );
 
-                needle.read_bytes(bytes, index.offset, index.size, volume.version)?;
+                needle.read_bytes(bytes, index.size, volume.version)?;
                 return Ok(len);
             }
         }
@@ -2,7 +2,6 @@ use std::{
     fs,
     fs::File,
     io::{ErrorKind, Read},
-    os::unix::fs::{FileExt, OpenOptionsExt},
     sync::Arc,
     time::SystemTime,
 };
@@ -24,11 +23,11 @@ use crate::{
             ShardId, DATA_SHARDS_COUNT, ERASURE_CODING_LARGE_BLOCK_SIZE,
             ERASURE_CODING_SMALL_BLOCK_SIZE,
         },
-        needle::NEEDLE_ID_SIZE,
+        needle::{NEEDLE_ID_SIZE, NEEDLE_PADDING_SIZE},
         version::{Version, VERSION2},
         NeedleError, NeedleId, NeedleValue, VolumeId,
     },
-    util::file::file_exists,
+    util::file::{file_exists, FileExt, OpenOptionsExt},
 };
 
 pub struct EcVolume {
@@ -42,6 +41,8 @@ pub struct EcVolume {
     pub shard_locations: DashMap<ShardId, Vec<FastStr>>,
     pub shard_locations_refresh_time: RwLock<SystemTime>,
     pub version: Version,
+    /// alignment of the needles of the volume the shards were encoded from
+    pub alignment: u32,
     ecj_file: File,
 }
 
@@ -69,9 +70,13 @@ impl EcVolume {
 
         // TODO: handle version
         let mut version = VERSION2;
+        let mut alignment = NEEDLE_PADDING_SIZE;
         let filename = format!("{}.vif", base_filename);
         if let Some(volume_info) = maybe_load_volume_info(&filename)? {
             version = volume_info.version as Version;
+            if volume_info.needle_alignment != 0 {
+                alignment = volume_info.needle_alignment;
+            }
         } else {
             let volume_info = VolumeInfo {
                 version: version as u32,
@@ -92,6 +97,7 @@ impl EcVolume {
             shard_locations: DashMap::new(),
             shard_locations_refresh_time: RwLock::new(SystemTime::now()),
             version,
+            alignment,
         })
     }
 
@@ -148,8 +154,8 @@ impl EcVolume {
             ERASURE_CODING_LARGE_BLOCK_SIZE,
             ERASURE_CODING_SMALL_BLOCK_SIZE,
             shard.ecd_filesize * DATA_SHARDS_COUNT as u64,
-            needle_value.offset.actual_offset(),
-            needle_value.size.actual_size(),
+            needle_value.offset.actual_offset(self.alignment),
+            needle_value.size.actual_size(self.alignment),
         );
         Ok((needle_value, intervals))
     }
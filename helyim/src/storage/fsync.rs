@@ -0,0 +1,171 @@
+use std::{
+    fs::File,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use dashmap::DashMap;
+use faststr::FastStr;
+use tokio::sync::watch;
+use tracing::error;
+
+use crate::storage::{
+    io_class::{spawn_io, IoClass},
+    VolumeError, VolumeId,
+};
+
+/// When a write is acknowledged to the client.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Durability {
+    /// once it is in the page cache, the os decides when it reaches the disk
+    Buffered,
+    /// once it is in the page cache, an fsync is started without waiting for it
+    Async,
+    /// once it is flushed to the disk by fsync
+    Sync,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Synced {
+    /// writes up to this sequence are on the disk
+    synced: u64,
+    /// writes up to this sequence may be lost, the error of the fsync which failed
+    failed: u64,
+    error: Option<FastStr>,
+}
+
+/// Group commit of the writes to one volume.
+///
+/// Every write takes a sequence, one fsync covers all writes before it started, so writes which
+/// arrive while an fsync is running share the next one instead of queueing an fsync each.
+pub struct FsyncQueue {
+    written: AtomicU64,
+    scheduled: AtomicBool,
+    synced: watch::Sender<Synced>,
+}
+
+impl Default for FsyncQueue {
+    fn default() -> Self {
+        Self {
+            written: AtomicU64::new(0),
+            scheduled: AtomicBool::new(false),
+            synced: watch::Sender::new(Synced::default()),
+        }
+    }
+}
+
+impl FsyncQueue {
+    /// record a write which has been appended, returns its sequence
+    pub fn written(&self) -> u64 {
+        self.written.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// fsync `files` on the fsync threads, unless an fsync is already queued which will cover the
+    /// latest write
+    pub fn schedule(self: &Arc<Self>, vid: VolumeId, files: &[&File]) -> Result<(), VolumeError> {
+        // sequentially consistent, so either a queued fsync sees this write or a new one is queued
+        if self.scheduled.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let files = match files
+            .iter()
+            .map(|file| file.try_clone())
+            .collect::<Result<Vec<File>, _>>()
+        {
+            Ok(files) => files,
+            Err(err) => {
+                self.scheduled.store(false, Ordering::SeqCst);
+                return Err(err.into());
+            }
+        };
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let fsync = queue.clone();
+            let result = spawn_io(IoClass::Fsync, move || {
+                // writes recorded from now on need another fsync
+                fsync.scheduled.store(false, Ordering::SeqCst);
+                let sequence = fsync.written.load(Ordering::SeqCst);
+                let result = files.iter().try_for_each(|file| file.sync_data());
+                match result {
+                    Ok(_) => fsync.synced.send_modify(|synced| {
+                        synced.synced = synced.synced.max(sequence);
+                    }),
+                    Err(err) => {
+                        error!("volume {vid}: fsync failed, error: {err}");
+                        fsync.fail(sequence, err.to_string());
+                    }
+                }
+            })
+            .await;
+            if let Err(err) = result {
+                // the fsync did not run, the writes waiting for it are failed instead of hanging
+                error!("volume {vid}: run fsync failed, error: {err}");
+                queue.scheduled.store(false, Ordering::SeqCst);
+                queue.fail(queue.written.load(Ordering::SeqCst), err.to_string());
+            }
+        });
+        Ok(())
+    }
+
+    fn fail(&self, sequence: u64, error: String) {
+        self.synced.send_modify(|synced| {
+            synced.failed = synced.failed.max(sequence);
+            synced.error = Some(FastStr::new(error));
+        });
+    }
+
+    /// wait until the write of `sequence` is on the disk
+    pub async fn wait(&self, vid: VolumeId, sequence: u64) -> Result<(), VolumeError> {
+        let mut rx = self.synced.subscribe();
+        let synced = rx
+            .wait_for(|synced| synced.synced >= sequence || synced.failed >= sequence)
+            .await
+            .map_err(|err| VolumeError::String(err.to_string()))?;
+        if synced.failed >= sequence {
+            let error = synced.error.clone().unwrap_or_default();
+            return Err(VolumeError::Fsync(vid, error));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct FsyncQueues {
+    queues: DashMap<VolumeId, Arc<FsyncQueue>>,
+}
+
+impl FsyncQueues {
+    pub fn get(&self, vid: VolumeId) -> Arc<FsyncQueue> {
+        self.queues.entry(vid).or_default().clone()
+    }
+
+    pub fn remove(&self, vid: VolumeId) {
+        self.queues.remove(&vid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, sync::Arc};
+
+    use crate::storage::fsync::FsyncQueue;
+
+    #[tokio::test]
+    async fn test_group_commit() {
+        let mut file = tempfile::tempfile().unwrap();
+        let queue = Arc::new(FsyncQueue::default());
+
+        let mut sequences = Vec::new();
+        for _ in 0..10 {
+            file.write_all(b"needle").unwrap();
+            sequences.push(queue.written());
+            queue.schedule(1, &[&file]).unwrap();
+        }
+        for sequence in sequences {
+            queue.wait(1, sequence).await.unwrap();
+        }
+        assert!(queue.synced.borrow().synced >= 10);
+    }
+}
@@ -0,0 +1,98 @@
+//! Entrypoints for fuzzing the parsers of on disk data and request paths.
+//!
+//! Every function takes arbitrary input and must return instead of panicking, the result is
+//! dropped. They are driven by the cargo-fuzz targets in `fuzz/`.
+
+use bytes::{Buf, Bytes};
+
+use crate::{
+    storage::{
+        needle::{read_index_entry, MAX_NEEDLE_ALIGNMENT, NEEDLE_HEADER_SIZE, NEEDLE_INDEX_SIZE},
+        types::Size,
+        version::{VERSION2, VERSION3},
+        volume::{SuperBlock, SUPER_BLOCK_SIZE},
+        Needle,
+    },
+    util::parser::parse_url_path,
+};
+
+/// decode a needle blob as it is read from a data file, the size of the index entry is taken
+/// from the header so the body is parsed as well
+pub fn needle(data: &[u8]) {
+    let bytes = Bytes::copy_from_slice(data);
+    let size = match data.get(12..NEEDLE_HEADER_SIZE as usize) {
+        Some(mut size) => Size(size.get_i32()),
+        None => Size(0),
+    };
+    let mut needle = Needle::default();
+    let _ = needle.read_bytes(bytes.clone(), size, VERSION2);
+    let mut needle = Needle::default();
+    let _ = needle.read_bytes(bytes, size, VERSION3);
+}
+
+/// decode the fields following the needle data
+pub fn needle_meta(data: &[u8]) {
+    let mut needle = Needle::default();
+    let _ = needle.read_needle_data(Bytes::copy_from_slice(data), VERSION2);
+    let _ = needle.read_needle_meta(Bytes::copy_from_slice(data), VERSION2);
+    let _ = needle.read_needle_meta(Bytes::copy_from_slice(data), VERSION3);
+}
+
+/// decode every complete entry of an index file
+pub fn index_entries(data: &[u8]) {
+    for entry in data.chunks_exact(NEEDLE_INDEX_SIZE as usize) {
+        let (_, offset, size) = read_index_entry(entry);
+        let _ = (
+            offset.actual_offset(MAX_NEEDLE_ALIGNMENT),
+            size.actual_size(MAX_NEEDLE_ALIGNMENT),
+            size.is_deleted(),
+        );
+    }
+}
+
+pub fn super_block(data: &[u8]) {
+    if let Some(buf) = data.get(..SUPER_BLOCK_SIZE) {
+        let mut super_block = [0u8; SUPER_BLOCK_SIZE];
+        super_block.copy_from_slice(buf);
+        if let Ok(super_block) = SuperBlock::parse(super_block) {
+            let _ = super_block.as_bytes();
+        }
+    }
+}
+
+/// parse the path of a read or upload request, like `/3,01637037d6/name.jpg`
+pub fn url_path(data: &[u8]) {
+    if let Ok(path) = std::str::from_utf8(data) {
+        if let Ok((_, fid, _, _)) = parse_url_path(path) {
+            let _ = Needle::new_with_fid(fid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::storage::fuzz::{index_entries, needle, needle_meta, super_block, url_path};
+
+    #[test]
+    fn test_malformed_inputs() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..10000 {
+            let len = rng.gen_range(0..64);
+            let data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            needle(&data);
+            needle_meta(&data);
+            index_entries(&data);
+            super_block(&data);
+            url_path(&data);
+        }
+
+        // a needle claiming more data than present
+        let mut data = vec![0u8; 16];
+        data[12..16].copy_from_slice(&64i32.to_be_bytes());
+        data.extend_from_slice(&u32::MAX.to_be_bytes());
+        needle(&data);
+        url_path("/3,ééééééééé".as_bytes());
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    io,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+};
+
+use kanal::{unbounded, Sender};
+use once_cell::sync::{Lazy, OnceCell};
+use tokio::{
+    runtime::{Builder, Runtime},
+    sync::oneshot,
+};
+use tracing::{error, warn};
+
+use crate::storage::VolumeError;
+
+/// threads running background storage jobs, which is also the max concurrent background jobs
+pub const BACKGROUND_IO_THREADS: usize = 2;
+/// nice value of the background threads, without an explicit io priority the CFQ and BFQ io
+/// schedulers derive the io priority of a thread from its nice value
+const BACKGROUND_IO_NICE: i32 = 19;
+
+/// threads running fsync, a slow disk blocks them instead of the request handling threads
+pub const FSYNC_IO_THREADS: usize = 4;
+
+static BACKGROUND_IO: Lazy<IoPool> =
+    Lazy::new(|| IoPool::new("background-io", BACKGROUND_IO_THREADS, true));
+static FSYNC_IO: Lazy<IoPool> = Lazy::new(|| IoPool::new("fsync-io", FSYNC_IO_THREADS, false));
+/// runtime whose blocking pool runs the foreground operations, if disk io is separated from the
+/// runtime serving requests
+static DISK_IO: OnceCell<Runtime> = OnceCell::new();
+
+/// run foreground operations on a dedicated runtime with `threads` blocking threads, 0 keeps
+/// them on the blocking pool of the current runtime
+pub fn init_disk_io(threads: usize) -> io::Result<()> {
+    if threads == 0 || DISK_IO.get().is_some() {
+        return Ok(());
+    }
+    let runtime = Builder::new_multi_thread()
+        .worker_threads(1)
+        .max_blocking_threads(threads)
+        .thread_name("disk-io")
+        .enable_all()
+        .build()?;
+    let _ = DISK_IO.set(runtime);
+    Ok(())
+}
+
+/// Priority class of a blocking storage operation.
+///
+/// User reads and writes are `Foreground`, internal bulk operations like replication, vacuum and
+/// erasure coding are `Background`, they are queued and run by a few low priority threads so they
+/// never compete with users for the blocking pool or the disk. `Fsync` runs on its own threads,
+/// so flushing a slow disk does not hold the blocking pool either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    Foreground,
+    Background,
+    Fsync,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Dedicated threads running queued jobs.
+struct IoPool {
+    jobs: Sender<Job>,
+    pending: AtomicU64,
+}
+
+impl IoPool {
+    fn new(name: &str, threads: usize, low_priority: bool) -> Self {
+        let (jobs, rx) = unbounded::<Job>();
+        for i in 0..threads {
+            let rx = rx.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("{name}-{i}"))
+                .spawn(move || {
+                    if low_priority {
+                        lower_thread_priority();
+                    }
+                    while let Ok(job) = rx.recv() {
+                        // a panicking job fails its caller, the thread keeps serving the pool
+                        if catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            error!("{} job panicked", thread::current().name().unwrap_or(""));
+                        }
+                    }
+                });
+            if let Err(err) = spawned {
+                error!("spawn {name} thread failed, error: {err}");
+            }
+        }
+        Self {
+            jobs,
+            pending: AtomicU64::new(0),
+        }
+    }
+
+    async fn spawn<F, T>(&self, f: F) -> Result<T, VolumeError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        // released even if the caller is dropped while the job is queued
+        let _pending = Pending::new(&self.pending);
+        let job: Job = Box::new(move || {
+            let _ = tx.send(f());
+        });
+        match self.jobs.send(job) {
+            Ok(_) => rx.await.map_err(|err| VolumeError::String(err.to_string())),
+            Err(err) => Err(VolumeError::String(err.to_string())),
+        }
+    }
+}
+
+/// counts a job as pending while it is alive
+struct Pending<'a>(&'a AtomicU64);
+
+impl<'a> Pending<'a> {
+    fn new(pending: &'a AtomicU64) -> Self {
+        pending.fetch_add(1, Ordering::Relaxed);
+        Self(pending)
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn lower_thread_priority() {
+    // on linux the nice value is per thread, `None` refers to the calling thread
+    if let Err(err) = rustix::process::setpriority_process(None, BACKGROUND_IO_NICE) {
+        warn!("lower background io thread priority failed, error: {err}");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lower_thread_priority() {}
+
+/// run a blocking storage operation with the priority of `class`
+pub async fn spawn_io<F, T>(class: IoClass, f: F) -> Result<T, VolumeError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match class {
+        IoClass::Foreground => {
+            let handle = match DISK_IO.get() {
+                Some(runtime) => runtime.spawn_blocking(f),
+                None => tokio::task::spawn_blocking(f),
+            };
+            handle
+                .await
+                .map_err(|err| VolumeError::String(err.to_string()))
+        }
+        IoClass::Background => BACKGROUND_IO.spawn(f).await,
+        IoClass::Fsync => FSYNC_IO.spawn(f).await,
+    }
+}
+
+/// background jobs queued or running
+pub fn background_io_pending() -> u64 {
+    BACKGROUND_IO.pending.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::storage::io_class::{init_disk_io, spawn_io, IoClass};
+
+    #[tokio::test]
+    async fn test_spawn_io() {
+        let name = spawn_io(IoClass::Background, || {
+            thread::current().name().map(|name| name.to_string())
+        })
+        .await
+        .unwrap();
+        assert!(name.unwrap().starts_with("background-io-"));
+
+        let value = spawn_io(IoClass::Foreground, || 1 + 1).await.unwrap();
+        assert_eq!(value, 2);
+
+        let name = spawn_io(IoClass::Fsync, || {
+            thread::current().name().map(|name| name.to_string())
+        })
+        .await
+        .unwrap();
+        assert!(name.unwrap().starts_with("fsync-io-"));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_io_panic() {
+        let result = spawn_io(IoClass::Background, || -> u32 { panic!("oops") }).await;
+        assert!(result.is_err());
+        // the pool survives the panic
+        let value = spawn_io(IoClass::Background, || 1 + 1).await.unwrap();
+        assert_eq!(value, 2);
+    }
+
+    #[tokio::test]
+    async fn test_disk_io_runtime() {
+        init_disk_io(2).unwrap();
+        let name = spawn_io(IoClass::Foreground, || {
+            thread::current().name().map(|name| name.to_string())
+        })
+        .await
+        .unwrap();
+        assert_eq!(name.as_deref(), Some("disk-io"));
+    }
+}
@@ -6,13 +6,22 @@ mod disk_location;
 
 pub mod erasure_coding;
 
+mod fsync;
+pub use fsync::Durability;
+
+mod io_class;
+pub use io_class::{spawn_io, IoClass};
+
 mod file_id;
 pub use file_id::FileId;
 
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+
 mod needle;
 pub use needle::{
     read_index_entry, walk_index_file, MemoryNeedleValueMap, Needle, NeedleError, NeedleMapType,
-    NeedleMapper, NeedleValue, NeedleValueMap,
+    NeedleMapper, NeedleValue, NeedleValueMap, NEEDLE_PADDING_SIZE,
 };
 
 mod server;
@@ -21,18 +30,25 @@ pub use server::VolumeServer;
 mod store;
 
 mod types;
-pub use types::{NeedleId, VolumeId};
+pub use types::{DiskType, NeedleId, VolumeId};
 
 mod ttl;
 pub use ttl::{Ttl, TtlError};
 
+mod usage;
+pub use usage::UsageKind;
+
 mod version;
 pub use version::CURRENT_VERSION;
 
 mod volume;
 pub use volume::{
+    check_needle_alignment,
     vacuum::{batch_vacuum_volume_check, batch_vacuum_volume_commit, batch_vacuum_volume_compact},
     ReplicaPlacement, VolumeError, VolumeInfo,
 };
 
+mod write_queue;
+pub use write_queue::WRITE_QUEUE_RETRY_AFTER_SECS;
+
 pub const BUFFER_SIZE_LIMIT: usize = 2 * 1024 * 1024;
@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::storage::NeedleId;
+
+/// bits per key, about 1% false positives with `HASHES` hashes
+const BITS_PER_KEY: usize = 10;
+const HASHES: u64 = 7;
+/// keys a filter is sized for at least, small volumes grow into it without rebuilding
+pub const MIN_CAPACITY: usize = 1 << 16;
+
+/// Bloom filter of the keys written to a volume.
+///
+/// A key the filter does not contain was never written, so reads of it are answered with not
+/// found before the needle map and the data file are touched. Deleted keys stay in the filter,
+/// their reads go on to the needle map which knows they are deleted.
+pub struct BloomFilter {
+    bits: Vec<AtomicU64>,
+    mask: u64,
+    len: AtomicUsize,
+    capacity: usize,
+}
+
+impl BloomFilter {
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let words = (capacity * BITS_PER_KEY).div_ceil(64).next_power_of_two();
+        Self {
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            mask: words as u64 * 64 - 1,
+            len: AtomicUsize::new(0),
+            capacity,
+        }
+    }
+
+    /// double hashing of the two halves of a mixed key
+    fn positions(&self, key: NeedleId) -> impl Iterator<Item = u64> + '_ {
+        let hash = mix(key);
+        let (h1, h2) = (hash & 0xFFFF_FFFF, (hash >> 32) | 1);
+        (0..HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) & self.mask)
+    }
+
+    pub fn insert(&self, key: NeedleId) {
+        for bit in self.positions(key) {
+            self.bits[(bit / 64) as usize].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+        self.len.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// false if `key` was never inserted, true if it may have been
+    pub fn may_contain(&self, key: NeedleId) -> bool {
+        self.positions(key).all(|bit| {
+            self.bits[(bit / 64) as usize].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0
+        })
+    }
+
+    /// more keys were inserted than the filter is sized for, false positives grow from now on
+    pub fn is_full(&self) -> bool {
+        self.len.load(Ordering::Relaxed) > self.capacity
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// splitmix64 finalizer, needle ids are mostly sequential
+fn mix(key: u64) -> u64 {
+    let mut z = key.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::needle::bloom::{BloomFilter, MIN_CAPACITY};
+
+    #[test]
+    fn test_bloom_filter() {
+        let filter = BloomFilter::with_capacity(0);
+        assert_eq!(filter.capacity(), MIN_CAPACITY);
+        for key in 0..MIN_CAPACITY as u64 {
+            filter.insert(key);
+        }
+        // no false negatives
+        assert!((0..MIN_CAPACITY as u64).all(|key| filter.may_contain(key)));
+        assert!(!filter.is_full());
+
+        let false_positives = (1u64 << 40..(1 << 40) + 100_000)
+            .filter(|key| filter.may_contain(*key))
+            .count();
+        assert!(false_positives < 2_000, "{false_positives} false positives");
+
+        filter.insert(u64::MAX);
+        assert!(filter.is_full());
+    }
+}
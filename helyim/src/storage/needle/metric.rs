@@ -1,9 +1,13 @@
 use std::sync::atomic::{AtomicU64, Ordering};
 
-use crate::storage::{types::Size, NeedleId};
+use crate::{
+    sequence::is_path_key,
+    storage::{types::Size, NeedleId},
+};
 
-#[derive(Default)]
 pub struct Metric {
+    /// needle alignment of the volume, the bytes of a needle include its padding
+    alignment: u32,
     max_file_key: AtomicU64,
     file_count: AtomicU64,
     deleted_count: AtomicU64,
@@ -12,6 +16,17 @@ pub struct Metric {
 }
 
 impl Metric {
+    pub fn new(alignment: u32) -> Self {
+        Self {
+            alignment,
+            max_file_key: AtomicU64::new(0),
+            file_count: AtomicU64::new(0),
+            deleted_count: AtomicU64::new(0),
+            deleted_bytes: AtomicU64::new(0),
+            file_bytes: AtomicU64::new(0),
+        }
+    }
+
     pub fn file_count(&self) -> u64 {
         self.file_count.load(Ordering::Relaxed)
     }
@@ -28,8 +43,9 @@ impl Metric {
         self.max_file_key.load(Ordering::Relaxed)
     }
 
+    /// keys derived from paths are left out, the max only tracks the keys of the sequencer
     pub fn maybe_max_file_key(&self, key: u64) {
-        if key > self.max_file_key() {
+        if !is_path_key(key) && key > self.max_file_key() {
             self.max_file_key.store(key, Ordering::Relaxed);
         }
     }
@@ -41,12 +57,12 @@ impl Metric {
     pub fn add_file(&self, size: Size) {
         self.file_count.fetch_add(1, Ordering::Relaxed);
         self.file_bytes
-            .fetch_add(size.actual_size(), Ordering::Relaxed);
+            .fetch_add(size.actual_size(self.alignment), Ordering::Relaxed);
     }
 
     pub fn delete_file(&self, size: Size) {
         self.deleted_count.fetch_add(1, Ordering::Relaxed);
         self.deleted_bytes
-            .fetch_add(size.actual_size(), Ordering::Relaxed);
+            .fetch_add(size.actual_size(self.alignment), Ordering::Relaxed);
     }
 }
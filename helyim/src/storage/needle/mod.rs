@@ -3,34 +3,44 @@
 use std::{
     fmt::{Display, Formatter},
     fs::File,
-    os::unix::fs::FileExt,
+    io::{self, ErrorKind, IoSlice},
 };
 
 use bytes::{Buf, BufMut, Bytes};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error};
 
-use crate::storage::{
-    crc,
-    ttl::Ttl,
-    types::{Cookie, Offset, Size},
-    version::{Version, CURRENT_VERSION, VERSION2},
-    NeedleId, VolumeId,
+use crate::{
+    errors::ErrorCode,
+    storage::{
+        crc,
+        ttl::Ttl,
+        types::{Cookie, Offset, Size},
+        version::{Version, CURRENT_VERSION, VERSION2, VERSION3},
+        NeedleId, VolumeId,
+    },
 };
 
+mod bloom;
+
 mod metric;
 
 mod needle_map;
-pub use needle_map::{read_index_entry, walk_index_file, NeedleMapType, NeedleMapper};
+pub use needle_map::{
+    compact_index_file, read_index_entry, walk_index_file, IndexCompaction, NeedleMapType,
+    NeedleMapper,
+};
 
 mod needle_value_map;
 pub use needle_value_map::{MemoryNeedleValueMap, NeedleValueMap, SortedIndexMap};
 
-use crate::storage::ttl::TtlError;
+use crate::{storage::ttl::TtlError, util::file::FileExt};
 
 pub const TOMBSTONE_FILE_SIZE: i32 = -1;
 pub const NEEDLE_HEADER_SIZE: u32 = 16;
+/// default alignment of the needles in a data file, a volume may be created with a larger one
 pub const NEEDLE_PADDING_SIZE: u32 = 8;
+pub const MAX_NEEDLE_ALIGNMENT: u32 = 64;
 pub const NEEDLE_ID_SIZE: u32 = 8;
 pub const OFFSET_SIZE: u32 = 4;
 pub const SIZE_SIZE: u32 = 4;
@@ -38,7 +48,6 @@ pub const TIMESTAMP_SIZE: u32 = 8;
 pub const NEEDLE_ENTRY_SIZE: u32 = NEEDLE_ID_SIZE + OFFSET_SIZE + SIZE_SIZE;
 pub const NEEDLE_CHECKSUM_SIZE: u32 = 4;
 pub const NEEDLE_INDEX_SIZE: u32 = 16;
-pub const MAX_POSSIBLE_VOLUME_SIZE: u64 = 4 * 1024 * 1024 * 1024 * 8;
 pub const PAIR_NAME_PREFIX: &str = "helyim-";
 pub const FLAG_GZIP: u8 = 0x01;
 pub const FLAG_HAS_NAME: u8 = 0x02;
@@ -50,18 +59,43 @@ pub const FLAG_IS_DELETE: u8 = 0x40;
 pub const FLAG_IS_CHUNK_MANIFEST: u8 = 0x80;
 
 pub const LAST_MODIFIED_BYTES_LENGTH: usize = 8;
+/// SeaweedFS keeps the seconds of the last modified time in 5 bytes
+pub const SEAWEEDFS_LAST_MODIFIED_BYTES_LENGTH: usize = 5;
 pub const TTL_BYTES_LENGTH: usize = 2;
 
 pub const NEEDLE_FLAG_OFFSET: usize = 20;
 pub const NEEDLE_ID_OFFSET: usize = 4;
 pub const NEEDLE_SIZE_OFFSET: usize = 12;
 
+/// the largest data file the 32 bits offsets of the index can address
+pub fn max_volume_size(alignment: u32) -> u64 {
+    (u32::MAX as u64 + 1) * alignment as u64
+}
+
+/// length of the last modified time in the needles of `version`
+fn last_modified_len(version: Version) -> usize {
+    if version == VERSION3 {
+        SEAWEEDFS_LAST_MODIFIED_BYTES_LENGTH
+    } else {
+        LAST_MODIFIED_BYTES_LENGTH
+    }
+}
+
+/// length of the append timestamp a needle of `version` has after its checksum
+pub fn timestamp_len(version: Version) -> u32 {
+    if version == VERSION3 {
+        TIMESTAMP_SIZE
+    } else {
+        0
+    }
+}
+
 /// Needle index
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct NeedleValue {
     /// needle offset
     ///
-    /// in data file, the real offset is `offset * NEEDLE_PADDING_SIZE`
+    /// in data file, the real offset is `offset * alignment` of the volume
     pub offset: Offset,
     /// needle data size
     pub size: Size,
@@ -92,11 +126,12 @@ impl leapfrog::Value for NeedleValue {
 }
 
 impl NeedleValue {
+    pub fn new(offset: Offset, size: Size) -> Self {
+        Self { offset, size }
+    }
+
     pub fn deleted() -> Self {
-        Self {
-            offset: Offset(0),
-            size: Size(-1),
-        }
+        Self::new(Offset(0), Size(-1))
     }
     pub fn as_bytes(&self, needle_id: NeedleId) -> Vec<u8> {
         let mut buf = vec![];
@@ -148,11 +183,16 @@ impl Display for Needle {
     }
 }
 
-pub fn read_needle_blob(file: &File, offset: Offset, size: Size) -> Result<Bytes, NeedleError> {
-    let size = size.actual_size();
+pub fn read_needle_blob(
+    file: &File,
+    offset: Offset,
+    size: Size,
+    alignment: u32,
+) -> Result<Bytes, NeedleError> {
+    let size = size.actual_size(alignment);
     let mut buf = vec![0; size as usize];
 
-    let offset = offset.actual_offset();
+    let offset = offset.actual_offset(alignment);
     file.read_exact_at(&mut buf, offset)?;
     Ok(Bytes::from(buf))
 }
@@ -206,27 +246,39 @@ impl Needle {
             return Ok(());
         }
         match version {
-            VERSION2 => {
+            VERSION2 | VERSION3 => {
                 let mut buf = vec![0u8; body_len as usize];
                 data_file.read_exact_at(&mut buf, offset)?;
-                self.read_needle_data(Bytes::from(buf))?;
-                self.checksum = crc::checksum(&self.data);
+                self.read_needle_data(Bytes::from(buf), version)?;
+                self.checksum = crc::needle_checksum(version, &self.data);
             }
             n => return Err(NeedleError::UnsupportedVersion(n)),
         }
         Ok(())
     }
 
-    pub fn read_needle_data(&mut self, bytes: Bytes) -> Result<(), NeedleError> {
+    pub fn read_needle_data(&mut self, bytes: Bytes, version: Version) -> Result<(), NeedleError> {
         let mut idx = 0;
         let len = bytes.len();
 
         if idx < len {
-            self.data_size = (&bytes[idx..idx + 4]).get_u32();
+            self.data_size = slice_at(&bytes, idx, 4)?.get_u32();
             idx += 4;
 
-            self.data = bytes.slice(idx..idx + self.data_size as usize);
+            self.data = slice_at(&bytes, idx, self.data_size as usize)?;
             idx += self.data_size as usize;
+            self.read_needle_meta(bytes.slice(idx..), version)?;
+        }
+
+        Ok(())
+    }
+
+    /// parse the fields following the needle data, starts with flags
+    pub fn read_needle_meta(&mut self, bytes: Bytes, version: Version) -> Result<(), NeedleError> {
+        let mut idx = 0;
+        let len = bytes.len();
+
+        if idx < len {
             self.flags = bytes[idx];
             idx += 1;
         }
@@ -234,41 +286,44 @@ impl Needle {
         if idx < len && self.has_name() {
             self.name_size = bytes[idx];
             idx += 1;
-            self.name = bytes.slice(idx..idx + self.name_size as usize);
+            self.name = slice_at(&bytes, idx, self.name_size as usize)?;
             idx += self.name_size as usize;
         }
 
         if idx < len && self.has_mime() {
             self.mime_size = bytes[idx];
             idx += 1;
-            self.mime = bytes.slice(idx..idx + self.mime_size as usize);
+            self.mime = slice_at(&bytes, idx, self.mime_size as usize)?;
             idx += self.mime_size as usize;
         }
 
         if idx < len && self.has_last_modified_date() {
-            self.last_modified = (&bytes[idx..idx + LAST_MODIFIED_BYTES_LENGTH]).get_u64();
-            idx += LAST_MODIFIED_BYTES_LENGTH;
+            let last_modified_len = last_modified_len(version);
+            self.last_modified =
+                slice_at(&bytes, idx, last_modified_len)?.get_uint(last_modified_len);
+            idx += last_modified_len;
         }
 
         if idx < len && self.has_ttl() {
-            self.ttl = Ttl::from_bytes(&bytes[idx..idx + TTL_BYTES_LENGTH])?;
+            self.ttl = Ttl::from_bytes(&slice_at(&bytes, idx, TTL_BYTES_LENGTH)?)?;
             idx += TTL_BYTES_LENGTH;
         }
 
         if idx < len && self.has_pairs() {
-            self.pairs_size = (&bytes[idx..idx + 2]).get_u16();
+            self.pairs_size = slice_at(&bytes, idx, 2)?.get_u16();
             idx += 2;
-            self.pairs = bytes.slice(idx..idx + self.pairs_size as usize);
+            self.pairs = slice_at(&bytes, idx, self.pairs_size as usize)?;
         }
 
         Ok(())
     }
 
-    pub fn append<W: FileExt>(
+    pub fn append(
         &mut self,
-        w: &W,
+        w: &File,
         offset: u64,
         version: Version,
+        alignment: u32,
     ) -> Result<(), NeedleError> {
         if version != CURRENT_VERSION {
             return Err(NeedleError::UnsupportedVersion(version));
@@ -280,92 +335,152 @@ impl Needle {
         self.pairs_size = self.pairs.len() as u16;
         self.size = Size(0);
 
-        let mut buf = vec![];
-        buf.put_u32(self.cookie);
-        buf.put_u64(self.id);
-        buf.put_i32(self.size.0);
-
+        // header, data and the following fields are submitted as separate buffers, so the data
+        // does not need to be copied into a temporary buffer.
+        let mut tail = Vec::with_capacity(self.tail_capacity(alignment));
         if self.data_size > 0 {
-            buf.put_u32(self.data_size);
-            buf.put_slice(&self.data);
-            buf.put_u8(self.flags);
+            tail.put_u8(self.flags);
             self.size.0 = 4 + self.data_size as i32 + 1; // one for flag;
             if self.has_name() {
-                buf.put_u8(self.name_size);
-                buf.put_slice(&self.name);
+                tail.put_u8(self.name_size);
+                tail.put_slice(&self.name);
                 self.size.0 += 1 + self.name_size as i32;
             }
             if self.has_mime() {
-                buf.put_u8(self.mime_size);
-                buf.put_slice(&self.mime);
+                tail.put_u8(self.mime_size);
+                tail.put_slice(&self.mime);
                 self.size.0 += 1 + self.mime_size as i32;
             }
             if self.has_last_modified_date() {
-                buf.put_u64(self.last_modified);
+                tail.put_u64(self.last_modified);
                 self.size.0 += LAST_MODIFIED_BYTES_LENGTH as i32;
             }
             if self.has_ttl() {
-                buf.put_slice(&self.ttl.as_bytes());
+                tail.put_slice(&self.ttl.as_bytes());
                 self.size.0 += TTL_BYTES_LENGTH as i32;
             }
             if self.has_pairs() {
-                buf.put_u16(self.pairs.len() as u16);
-                buf.put_slice(&self.pairs);
+                tail.put_u16(self.pairs.len() as u16);
+                tail.put_slice(&self.pairs);
                 self.size.0 += 2 + self.pairs.len() as i32;
             }
         }
-        if self.size > 0 {
-            (&mut buf[12..16]).put_u32(self.size.0 as u32);
-        }
+        tail.put_u32(self.checksum);
+        tail.put_bytes(0, self.size.padding_len(alignment) as usize);
 
-        buf.put_u32(self.checksum);
+        let mut header = Vec::with_capacity(NEEDLE_HEADER_SIZE as usize + 4);
+        header.put_u32(self.cookie);
+        header.put_u64(self.id);
+        header.put_i32(self.size.0);
+        if self.data_size > 0 {
+            header.put_u32(self.data_size);
+        }
 
-        let padding = self.size.padding_len();
-        buf.put_slice(&vec![0; padding as usize]);
-        w.write_all_at(&buf, offset)?;
+        let mut bufs = [
+            IoSlice::new(&header),
+            IoSlice::new(&self.data),
+            IoSlice::new(&tail),
+        ];
+        write_all_vectored_at(w, &mut bufs, offset)?;
 
         Ok(())
     }
 
+    /// upper bound of the fields following the data, including checksum and padding
+    fn tail_capacity(&self, alignment: u32) -> usize {
+        let fields = 1 + (1 + self.name.len()) + (1 + self.mime.len()) + (2 + self.pairs.len());
+        fields
+            + LAST_MODIFIED_BYTES_LENGTH
+            + TTL_BYTES_LENGTH
+            + (NEEDLE_CHECKSUM_SIZE + alignment) as usize
+    }
+
     pub fn read_bytes(
         &mut self,
         bytes: Bytes,
-        offset: Offset,
         size: Size,
         version: Version,
     ) -> Result<(), NeedleError> {
-        self.parse_needle_header(&bytes);
+        self.parse_needle_header(&slice_at(&bytes, 0, NEEDLE_HEADER_SIZE as usize)?);
 
-        if self.size != size && offset.actual_offset() < MAX_POSSIBLE_VOLUME_SIZE {
+        if self.size != size {
             return Err(NeedleError::SizeNotMatch(self.size, size));
         }
 
-        if version == VERSION2 {
-            let end = NEEDLE_HEADER_SIZE + self.size.0 as u32;
-            self.read_needle_data(bytes.slice(NEEDLE_HEADER_SIZE as usize..end as usize))?;
+        if version == VERSION2 || version == VERSION3 {
+            let body = slice_at(
+                &bytes,
+                NEEDLE_HEADER_SIZE as usize,
+                self.size.0 as u32 as usize,
+            )?;
+            self.read_needle_data(body, version)?;
         }
 
-        let checksum_start = NEEDLE_HEADER_SIZE + size.0 as u32;
-        let checksum_end = (NEEDLE_HEADER_SIZE + size.0 as u32 + NEEDLE_CHECKSUM_SIZE) as usize;
-        self.checksum = (&bytes[checksum_start as usize..checksum_end]).get_u32();
-        let checksum = crc::checksum(&self.data);
-
-        if self.checksum != checksum {
+        let checksum_start = NEEDLE_HEADER_SIZE as usize + size.0 as u32 as usize;
+        self.checksum = slice_at(&bytes, checksum_start, NEEDLE_CHECKSUM_SIZE as usize)?.get_u32();
+        let checksum = crc::needle_checksum(version, &self.data);
+        if !crc::checksum_matches(version, self.checksum, checksum) {
             return Err(NeedleError::Crc(self.checksum, checksum));
         }
 
         Ok(())
     }
 
+    /// Read needle header and the fields following the data, but leave the data on disk.
+    ///
+    /// Returns the actual offset of the needle data in data file.
+    pub fn read_meta(
+        &mut self,
+        file: &File,
+        offset: Offset,
+        size: Size,
+        version: Version,
+        alignment: u32,
+    ) -> Result<u64, NeedleError> {
+        if version != VERSION2 && version != VERSION3 {
+            return Err(NeedleError::UnsupportedVersion(version));
+        }
+
+        let actual_offset = offset.actual_offset(alignment);
+        let mut header = [0u8; NEEDLE_HEADER_SIZE as usize + 4];
+        file.read_exact_at(&mut header, actual_offset)?;
+        self.parse_needle_header(&header);
+        if self.size != size {
+            return Err(NeedleError::SizeNotMatch(self.size, size));
+        }
+
+        let data_offset = actual_offset + NEEDLE_HEADER_SIZE as u64 + 4;
+        if size.0 == 0 {
+            self.data_size = 0;
+            return Ok(data_offset);
+        }
+        self.data_size = (&header[NEEDLE_HEADER_SIZE as usize..]).get_u32();
+
+        let meta_len = (size.0 as usize)
+            .checked_sub(4 + self.data_size as usize)
+            .ok_or(NeedleError::Truncated(
+                4 + self.data_size as usize,
+                size.0 as usize,
+            ))?;
+        let mut meta = vec![0u8; meta_len + NEEDLE_CHECKSUM_SIZE as usize];
+        file.read_exact_at(&mut meta, data_offset + self.data_size as u64)?;
+        let meta = Bytes::from(meta);
+        self.read_needle_meta(meta.slice(..meta_len), version)?;
+        self.checksum = (&meta[meta_len..]).get_u32();
+
+        Ok(data_offset)
+    }
+
     pub fn read_data(
         &mut self,
         file: &File,
         offset: Offset,
         size: Size,
         version: Version,
+        alignment: u32,
     ) -> Result<(), NeedleError> {
-        let bytes = read_needle_blob(file, offset, size)?;
-        self.read_bytes(bytes, offset, size, version)
+        let bytes = read_needle_blob(file, offset, size, alignment)?;
+        self.read_bytes(bytes, size, version)
     }
 
     pub fn has_ttl(&self) -> bool {
@@ -433,21 +548,21 @@ impl Needle {
     }
 
     pub fn etag(&self) -> String {
-        let mut buf: Vec<u8> = vec![0; 4];
+        let mut buf: Vec<u8> = Vec::with_capacity(4);
         buf.put_u32(self.checksum);
         format!("{}{}{}{}", buf[0], buf[1], buf[2], buf[3])
     }
 
-    pub fn disk_size(&self) -> u64 {
-        self.size.actual_size()
+    pub fn disk_size(&self, alignment: u32) -> u64 {
+        self.size.actual_size(alignment)
     }
 
     pub fn data_size(&self) -> usize {
         self.data.len()
     }
 
-    pub fn body_len(&self) -> u32 {
-        let padding = self.size.padding_len();
+    pub fn body_len(&self, alignment: u32) -> u32 {
+        let padding = self.size.padding_len(alignment);
         self.size.0 as u32 + NEEDLE_CHECKSUM_SIZE + padding
     }
 }
@@ -478,12 +593,61 @@ pub enum NeedleError {
     UnsupportedVersion(Version),
     #[error("Crc error, read: {0}, calculate: {1}, may be data on disk corrupted")]
     Crc(u32, u32),
+    #[error("{0} not match, expected {1} but got {2}")]
+    ContentChecksumMismatch(String, String, String),
+    #[error("Precondition failed, if-match: {0}, current etag: {1:?}")]
+    PreconditionFailed(String, Option<String>),
+    #[error("Needle {0} exists with other content, write with `op=replace` to overwrite it")]
+    Exists(NeedleId),
+    #[error("Needle is truncated, {0} bytes expected but only {1} bytes found")]
+    Truncated(usize, usize),
     #[error("Invalid file id: {0}")]
     InvalidFid(String),
     #[error("key hash: {0} is too short or too long")]
     InvalidKeyHash(String),
 }
 
+impl NeedleError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            NeedleError::NotFound(_) => ErrorCode::NeedleNotFound,
+            NeedleError::Deleted(..) => ErrorCode::NeedleDeleted,
+            NeedleError::Expired(..) => ErrorCode::NeedleExpired,
+            NeedleError::CookieNotMatch(..) => ErrorCode::CookieMismatch,
+            NeedleError::ContentChecksumMismatch(..) => ErrorCode::ChecksumMismatch,
+            NeedleError::PreconditionFailed(..) => ErrorCode::PreconditionFailed,
+            NeedleError::Exists(_) => ErrorCode::Conflict,
+            NeedleError::Crc(..) | NeedleError::SizeNotMatch(..) | NeedleError::Truncated(..) => {
+                ErrorCode::DataCorrupted
+            }
+            NeedleError::Io(_) | NeedleError::Box(_) | NeedleError::UnsupportedVersion(_) => {
+                ErrorCode::Internal
+            }
+            NeedleError::ParseIntError(_)
+            | NeedleError::Ttl(_)
+            | NeedleError::InvalidFid(_)
+            | NeedleError::InvalidKeyHash(_) => ErrorCode::BadRequest,
+        }
+    }
+
+    pub fn volume_id(&self) -> Option<VolumeId> {
+        match self {
+            NeedleError::Deleted(vid, _) | NeedleError::Expired(vid, _) => Some(*vid),
+            _ => None,
+        }
+    }
+
+    pub fn needle_id(&self) -> Option<NeedleId> {
+        match self {
+            NeedleError::Deleted(_, nid)
+            | NeedleError::Expired(_, nid)
+            | NeedleError::NotFound(nid)
+            | NeedleError::Exists(nid) => Some(*nid),
+            _ => None,
+        }
+    }
+}
+
 impl From<NeedleError> for tonic::Status {
     fn from(value: NeedleError) -> Self {
         tonic::Status::internal(value.to_string())
@@ -491,7 +655,7 @@ impl From<NeedleError> for tonic::Status {
 }
 
 fn parse_key_hash(hash: &str) -> Result<(NeedleId, Cookie), NeedleError> {
-    if hash.len() <= 8 || hash.len() > 24 {
+    if hash.len() <= 8 || hash.len() > 24 || !hash.is_ascii() {
         return Err(NeedleError::InvalidKeyHash(hash.to_string()));
     }
 
@@ -503,19 +667,72 @@ fn parse_key_hash(hash: &str) -> Result<(NeedleId, Cookie), NeedleError> {
     Ok((key, cookie))
 }
 
+/// the `len` bytes at `idx`, a length read from a corrupted needle fails instead of panicking
+fn slice_at(bytes: &Bytes, idx: usize, len: usize) -> Result<Bytes, NeedleError> {
+    match idx.checked_add(len) {
+        Some(end) if end <= bytes.len() => Ok(bytes.slice(idx..end)),
+        _ => Err(NeedleError::Truncated(idx.saturating_add(len), bytes.len())),
+    }
+}
+
+/// write all buffers at `offset` with `pwritev`, which takes one syscall in most cases
+#[cfg(unix)]
+fn write_all_vectored_at(
+    w: &File,
+    mut bufs: &mut [IoSlice<'_>],
+    mut offset: u64,
+) -> Result<(), NeedleError> {
+    use rustix::io::{pwritev, Errno};
+
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match pwritev(w, bufs, offset) {
+            Ok(0) => {
+                return Err(NeedleError::Io(io::Error::new(
+                    ErrorKind::WriteZero,
+                    "failed to write whole needle",
+                )));
+            }
+            Ok(n) => {
+                offset += n as u64;
+                IoSlice::advance_slices(&mut bufs, n);
+            }
+            Err(Errno::INTR) => continue,
+            Err(err) => return Err(NeedleError::Io(err.into())),
+        }
+    }
+    Ok(())
+}
+
+/// write the buffers one after another, windows has no positioned vectored write
+#[cfg(not(unix))]
+fn write_all_vectored_at(
+    w: &File,
+    bufs: &mut [IoSlice<'_>],
+    mut offset: u64,
+) -> Result<(), NeedleError> {
+    for buf in bufs.iter() {
+        w.write_all_at(buf, offset)?;
+        offset += buf.len() as u64;
+    }
+    Ok(())
+}
+
 pub fn read_needle_header(
     file: &File,
     version: Version,
     offset: u64,
+    alignment: u32,
 ) -> Result<(Needle, u32), NeedleError> {
     let mut needle = Needle::default();
     let mut body_len = 0;
 
-    if version == VERSION2 {
+    if version == VERSION2 || version == VERSION3 {
         let mut buf = vec![0u8; NEEDLE_ENTRY_SIZE as usize];
         file.read_exact_at(&mut buf, offset)?;
         needle.parse_needle_header(&buf);
-        body_len = needle.body_len();
+        // the padding does not change, the timestamp is a multiple of the alignment
+        body_len = needle.body_len(alignment) + timestamp_len(version);
     }
 
     Ok((needle, body_len))
@@ -523,7 +740,87 @@ pub fn read_needle_header(
 
 #[cfg(test)]
 mod tests {
-    use crate::storage::needle::parse_key_hash;
+    use bytes::Bytes;
+    use tempfile::tempfile;
+
+    use crate::storage::{
+        crc,
+        needle::{parse_key_hash, Needle, NEEDLE_PADDING_SIZE},
+        types::{Offset, Size},
+        CURRENT_VERSION,
+    };
+
+    #[test]
+    pub fn test_append_and_read_needle() {
+        let file = tempfile().unwrap();
+        let data = Bytes::from_static(b"Hello Helyim");
+        let mut needle = Needle {
+            id: 0x4ed4,
+            cookie: 0xc8116e41,
+            checksum: crc::checksum(&data),
+            data,
+            name: Bytes::from_static(b"hello.txt"),
+            mime: Bytes::from_static(b"text/plain"),
+            pairs: Bytes::from_static(br#"{"helyim-a":"b"}"#),
+            last_modified: 1700000000,
+            ..Default::default()
+        };
+        needle.set_name();
+        needle.set_has_mime();
+        needle.set_has_pairs();
+        needle.set_has_last_modified_date();
+        needle
+            .append(&file, 0, CURRENT_VERSION, NEEDLE_PADDING_SIZE)
+            .unwrap();
+        assert_eq!(
+            file.metadata().unwrap().len(),
+            needle.disk_size(NEEDLE_PADDING_SIZE)
+        );
+
+        let mut read = Needle::default();
+        read.read_data(
+            &file,
+            Offset(0),
+            needle.size,
+            CURRENT_VERSION,
+            NEEDLE_PADDING_SIZE,
+        )
+        .unwrap();
+        assert_eq!(read.id, needle.id);
+        assert_eq!(read.cookie, needle.cookie);
+        assert_eq!(read.data, needle.data);
+        assert_eq!(read.name, needle.name);
+        assert_eq!(read.mime, needle.mime);
+        assert_eq!(read.pairs, needle.pairs);
+        assert_eq!(read.last_modified, needle.last_modified);
+
+        let mut meta = Needle::default();
+        let data_offset = meta
+            .read_meta(
+                &file,
+                Offset(0),
+                needle.size,
+                CURRENT_VERSION,
+                NEEDLE_PADDING_SIZE,
+            )
+            .unwrap();
+        assert_eq!(data_offset, 20);
+        assert!(meta.data.is_empty());
+        assert_eq!(meta.data_size as usize, needle.data_size());
+        assert_eq!(meta.name, needle.name);
+        assert_eq!(meta.checksum, needle.checksum);
+
+        let mut mismatch = Needle::default();
+        assert!(mismatch
+            .read_meta(
+                &file,
+                Offset(0),
+                Size(1),
+                CURRENT_VERSION,
+                NEEDLE_PADDING_SIZE
+            )
+            .is_err());
+    }
 
     #[test]
     pub fn test_parse_key_hash() {
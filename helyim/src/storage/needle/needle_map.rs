@@ -1,19 +1,30 @@
 use std::{
-    fs::File,
-    io::{BufReader, Read},
-    os::unix::fs::FileExt,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
     sync::Arc,
 };
 
 use bytes::Buf;
+use indexmap::IndexMap;
+use parking_lot::{RwLock, RwLockReadGuard};
+use serde::Serialize;
 use tracing::{debug, error};
 
-use crate::storage::{
-    needle::{metric::Metric, MemoryNeedleValueMap, NeedleValue, NeedleValueMap},
-    types::{Offset, Size},
-    NeedleError, NeedleId, VolumeError, VolumeId,
+use crate::{
+    storage::{
+        needle::{
+            bloom::BloomFilter, metric::Metric, MemoryNeedleValueMap, NeedleValue, NeedleValueMap,
+            NEEDLE_INDEX_SIZE, NEEDLE_PADDING_SIZE,
+        },
+        types::{Offset, Size},
+        NeedleError, NeedleId, VolumeError, VolumeId,
+    },
+    util::file::{FileExt, OpenOptionsExt},
 };
 
+/// max index entries applied to the needle map at once when an index file is replayed
+const INDEX_REPLAY_BATCH_SIZE: usize = 1024;
+
 #[derive(Copy, Clone, Debug, Default)]
 pub enum NeedleMapType {
     #[default]
@@ -25,6 +36,10 @@ pub struct NeedleMapper {
     needle_value_map: Box<dyn NeedleValueMap>,
     index_file: Option<File>,
     metric: Arc<Metric>,
+    /// keys ever set, reads of other keys are not found without a lookup
+    bloom_filter: Option<RwLock<BloomFilter>>,
+    /// build the bloom filter when the index file is loaded
+    read_bloom_filter: bool,
 }
 
 impl Default for NeedleMapper {
@@ -32,43 +47,129 @@ impl Default for NeedleMapper {
         NeedleMapper {
             volume_id: 0,
             needle_value_map: Box::new(MemoryNeedleValueMap::new()),
-            metric: Arc::new(Metric::default()),
+            metric: Arc::new(Metric::new(NEEDLE_PADDING_SIZE)),
             index_file: None,
+            bloom_filter: None,
+            read_bloom_filter: false,
         }
     }
 }
 
 impl NeedleMapper {
-    pub fn new(volume_id: VolumeId, kind: NeedleMapType) -> NeedleMapper {
+    pub fn new(volume_id: VolumeId, kind: NeedleMapType, alignment: u32) -> NeedleMapper {
         #[allow(unreachable_patterns)]
         match kind {
             NeedleMapType::NeedleMapInMemory => NeedleMapper {
                 needle_value_map: Box::new(MemoryNeedleValueMap::new()),
                 volume_id,
+                metric: Arc::new(Metric::new(alignment)),
                 ..Default::default()
             },
             _ => panic!("not support map type: {:?}", kind),
         }
     }
 
+    pub fn with_bloom_filter(mut self, enabled: bool) -> Self {
+        self.read_bloom_filter = enabled;
+        self
+    }
+
+    /// build the bloom filter of the loaded keys, it is kept up to date from now on
+    pub fn enable_bloom_filter(&mut self) {
+        self.read_bloom_filter = true;
+        let filter = self.build_bloom_filter(self.file_count() as usize * 2);
+        self.bloom_filter = Some(RwLock::new(filter));
+    }
+
     pub fn load_index_file(&mut self, mut index_file: File) -> Result<(), VolumeError> {
+        // consecutive puts and deletes are replayed in batches, switching between them flushes
+        // the pending batch so the replay order is kept
+        let mut puts = Vec::with_capacity(INDEX_REPLAY_BATCH_SIZE);
+        let mut deletes = Vec::with_capacity(INDEX_REPLAY_BATCH_SIZE);
         walk_index_file(
             &mut index_file,
             |key, offset, size| -> Result<(), NeedleError> {
                 if offset == 0 || size.is_deleted() {
-                    self.delete(key)
-                        .map_err(|err| NeedleError::Box(err.into()))?;
+                    if !puts.is_empty() {
+                        self.set_batch(&puts)
+                            .map_err(|err| NeedleError::Box(err.into()))?;
+                        puts.clear();
+                    }
+                    deletes.push(key);
+                    if deletes.len() >= INDEX_REPLAY_BATCH_SIZE {
+                        self.delete_batch(&deletes)
+                            .map_err(|err| NeedleError::Box(err.into()))?;
+                        deletes.clear();
+                    }
                 } else {
-                    self.set(key, NeedleValue { offset, size })
-                        .map_err(|err| NeedleError::Box(err.into()))?;
+                    if !deletes.is_empty() {
+                        self.delete_batch(&deletes)
+                            .map_err(|err| NeedleError::Box(err.into()))?;
+                        deletes.clear();
+                    }
+                    puts.push((key, NeedleValue::new(offset, size)));
+                    if puts.len() >= INDEX_REPLAY_BATCH_SIZE {
+                        self.set_batch(&puts)
+                            .map_err(|err| NeedleError::Box(err.into()))?;
+                        puts.clear();
+                    }
                 }
                 Ok(())
             },
         )?;
+        self.set_batch(&puts)?;
+        self.delete_batch(&deletes)?;
         self.index_file = Some(index_file);
+        if self.read_bloom_filter {
+            self.enable_bloom_filter();
+        }
         Ok(())
     }
 
+    fn build_bloom_filter(&self, capacity: usize) -> BloomFilter {
+        let filter = BloomFilter::with_capacity(capacity);
+        for (key, _) in self.iter() {
+            filter.insert(key);
+        }
+        filter
+    }
+
+    /// add `keys` to the bloom filter, the guard is held until they are set in the needle map,
+    /// so the filter is not rebuilt without them
+    fn bloom_insert(&self, keys: &[NeedleId]) -> Option<RwLockReadGuard<'_, BloomFilter>> {
+        let filter = self.bloom_filter.as_ref()?.read();
+        for key in keys {
+            filter.insert(*key);
+        }
+        Some(filter)
+    }
+
+    /// double a bloom filter holding more keys than it is sized for
+    fn grow_bloom_filter(&self) {
+        let Some(filter) = self.bloom_filter.as_ref() else {
+            return;
+        };
+        if !filter.read().is_full() {
+            return;
+        }
+        let mut filter = filter.write();
+        if filter.is_full() {
+            *filter = self.build_bloom_filter(filter.capacity() * 2);
+            debug!(
+                "volume {} bloom filter grows to {} keys",
+                self.volume_id,
+                filter.capacity()
+            );
+        }
+    }
+
+    /// false if `key` was never set, always true without a bloom filter
+    pub fn may_contain(&self, key: NeedleId) -> bool {
+        self.bloom_filter
+            .as_ref()
+            .map_or(true, |filter| filter.read().may_contain(key))
+    }
+
     pub fn set(
         &self,
         key: NeedleId,
@@ -79,7 +180,10 @@ impl NeedleMapper {
         self.metric.maybe_max_file_key(key);
         self.metric.add_file(index.size);
 
+        let filter = self.bloom_insert(&[key]);
         let old = self.needle_value_map.set(key, index);
+        drop(filter);
+        self.grow_bloom_filter();
         if let Some(n) = old {
             self.metric.delete_file(n.size);
         }
@@ -101,10 +205,66 @@ impl NeedleMapper {
         Ok(deleted)
     }
 
+    /// set a batch of entries, the index entries are appended with a single write
+    pub fn set_batch(&self, entries: &[(NeedleId, NeedleValue)]) -> Result<(), VolumeError> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        for (key, index) in entries {
+            self.metric.maybe_max_file_key(*key);
+            self.metric.add_file(index.size);
+        }
+
+        let keys: Vec<NeedleId> = entries.iter().map(|(key, _)| *key).collect();
+        let filter = self.bloom_insert(&keys);
+        let olds = self.needle_value_map.set_batch(entries);
+        drop(filter);
+        self.grow_bloom_filter();
+        for old in olds.into_iter().flatten() {
+            self.metric.delete_file(old.size);
+        }
+
+        let mut buf = Vec::with_capacity(entries.len() * NEEDLE_INDEX_SIZE as usize);
+        for (key, index) in entries {
+            buf.extend_from_slice(&index.as_bytes(*key));
+        }
+        self.append_bytes_to_index_file(&buf)
+    }
+
+    /// delete a batch of keys, tombstones are appended with a single write
+    pub fn delete_batch(&self, keys: &[NeedleId]) -> Result<(), VolumeError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let deleted = self.needle_value_map.delete_batch(keys);
+
+        let mut buf = vec![];
+        for (key, index) in keys.iter().zip(deleted) {
+            if let Some(index) = index {
+                self.metric.delete_file(index.size);
+                buf.extend_from_slice(&index.as_bytes(*key));
+            }
+        }
+        self.append_bytes_to_index_file(&buf)
+    }
+
     pub fn get(&self, key: NeedleId) -> Option<NeedleValue> {
         self.needle_value_map.get(key)
     }
 
+    /// snapshot of the live needles, in no particular order
+    pub fn iter(&self) -> Vec<(NeedleId, NeedleValue)> {
+        self.needle_value_map.iter()
+    }
+
+    /// visit the live needles in ascending offset order
+    pub fn ascending_visit<F>(&self, mut visit: F) -> Result<(), NeedleError>
+    where
+        F: FnMut(NeedleId, NeedleValue) -> Result<(), NeedleError>,
+    {
+        self.needle_value_map.ascending_visit(&mut visit)
+    }
+
     pub fn file_count(&self) -> u64 {
         self.metric.file_count()
     }
@@ -121,10 +281,18 @@ impl NeedleMapper {
         self.metric.max_file_key()
     }
 
+    pub fn maybe_max_file_key(&self, key: NeedleId) {
+        self.metric.maybe_max_file_key(key);
+    }
+
     pub fn content_size(&self) -> u64 {
         self.metric.file_bytes()
     }
 
+    pub fn index_file(&self) -> Option<&File> {
+        self.index_file.as_ref()
+    }
+
     pub fn index_file_size(&self) -> Result<u64, VolumeError> {
         let size = match self.index_file.as_ref() {
             Some(file) => file.metadata()?.len(),
@@ -138,10 +306,16 @@ impl NeedleMapper {
         key: NeedleId,
         value: NeedleValue,
     ) -> Result<(), VolumeError> {
+        self.append_bytes_to_index_file(&value.as_bytes(key))
+    }
+
+    fn append_bytes_to_index_file(&self, buf: &[u8]) -> Result<(), VolumeError> {
+        if buf.is_empty() {
+            return Ok(());
+        }
         if let Some(file) = self.index_file.as_ref() {
-            let buf = value.as_bytes(key);
             let offset = file.metadata()?.len();
-            if let Err(err) = file.write_all_at(&buf, offset) {
+            if let Err(err) = file.write_all_at(buf, offset) {
                 error!(
                     "failed to write index file, volume {}, error: {err}",
                     self.volume_id
@@ -181,3 +355,124 @@ where
 
     Ok(())
 }
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexCompaction {
+    pub entries_before: u64,
+    pub entries_after: u64,
+}
+
+/// rewrite an index file keeping only the latest entry of live needles, tombstones and superseded
+/// entries are dropped, the entries are written in offset order.
+pub fn compact_index_file(src: &str, dst: &str) -> Result<IndexCompaction, VolumeError> {
+    let mut src = File::open(src)?;
+    let mut entries_before = 0;
+    let mut live = IndexMap::new();
+    walk_index_file(&mut src, |key, offset, size| -> Result<(), NeedleError> {
+        entries_before += 1;
+        if offset == 0 || size.is_deleted() {
+            live.shift_remove(&key);
+        } else {
+            live.insert(key, NeedleValue::new(offset, size));
+        }
+        Ok(())
+    })?;
+    live.sort_by(|_, v1, _, v2| v1.offset.0.cmp(&v2.offset.0));
+
+    let dst = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o644)
+        .open(dst)?;
+    let mut writer = BufWriter::new(&dst);
+    for (key, value) in live.iter() {
+        writer.write_all(&value.as_bytes(*key))?;
+    }
+    writer.flush()?;
+    drop(writer);
+    dst.sync_all()?;
+
+    Ok(IndexCompaction {
+        entries_before,
+        entries_after: live.len() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write};
+
+    use tempfile::Builder;
+
+    use crate::storage::{
+        needle::{
+            bloom::MIN_CAPACITY, needle_map::compact_index_file, walk_index_file, NeedleMapType,
+            NeedleMapper, NeedleValue, NEEDLE_PADDING_SIZE,
+        },
+        types::{Offset, Size},
+        NeedleError,
+    };
+
+    #[test]
+    fn test_bloom_filter_grows() {
+        let index_file = tempfile::tempfile().unwrap();
+        let mut mapper =
+            NeedleMapper::new(1, NeedleMapType::NeedleMapInMemory, NEEDLE_PADDING_SIZE)
+                .with_bloom_filter(true);
+        mapper.load_index_file(index_file).unwrap();
+        assert!(!mapper.may_contain(1));
+
+        let count = MIN_CAPACITY as u64 + 1;
+        let entries: Vec<_> = (1..=count)
+            .map(|key| (key, NeedleValue::new(Offset(key as u32), Size(10))))
+            .collect();
+        mapper.set_batch(&entries).unwrap();
+        assert_eq!(
+            mapper.bloom_filter.as_ref().unwrap().read().capacity(),
+            MIN_CAPACITY * 2
+        );
+        // the rebuilt filter keeps every key, deleted keys stay until the next load
+        assert!((1..=count).all(|key| mapper.may_contain(key)));
+        mapper.delete(1).unwrap();
+        assert!(mapper.may_contain(1));
+    }
+
+    #[test]
+    fn test_compact_index_file() {
+        let dir = Builder::new().prefix("idx").tempdir().unwrap();
+        let src = dir.path().join("1.idx");
+        let dst = dir.path().join("1.idx.compacting");
+
+        let entries = [
+            (1, 1, 10),
+            (2, 2, 10),
+            // overwrite needle 1
+            (1, 3, 12),
+            // delete needle 2
+            (2, 2, -1),
+            (3, 4, 10),
+        ];
+        let mut file = File::create(&src).unwrap();
+        for (key, offset, size) in entries {
+            let value = NeedleValue::new(Offset(offset), Size(size));
+            file.write_all(&value.as_bytes(key)).unwrap();
+        }
+
+        let compaction = compact_index_file(src.to_str().unwrap(), dst.to_str().unwrap()).unwrap();
+        assert_eq!(compaction.entries_before, 5);
+        assert_eq!(compaction.entries_after, 2);
+
+        let mut live = vec![];
+        walk_index_file(
+            &mut File::open(&dst).unwrap(),
+            |key, offset, size| -> Result<(), NeedleError> {
+                live.push((key, offset.0, size.0));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(live, vec![(1, 3, 12), (3, 4, 10)]);
+    }
+}
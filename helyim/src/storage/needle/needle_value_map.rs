@@ -1,17 +1,49 @@
-use std::{fs, os::unix::fs::OpenOptionsExt};
+use std::fs;
 
 use indexmap::IndexMap;
 use leapfrog::LeapMap;
 use parking_lot::RwLock;
 
-use crate::storage::{
-    needle::NeedleValue, types::Size, walk_index_file, NeedleError, NeedleId, VolumeError,
+use crate::{
+    storage::{
+        needle::NeedleValue, types::Size, walk_index_file, NeedleError, NeedleId, VolumeError,
+    },
+    util::file::OpenOptionsExt,
 };
 
 pub trait NeedleValueMap: Send + Sync {
     fn set(&self, key: NeedleId, value: NeedleValue) -> Option<NeedleValue>;
     fn delete(&self, key: NeedleId) -> Option<NeedleValue>;
     fn get(&self, key: NeedleId) -> Option<NeedleValue>;
+
+    /// set a batch of entries, returns the replaced values in the order of `entries`
+    fn set_batch(&self, entries: &[(NeedleId, NeedleValue)]) -> Vec<Option<NeedleValue>> {
+        entries
+            .iter()
+            .map(|(key, value)| self.set(*key, *value))
+            .collect()
+    }
+
+    /// delete a batch of keys, returns the removed values in the order of `keys`
+    fn delete_batch(&self, keys: &[NeedleId]) -> Vec<Option<NeedleValue>> {
+        keys.iter().map(|key| self.delete(*key)).collect()
+    }
+
+    /// snapshot of the live needles, in no particular order
+    fn iter(&self) -> Vec<(NeedleId, NeedleValue)>;
+
+    /// visit the live needles in ascending offset order, stop with the error returned by `visit`
+    fn ascending_visit(
+        &self,
+        visit: &mut dyn FnMut(NeedleId, NeedleValue) -> Result<(), NeedleError>,
+    ) -> Result<(), NeedleError> {
+        let mut needles = self.iter();
+        needles.sort_by_key(|(_, value)| value.offset.0);
+        for (key, value) in needles {
+            visit(key, value)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct MemoryNeedleValueMap {
@@ -47,6 +79,13 @@ impl NeedleValueMap for MemoryNeedleValueMap {
             None => None,
         }
     }
+
+    fn iter(&self) -> Vec<(NeedleId, NeedleValue)> {
+        self.map
+            .iter()
+            .filter_map(|mut item| item.key_value())
+            .collect()
+    }
 }
 
 pub struct SortedIndexMap {
@@ -68,7 +107,7 @@ impl SortedIndexMap {
                 if offset == 0 || size.is_deleted() {
                     nm.delete(needle_id);
                 } else {
-                    nm.set(needle_id, NeedleValue { offset, size });
+                    nm.set(needle_id, NeedleValue::new(offset, size));
                 }
                 Ok(())
             },
@@ -76,7 +115,8 @@ impl SortedIndexMap {
         Ok(nm)
     }
 
-    pub fn ascending_visit<F>(&self, mut visit: F) -> Result<(), NeedleError>
+    /// visit the needles in ascending key order
+    pub fn key_ascending_visit<F>(&self, mut visit: F) -> Result<(), NeedleError>
     where
         F: FnMut(&NeedleId, &NeedleValue) -> Result<(), NeedleError>,
     {
@@ -100,4 +140,73 @@ impl NeedleValueMap for SortedIndexMap {
     fn get(&self, key: NeedleId) -> Option<NeedleValue> {
         self.map.read().get(&key).copied()
     }
+
+    fn set_batch(&self, entries: &[(NeedleId, NeedleValue)]) -> Vec<Option<NeedleValue>> {
+        let mut map = self.map.write();
+        entries
+            .iter()
+            .map(|(key, value)| map.insert(*key, *value))
+            .collect()
+    }
+
+    fn delete_batch(&self, keys: &[NeedleId]) -> Vec<Option<NeedleValue>> {
+        let mut map = self.map.write();
+        keys.iter().map(|key| map.shift_remove(key)).collect()
+    }
+
+    fn iter(&self) -> Vec<(NeedleId, NeedleValue)> {
+        self.map
+            .read()
+            .iter()
+            .map(|(key, value)| (*key, *value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{
+        needle::{MemoryNeedleValueMap, NeedleValue, NeedleValueMap, SortedIndexMap},
+        types::{Offset, Size},
+        NeedleError,
+    };
+
+    #[test]
+    fn test_ascending_visit() {
+        let map = MemoryNeedleValueMap::new();
+        for (key, offset) in [(1, 30), (2, 10), (3, 20)] {
+            map.set(key, NeedleValue::new(Offset(offset), Size(8)));
+        }
+        map.delete(3);
+        assert_eq!(map.iter().len(), 2);
+
+        let mut keys = vec![];
+        map.ascending_visit(&mut |key, _| -> Result<(), NeedleError> {
+            keys.push(key);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(keys, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_batch() {
+        let value = |offset| NeedleValue::new(Offset(offset), Size(8));
+        let maps: [Box<dyn NeedleValueMap>; 2] = [
+            Box::new(MemoryNeedleValueMap::new()),
+            Box::new(SortedIndexMap {
+                map: Default::default(),
+            }),
+        ];
+        for map in maps {
+            map.set(1, value(1));
+            let olds = map.set_batch(&[(1, value(2)), (2, value(3))]);
+            assert_eq!(olds, vec![Some(value(1)), None]);
+            assert_eq!(map.get(1), Some(value(2)));
+
+            let deleted = map.delete_batch(&[2, 3]);
+            assert_eq!(deleted, vec![Some(value(3)), None]);
+            assert_eq!(map.iter().len(), 1);
+        }
+    }
 }
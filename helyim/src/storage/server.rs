@@ -1,64 +1,89 @@
 use std::{
-    ffi::OsString, fs, net::SocketAddr, os::unix::fs::FileExt, path::Path, pin::Pin,
+    ffi::OsString, fs, future::ready, net::SocketAddr, path::Path, pin::Pin,
     result::Result as StdResult, sync::Arc, time::Duration,
 };
 
 use async_stream::stream;
-use axum::{extract::DefaultBodyLimit, routing::get, Router};
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware::from_fn,
+    routing::{get, post},
+    Router,
+};
 use faststr::FastStr;
 use helyim_proto::{
     directory::HeartbeatRequest,
     volume::{
         volume_server_server::{VolumeServer as HelyimVolumeServer, VolumeServerServer},
-        AllocateVolumeRequest, AllocateVolumeResponse, VacuumVolumeCheckRequest,
+        AllocateVolumeRequest, AllocateVolumeResponse, CopyFileRequest, CopyFileResponse,
+        ReadNeedleBlobRequest, ReadNeedleBlobResponse, VacuumVolumeCheckRequest,
         VacuumVolumeCheckResponse, VacuumVolumeCleanupRequest, VacuumVolumeCleanupResponse,
         VacuumVolumeCommitRequest, VacuumVolumeCommitResponse, VacuumVolumeCompactRequest,
-        VacuumVolumeCompactResponse, VolumeDeleteRequest, VolumeDeleteResponse,
-        VolumeEcBlobDeleteRequest, VolumeEcBlobDeleteResponse, VolumeEcShardReadRequest,
-        VolumeEcShardReadResponse, VolumeEcShardsCopyRequest, VolumeEcShardsCopyResponse,
-        VolumeEcShardsDeleteRequest, VolumeEcShardsDeleteResponse, VolumeEcShardsGenerateRequest,
-        VolumeEcShardsGenerateResponse, VolumeEcShardsMountRequest, VolumeEcShardsMountResponse,
-        VolumeEcShardsRebuildRequest, VolumeEcShardsRebuildResponse, VolumeEcShardsToVolumeRequest,
-        VolumeEcShardsToVolumeResponse, VolumeEcShardsUnmountRequest,
-        VolumeEcShardsUnmountResponse, VolumeInfo, VolumeMarkReadonlyRequest,
-        VolumeMarkReadonlyResponse,
+        VacuumVolumeCompactResponse, VolumeCopyRequest, VolumeCopyResponse, VolumeDeleteRequest,
+        VolumeDeleteResponse, VolumeEcBlobDeleteRequest, VolumeEcBlobDeleteResponse,
+        VolumeEcShardReadRequest, VolumeEcShardReadResponse, VolumeEcShardsCopyRequest,
+        VolumeEcShardsCopyResponse, VolumeEcShardsDeleteRequest, VolumeEcShardsDeleteResponse,
+        VolumeEcShardsGenerateRequest, VolumeEcShardsGenerateResponse, VolumeEcShardsMountRequest,
+        VolumeEcShardsMountResponse, VolumeEcShardsRebuildRequest, VolumeEcShardsRebuildResponse,
+        VolumeEcShardsToVolumeRequest, VolumeEcShardsToVolumeResponse,
+        VolumeEcShardsUnmountRequest, VolumeEcShardsUnmountResponse, VolumeInfo,
+        VolumeMarkReadonlyRequest, VolumeMarkReadonlyResponse, VolumeMarkWritableRequest,
+        VolumeMarkWritableResponse,
     },
 };
 use tokio::{net::TcpListener, time::sleep};
 use tokio_stream::{wrappers::UnboundedReceiverStream, Stream, StreamExt};
 use tonic::{transport::Server as TonicServer, Request, Response, Status};
-use tower_http::{compression::CompressionLayer, timeout::TimeoutLayer};
+use tower_http::{
+    catch_panic::CatchPanicLayer, compression::CompressionLayer, timeout::TimeoutLayer,
+};
 use tracing::{debug, error, info, warn};
 
 use crate::{
     errors::Result,
-    operation::{list_master, Looker},
+    operation::{list_master, usage_metrics, ClusterStatus, Looker, SERVER_USAGE_PREFIX},
     proto::save_volume_info,
     storage::{
         api::{
-            delete_handler,
+            bump_max_file_key_handler, delete_handler,
             erasure_coding::{
                 generate_ec_shards_handler, generate_volume_from_ec_shards_handler,
                 rebuild_missing_ec_shards_handler,
             },
-            get_or_head_handler, post_handler, status_handler, StorageState,
+            get_or_head_handler, max_file_key_handler, metrics_handler, post_handler,
+            quarantine_volume_handler, readyz_handler, status_handler, usage_handler,
+            vacuum_volume_handler, StorageState,
         },
+        crc,
         erasure_coding::{
             ec_shard_base_filename, find_data_filesize, rebuild_ec_files, rebuild_ecx_file, to_ext,
             write_data_file, write_ec_files, write_index_file_from_ec_index,
             write_sorted_file_from_index, ShardId,
         },
+        io_class::{init_disk_io, spawn_io, IoClass},
         needle::NeedleMapType,
-        store::{Store, StoreRef},
+        store::{parse_disk_type, Store, StoreRef},
         version::Version,
+        volume::{DATA_FILE_SUFFIX, IDX_FILE_SUFFIX},
         VolumeError, BUFFER_SIZE_LIMIT,
     },
     util::{
-        args::VolumeOptions,
+        args::{TimeoutOptions, VolumeOptions},
+        buffer::BUFFER_POOL,
+        capability::check_protocol_version,
         chan::{delta_volume_channel, DeltaVolumeInfoReceiver},
-        file::file_exists,
-        grpc::{grpc_port, helyim_client},
-        http::{default_handler, favicon_handler},
+        file::{file_exists, FileExt},
+        grpc::{grpc_port, helyim_client, standard_services},
+        http::{
+            default_handler, favicon_handler,
+            health::healthz_handler,
+            panic_response, pool_stats_handler,
+            request_id::{request_id, GrpcRequestIdLayer},
+        },
+        log::{log_levels_handler, set_log_level_handler},
+        pushgateway::push_loop,
+        retry::{set_retry_policy, RetryPolicy},
+        sign::{set_cluster_secret, verify_signature, VerifyGrpc},
         sys::exit,
     },
 };
@@ -83,21 +108,38 @@ impl VolumeServer {
         let (shutdown, mut shutdown_rx) = async_broadcast::broadcast(16);
 
         let options = Arc::new(volume_opts);
+        set_retry_policy(options.retry.policy());
+        set_cluster_secret(options.cluster_secret.clone());
+        init_disk_io(options.disk_io_threads)?;
 
         let (delta_volume_tx, delta_volume_rx) = delta_volume_channel();
         let store = Arc::new(Store::new(options.clone(), needle_map_type, delta_volume_tx).await?);
 
         let addr = format!("{}:{}", options.ip, grpc_port(options.port)).parse()?;
 
-        // get leader from master
-        let cluster_status = list_master(&options.master_server).await?;
+        // get leader from master, an unreachable master does not stop the startup, the heartbeat
+        // keeps retrying the registration
+        let (current_master, seed_master_nodes) = match list_master(&options.master_server).await {
+            Ok(cluster_status) if !cluster_status.peers.is_empty() => (
+                cluster_status.leader,
+                cluster_status.peers.into_values().collect(),
+            ),
+            Ok(cluster_status) => (cluster_status.leader, vec![options.master_server.clone()]),
+            Err(err) => {
+                warn!(
+                    "list master {} failed: {err}, will keep retrying to register",
+                    options.master_server
+                );
+                (FastStr::empty(), vec![options.master_server.clone()])
+            }
+        };
 
         let storage = VolumeServer {
             options,
             needle_map_type,
             read_redirect,
-            current_master: cluster_status.leader,
-            seed_master_nodes: cluster_status.peers.into_values().collect(),
+            current_master,
+            seed_master_nodes,
             store: store.clone(),
             shutdown,
         };
@@ -105,18 +147,39 @@ impl VolumeServer {
         tokio::spawn(Self::heartbeat(
             store.clone(),
             storage.seed_master_nodes.clone(),
+            storage.options.master_server.clone(),
             storage.options.pulse,
             delta_volume_rx,
             storage.shutdown.new_receiver(),
         ));
+        let metrics_store = store.clone();
+        tokio::spawn(push_loop(
+            storage.options.metrics_push.clone(),
+            FastStr::new(format!("{}:{}", storage.options.ip, storage.options.port)),
+            "volume",
+            move || {
+                ready(Some(usage_metrics(
+                    SERVER_USAGE_PREFIX,
+                    &metrics_store.collection_usages(),
+                )))
+            },
+            storage.shutdown.new_receiver(),
+        ));
 
+        let (health, reflection) =
+            standard_services::<VolumeServerServer<StorageGrpcServer>>().await?;
         tokio::spawn(async move {
             info!("volume grpc server starting up. binding addr: {addr}");
             if let Err(err) = TonicServer::builder()
-                .add_service(VolumeServerServer::new(StorageGrpcServer {
-                    store,
-                    needle_map_type,
-                }))
+                .layer(GrpcRequestIdLayer)
+                .add_service(health)
+                .add_service(reflection)
+                .add_service(VerifyGrpc::new(VolumeServerServer::new(
+                    StorageGrpcServer {
+                        store,
+                        needle_map_type,
+                    },
+                )))
                 .serve_with_shutdown(addr, async {
                     let _ = shutdown_rx.recv().await;
                 })
@@ -140,6 +203,7 @@ impl VolumeServer {
         let needle_map_type = self.needle_map_type;
         let read_redirect = self.read_redirect;
         let pulse = self.options.pulse;
+        info!("needle checksum acceleration: {}", crc::acceleration());
 
         let state = StorageState {
             store,
@@ -152,7 +216,12 @@ impl VolumeServer {
         let addr = format!("{}:{}", self.options.ip, self.options.port).parse()?;
         let shutdown_rx = self.shutdown.new_receiver();
 
-        tokio::spawn(start_volume_server(state, addr, shutdown_rx));
+        tokio::spawn(start_volume_server(
+            state,
+            self.options.timeout.clone(),
+            addr,
+            shutdown_rx,
+        ));
 
         Ok(())
     }
@@ -162,44 +231,66 @@ impl VolumeServer {
     async fn heartbeat(
         store: StoreRef,
         seed_masters: Vec<FastStr>,
+        master_server: FastStr,
         pulse: u64,
         delta_volume_rx: DeltaVolumeInfoReceiver,
         mut shutdown: async_broadcast::Receiver<()>,
     ) {
-        let mut new_leader = FastStr::empty();
+        let backoff = heartbeat_backoff(pulse);
+        let mut masters = seed_masters;
+        let mut next = 0;
+        let mut leader = FastStr::empty();
+        let mut failures = 0;
         loop {
-            for master in seed_masters.iter() {
-                if !new_leader.is_empty() && &new_leader != master {
-                    sleep(Duration::from_secs(pulse)).await;
+            // never left without a master to ask, like after a discovery without peers
+            if masters.is_empty() {
+                masters = vec![master_server.clone()];
+            }
+            // stick to the known leader, walk the masters round robin until one names the leader
+            let master = if leader.is_empty() {
+                next += 1;
+                masters[(next - 1) % masters.len()].clone()
+            } else {
+                leader.clone()
+            };
+            store.set_current_master(master.clone()).await;
+            let ret = tokio::select! {
+                ret = VolumeServer::do_heartbeat(
+                    &master,
+                    store.clone(),
+                    pulse,
+                    delta_volume_rx.clone(),
+                    &mut failures,
+                    shutdown.clone(),
+                ) => ret,
+                _ = shutdown.recv() => {
+                    info!("stopping heartbeat.");
+                    return;
+                }
+            };
+            match ret {
+                Err(VolumeError::LeaderChanged(new, old)) if !new.is_empty() => {
+                    // register with the new leader right away
+                    info!("leader changed from {old} to {new}, register with the new leader");
+                    leader = new;
                     continue;
                 }
-                store.set_current_master(master.clone()).await;
-                let delta_volume = delta_volume_rx.clone();
+                Ok(()) => warn!("heartbeat stream to {master} closed"),
+                Err(err) => warn!("heartbeat to {master} error: {err}"),
+            }
+            leader = FastStr::empty();
+            failures += 1;
+            store.set_current_master(FastStr::empty()).await;
+
+            // every master failed once, the masters may have been replaced
+            if failures as usize % masters.len() == 0 {
                 tokio::select! {
-                    ret = VolumeServer::do_heartbeat(
-                        master,
-                        store.clone(),
-                        pulse,
-                        delta_volume,
-                        shutdown.clone(),
-                    ) => {
-                        match ret {
-                            Err(VolumeError::LeaderChanged(new, old)) => {
-                                if !new.is_empty() {
-                                    new_leader = new;
-                                }
-                            }
-                            Err(err @ (VolumeError::StartHeartbeat | VolumeError::SendHeartbeat(_))) => {
-                                warn!("heartbeat to {master} error: {err}");
-                                new_leader = FastStr::empty();
-                                store.set_current_master(FastStr::empty()).await;
-                            }
-                            Err(err) => {
-                                error!("heartbeat but error occur: {err}");
-                            }
-                            _ => continue
+                    status = discover_masters(&masters) => {
+                        if let Some(status) = status.filter(|status| !status.peers.is_empty()) {
+                            info!("discovered masters {:?}, leader is {}", status.peers, status.leader);
+                            masters = status.peers.into_values().collect();
+                            leader = status.leader;
                         }
-                        sleep(Duration::from_secs(pulse)).await;
                     }
                     _ = shutdown.recv() => {
                         info!("stopping heartbeat.");
@@ -207,6 +298,16 @@ impl VolumeServer {
                     }
                 }
             }
+
+            let wait = backoff.backoff(failures);
+            debug!("retry heartbeat after {wait:?}, {failures} failures in a row");
+            tokio::select! {
+                _ = sleep(wait) => {}
+                _ = shutdown.recv() => {
+                    info!("stopping heartbeat.");
+                    return;
+                }
+            }
         }
     }
 
@@ -215,6 +316,7 @@ impl VolumeServer {
         store: StoreRef,
         pulse: u64,
         delta_volume: DeltaVolumeInfoReceiver,
+        failures: &mut u32,
         mut shutdown_rx: async_broadcast::Receiver<()>,
     ) -> StdResult<(), VolumeError> {
         let mut interval = tokio::time::interval(Duration::from_secs(pulse));
@@ -259,6 +361,24 @@ impl VolumeServer {
                         heartbeat.deleted_ec_shards.push(message);
                         yield heartbeat;
                     }
+                    Ok(vid) = delta_volume.sealed_volumes_rx.recv() => {
+                        info!("volume server {}:{} seals volume {vid}", store_ref.ip, store_ref.port);
+
+                        // a full heartbeat carries the read only state of the volume
+                        match store_ref.collect_heartbeat() {
+                            Ok(heartbeat) => yield heartbeat,
+                            Err(err) => error!("collect heartbeat error: {err}")
+                        }
+                    }
+                    Ok(vid) = delta_volume.quarantined_volumes_rx.recv() => {
+                        info!("volume server {}:{} changes the quarantine of volume {vid}", store_ref.ip, store_ref.port);
+
+                        // a full heartbeat carries the quarantine of the volume
+                        match store_ref.collect_heartbeat() {
+                            Ok(heartbeat) => yield heartbeat,
+                            Err(err) => error!("collect heartbeat error: {err}")
+                        }
+                    }
                     // to avoid server side got `channel closed` error
                     _ = shutdown_rx.recv() => {
                         break;
@@ -275,6 +395,7 @@ impl VolumeServer {
                 while let Some(response) = stream.next().await {
                     match response {
                         Ok(response) => {
+                            *failures = 0;
                             if let Ok(response) = serde_json::to_string(&response) {
                                 debug!("heartbeat reply: {response}");
                             }
@@ -289,13 +410,17 @@ impl VolumeServer {
                                 store.set_current_master(new_leader.clone()).await;
                                 return Err(VolumeError::LeaderChanged(new_leader, old_leader));
                             }
+                            if response.protocol_version != 0 {
+                                if let Err(err) = check_protocol_version(response.protocol_version)
+                                {
+                                    error!("master {master} is not compatible: {err}");
+                                    return Err(VolumeError::String(err));
+                                }
+                            }
                             store.set_volume_size_limit(response.volume_size_limit);
                         }
                         Err(err) => {
-                            error!(
-                                "send heartbeat to {master} error: {err}, will try again after \
-                                 {pulse}s."
-                            );
+                            error!("send heartbeat to {master} error: {err}");
                             return Err(VolumeError::SendHeartbeat(master.clone()));
                         }
                     }
@@ -303,22 +428,47 @@ impl VolumeServer {
                 Ok(())
             }
             Err(err) => {
-                error!("heartbeat starting up failed: {err}, will try agent after {pulse}s.");
+                error!("heartbeat to {master} starting up failed: {err}");
                 Err(VolumeError::StartHeartbeat)
             }
         }
     }
 }
 
+/// the backoff between heartbeat attempts, it grows with the failures in a row up to ten pulses
+fn heartbeat_backoff(pulse: u64) -> RetryPolicy {
+    RetryPolicy {
+        attempts: u32::MAX,
+        initial_backoff: Duration::from_millis(500),
+        max_backoff: Duration::from_secs(pulse.max(1) * 10),
+    }
+}
+
+/// the current leader and masters from the first master answering
+async fn discover_masters(masters: &[FastStr]) -> Option<ClusterStatus> {
+    for master in masters {
+        match list_master(master).await {
+            Ok(status) => return Some(status),
+            Err(err) => debug!("list master {master} failed: {err}"),
+        }
+    }
+    None
+}
+
 async fn start_volume_server(
     state: StorageState,
+    timeout: TimeoutOptions,
     addr: SocketAddr,
     mut shutdown: async_broadcast::Receiver<()>,
 ) {
-    let app = Router::new()
-        .route("/", get(default_handler))
-        .route("/status", get(status_handler))
-        .route("/favicon.ico", get(favicon_handler))
+    // ec encoding and rebuilding read whole volumes, they get a much longer deadline
+    let admin = Router::new()
+        .route(
+            "/admin/volume/max_file_key",
+            get(max_file_key_handler).post(bump_max_file_key_handler),
+        )
+        .route("/admin/volume/vacuum", post(vacuum_volume_handler))
+        .route("/admin/volume/quarantine", post(quarantine_volume_handler))
         .route(
             "/volume/ec/generate",
             get(generate_ec_shards_handler).put(generate_ec_shards_handler),
@@ -331,6 +481,25 @@ async fn start_volume_server(
             "/volume/ec/rebuild",
             get(rebuild_missing_ec_shards_handler).put(rebuild_missing_ec_shards_handler),
         )
+        .route(
+            "/admin/log-level",
+            get(log_levels_handler).put(set_log_level_handler),
+        )
+        // called by the masters, the other volume servers and signed admin tools only
+        .layer(from_fn(verify_signature))
+        .layer(TimeoutLayer::new(Duration::from_secs(
+            timeout.admin_timeout,
+        )));
+
+    let app = Router::new()
+        .route("/", get(default_handler))
+        .route("/status", get(status_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .route("/favicon.ico", get(favicon_handler))
+        .route("/stats/pool", get(pool_stats_handler))
+        .route("/stats/usage", get(usage_handler))
+        .route("/metrics", get(metrics_handler))
         .fallback_service(
             get(get_or_head_handler)
                 .head(get_or_head_handler)
@@ -339,16 +508,23 @@ async fn start_volume_server(
                 .fallback(default_handler)
                 .with_state(state.clone()),
         )
+        .layer(TimeoutLayer::new(Duration::from_secs(
+            timeout.request_timeout,
+        )))
+        .merge(admin)
         .layer((
+            from_fn(request_id),
+            CatchPanicLayer::custom(panic_response),
             CompressionLayer::new(),
             DefaultBodyLimit::max(1024 * 1024 * 50),
-            TimeoutLayer::new(Duration::from_secs(10)),
         ))
         .with_state(state);
 
     info!("volume api server is starting up. binding addr: {addr}");
     match TcpListener::bind(addr).await {
         Ok(listener) => {
+            // serves both http/1.1 and h2c, clients with many small requests can multiplex them
+            // over one connection with http/2 prior knowledge
             if let Err(err) = axum::serve(listener, app.into_make_service())
                 .with_graceful_shutdown(async move {
                     let _ = shutdown.recv().await;
@@ -385,6 +561,8 @@ impl HelyimVolumeServer for StorageGrpcServer {
                 request.replication,
                 request.ttl,
                 request.preallocate,
+                request.disk_type,
+                request.needle_alignment,
             )
             .await?;
         Ok(Response::new(AllocateVolumeResponse {}))
@@ -399,6 +577,120 @@ impl HelyimVolumeServer for StorageGrpcServer {
         Ok(Response::new(VolumeDeleteResponse {}))
     }
 
+    async fn volume_copy(
+        &self,
+        request: Request<VolumeCopyRequest>,
+    ) -> StdResult<Response<VolumeCopyResponse>, Status> {
+        let request = request.into_inner();
+        self.store
+            .copy_volume(
+                request.volume_id,
+                FastStr::new(request.collection),
+                &request.source_data_node,
+                self.needle_map_type,
+                parse_disk_type(&request.disk_type)?,
+            )
+            .await?;
+        Ok(Response::new(VolumeCopyResponse {}))
+    }
+
+    type CopyFileStream = Pin<Box<dyn Stream<Item = StdResult<CopyFileResponse, Status>> + Send>>;
+
+    async fn copy_file(
+        &self,
+        request: Request<CopyFileRequest>,
+    ) -> StdResult<Response<Self::CopyFileStream>, Status> {
+        let request = request.into_inner();
+        let filename = match self.store.find_volume(request.volume_id) {
+            Some(volume) => match request.ext.as_str() {
+                DATA_FILE_SUFFIX => volume.data_filename(),
+                IDX_FILE_SUFFIX => volume.index_filename(),
+                ext => {
+                    return Err(Status::invalid_argument(format!(
+                        "unsupported file extension: {ext}"
+                    )))
+                }
+            },
+            None => {
+                return Err(Status::not_found(format!(
+                    "volume {} is not found",
+                    request.volume_id
+                )))
+            }
+        };
+        let file = fs::File::open(&filename)?;
+        // only copy what is there now, the file may be appended while copying
+        let stop_offset = file.metadata()?.len();
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let copy = move || {
+            let mut offset = 0;
+            while offset < stop_offset {
+                let len = (stop_offset - offset).min(BUFFER_SIZE_LIMIT as u64) as usize;
+                let mut buffer = vec![0u8; len];
+                let response = file
+                    .read_exact_at(&mut buffer, offset)
+                    .map(|_| CopyFileResponse {
+                        file_content: buffer,
+                    })
+                    .map_err(|err| Status::internal(err.to_string()));
+                let failed = response.is_err();
+                if tx.send(response).is_err() || failed {
+                    break;
+                }
+                offset += len as u64;
+            }
+        };
+        tokio::spawn(spawn_io(IoClass::Background, copy));
+
+        let stream = UnboundedReceiverStream::new(rx);
+        Ok(Response::new(Box::pin(stream) as Self::CopyFileStream))
+    }
+
+    type ReadNeedleBlobStream =
+        Pin<Box<dyn Stream<Item = StdResult<ReadNeedleBlobResponse, Status>> + Send>>;
+
+    async fn read_needle_blob(
+        &self,
+        request: Request<ReadNeedleBlobRequest>,
+    ) -> StdResult<Response<Self::ReadNeedleBlobStream>, Status> {
+        let request = request.into_inner();
+        let (file, offset, size) = self
+            .store
+            .locate_volume_needle_blob(request.volume_id, request.needle_id)?;
+
+        // every frame is read when the receiver asks for it, so a slow receiver holds back the
+        // reads without pinning an io thread or buffering the needle
+        let file = Arc::new(file);
+        let stream = stream! {
+            let mut read = 0;
+            while read < size {
+                let len = (size - read).min(BUFFER_SIZE_LIMIT as u64) as usize;
+                let file = file.clone();
+                let frame = spawn_io(IoClass::Foreground, move || {
+                    let mut buffer = vec![0u8; len];
+                    file.read_exact_at(&mut buffer, offset + read).map(|_| buffer)
+                })
+                .await;
+                match frame {
+                    Ok(Ok(needle_blob)) => {
+                        read += len as u64;
+                        yield Ok(ReadNeedleBlobResponse { needle_blob, size });
+                    }
+                    Ok(Err(err)) => {
+                        yield Err(Status::internal(err.to_string()));
+                        break;
+                    }
+                    Err(err) => {
+                        yield Err(Status::internal(err.to_string()));
+                        break;
+                    }
+                }
+            }
+        };
+        Ok(Response::new(Box::pin(stream) as Self::ReadNeedleBlobStream))
+    }
+
     async fn volume_mark_readonly(
         &self,
         request: Request<VolumeMarkReadonlyRequest>,
@@ -408,6 +700,15 @@ impl HelyimVolumeServer for StorageGrpcServer {
         Ok(Response::new(VolumeMarkReadonlyResponse {}))
     }
 
+    async fn volume_mark_writable(
+        &self,
+        request: Request<VolumeMarkWritableRequest>,
+    ) -> StdResult<Response<VolumeMarkWritableResponse>, Status> {
+        let request = request.into_inner();
+        self.store.mark_volume_writable(request.volume_id).await?;
+        Ok(Response::new(VolumeMarkWritableResponse {}))
+    }
+
     async fn vacuum_volume_check(
         &self,
         request: Request<VacuumVolumeCheckRequest>,
@@ -424,8 +725,11 @@ impl HelyimVolumeServer for StorageGrpcServer {
     ) -> StdResult<Response<VacuumVolumeCompactResponse>, Status> {
         let request = request.into_inner();
         debug!("vacuum volume {} compact", request.volume_id);
-        self.store
-            .compact_volume(request.volume_id, request.preallocate)?;
+        let store = self.store.clone();
+        spawn_io(IoClass::Background, move || {
+            store.compact_volume(request.volume_id, request.preallocate)
+        })
+        .await??;
         Ok(Response::new(VacuumVolumeCompactResponse {}))
     }
 
@@ -463,17 +767,23 @@ impl HelyimVolumeServer for StorageGrpcServer {
                         volume.collection
                     )));
                 }
-                // write .ecx file
-                write_sorted_file_from_index(&base_filename, ".ecx")?;
-                // write .ec00 - .ec13 files
-                write_ec_files(&base_filename)?;
-
                 let volume_info = VolumeInfo {
                     version: volume.version() as u32,
+                    needle_alignment: volume.alignment(),
                     ..Default::default()
                 };
-                // write .vif files
-                save_volume_info(&format!("{}.vif", base_filename), volume_info)?;
+                drop(volume);
+
+                spawn_io(IoClass::Background, move || {
+                    // write .ecx file
+                    write_sorted_file_from_index(&base_filename, ".ecx")?;
+                    // write .ec00 - .ec13 files
+                    write_ec_files(&base_filename)?;
+                    // write .vif files
+                    save_volume_info(&format!("{}.vif", base_filename), volume_info)?;
+                    Ok::<(), Status>(())
+                })
+                .await??;
                 Ok(Response::new(VolumeEcShardsGenerateResponse::default()))
             }
             None => Err(Status::not_found(format!(
@@ -495,8 +805,12 @@ impl HelyimVolumeServer for StorageGrpcServer {
             let ecx_filename = format!("{}/{}.ecx", location.directory, base_filename);
             if file_exists(&ecx_filename)? {
                 let base_filename = format!("{}/{}", location.directory, base_filename);
-                rebuilt_shard_ids.extend(rebuild_ec_files(&base_filename)?);
-                rebuild_ecx_file(&base_filename)?;
+                rebuilt_shard_ids = spawn_io(IoClass::Background, move || {
+                    let rebuilt_shard_ids = rebuild_ec_files(&base_filename)?;
+                    rebuild_ecx_file(&base_filename)?;
+                    Ok::<_, Status>(rebuilt_shard_ids)
+                })
+                .await??;
                 break;
             }
         }
@@ -633,7 +947,7 @@ impl HelyimVolumeServer for StorageGrpcServer {
                 let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
 
                 tokio::spawn(async move {
-                    let mut buffer = vec![0u8; buf_size];
+                    let mut buffer = BUFFER_POOL.get(buf_size);
                     let mut start_offset = request.offset as u64;
                     let mut bytes_to_read = request.size as usize;
                     while bytes_to_read > 0 {
@@ -1,4 +1,8 @@
 use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::Write,
+    panic::{catch_unwind, AssertUnwindSafe},
     result::Result as StdResult,
     sync::{
         atomic::{AtomicU64, Ordering},
@@ -8,23 +12,37 @@ use std::{
 
 use dashmap::mapref::one::{Ref, RefMut};
 use faststr::FastStr;
-use helyim_proto::directory::{
-    HeartbeatRequest, VolumeInformationMessage, VolumeShortInformationMessage,
+use helyim_proto::{
+    directory::{HeartbeatRequest, VolumeInformationMessage, VolumeShortInformationMessage},
+    volume::CopyFileRequest,
 };
 use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 
 use crate::{
     anyhow,
-    errors::{Error, Result},
+    errors::{Error, ErrorCode, Result},
+    operation::CollectionUsage,
     storage::{
         disk_location::DiskLocation,
-        needle::{Needle, NeedleMapType, MAX_POSSIBLE_VOLUME_SIZE},
+        fsync::{FsyncQueue, FsyncQueues},
+        io_class::{spawn_io, IoClass},
+        needle::{max_volume_size, IndexCompaction, Needle, NeedleMapType, NEEDLE_PADDING_SIZE},
         types::Size,
-        volume::Volume,
-        ReplicaPlacement, Ttl, VolumeError, VolumeId,
+        usage::{UsageCounters, UsageKind},
+        version::Version,
+        volume::{NeedleVerification, Volume, DATA_FILE_SUFFIX, IDX_FILE_SUFFIX},
+        write_queue::{InFlight, InFlightWrites, WriteOutcome, WriteQueues},
+        DiskType, Durability, NeedleError, NeedleId, ReplicaPlacement, Ttl, VolumeError, VolumeId,
+    },
+    util::{
+        args::VolumeOptions,
+        capability::{Capabilities, PROTOCOL_VERSION},
+        chan::DeltaVolumeInfoSender,
+        grpc::volume_server_client,
+        http::etag_matches,
+        sys::panic_message,
     },
-    util::{args::VolumeOptions, chan::DeltaVolumeInfoSender},
 };
 
 const MAX_TTL_VOLUME_REMOVAL_DELAY_MINUTES: u64 = 10;
@@ -46,6 +64,18 @@ pub struct Store {
     pub delta_volume_tx: DeltaVolumeInfoSender,
 
     pub current_master: RwLock<FastStr>,
+
+    pub durability: Durability,
+    pub protect_overwrite: bool,
+    /// keep a bloom filter of the needle keys of every volume
+    pub read_bloom_filter: bool,
+    /// alignment of the needles of volumes allocated without one
+    pub needle_alignment: u32,
+    pub usage: UsageCounters,
+
+    write_queues: WriteQueues,
+    in_flight_writes: InFlightWrites,
+    fsync_queues: FsyncQueues,
 }
 
 impl Store {
@@ -58,11 +88,14 @@ impl Store {
 
         let folders = options.paths();
         let max_counts = options.max_volumes();
+        let disk_types = options.disk_types()?;
         assert_eq!(folders.len(), max_counts.len());
 
         for i in 0..folders.len() {
-            let location = DiskLocation::new(&folders[i], max_counts[i]);
-            location.load_existing_volumes(needle_map_type).await?;
+            let location = DiskLocation::new(&folders[i], max_counts[i], disk_types[i]);
+            location
+                .load_existing_volumes(needle_map_type, options.read_bloom_filter)
+                .await?;
             // load erasure coding shards
             location.load_all_shards().await?;
             locations.push(location);
@@ -80,6 +113,14 @@ impl Store {
             volume_size_limit: AtomicU64::new(0),
             delta_volume_tx,
             current_master: RwLock::new(FastStr::empty()),
+            durability: options.durability,
+            protect_overwrite: options.protect_overwrite,
+            read_bloom_filter: options.read_bloom_filter,
+            needle_alignment: options.needle_alignment,
+            usage: UsageCounters::default(),
+            write_queues: WriteQueues::default(),
+            in_flight_writes: InFlightWrites::default(),
+            fsync_queues: FsyncQueues::default(),
         })
     }
 
@@ -122,6 +163,42 @@ impl Store {
         None
     }
 
+    /// count a request against the collection of the volume or ec volume `vid`
+    pub fn record_usage(&self, vid: VolumeId, kind: UsageKind, bytes: u64) {
+        let collection = match self.find_volume(vid) {
+            Some(volume) => volume.collection.clone(),
+            None => match self.find_ec_volume(vid) {
+                Some(volume) => volume.collection.clone(),
+                None => return,
+            },
+        };
+        self.usage.record(&collection, kind, bytes);
+    }
+
+    /// stored bytes of the local volumes and requests served, per collection
+    pub fn collection_usages(&self) -> Vec<CollectionUsage> {
+        let mut usages: BTreeMap<FastStr, CollectionUsage> = BTreeMap::new();
+        for location in self.locations.iter() {
+            for volume in location.volumes.iter() {
+                let usage = usages
+                    .entry(volume.collection.clone())
+                    .or_insert_with(|| CollectionUsage::new(volume.collection.clone()));
+                usage.volumes += 1;
+                usage.files += volume.file_count().saturating_sub(volume.deleted_count());
+                usage.size += volume.data_file_size().unwrap_or(0);
+                usage.deleted_bytes += volume.deleted_bytes();
+            }
+        }
+        for message in self.usage.snapshot() {
+            let collection = FastStr::new(&message.collection);
+            usages
+                .entry(collection.clone())
+                .or_insert_with(|| CollectionUsage::new(collection))
+                .add_requests(&message);
+        }
+        usages.into_values().collect()
+    }
+
     pub fn find_volume_mut(&self, vid: VolumeId) -> Option<RefMut<VolumeId, Volume>> {
         for location in self.locations.iter() {
             let volume = location.get_volume_mut(vid);
@@ -136,14 +213,54 @@ impl Store {
         &self,
         vid: VolumeId,
         needle: &mut Needle,
+    ) -> StdResult<usize, VolumeError> {
+        self.delete_volume_needle_if_match(vid, needle, None).await
+    }
+
+    /// delete the needle only if the etag of the stored needle matches `if_match`, like writes
+    /// the check and the delete are done in the write queue of the volume.
+    pub async fn delete_volume_needle_if_match(
+        &self,
+        vid: VolumeId,
+        needle: &mut Needle,
+        if_match: Option<&str>,
     ) -> StdResult<usize, VolumeError> {
         match self.find_volume(vid) {
             Some(volume) => {
+                if volume.quarantined() {
+                    return Err(VolumeError::Quarantined(vid));
+                }
                 if volume.no_write_or_delete() {
                     return Err(VolumeError::Readonly(vid));
                 }
-                if MAX_POSSIBLE_VOLUME_SIZE >= volume.content_size() + Size(0).actual_size() {
-                    return volume.delete_needle(needle);
+                drop(volume);
+
+                let queue = self.write_queues.get(vid);
+                let _ticket = queue.enter(vid).await?;
+                let volume = match self.find_volume(vid) {
+                    Some(volume) => volume,
+                    None => return Ok(0),
+                };
+                if let Some(if_match) = if_match {
+                    let mut current = Needle {
+                        id: needle.id,
+                        ..Default::default()
+                    };
+                    let etag = volume
+                        .read_needle(&mut current)
+                        .ok()
+                        .map(|_| current.etag());
+                    if !etag_matches(if_match, etag.as_deref()) {
+                        return Err(
+                            NeedleError::PreconditionFailed(if_match.to_string(), etag).into()
+                        );
+                    }
+                }
+                let alignment = volume.alignment();
+                if max_volume_size(alignment)
+                    >= volume.content_size() + Size(0).actual_size(alignment)
+                {
+                    return self.isolate(&volume, || volume.delete_needle(needle));
                 }
                 Err(VolumeError::VolumeSizeLimit(
                     self.volume_size_limit(),
@@ -156,24 +273,179 @@ impl Store {
 
     pub async fn read_volume_needle(&self, vid: VolumeId, needle: &mut Needle) -> Result<usize> {
         match self.find_volume(vid) {
-            Some(volume) => Ok(volume.read_needle(needle)?),
+            Some(volume) => Ok(self.isolate(&volume, || volume.read_needle(needle))?),
+            None => Err(VolumeError::NotFound(vid).into()),
+        }
+    }
+
+    pub fn verify_volume_needle(&self, vid: VolumeId, key: NeedleId) -> Result<NeedleVerification> {
+        match self.find_volume(vid) {
+            Some(volume) => Ok(self.isolate(&volume, || volume.verify_needle(key))?),
+            None => Err(VolumeError::NotFound(vid).into()),
+        }
+    }
+
+    pub async fn locate_volume_needle_data(
+        &self,
+        vid: VolumeId,
+        needle: &mut Needle,
+        min_size: u64,
+    ) -> Result<Option<(File, u64, Version)>> {
+        match self.find_volume(vid) {
+            Some(volume) => {
+                Ok(self.isolate(&volume, || volume.locate_needle_data(needle, min_size))?)
+            }
+            None => Err(VolumeError::NotFound(vid).into()),
+        }
+    }
+
+    pub fn locate_volume_needle_blob(
+        &self,
+        vid: VolumeId,
+        needle_id: NeedleId,
+    ) -> Result<(File, u64, u64)> {
+        match self.find_volume(vid) {
+            Some(volume) => Ok(self.isolate(&volume, || volume.locate_needle_blob(needle_id))?),
             None => Err(VolumeError::NotFound(vid).into()),
         }
     }
 
     pub async fn write_volume_needle(&self, vid: VolumeId, needle: &mut Needle) -> Result<usize> {
+        self.write_volume_needle_if_match(vid, needle, None, true)
+            .await
+    }
+
+    /// write the needle only if the etag of the stored needle matches `if_match`, the check and
+    /// the write are done in the write queue of the volume, so concurrent writers can not
+    /// interleave between them. if overwrites are protected, an existing needle with other
+    /// content is only overwritten with `replace`. an unconditional upload identical to one being
+    /// written is acknowledged with the outcome of that write.
+    pub async fn write_volume_needle_if_match(
+        &self,
+        vid: VolumeId,
+        needle: &mut Needle,
+        if_match: Option<&str>,
+        replace: bool,
+    ) -> Result<usize> {
+        if if_match.is_some() {
+            return self.write_queued(vid, needle, if_match, replace).await;
+        }
+        match self.in_flight_writes.join(vid, needle) {
+            InFlight::Leader(guard) => {
+                let result = self.write_queued(vid, needle, None, replace).await;
+                guard.finish(match result {
+                    Ok(size) => WriteOutcome::Written(size),
+                    Err(_) => WriteOutcome::Failed,
+                });
+                result
+            }
+            InFlight::Follower(mut outcome) => {
+                let outcome = outcome
+                    .wait_for(|outcome| *outcome != WriteOutcome::Pending)
+                    .await
+                    .map(|outcome| *outcome);
+                if let Ok(WriteOutcome::Written(size)) = outcome {
+                    debug!(
+                        "volume {vid}: needle {} is written by an identical upload",
+                        needle.id
+                    );
+                    return Ok(size);
+                }
+                self.write_queued(vid, needle, None, replace).await
+            }
+            InFlight::Other => self.write_queued(vid, needle, None, replace).await,
+        }
+    }
+
+    async fn write_queued(
+        &self,
+        vid: VolumeId,
+        needle: &mut Needle,
+        if_match: Option<&str>,
+        replace: bool,
+    ) -> Result<usize> {
         match self.find_volume(vid) {
             Some(volume) => {
+                if volume.quarantined() {
+                    return Err(VolumeError::Quarantined(vid).into());
+                }
                 if volume.readonly() {
                     return Err(VolumeError::Readonly(vid).into());
                 }
+                // do not hold the volume ref while waiting in the queue
+                drop(volume);
 
-                Ok(volume.write_needle(needle)?)
+                let queue = self.write_queues.get(vid);
+                let ticket = queue.enter(vid).await?;
+                match self.find_volume(vid) {
+                    Some(volume) => {
+                        if let Some(if_match) = if_match {
+                            let mut current = Needle {
+                                id: needle.id,
+                                ..Default::default()
+                            };
+                            let etag = volume
+                                .read_needle(&mut current)
+                                .ok()
+                                .map(|_| current.etag());
+                            if !etag_matches(if_match, etag.as_deref()) {
+                                return Err(NeedleError::PreconditionFailed(
+                                    if_match.to_string(),
+                                    etag,
+                                )
+                                .into());
+                            }
+                        }
+                        if self.protect_overwrite
+                            && !replace
+                            && volume.contains_needle(needle.id)?
+                        {
+                            // a retried upload of the same content is acknowledged, the stored
+                            // needle is only read back when it would be a conflict otherwise
+                            if volume.is_file_unchanged(needle) {
+                                return Ok(needle.data_size());
+                            }
+                            return Err(NeedleError::Exists(needle.id).into());
+                        }
+                        let size = self.isolate(&volume, || volume.write_needle(needle))?;
+                        let fsync = self.fsync(vid, &volume)?;
+                        let sealed = self.seal_if_full(vid, &volume);
+                        drop(volume);
+                        // later writes can be appended while this one waits for its fsync
+                        drop(ticket);
+                        if sealed {
+                            self.delta_volume_tx.seal_volume(vid).await;
+                        }
+                        if let Some((queue, sequence)) = fsync {
+                            queue.wait(vid, sequence).await?;
+                        }
+                        Ok(size)
+                    }
+                    None => Err(VolumeError::NotFound(vid).into()),
+                }
             }
             None => Err(VolumeError::NotFound(vid).into()),
         }
     }
 
+    /// start flushing a write of `volume` according to the durability policy, returns what to
+    /// wait for before the write is acknowledged
+    fn fsync(&self, vid: VolumeId, volume: &Volume) -> Result<Option<(Arc<FsyncQueue>, u64)>> {
+        if self.durability == Durability::Buffered {
+            return Ok(None);
+        }
+        let queue = self.fsync_queues.get(vid);
+        let sequence = queue.written();
+        match volume.index_file()? {
+            Some(index_file) => queue.schedule(vid, &[volume.data_file()?, index_file])?,
+            None => queue.schedule(vid, &[volume.data_file()?])?,
+        }
+        match self.durability {
+            Durability::Sync => Ok(Some((queue, sequence))),
+            _ => Ok(None),
+        }
+    }
+
     pub async fn delete_volume(&self, vid: VolumeId) -> Result<()> {
         let volume = self.find_volume(vid);
         if volume.is_none() {
@@ -191,8 +463,11 @@ impl Store {
                             as u32,
                         version: volume.version() as u32,
                         ttl: volume.super_block.ttl.to_u32(),
+                        disk_type: location.disk_type.as_str().to_string(),
                     })
                     .await;
+                self.write_queues.remove(vid);
+                self.fsync_queues.remove(vid);
                 return Ok(());
             }
         }
@@ -209,10 +484,63 @@ impl Store {
         }
     }
 
-    async fn find_free_location(&self) -> Result<Option<&DiskLocation>> {
+    pub async fn mark_volume_writable(&self, volume_id: VolumeId) -> StdResult<(), VolumeError> {
+        match self.find_volume(volume_id) {
+            Some(volume) => {
+                volume.set_no_write_or_delete(false);
+                Ok(())
+            }
+            None => Err(VolumeError::NotFound(volume_id)),
+        }
+    }
+
+    /// seal the volume once its data file reaches the volume size limit, writes are rejected
+    /// locally from then on, deletes are still allowed. Returns whether the volume was sealed
+    /// by this call.
+    fn seal_if_full(&self, vid: VolumeId, volume: &Volume) -> bool {
+        let volume_size_limit = self.volume_size_limit();
+        if volume_size_limit == 0 || volume.readonly() {
+            return false;
+        }
+        match volume.data_file_size() {
+            Ok(size) if size >= volume_size_limit => {
+                if let Err(err) = volume.set_sealed(true) {
+                    warn!("persist the seal of volume {vid} failed: {err}");
+                }
+                info!("volume {vid} reaches size limit {volume_size_limit}, size: {size}, sealed");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// a sealed volume is writable again if compaction brought it under the size limit, other
+    /// readonly flags stay as they are
+    fn unseal_if_shrunk(&self, vid: VolumeId, volume: &Volume) {
+        if !volume.sealed() {
+            return;
+        }
+        if let Ok(size) = volume.data_file_size() {
+            if size < self.volume_size_limit() {
+                match volume.set_sealed(false) {
+                    Ok(()) => info!("volume {vid} shrinks to {size}, unsealed"),
+                    Err(err) => warn!("remove the seal of volume {vid} failed: {err}"),
+                }
+            }
+        }
+    }
+
+    /// the location with the most free slots, only locations of `disk_type` if it is set
+    async fn find_free_location(
+        &self,
+        disk_type: Option<DiskType>,
+    ) -> Result<Option<&DiskLocation>> {
         let mut disk_location = None;
         let mut max_free: i64 = 0;
         for location in self.locations.iter() {
+            if disk_type.is_some_and(|disk_type| disk_type != location.disk_type) {
+                continue;
+            }
             let free = location.max_volume_count - location.volumes.len() as i64;
             if free > max_free {
                 max_free = free;
@@ -231,6 +559,8 @@ impl Store {
         replica_placement: ReplicaPlacement,
         ttl: Ttl,
         preallocate: i64,
+        disk_type: Option<DiskType>,
+        alignment: u32,
     ) -> Result<()> {
         debug!(
             "add volume: {}, collection: {}, ttl: {}, replica placement: {}",
@@ -241,7 +571,7 @@ impl Store {
         }
 
         let location = self
-            .find_free_location()
+            .find_free_location(disk_type)
             .await?
             .ok_or::<Error>(anyhow!("no more free space left"))?;
 
@@ -253,7 +583,9 @@ impl Store {
             replica_placement,
             ttl,
             preallocate,
-        )?;
+            alignment,
+        )?
+        .with_read_bloom_filter(self.read_bloom_filter);
 
         let version = volume.version();
         location.add_volume(vid, volume);
@@ -265,6 +597,7 @@ impl Store {
                 replica_placement: Into::<u8>::into(replica_placement) as u32,
                 version: version as u32,
                 ttl: ttl.to_u32(),
+                disk_type: location.disk_type.as_str().to_string(),
             })
             .await;
         Ok(())
@@ -278,9 +611,17 @@ impl Store {
         replica_placement: String,
         ttl: String,
         preallocate: i64,
+        disk_type: String,
+        needle_alignment: u32,
     ) -> Result<()> {
         let rp = ReplicaPlacement::new(&replica_placement)?;
         let ttl = Ttl::new(&ttl)?;
+        let disk_type = parse_disk_type(&disk_type)?;
+        // 0 leaves the alignment to the volume server
+        let alignment = match needle_alignment {
+            0 => self.needle_alignment,
+            alignment => alignment,
+        };
 
         let collection = FastStr::new(collection);
         self.do_add_volume(
@@ -290,11 +631,102 @@ impl Store {
             rp,
             ttl,
             preallocate,
+            disk_type,
+            alignment,
         )
         .await?;
         Ok(())
     }
 
+    /// Copy the index and data file of a volume from `source` and mount it. The index file is
+    /// copied first, every needle it references is already in the data file copied after it.
+    pub async fn copy_volume(
+        &self,
+        vid: VolumeId,
+        collection: FastStr,
+        source: &str,
+        needle_map_type: NeedleMapType,
+        disk_type: Option<DiskType>,
+    ) -> Result<()> {
+        if self.find_volume(vid).is_some() {
+            return Err(anyhow!("volume id {} already exists!", vid));
+        }
+        let location = self
+            .find_free_location(disk_type)
+            .await?
+            .ok_or::<Error>(anyhow!("no more free space left"))?;
+
+        let mut base_filename = location.directory.to_string();
+        if !base_filename.ends_with('/') {
+            base_filename.push('/');
+        }
+        if !collection.is_empty() {
+            base_filename.push_str(&format!("{collection}_"));
+        }
+        base_filename.push_str(&vid.to_string());
+
+        let client = volume_server_client(source)?;
+        let mut copied = vec![];
+        for ext in [IDX_FILE_SUFFIX, DATA_FILE_SUFFIX] {
+            // the data file is renamed last, the volume is not loaded on restart until it is there
+            let tmp_filename = format!("{base_filename}.{ext}.copying");
+            let request = CopyFileRequest {
+                volume_id: vid,
+                ext: ext.to_string(),
+            };
+            let result = async {
+                let mut stream = client.copy_file(request).await?.into_inner();
+                let path = tmp_filename.clone();
+                let mut file = spawn_io(IoClass::Background, move || File::create(path)).await??;
+                while let Some(response) = stream.message().await? {
+                    file = spawn_io(IoClass::Background, move || {
+                        file.write_all(&response.file_content).map(|_| file)
+                    })
+                    .await??;
+                }
+                spawn_io(IoClass::Fsync, move || file.sync_all()).await??;
+                Ok::<(), Error>(())
+            }
+            .await;
+            if let Err(err) = result {
+                let _ = fs::remove_file(&tmp_filename);
+                for (tmp_filename, _) in copied {
+                    let _ = fs::remove_file(tmp_filename);
+                }
+                error!("copy volume {vid} {ext} file from {source} failed, error: {err}");
+                return Err(err);
+            }
+            copied.push((tmp_filename, format!("{base_filename}.{ext}")));
+        }
+        for (tmp_filename, filename) in copied {
+            fs::rename(tmp_filename, filename)?;
+        }
+
+        let volume = Volume::new(
+            location.directory.clone(),
+            collection.clone(),
+            vid,
+            needle_map_type,
+            ReplicaPlacement::default(),
+            Ttl::default(),
+            0,
+            NEEDLE_PADDING_SIZE,
+        )?
+        .with_read_bloom_filter(self.read_bloom_filter);
+        let message = VolumeShortInformationMessage {
+            id: vid,
+            collection: collection.to_string(),
+            replica_placement: Into::<u8>::into(volume.super_block.replica_placement) as u32,
+            version: volume.version() as u32,
+            ttl: volume.super_block.ttl.to_u32(),
+            disk_type: location.disk_type.as_str().to_string(),
+        };
+        location.add_volume(vid, volume);
+        self.delta_volume_tx.add_volume(message).await;
+        info!("copy volume {vid} from {source} success");
+        Ok(())
+    }
+
     pub fn collect_heartbeat(&self) -> Result<HeartbeatRequest> {
         let mut heartbeat = HeartbeatRequest::default();
 
@@ -303,6 +735,10 @@ impl Store {
         for location in self.locations.iter() {
             let mut deleted_vids = Vec::new();
             max_volume_count += location.max_volume_count;
+            *heartbeat
+                .max_volume_counts
+                .entry(location.disk_type.as_str().to_string())
+                .or_default() += location.max_volume_count as u32;
             for volume in location.volumes.iter() {
                 let vid = volume.key();
                 let volume_max_file_key = volume.max_file_key();
@@ -319,10 +755,12 @@ impl Store {
                         file_count: volume.file_count(),
                         delete_count: volume.deleted_count(),
                         deleted_bytes: volume.deleted_bytes(),
-                        read_only: volume.no_write_or_delete(),
+                        read_only: volume.readonly(),
                         replica_placement: rp as u32,
                         version: volume.version() as u32,
                         ttl: volume.super_block.ttl.into(),
+                        disk_type: location.disk_type.as_str().to_string(),
+                        quarantined: volume.quarantined(),
                     };
                     heartbeat.volumes.push(msg);
                 } else if volume.expired_long_enough(MAX_TTL_VOLUME_REMOVAL_DELAY_MINUTES) {
@@ -348,6 +786,12 @@ impl Store {
         heartbeat.rack = self.rack.to_string();
         heartbeat.has_no_volumes = heartbeat.volumes.is_empty();
         heartbeat.has_no_ec_shards = heartbeat.ec_shards.is_empty();
+        heartbeat.usages = self.usage.snapshot();
+
+        let capabilities = Capabilities::local();
+        heartbeat.version = capabilities.version.to_string();
+        heartbeat.features = capabilities.feature_list();
+        heartbeat.protocol_version = PROTOCOL_VERSION;
 
         Ok(heartbeat)
     }
@@ -372,7 +816,7 @@ impl Store {
         match self.find_volume(vid) {
             Some(volume) => {
                 // TODO: check disk status
-                volume.compact()?;
+                self.isolate(&volume, || volume.compact())?;
                 info!("volume {vid} compacting success.");
                 Ok(())
             }
@@ -388,6 +832,7 @@ impl Store {
             Some(mut volume) => {
                 // TODO: check disk status
                 volume.commit_compact()?;
+                self.unseal_if_shrunk(vid, &volume);
                 info!("volume {vid} committing compaction success.");
                 Ok(())
             }
@@ -398,6 +843,70 @@ impl Store {
         }
     }
 
+    /// quarantine a volume suspected to be corrupted or lift its quarantine, the master learns
+    /// about it from the heartbeat sent right away
+    pub async fn quarantine_volume(&self, vid: VolumeId, quarantined: bool) -> Result<()> {
+        match self.find_volume(vid) {
+            Some(volume) => {
+                volume.set_quarantined(quarantined)?;
+                drop(volume);
+                if quarantined {
+                    warn!("volume {vid} is quarantined");
+                } else {
+                    info!("volume {vid} is released from quarantine");
+                }
+                self.delta_volume_tx.quarantine_volume(vid).await;
+                Ok(())
+            }
+            None => Err(VolumeError::NotFound(vid).into()),
+        }
+    }
+
+    /// run an operation on `volume`, an integrity error quarantines the volume and a panic fails
+    /// the operation instead of unwinding into the request or taking down the server. the master
+    /// learns about the quarantine from the heartbeat sent right away.
+    fn isolate<T, F>(&self, volume: &Volume, f: F) -> StdResult<T, VolumeError>
+    where
+        F: FnOnce() -> StdResult<T, VolumeError>,
+    {
+        let vid = volume.id();
+        match catch_unwind(AssertUnwindSafe(f)) {
+            Ok(Err(err)) if err.code() == ErrorCode::DataCorrupted => {
+                error!("volume {vid} is corrupted: {err}, quarantine it");
+                match volume.set_quarantined(true) {
+                    Ok(()) => self.delta_volume_tx.try_quarantine_volume(vid),
+                    Err(err) => error!("quarantine volume {vid} failed: {err}"),
+                }
+                Err(err)
+            }
+            Ok(result) => result,
+            Err(panic) => {
+                let message = panic_message(panic.as_ref());
+                error!("volume {vid} panicked: {message}");
+                Err(VolumeError::Panicked(vid, message))
+            }
+        }
+    }
+
+    pub fn is_volume_quarantined(&self, vid: VolumeId) -> bool {
+        self.find_volume(vid)
+            .map_or(false, |volume| volume.quarantined())
+    }
+
+    /// compact and commit a volume at once, the data file and the index only keep live needles
+    pub fn vacuum_volume(&self, vid: VolumeId) -> Result<IndexCompaction> {
+        self.compact_volume(vid, 0)?;
+        match self.find_volume_mut(vid) {
+            Some(mut volume) => {
+                let compaction = volume.commit_compact()?;
+                self.unseal_if_shrunk(vid, &volume);
+                info!("volume {vid} vacuum success.");
+                Ok(compaction)
+            }
+            None => Err(VolumeError::NotFound(vid).into()),
+        }
+    }
+
     pub fn commit_cleanup_volume(&self, vid: VolumeId) -> Result<()> {
         match self.find_volume(vid) {
             Some(volume) => {
@@ -415,13 +924,123 @@ impl Store {
 
 pub type StoreRef = Arc<Store>;
 
+/// an empty disk type from the master means any disk
+pub fn parse_disk_type(disk_type: &str) -> StdResult<Option<DiskType>, VolumeError> {
+    if disk_type.is_empty() {
+        return Ok(None);
+    }
+    DiskType::new(disk_type).map(Some)
+}
+
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::{path::Path, result::Result as StdResult, sync::Arc, time::Duration};
 
+    use bytes::Bytes;
+    use clap::Parser;
     use futures::{channel::mpsc::channel, SinkExt, StreamExt};
+    use tempfile::Builder;
     use tokio::time::timeout;
 
+    use crate::{
+        errors::ErrorCode,
+        storage::{crc, store::Store, Needle, NeedleMapType, VolumeError},
+        util::{
+            args::{Command, Opts},
+            chan::delta_volume_channel,
+        },
+    };
+
+    async fn setup_store(dir: &Path, args: &[&str]) -> Store {
+        let mut argv = vec![
+            "helyim".to_string(),
+            "volume".to_string(),
+            format!("--folders={}", dir.display()),
+        ];
+        argv.extend(args.iter().map(|arg| arg.to_string()));
+        let Command::Volume(options) = Opts::parse_from(argv).command else {
+            unreachable!()
+        };
+        let (delta_volume_tx, _delta_volume_rx) = delta_volume_channel();
+        let store = Store::new(
+            Arc::new(options),
+            NeedleMapType::NeedleMapInMemory,
+            delta_volume_tx,
+        )
+        .await
+        .unwrap();
+        store
+            .add_volume(
+                1,
+                String::new(),
+                NeedleMapType::NeedleMapInMemory,
+                "000".to_string(),
+                String::new(),
+                0,
+                String::new(),
+                0,
+            )
+            .await
+            .unwrap();
+        store
+    }
+
+    #[tokio::test]
+    pub async fn test_panic_isolation() {
+        let dir = Builder::new()
+            .prefix("panic_isolation")
+            .tempdir_in(".")
+            .unwrap();
+        let store = setup_store(dir.path(), &[]).await;
+
+        let volume = store.find_volume(1).unwrap();
+        let result: StdResult<(), VolumeError> = store.isolate(&volume, || panic!("boom"));
+        assert!(matches!(result, Err(VolumeError::Panicked(1, _))));
+        // a bug is not a sign of corrupted data
+        assert!(!volume.quarantined());
+
+        let result: StdResult<(), VolumeError> = store.isolate(&volume, || {
+            Err(VolumeError::DataIntegrity("bad needle".to_string()))
+        });
+        assert!(matches!(result, Err(VolumeError::DataIntegrity(_))));
+        assert!(volume.quarantined());
+    }
+
+    #[tokio::test]
+    pub async fn test_protect_overwrite() {
+        let dir = Builder::new()
+            .prefix("protect_overwrite")
+            .tempdir_in(".")
+            .unwrap();
+        let store = setup_store(dir.path(), &["--protect-overwrite"]).await;
+
+        let needle = |data: &'static [u8]| Needle {
+            id: 1,
+            checksum: crc::checksum(data),
+            data: Bytes::from_static(data),
+            ..Default::default()
+        };
+        store
+            .write_volume_needle_if_match(1, &mut needle(b"first"), None, false)
+            .await
+            .unwrap();
+        // the same content is acknowledged again
+        store
+            .write_volume_needle_if_match(1, &mut needle(b"first"), None, false)
+            .await
+            .unwrap();
+
+        let err = store
+            .write_volume_needle_if_match(1, &mut needle(b"second"), None, false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+        store
+            .write_volume_needle_if_match(1, &mut needle(b"second"), None, true)
+            .await
+            .unwrap();
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     pub async fn test_async_scope() {
         let timeout = timeout(Duration::from_secs(1), async {
@@ -3,8 +3,9 @@ use std::{
     fmt::{Debug, Display, Formatter},
 };
 
-use crate::storage::needle::{
-    NEEDLE_CHECKSUM_SIZE, NEEDLE_HEADER_SIZE, NEEDLE_PADDING_SIZE, TOMBSTONE_FILE_SIZE,
+use crate::storage::{
+    needle::{NEEDLE_CHECKSUM_SIZE, NEEDLE_HEADER_SIZE, TOMBSTONE_FILE_SIZE},
+    VolumeError,
 };
 
 macro_rules! def_needle_type {
@@ -43,15 +44,14 @@ pub type NeedleId = u64;
 
 def_needle_type!(Offset, u32);
 
+/// Offsets count units of the needle alignment of their volume.
 impl Offset {
-    pub fn actual_offset(&self) -> u64 {
-        (self.0 * NEEDLE_PADDING_SIZE) as u64
+    pub fn actual_offset(&self, alignment: u32) -> u64 {
+        self.0 as u64 * alignment as u64
     }
-}
 
-impl From<u64> for Offset {
-    fn from(value: u64) -> Self {
-        Self(value as u32 / NEEDLE_PADDING_SIZE)
+    pub fn from_actual(offset: u64, alignment: u32) -> Self {
+        Self((offset / alignment as u64) as u32)
     }
 }
 
@@ -62,26 +62,52 @@ impl Size {
         self.0 < 0 || self.0 == TOMBSTONE_FILE_SIZE
     }
 
-    pub fn padding_len(&self) -> u32 {
-        NEEDLE_PADDING_SIZE
-            - ((NEEDLE_HEADER_SIZE + self.0 as u32 + NEEDLE_CHECKSUM_SIZE) % NEEDLE_PADDING_SIZE)
+    pub fn padding_len(&self, alignment: u32) -> u32 {
+        alignment - (self.unpadded_size() % alignment as u64) as u32
+    }
+
+    pub fn actual_size(&self, alignment: u32) -> u64 {
+        self.unpadded_size() + self.padding_len(alignment) as u64
     }
 
-    pub fn actual_size(&self) -> u64 {
-        (NEEDLE_HEADER_SIZE + self.0 as u32 + NEEDLE_CHECKSUM_SIZE + self.padding_len()) as u64
+    /// computed in u64, the size of a corrupted entry must not overflow
+    fn unpadded_size(&self) -> u64 {
+        NEEDLE_HEADER_SIZE as u64 + self.0 as u32 as u64 + NEEDLE_CHECKSUM_SIZE as u64
     }
 }
 
 pub type Cookie = u32;
 
-#[derive(Copy, Clone)]
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum DiskType {
     // Hard disk drive,
+    #[default]
     Hdd,
     // Solid state drive
     Ssd,
 }
 
+impl DiskType {
+    /// parse the disk type of a volume server directory or an assign request, empty is hdd
+    pub fn new(disk_type: &str) -> Result<Self, VolumeError> {
+        match disk_type.to_lowercase().as_str() {
+            "" | "hdd" => Ok(DiskType::Hdd),
+            "ssd" => Ok(DiskType::Ssd),
+            other => Err(VolumeError::String(format!("unknown disk type: {other}"))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiskType::Hdd => "hdd",
+            DiskType::Ssd => "ssd",
+        }
+    }
+}
+
 impl Display for DiskType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
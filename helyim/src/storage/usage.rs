@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashMap;
+use faststr::FastStr;
+use helyim_proto::directory::CollectionUsageMessage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageKind {
+    Read,
+    Write,
+    Delete,
+}
+
+#[derive(Debug, Default)]
+struct RequestCounters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    deletes: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+/// requests served per collection since the volume server started, replicated writes and
+/// deletes are counted on every replica
+#[derive(Debug, Default)]
+pub struct UsageCounters {
+    collections: DashMap<FastStr, RequestCounters>,
+}
+
+impl UsageCounters {
+    pub fn record(&self, collection: &FastStr, kind: UsageKind, bytes: u64) {
+        let counters = match self.collections.get(collection) {
+            Some(counters) => counters,
+            None => self
+                .collections
+                .entry(collection.clone())
+                .or_default()
+                .downgrade(),
+        };
+        match kind {
+            UsageKind::Read => {
+                counters.reads.fetch_add(1, Ordering::Relaxed);
+                counters.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+            }
+            UsageKind::Write => {
+                counters.writes.fetch_add(1, Ordering::Relaxed);
+                counters.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+            }
+            UsageKind::Delete => {
+                counters.deletes.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<CollectionUsageMessage> {
+        let mut usages: Vec<CollectionUsageMessage> = self
+            .collections
+            .iter()
+            .map(|counters| CollectionUsageMessage {
+                collection: counters.key().to_string(),
+                reads: counters.reads.load(Ordering::Relaxed),
+                writes: counters.writes.load(Ordering::Relaxed),
+                deletes: counters.deletes.load(Ordering::Relaxed),
+                bytes_read: counters.bytes_read.load(Ordering::Relaxed),
+                bytes_written: counters.bytes_written.load(Ordering::Relaxed),
+            })
+            .collect();
+        usages.sort_by(|a, b| a.collection.cmp(&b.collection));
+        usages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use faststr::FastStr;
+
+    use crate::storage::usage::{UsageCounters, UsageKind};
+
+    #[test]
+    pub fn test_usage_counters() {
+        let counters = UsageCounters::default();
+        let pictures = FastStr::new("pictures");
+        counters.record(&pictures, UsageKind::Write, 100);
+        counters.record(&pictures, UsageKind::Read, 100);
+        counters.record(&pictures, UsageKind::Read, 50);
+        counters.record(&FastStr::empty(), UsageKind::Delete, 0);
+
+        let usages = counters.snapshot();
+        assert_eq!(usages.len(), 2);
+        assert_eq!(usages[0].collection, "");
+        assert_eq!(usages[0].deletes, 1);
+        assert_eq!(usages[1].collection, "pictures");
+        assert_eq!(usages[1].reads, 2);
+        assert_eq!(usages[1].bytes_read, 150);
+        assert_eq!(usages[1].writes, 1);
+        assert_eq!(usages[1].bytes_written, 100);
+    }
+}
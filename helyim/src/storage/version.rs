@@ -1,6 +1,8 @@
 pub type Version = u8;
 
+/// the needle layout of helyim, new volumes are created with it
 pub const VERSION2: Version = 2;
+/// the needle layout of SeaweedFS volumes, such volumes are loaded read only
 pub const VERSION3: Version = 3;
 
 pub const CURRENT_VERSION: Version = VERSION2;
@@ -1,12 +1,18 @@
-use std::{fs::File, os::unix::fs::FileExt};
-
-use crate::storage::{
-    needle::NEEDLE_INDEX_SIZE,
-    read_index_entry,
-    types::{Offset, Size},
-    version::Version,
-    volume::Volume,
-    Needle, NeedleId, VolumeError,
+use std::fs::File;
+
+use bytes::Buf;
+
+use crate::{
+    storage::{
+        crc,
+        needle::{read_needle_blob, NEEDLE_CHECKSUM_SIZE, NEEDLE_HEADER_SIZE, NEEDLE_INDEX_SIZE},
+        read_index_entry,
+        types::{Offset, Size},
+        version::Version,
+        volume::Volume,
+        Needle, NeedleError, NeedleId, VolumeError,
+    },
+    util::file::FileExt,
 };
 
 pub fn verify_index_file_integrity(index_file: &File) -> Result<u64, VolumeError> {
@@ -32,7 +38,14 @@ pub fn check_volume_data_integrity(volume: &Volume, index_file: &File) -> Result
         return Ok(());
     }
     let version = volume.version();
-    verify_needle_integrity(volume.data_file()?, version, key, offset, size)
+    verify_needle_integrity(
+        volume.data_file()?,
+        version,
+        volume.alignment(),
+        key,
+        offset,
+        size,
+    )
 }
 
 pub fn read_index_entry_at_offset(index_file: &File, offset: u64) -> Result<Vec<u8>, VolumeError> {
@@ -44,12 +57,13 @@ pub fn read_index_entry_at_offset(index_file: &File, offset: u64) -> Result<Vec<
 fn verify_needle_integrity(
     data_file: &File,
     version: Version,
+    alignment: u32,
     key: NeedleId,
     offset: Offset,
     size: Size,
 ) -> Result<(), VolumeError> {
     let mut needle = Needle::default();
-    needle.read_data(data_file, offset, size, version)?;
+    needle.read_data(data_file, offset, size, version, alignment)?;
     if needle.id != key {
         return Err(VolumeError::DataIntegrity(format!(
             "index key {key} does not match needle's id {}",
@@ -59,6 +73,73 @@ fn verify_needle_integrity(
     Ok(())
 }
 
+/// Result of checking one needle on disk against its index entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NeedleVerification {
+    pub offset: u64,
+    pub index_size: Size,
+    /// id and size in the needle header on disk
+    pub header_id: NeedleId,
+    pub header_size: Size,
+    pub stored_checksum: u32,
+    /// `None` if the data size in the needle body is out of range
+    pub computed_checksum: Option<u32>,
+}
+
+impl NeedleVerification {
+    /// the needle header matches the index entry
+    pub fn index_ok(&self, key: NeedleId) -> bool {
+        self.header_id == key && self.header_size == self.index_size
+    }
+
+    pub fn checksum_ok(&self) -> bool {
+        self.computed_checksum == Some(self.stored_checksum)
+    }
+}
+
+impl Volume {
+    /// read the needle of `key` without trusting anything but the index entry, the checksum is
+    /// recomputed from the data on disk
+    pub fn verify_needle(&self, key: NeedleId) -> Result<NeedleVerification, VolumeError> {
+        let _lock = self.data_file_lock.read();
+        let nv = match self.get_index(key)? {
+            Some(nv) if nv.offset != 0 && !nv.size.is_deleted() => nv,
+            Some(_) => return Err(NeedleError::Deleted(self.id, key).into()),
+            None => return Err(NeedleError::NotFound(key).into()),
+        };
+        let blob = read_needle_blob(self.data_file()?, nv.offset, nv.size, self.alignment())?;
+
+        let mut header = &blob[..NEEDLE_HEADER_SIZE as usize];
+        header.advance(4);
+        let header_id = header.get_u64();
+        let header_size = Size(header.get_i32());
+
+        let body = &blob[NEEDLE_HEADER_SIZE as usize..];
+        let body_size = nv.size.0 as usize;
+        let stored_checksum =
+            (&body[body_size..body_size + NEEDLE_CHECKSUM_SIZE as usize]).get_u32();
+        let version = self.version();
+        let computed_checksum = if body_size == 0 {
+            Some(crc::needle_checksum(version, &[]))
+        } else if body_size >= 4 {
+            let data_size = (&body[..4]).get_u32() as usize;
+            (data_size <= body_size - 4)
+                .then(|| crc::needle_checksum(version, &body[4..4 + data_size]))
+        } else {
+            None
+        };
+
+        Ok(NeedleVerification {
+            offset: nv.offset.actual_offset(self.alignment()),
+            index_size: nv.size,
+            header_id,
+            header_size,
+            stored_checksum,
+            computed_checksum,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use bytes::Bytes;
@@ -66,10 +147,14 @@ mod tests {
     use rand::random;
     use tempfile::Builder;
 
-    use crate::storage::{
-        crc,
-        volume::{checking::check_volume_data_integrity, Volume},
-        FileId, Needle, NeedleMapType, ReplicaPlacement, Ttl,
+    use crate::{
+        storage::{
+            crc,
+            needle::{NEEDLE_HEADER_SIZE, NEEDLE_PADDING_SIZE},
+            volume::{checking::check_volume_data_integrity, Volume},
+            FileId, Needle, NeedleMapType, ReplicaPlacement, Ttl,
+        },
+        util::file::FileExt,
     };
 
     #[test]
@@ -87,6 +172,7 @@ mod tests {
             ReplicaPlacement::default(),
             Ttl::default(),
             0,
+            NEEDLE_PADDING_SIZE,
         )
         .unwrap();
 
@@ -112,4 +198,49 @@ mod tests {
 
         assert!(check_volume_data_integrity(&volume, &index_file).is_ok());
     }
+
+    #[test]
+    pub fn test_verify_needle() {
+        let dir = Builder::new()
+            .prefix("verify_needle")
+            .tempdir_in(".")
+            .unwrap();
+        let dir = FastStr::new(dir.path().to_str().unwrap());
+        let volume = Volume::new(
+            dir,
+            FastStr::empty(),
+            1,
+            NeedleMapType::NeedleMapInMemory,
+            ReplicaPlacement::default(),
+            Ttl::default(),
+            0,
+            NEEDLE_PADDING_SIZE,
+        )
+        .unwrap();
+
+        let data = Bytes::from_static(b"Hello World");
+        let mut needle = Needle {
+            checksum: crc::checksum(&data),
+            data,
+            ..Default::default()
+        };
+        needle.parse_path(&format!("{:x}{:08x}", 1, 1)).unwrap();
+        volume.write_needle(&mut needle).unwrap();
+
+        let verification = volume.verify_needle(1).unwrap();
+        assert!(verification.index_ok(1));
+        assert!(verification.checksum_ok());
+
+        // flip the first byte of the data
+        let data_offset = verification.offset + NEEDLE_HEADER_SIZE as u64 + 4;
+        volume
+            .data_file()
+            .unwrap()
+            .write_all_at(b"h", data_offset)
+            .unwrap();
+        let verification = volume.verify_needle(1).unwrap();
+        assert!(verification.index_ok(1));
+        assert!(!verification.checksum_ok());
+        assert!(!verification.index_ok(2));
+    }
 }
@@ -2,7 +2,6 @@ use std::{
     fmt::Display,
     fs::{self, metadata, File},
     io::ErrorKind,
-    os::unix::fs::{FileExt, OpenOptionsExt},
     path::Path,
     sync::{
         atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering},
@@ -11,33 +10,35 @@ use std::{
     time::SystemTimeError,
 };
 
-use axum::{
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    Json,
-};
+use axum::response::{IntoResponse, Response};
 use bytes::{Buf, BufMut};
 use faststr::FastStr;
 use parking_lot::RwLock;
-use rustix::fs::ftruncate;
-use serde_json::json;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use crate::{
+    errors::{Error, ErrorCode},
     storage::{
         needle::{
-            read_needle_header, Needle, NeedleMapType, NeedleMapper, NeedleValue,
-            NEEDLE_PADDING_SIZE,
+            max_volume_size, read_needle_header, timestamp_len, Needle, NeedleMapType,
+            NeedleMapper, NeedleValue, MAX_NEEDLE_ALIGNMENT, NEEDLE_PADDING_SIZE,
         },
         ttl::Ttl,
-        version::{Version, CURRENT_VERSION},
+        version::{Version, CURRENT_VERSION, VERSION2, VERSION3},
         volume::checking::check_volume_data_integrity,
         VolumeId,
     },
-    util::time::{get_time, now},
+    util::{
+        file::{FileExt, OpenOptionsExt},
+        time::{get_time, now},
+    },
 };
 
 mod checking;
+pub use checking::NeedleVerification;
+
+#[cfg(test)]
+mod crash;
 
 mod replica_placement;
 
@@ -62,6 +63,10 @@ pub const DATA_FILE_SUFFIX: &str = "dat";
 pub const COMPACT_DATA_FILE_SUFFIX: &str = "cpd";
 pub const IDX_FILE_SUFFIX: &str = "idx";
 pub const COMPACT_IDX_FILE_SUFFIX: &str = "cpx";
+/// marker file of a quarantined volume, so the quarantine survives restarts
+pub const QUARANTINE_FILE_SUFFIX: &str = "qrt";
+/// marker file of a volume sealed at the size limit, so it stays sealed across restarts
+pub const SEAL_FILE_SUFFIX: &str = "sld";
 
 #[derive(Debug)]
 pub struct SuperBlock {
@@ -69,6 +74,11 @@ pub struct SuperBlock {
     pub replica_placement: ReplicaPlacement,
     pub ttl: Ttl,
     compact_revision: AtomicU16,
+    /// needles start at multiples of it, offsets in the index count it, so a larger alignment
+    /// addresses a larger data file at the cost of more padding
+    pub alignment: u32,
+    /// length of the extra block SeaweedFS keeps after the super block, the needles follow it
+    pub extra_size: u16,
 }
 
 impl Default for SuperBlock {
@@ -78,21 +88,52 @@ impl Default for SuperBlock {
             replica_placement: ReplicaPlacement::default(),
             ttl: Ttl::default(),
             compact_revision: AtomicU16::new(0),
+            alignment: NEEDLE_PADDING_SIZE,
+            extra_size: 0,
         }
     }
 }
 
+/// a needle alignment is a power of two from the default 8 up to `MAX_NEEDLE_ALIGNMENT` bytes
+pub fn check_needle_alignment(alignment: u32) -> Result<(), VolumeError> {
+    if alignment.is_power_of_two()
+        && (NEEDLE_PADDING_SIZE..=MAX_NEEDLE_ALIGNMENT).contains(&alignment)
+    {
+        Ok(())
+    } else {
+        Err(VolumeError::String(format!(
+            "needle alignment should be a power of two in [{NEEDLE_PADDING_SIZE}, \
+             {MAX_NEEDLE_ALIGNMENT}], got {alignment}"
+        )))
+    }
+}
+
 impl SuperBlock {
     pub fn parse(buf: [u8; SUPER_BLOCK_SIZE]) -> Result<SuperBlock, VolumeError> {
         let rp = ReplicaPlacement::from_u8(buf[1])?;
         let ttl = Ttl::from_bytes(&buf[2..4])?;
         let compact_revision = (&buf[4..6]).get_u16();
         let compact_revision = AtomicU16::new(compact_revision);
+        let (alignment, extra_size) = match buf[0] {
+            // stored as the shift from the default alignment, 0 in super blocks written before
+            VERSION2 => (
+                NEEDLE_PADDING_SIZE
+                    .checked_shl(buf[6] as u32)
+                    .unwrap_or_default(),
+                0,
+            ),
+            // SeaweedFS volumes have the default alignment and the length of the extra block
+            VERSION3 => (NEEDLE_PADDING_SIZE, (&buf[6..8]).get_u16()),
+            version => return Err(NeedleError::UnsupportedVersion(version).into()),
+        };
+        check_needle_alignment(alignment)?;
         Ok(SuperBlock {
             version: buf[0],
             replica_placement: rp,
             ttl,
             compact_revision,
+            alignment,
+            extra_size,
         })
     }
 
@@ -107,9 +148,19 @@ impl SuperBlock {
             idx += 1;
         }
         (&mut buf[4..6]).put_u16(self.compact_revision());
+        if self.version == VERSION3 {
+            (&mut buf[6..8]).put_u16(self.extra_size);
+        } else {
+            buf[6] = (self.alignment / NEEDLE_PADDING_SIZE).trailing_zeros() as u8;
+        }
         buf
     }
 
+    /// offset of the first needle, right after the super block and its extra block
+    pub fn data_start(&self) -> u64 {
+        (SUPER_BLOCK_SIZE as u64 + self.extra_size as u64).next_multiple_of(self.alignment as u64)
+    }
+
     pub fn compact_revision(&self) -> u16 {
         self.compact_revision.load(Ordering::Relaxed)
     }
@@ -130,10 +181,13 @@ pub struct Volume {
 
     needle_mapper: Option<NeedleMapper>,
     needle_map_type: NeedleMapType,
+    read_bloom_filter: bool,
     pub super_block: Arc<SuperBlock>,
 
     no_write_or_delete: Arc<AtomicBool>,
     no_write_can_delete: Arc<AtomicBool>,
+    quarantined: Arc<AtomicBool>,
+    sealed: Arc<AtomicBool>,
     is_compacting: Arc<AtomicBool>,
 
     last_modified: Arc<AtomicU64>,
@@ -165,10 +219,13 @@ impl Volume {
         replica_placement: ReplicaPlacement,
         ttl: Ttl,
         _preallocate: i64,
+        alignment: u32,
     ) -> Result<Volume, VolumeError> {
+        check_needle_alignment(alignment)?;
         let sb = SuperBlock {
             replica_placement,
             ttl,
+            alignment,
             ..Default::default()
         };
 
@@ -181,8 +238,11 @@ impl Volume {
             data_file_lock: RwLock::new(()),
             needle_map_type,
             needle_mapper: None,
+            read_bloom_filter: false,
             no_write_or_delete: Arc::new(AtomicBool::new(false)),
             no_write_can_delete: Arc::new(AtomicBool::new(false)),
+            quarantined: Arc::new(AtomicBool::new(false)),
+            sealed: Arc::new(AtomicBool::new(false)),
             is_compacting: Arc::new(AtomicBool::new(false)),
 
             last_compact_index_offset: Arc::new(AtomicU64::new(0)),
@@ -195,6 +255,15 @@ impl Volume {
         Ok(v)
     }
 
+    /// keep a bloom filter of the needle keys, reads of keys never written skip the needle map
+    pub fn with_read_bloom_filter(mut self, enabled: bool) -> Self {
+        self.read_bloom_filter = enabled;
+        if let (true, Some(needle_mapper)) = (enabled, self.needle_mapper.as_mut()) {
+            needle_mapper.enable_bloom_filter();
+        }
+        self
+    }
+
     pub fn load(&mut self, create_if_missing: bool, load_index: bool) -> Result<(), VolumeError> {
         let _lock = self.data_file_lock.write();
 
@@ -221,51 +290,74 @@ impl Volume {
                         .create(true)
                         .truncate(true)
                         .mode(0o644)
-                        .open(&name)?;
+                        .open(&name)
+                        .map_err(VolumeError::file(self.id, &name))?;
                     info!("create volume {} data file success", self.id);
-                    metadata(&name)?
+                    metadata(&name).map_err(VolumeError::file(self.id, &name))?
                 } else {
-                    return Err(VolumeError::Io(err));
+                    return Err(VolumeError::file(self.id, &name)(err));
                 }
             }
         };
 
         let file = if meta.permissions().readonly() {
             self.set_no_write_or_delete(true);
-            fs::OpenOptions::new().read(true).open(&name)?
+            fs::OpenOptions::new()
+                .read(true)
+                .open(&name)
+                .map_err(VolumeError::file(self.id, &name))?
         } else {
             self.set_last_modified(get_time(meta.modified()?)?.as_secs());
             fs::OpenOptions::new()
                 .read(true)
                 .write(true)
                 .mode(0o644)
-                .open(&name)?
+                .open(&name)
+                .map_err(VolumeError::file(self.id, &name))?
         };
 
         self.data_file = Some(file);
+        if Path::new(&self.quarantine_filename()).exists() {
+            warn!("volume {} is quarantined", self.id);
+            self.quarantined.store(true, Ordering::Relaxed);
+        }
+        if Path::new(&self.seal_filename()).exists() {
+            info!("volume {} is sealed", self.id);
+            self.sealed.store(true, Ordering::Relaxed);
+        }
 
         if has_super_block {
             let super_block = self.read_super_block()?;
+            // needles of other versions are read but never appended
+            if super_block.version != CURRENT_VERSION {
+                info!(
+                    "volume {} has version {}, it is loaded read only",
+                    self.id, super_block.version
+                );
+                self.set_no_write_or_delete(true);
+            }
             self.super_block = Arc::new(super_block);
         } else {
             self.write_super_block()?;
         }
 
         if load_index {
+            let index_filename = self.index_filename();
             let index_file = if self.no_write_or_delete() {
                 fs::OpenOptions::new()
                     .read(true)
                     .mode(0o644)
-                    .open(self.index_filename())?
+                    .open(&index_filename)
             } else {
                 fs::OpenOptions::new()
                     .read(true)
                     .create(true)
-                    .truncate(true)
+                    .truncate(false)
                     .write(true)
                     .mode(0o644)
-                    .open(self.index_filename())?
-            };
+                    .open(&index_filename)
+            }
+            .map_err(VolumeError::file(self.id, &index_filename))?;
 
             if let Err(err) = check_volume_data_integrity(self, &index_file) {
                 self.set_no_write_or_delete(true);
@@ -276,13 +368,12 @@ impl Volume {
                 );
             }
 
-            let needle_mapper = if self.no_write_or_delete() || self.no_write_can_delete() {
-                todo!("load index file from sorted file")
-            } else {
-                let mut needle_mapper = NeedleMapper::new(self.id, self.needle_map_type);
-                needle_mapper.load_index_file(index_file)?;
-                needle_mapper
-            };
+            // a volume failing the integrity check is readonly, but its index is still loaded so
+            // the needles before the damaged tail stay readable
+            let mut needle_mapper =
+                NeedleMapper::new(self.id, self.needle_map_type, self.alignment())
+                    .with_bloom_filter(self.read_bloom_filter);
+            needle_mapper.load_index_file(index_file)?;
             self.needle_mapper = Some(needle_mapper);
             info!("load index file `{}` success", self.index_filename());
         }
@@ -292,6 +383,9 @@ impl Volume {
 
     pub fn write_needle(&self, needle: &mut Needle) -> Result<usize, VolumeError> {
         let volume_id = self.id;
+        if self.quarantined() {
+            return Err(VolumeError::Quarantined(volume_id));
+        }
         if self.readonly() {
             return Err(VolumeError::Readonly(volume_id));
         }
@@ -302,20 +396,27 @@ impl Volume {
             let _lock = self.data_file_lock.write();
             let file = self.data_file()?;
 
-            let offset = append_needle_at(file)?;
-            if let Err(err) = needle.append(file, offset, version) {
+            let offset = append_needle_at(file, self.alignment())?;
+            // offsets beyond it can not be kept in the index
+            let max_size = max_volume_size(self.alignment());
+            if offset >= max_size {
+                return Err(VolumeError::VolumeSizeLimit(max_size, offset));
+            }
+            if let Err(err) = needle.append(file, offset, version, self.alignment()) {
                 error!(
-                    "volume {volume_id}: write needle {} error: {err}, will do ftruncate.",
+                    "volume {volume_id}: write needle {} error: {err}, will truncate data file.",
                     needle.id
                 );
-                ftruncate(file, offset)?;
-                return Err(err.into());
+                file.set_len(offset)?;
+                return Err(VolumeError::NeedleAt {
+                    volume: volume_id,
+                    needle: needle.id,
+                    offset,
+                    source: err,
+                });
             }
 
-            let nv = NeedleValue {
-                offset: offset.into(),
-                size: needle.size,
-            };
+            let nv = NeedleValue::new(Offset::from_actual(offset, self.alignment()), needle.size);
             self.set_index(needle.id, nv)?;
         }
 
@@ -327,7 +428,10 @@ impl Volume {
     }
 
     pub fn delete_needle(&self, needle: &mut Needle) -> Result<usize, VolumeError> {
-        if self.readonly() {
+        if self.quarantined() {
+            return Err(VolumeError::Quarantined(self.id));
+        }
+        if self.no_write_or_delete() {
             return Err(VolumeError::Readonly(self.id));
         }
 
@@ -342,8 +446,8 @@ impl Volume {
             let version = self.version();
             let file = self.data_file()?;
 
-            let offset = append_needle_at(file)?;
-            needle.append(file, offset, version)?;
+            let offset = append_needle_at(file, self.alignment())?;
+            needle.append(file, offset, version, self.alignment())?;
 
             self.delete_index(needle.id)?;
         }
@@ -352,6 +456,10 @@ impl Volume {
     }
 
     pub fn read_needle(&self, needle: &mut Needle) -> Result<usize, VolumeError> {
+        // absent keys, like the ones of an enumeration, never take the data file lock
+        if !self.needle_mapper()?.may_contain(needle.id) {
+            return Err(NeedleError::NotFound(needle.id).into());
+        }
         let _lock = self.data_file_lock.read();
 
         match self.get_index(needle.id)? {
@@ -363,36 +471,149 @@ impl Volume {
                 let version = self.version();
 
                 let data_file = self.data_file()?;
-                needle.read_data(data_file, nv.offset, nv.size, version)?;
+                needle
+                    .read_data(data_file, nv.offset, nv.size, version, self.alignment())
+                    .map_err(|err| VolumeError::NeedleAt {
+                        volume: self.id,
+                        needle: needle.id,
+                        offset: nv.offset.actual_offset(self.alignment()),
+                        source: err,
+                    })?;
+
+                self.check_needle_expired(needle)?;
+                Ok(needle.data_size())
+            }
+            None => {
+                debug!("needle {} is not found, volume: {}", needle.id, self.id);
+                Err(NeedleError::NotFound(needle.id).into())
+            }
+        }
+    }
 
-                let data_size = needle.data_size();
-                if !needle.has_ttl() {
-                    return Ok(data_size);
-                }
-                let minutes = needle.ttl.minutes();
-                if minutes == 0 {
-                    return Ok(data_size);
+    /// whether `needle` is identical to the stored needle with the same id, writing it again would
+    /// only append garbage. retried uploads of a ttl needle are always written to refresh it.
+    pub fn is_file_unchanged(&self, needle: &Needle) -> bool {
+        if self.super_block.ttl.minutes() != 0 || needle.has_ttl() {
+            return false;
+        }
+        let mut stored = Needle {
+            id: needle.id,
+            ..Default::default()
+        };
+        if self.read_needle(&mut stored).is_err() {
+            return false;
+        }
+        stored.cookie == needle.cookie
+            && stored.checksum == needle.checksum
+            && stored.data == needle.data
+            && stored.name == needle.name
+            && stored.mime == needle.mime
+            && stored.pairs == needle.pairs
+    }
+
+    /// Locate the data of a large uncompressed needle, so that it can be streamed from the data
+    /// file directly instead of being loaded into memory.
+    ///
+    /// Returns `None` if the needle is smaller than `min_size` or gzipped, and the caller should
+    /// fall back to `read_needle`.
+    pub fn locate_needle_data(
+        &self,
+        needle: &mut Needle,
+        min_size: u64,
+    ) -> Result<Option<(File, u64, Version)>, VolumeError> {
+        if !self.needle_mapper()?.may_contain(needle.id) {
+            return Err(NeedleError::NotFound(needle.id).into());
+        }
+        let _lock = self.data_file_lock.read();
+
+        match self.get_index(needle.id)? {
+            Some(nv) => {
+                if nv.offset == 0 || nv.size.is_deleted() {
+                    return Err(NeedleError::Deleted(self.id, needle.id).into());
                 }
-                if !needle.has_last_modified_date() {
-                    return Ok(data_size);
+                if (nv.size.0 as u64) < min_size {
+                    return Ok(None);
                 }
-                if now().as_secs() < (needle.last_modified + minutes as u64 * 60) {
-                    return Ok(data_size);
+
+                let version = self.version();
+                let data_file = self.data_file()?;
+                let data_offset =
+                    needle.read_meta(data_file, nv.offset, nv.size, version, self.alignment())?;
+                if needle.is_gzipped() {
+                    return Ok(None);
                 }
-                error!("needle {} is expired, volume: {}", needle.id, self.id);
-                Err(NeedleError::Expired(self.id, needle.id).into())
+
+                self.check_needle_expired(needle)?;
+                Ok(Some((data_file.try_clone()?, data_offset, version)))
             }
             None => {
-                error!("needle {} is not found, volume: {}", needle.id, self.id);
+                debug!("needle {} is not found, volume: {}", needle.id, self.id);
                 Err(NeedleError::NotFound(needle.id).into())
             }
         }
     }
 
+    /// the data file, offset and size of the stored record of `needle_id`, so it can be streamed
+    /// without being loaded into memory. the file stays readable even if a vacuum replaces it.
+    pub fn locate_needle_blob(&self, needle_id: NeedleId) -> Result<(File, u64, u64), VolumeError> {
+        if !self.needle_mapper()?.may_contain(needle_id) {
+            return Err(NeedleError::NotFound(needle_id).into());
+        }
+        let _lock = self.data_file_lock.read();
+
+        match self.get_index(needle_id)? {
+            Some(nv) if nv.offset != 0 && !nv.size.is_deleted() => {
+                let mut needle = Needle {
+                    id: needle_id,
+                    ..Default::default()
+                };
+                let data_file = self.data_file()?;
+                needle.read_meta(
+                    data_file,
+                    nv.offset,
+                    nv.size,
+                    self.version(),
+                    self.alignment(),
+                )?;
+                self.check_needle_expired(&needle)?;
+                Ok((
+                    data_file.try_clone()?,
+                    nv.offset.actual_offset(self.alignment()),
+                    nv.size.actual_size(self.alignment()) + timestamp_len(self.version()) as u64,
+                ))
+            }
+            Some(_) => Err(NeedleError::Deleted(self.id, needle_id).into()),
+            None => Err(NeedleError::NotFound(needle_id).into()),
+        }
+    }
+
+    fn check_needle_expired(&self, needle: &Needle) -> Result<(), VolumeError> {
+        if !needle.has_ttl() || !needle.has_last_modified_date() {
+            return Ok(());
+        }
+        let minutes = needle.ttl.minutes();
+        if minutes == 0 {
+            return Ok(());
+        }
+        if now().as_secs() < (needle.last_modified + minutes as u64 * 60) {
+            return Ok(());
+        }
+        error!("needle {} is expired, volume: {}", needle.id, self.id);
+        Err(NeedleError::Expired(self.id, needle.id).into())
+    }
+
+    pub fn id(&self) -> VolumeId {
+        self.id
+    }
+
     pub fn version(&self) -> Version {
         self.super_block.version
     }
 
+    pub fn alignment(&self) -> u32 {
+        self.super_block.alignment
+    }
+
     pub fn filename(&self) -> String {
         let mut dirname = self.dir.to_string();
         if !dirname.ends_with('/') {
@@ -417,6 +638,8 @@ impl Volume {
             delete_count: self.deleted_count() as i64,
             delete_bytes: self.deleted_bytes(),
             read_only: self.readonly(),
+            quarantined: self.quarantined(),
+            ..Default::default()
         }
     }
 
@@ -427,6 +650,12 @@ impl Volume {
 
         fs::remove_file(Path::new(&self.data_filename()))?;
         fs::remove_file(Path::new(&self.index_filename()))?;
+        if self.quarantined() {
+            fs::remove_file(Path::new(&self.quarantine_filename()))?;
+        }
+        if self.sealed() {
+            fs::remove_file(Path::new(&self.seal_filename()))?;
+        }
 
         Ok(())
     }
@@ -444,6 +673,11 @@ impl Volume {
         }
     }
 
+    /// the `.idx` file, `None` before the index is loaded
+    pub fn index_file(&self) -> Result<Option<&File>, VolumeError> {
+        Ok(self.needle_mapper()?.index_file())
+    }
+
     pub fn data_filename(&self) -> String {
         format!("{}.{DATA_FILE_SUFFIX}", self.filename())
     }
@@ -452,6 +686,14 @@ impl Volume {
         format!("{}.{IDX_FILE_SUFFIX}", self.filename())
     }
 
+    pub fn quarantine_filename(&self) -> String {
+        format!("{}.{QUARANTINE_FILE_SUFFIX}", self.filename())
+    }
+
+    pub fn seal_filename(&self) -> String {
+        format!("{}.{SEAL_FILE_SUFFIX}", self.filename())
+    }
+
     /// volume is expired if modified time + volume ttl < now
     /// except when volume is empty
     /// or when the volume does not have a ttl
@@ -548,6 +790,17 @@ impl Volume {
         Ok(self.needle_mapper()?.get(key))
     }
 
+    /// whether a live needle of `key` is in the needle map, the data file is not read
+    pub fn contains_needle(&self, key: NeedleId) -> Result<bool, VolumeError> {
+        let needle_mapper = self.needle_mapper()?;
+        if !needle_mapper.may_contain(key) {
+            return Ok(false);
+        }
+        Ok(needle_mapper
+            .get(key)
+            .map_or(false, |nv| nv.offset != 0 && !nv.size.is_deleted()))
+    }
+
     pub fn delete_index(&self, key: NeedleId) -> Result<Option<NeedleValue>, VolumeError> {
         self.needle_mapper()?.delete(key)
     }
@@ -576,6 +829,13 @@ impl Volume {
         }
     }
 
+    /// raise the max file key, it is reported to master by heartbeat, so the sequencer skips it
+    pub fn bump_max_file_key(&self, key: u64) -> Result<(), VolumeError> {
+        let _lock = self.data_file_lock.read();
+        self.needle_mapper()?.maybe_max_file_key(key);
+        Ok(())
+    }
+
     pub fn file_count(&self) -> u64 {
         let _lock = self.data_file_lock.read();
         match self.needle_mapper() {
@@ -629,6 +889,44 @@ impl Volume {
     pub fn readonly(&self) -> bool {
         self.no_write_or_delete.load(Ordering::Relaxed)
             || self.no_write_can_delete.load(Ordering::Relaxed)
+            || self.quarantined.load(Ordering::Relaxed)
+            || self.sealed.load(Ordering::Relaxed)
+    }
+
+    /// a sealed volume reached the volume size limit, it takes no more writes but deletes until
+    /// compaction brings it under the limit again
+    pub fn sealed(&self) -> bool {
+        self.sealed.load(Ordering::Relaxed)
+    }
+
+    /// the flag is set even if the marker file can not be written, the volume is then only sealed
+    /// until the next restart
+    pub fn set_sealed(&self, sealed: bool) -> Result<(), VolumeError> {
+        self.sealed.store(sealed, Ordering::Relaxed);
+        let marker = self.seal_filename();
+        if sealed {
+            fs::write(&marker, now().as_secs().to_string())?;
+        } else if Path::new(&marker).exists() {
+            fs::remove_file(&marker)?;
+        }
+        Ok(())
+    }
+
+    /// a quarantined volume is suspected to be corrupted, it is still readable but it is not
+    /// written, deleted from or vacuumed until the quarantine is lifted
+    pub fn quarantined(&self) -> bool {
+        self.quarantined.load(Ordering::Relaxed)
+    }
+
+    pub fn set_quarantined(&self, quarantined: bool) -> Result<(), VolumeError> {
+        let marker = self.quarantine_filename();
+        if quarantined {
+            fs::write(&marker, now().as_secs().to_string())?;
+        } else if Path::new(&marker).exists() {
+            fs::remove_file(&marker)?;
+        }
+        self.quarantined.store(quarantined, Ordering::Relaxed);
+        Ok(())
     }
 
     pub fn set_last_modified(&self, last_modified: u64) {
@@ -680,6 +978,7 @@ pub enum VolumeError {
     #[error("Parse integer error: {0}")]
     ParseInt(#[from] std::num::ParseIntError),
 
+    #[cfg(unix)]
     #[error("Errno: {0}")]
     Errno(#[from] rustix::io::Errno),
     #[error("Serde json error: {0}")]
@@ -693,6 +992,29 @@ pub enum VolumeError {
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
 
+    #[error("Volume {volume}: {path}: {source}")]
+    File {
+        volume: VolumeId,
+        path: FastStr,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Volume {volume}: needle {needle} at offset {offset}: {source}")]
+    NeedleAt {
+        volume: VolumeId,
+        needle: NeedleId,
+        offset: u64,
+        #[source]
+        source: NeedleError,
+    },
+    #[error("Volume {volume}: compact revision is {actual} but {expected} is expected")]
+    CompactRevision {
+        volume: VolumeId,
+        expected: u16,
+        actual: u16,
+    },
+    #[error("Invalid volume id: {0:?}")]
+    InvalidVolumeId(String),
     #[error("Invalid replica placement: {0}")]
     ReplicaPlacement(String),
     #[error("No writable volumes.")]
@@ -709,6 +1031,16 @@ pub enum VolumeError {
     Readonly(VolumeId),
     #[error("Volume {0} is compacting.")]
     Compacting(VolumeId),
+    #[error("Volume {0} is quarantined.")]
+    Quarantined(VolumeId),
+    #[error("Volume {0} panicked: {1}")]
+    Panicked(VolumeId, String),
+    #[error("Too many pending writes on volume {0}.")]
+    WriteQueueFull(VolumeId),
+    #[error("Volume {0}: fsync failed: {1}")]
+    Fsync(VolumeId, FastStr),
+    #[error("Volume {0}: {1} of {2} required copies are written")]
+    Replication(VolumeId, usize, usize),
     #[error("Needle error: {0}")]
     Needle(#[from] NeedleError),
     #[error("Ttl error: {0}")]
@@ -721,8 +1053,14 @@ pub enum VolumeError {
     VolumeSizeLimit(u64, u64),
     #[error("Wrong node type")]
     WrongNodeType,
+    #[error("Data node {0} is not found.")]
+    DataNodeNotFound(FastStr),
+    #[error("Data node {0} does not support features {1:?}")]
+    UnsupportedFeatures(FastStr, Vec<FastStr>),
     #[error("Master not found")]
     MasterNotFound,
+    #[error("Waiting for declared data nodes to join: {0:?}")]
+    Bootstrapping(Vec<FastStr>),
 
     // heartbeat
     #[error("Start heartbeat failed.")]
@@ -745,14 +1083,93 @@ impl From<nom::Err<nom::error::Error<&str>>> for VolumeError {
     }
 }
 
+impl VolumeError {
+    /// attach the volume and the file being accessed to an io error
+    pub fn file<P: AsRef<str>>(volume: VolumeId, path: P) -> impl FnOnce(std::io::Error) -> Self {
+        let path = FastStr::new(path.as_ref());
+        move |source| VolumeError::File {
+            volume,
+            path,
+            source,
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            VolumeError::NotFound(_) | VolumeError::NotLoad(_) => ErrorCode::VolumeNotFound,
+            VolumeError::Readonly(_) | VolumeError::VolumeSizeLimit(..) => ErrorCode::ReadOnly,
+            VolumeError::Compacting(_) => ErrorCode::Compacting,
+            VolumeError::Quarantined(_) => ErrorCode::Quarantined,
+            VolumeError::WriteQueueFull(_) => ErrorCode::WriteQueueFull,
+            VolumeError::NoWritableVolumes => ErrorCode::NoWritableVolumes,
+            VolumeError::NoFreeSpace(_) => ErrorCode::NoFreeSpace,
+            VolumeError::DataIntegrity(_) => ErrorCode::DataCorrupted,
+            VolumeError::Bootstrapping(_) => ErrorCode::Bootstrapping,
+            VolumeError::Replication(..) => ErrorCode::ReplicationIncomplete,
+            VolumeError::LeaderChanged(..) | VolumeError::MasterNotFound => ErrorCode::NotLeader,
+            VolumeError::Needle(err) | VolumeError::NeedleAt { source: err, .. } => err.code(),
+            VolumeError::String(_)
+            | VolumeError::InvalidVolumeId(_)
+            | VolumeError::ParseInt(_)
+            | VolumeError::SerdeJson(_)
+            | VolumeError::ReplicaPlacement(_)
+            | VolumeError::Ttl(_)
+            | VolumeError::HasLoaded(_)
+            | VolumeError::UnsupportedFeatures(..) => ErrorCode::BadRequest,
+            VolumeError::Io(_)
+            | VolumeError::Box(_)
+            | VolumeError::SystemTimeError(_)
+            | VolumeError::TonicStatus(_)
+            | VolumeError::TonicTransport(_)
+            | VolumeError::TaskJoin(_)
+            | VolumeError::Reqwest(_)
+            | VolumeError::File { .. }
+            | VolumeError::CompactRevision { .. }
+            | VolumeError::Panicked(..)
+            | VolumeError::Fsync(..)
+            | VolumeError::NeedleMapperNotLoad(_)
+            | VolumeError::WrongNodeType
+            | VolumeError::DataNodeNotFound(_)
+            | VolumeError::StartHeartbeat
+            | VolumeError::SendHeartbeat(_) => ErrorCode::Internal,
+            #[cfg(unix)]
+            VolumeError::Errno(_) => ErrorCode::Internal,
+        }
+    }
+
+    pub fn volume_id(&self) -> Option<VolumeId> {
+        match self {
+            VolumeError::NotFound(vid)
+            | VolumeError::NotLoad(vid)
+            | VolumeError::HasLoaded(vid)
+            | VolumeError::Readonly(vid)
+            | VolumeError::Compacting(vid)
+            | VolumeError::Quarantined(vid)
+            | VolumeError::WriteQueueFull(vid)
+            | VolumeError::NeedleMapperNotLoad(vid)
+            | VolumeError::Panicked(vid, _)
+            | VolumeError::Fsync(vid, _)
+            | VolumeError::Replication(vid, ..) => Some(*vid),
+            VolumeError::File { volume, .. }
+            | VolumeError::NeedleAt { volume, .. }
+            | VolumeError::CompactRevision { volume, .. } => Some(*volume),
+            VolumeError::Needle(err) => err.volume_id(),
+            _ => None,
+        }
+    }
+
+    pub fn needle_id(&self) -> Option<NeedleId> {
+        match self {
+            VolumeError::NeedleAt { needle, .. } => Some(*needle),
+            VolumeError::Needle(err) => err.needle_id(),
+            _ => None,
+        }
+    }
+}
+
 impl IntoResponse for VolumeError {
     fn into_response(self) -> Response {
-        let error = self.to_string();
-        let error = json!({
-            "error": error
-        });
-        let response = (StatusCode::BAD_REQUEST, Json(error));
-        response.into_response()
+        Error::Volume(self).into_response()
     }
 }
 
@@ -773,12 +1190,9 @@ fn load_volume_without_index(
     Ok(volume)
 }
 
-fn append_needle_at(file: &File) -> std::io::Result<u64> {
-    let mut offset = file.metadata()?.len();
-    if offset % NEEDLE_PADDING_SIZE as u64 != 0 {
-        offset = offset + (NEEDLE_PADDING_SIZE as u64 - offset % NEEDLE_PADDING_SIZE as u64);
-    }
-    Ok(offset)
+fn append_needle_at(file: &File, alignment: u32) -> std::io::Result<u64> {
+    let offset = file.metadata()?.len();
+    Ok(offset.next_multiple_of(alignment as u64))
 }
 
 pub fn scan_volume_file<VSB, VN>(
@@ -798,9 +1212,11 @@ where
     visit_super_block(&volume.super_block)?;
 
     let version = volume.version();
-    let mut offset = SUPER_BLOCK_SIZE as u64;
+    let alignment = volume.alignment();
+    let mut offset = volume.super_block.data_start();
 
-    let (mut needle, mut rest) = read_needle_header(volume.data_file()?, version, offset)?;
+    let (mut needle, mut rest) =
+        read_needle_header(volume.data_file()?, version, offset, alignment)?;
 
     let data_file = volume.data_file()?;
     loop {
@@ -827,7 +1243,7 @@ where
         offset += (NEEDLE_ENTRY_SIZE + rest) as u64;
 
         info!("new entry offset: {offset}");
-        match read_needle_header(data_file, version, offset) {
+        match read_needle_header(data_file, version, offset, alignment) {
             Ok((n, body_len)) => {
                 needle = n;
                 rest = body_len;
@@ -851,21 +1267,35 @@ where
 
 #[cfg(test)]
 pub mod tests {
-    use std::sync::Arc;
+    use std::{path::Path, sync::Arc};
 
-    use bytes::Bytes;
+    use bytes::{BufMut, Bytes};
     use faststr::FastStr;
     use rand::random;
     use tempfile::Builder;
 
-    use crate::storage::{
-        crc,
-        needle::NeedleMapType,
-        volume::{scan_volume_file, SuperBlock, Volume, VolumeError},
-        FileId, Needle, ReplicaPlacement, Ttl,
+    use crate::{
+        storage::{
+            crc,
+            needle::{
+                NeedleError, NeedleMapType, FLAG_HAS_LAST_MODIFIED_DATE, NEEDLE_PADDING_SIZE,
+                SEAWEEDFS_LAST_MODIFIED_BYTES_LENGTH,
+            },
+            types::Size,
+            version::VERSION3,
+            volume::{
+                load_volume_without_index, scan_volume_file, SuperBlock, Volume, VolumeError,
+            },
+            FileId, Needle, ReplicaPlacement, Ttl,
+        },
+        util::file::FileExt,
     };
 
     pub fn setup(dir: FastStr) -> Volume {
+        setup_with_alignment(dir, NEEDLE_PADDING_SIZE)
+    }
+
+    fn setup_with_alignment(dir: FastStr, alignment: u32) -> Volume {
         let volume = Volume::new(
             dir,
             FastStr::empty(),
@@ -874,6 +1304,7 @@ pub mod tests {
             ReplicaPlacement::default(),
             Ttl::default(),
             0,
+            alignment,
         )
         .unwrap();
 
@@ -895,6 +1326,208 @@ pub mod tests {
         volume
     }
 
+    #[test]
+    pub fn test_error_context() {
+        let dir = Builder::new()
+            .prefix("error_context")
+            .tempdir_in(".")
+            .unwrap();
+        let dir = FastStr::new(dir.path().join("missing").to_str().unwrap());
+        match load_volume_without_index(dir, FastStr::empty(), 7, NeedleMapType::NeedleMapInMemory)
+        {
+            Err(VolumeError::File { volume, path, .. }) => {
+                assert_eq!(volume, 7);
+                assert!(path.ends_with("7.dat"));
+            }
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => panic!("volume should not be loaded"),
+        }
+    }
+
+    #[test]
+    pub fn test_locate_needle_data() {
+        let dir = Builder::new()
+            .prefix("locate_needle_data")
+            .tempdir_in(".")
+            .unwrap();
+        let dir = FastStr::new(dir.path().to_str().unwrap());
+        let volume = setup(dir);
+
+        let fid = FileId::new(volume.id, 1, 0);
+        let mut needle = Needle {
+            id: fid.key,
+            ..Default::default()
+        };
+        let (data_file, offset, _) = volume.locate_needle_data(&mut needle, 0).unwrap().unwrap();
+        let mut data = vec![0u8; needle.data_size as usize];
+        data_file.read_exact_at(&mut data, offset).unwrap();
+        assert_eq!(data, b"Hello World");
+        assert_eq!(needle.checksum, crc::checksum(&data));
+
+        let mut needle = Needle {
+            id: fid.key,
+            ..Default::default()
+        };
+        assert!(volume
+            .locate_needle_data(&mut needle, u64::MAX)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    pub fn test_locate_needle_blob() {
+        let dir = Builder::new()
+            .prefix("locate_needle_blob")
+            .tempdir_in(".")
+            .unwrap();
+        let dir = FastStr::new(dir.path().to_str().unwrap());
+        let volume = setup(dir);
+
+        let fid = FileId::new(volume.id, 1, 0);
+        let (data_file, offset, size) = volume.locate_needle_blob(fid.key).unwrap();
+        assert_eq!(
+            size,
+            volume
+                .get_index(fid.key)
+                .unwrap()
+                .unwrap()
+                .size
+                .actual_size(NEEDLE_PADDING_SIZE)
+        );
+        let mut blob = vec![0u8; size as usize];
+        data_file.read_exact_at(&mut blob, offset).unwrap();
+        let mut needle = Needle::default();
+        needle.parse_needle_header(&blob);
+        assert_eq!(needle.id, fid.key);
+
+        assert!(volume.locate_needle_blob(u64::MAX).is_err());
+
+        // an expired needle is not served
+        let data = Bytes::from_static(b"expired");
+        let mut needle = Needle {
+            checksum: crc::checksum(&data),
+            data,
+            ttl: Ttl::new("1m").unwrap(),
+            last_modified: 1,
+            ..Default::default()
+        };
+        needle.set_has_ttl();
+        needle.set_has_last_modified_date();
+        needle.parse_path(&format!("{:x}{:08x}", 2000, 0)).unwrap();
+        volume.write_needle(&mut needle).unwrap();
+        assert!(matches!(
+            volume.locate_needle_blob(2000),
+            Err(VolumeError::Needle(NeedleError::Expired(1, 2000)))
+        ));
+    }
+
+    #[test]
+    pub fn test_is_file_unchanged() {
+        let dir = Builder::new()
+            .prefix("file_unchanged")
+            .tempdir_in(".")
+            .unwrap();
+        let dir = FastStr::new(dir.path().to_str().unwrap());
+        let volume = setup(dir);
+
+        let data = Bytes::from_static(b"Hello Helyim");
+        let mut needle = Needle {
+            checksum: crc::checksum(&data),
+            data,
+            ..Default::default()
+        };
+        needle.parse_path(&format!("{:x}{:08x}", 1000, 1)).unwrap();
+        assert!(!volume.is_file_unchanged(&needle));
+        volume.write_needle(&mut needle).unwrap();
+        assert!(volume.is_file_unchanged(&needle));
+
+        let data = Bytes::from_static(b"Hello World");
+        let mut changed = Needle {
+            checksum: crc::checksum(&data),
+            data,
+            ..Default::default()
+        };
+        changed.parse_path(&format!("{:x}{:08x}", 1000, 1)).unwrap();
+        assert!(!volume.is_file_unchanged(&changed));
+    }
+
+    #[test]
+    pub fn test_quarantine() {
+        let dir = Builder::new().prefix("quarantine").tempdir_in(".").unwrap();
+        let dir = FastStr::new(dir.path().to_str().unwrap());
+        let volume = setup(dir);
+
+        volume.set_quarantined(true).unwrap();
+        assert!(volume.readonly());
+        assert!(Path::new(&volume.quarantine_filename()).exists());
+
+        let mut needle = Needle {
+            data: Bytes::from_static(b"Hello Helyim"),
+            ..Default::default()
+        };
+        needle.parse_path(&format!("{:x}{:08x}", 1, 0)).unwrap();
+        assert!(matches!(
+            volume.write_needle(&mut needle),
+            Err(VolumeError::Quarantined(_))
+        ));
+        assert!(matches!(
+            volume.delete_needle(&mut needle),
+            Err(VolumeError::Quarantined(_))
+        ));
+        assert!(matches!(volume.compact(), Err(VolumeError::Quarantined(_))));
+
+        volume.set_quarantined(false).unwrap();
+        assert!(!volume.readonly());
+        assert!(!Path::new(&volume.quarantine_filename()).exists());
+        volume.write_needle(&mut needle).unwrap();
+    }
+
+    #[test]
+    pub fn test_seal() {
+        let dir = Builder::new().prefix("seal").tempdir_in(".").unwrap();
+        let dir = FastStr::new(dir.path().to_str().unwrap());
+        let volume = setup(dir.clone());
+
+        volume.set_sealed(true).unwrap();
+        assert!(volume.readonly());
+        let mut needle = Needle {
+            data: Bytes::from_static(b"Hello Helyim"),
+            ..Default::default()
+        };
+        needle.parse_path(&format!("{:x}{:08x}", 1, 0)).unwrap();
+        assert!(matches!(
+            volume.write_needle(&mut needle),
+            Err(VolumeError::Readonly(_))
+        ));
+        // a sealed volume still takes deletes
+        let mut deleted = Needle::default();
+        deleted.parse_path(&format!("{:x}{:08x}", 2, 0)).unwrap();
+        volume.delete_needle(&mut deleted).unwrap();
+
+        // the seal survives a restart
+        drop(volume);
+        let volume = Volume::new(
+            dir,
+            FastStr::empty(),
+            1,
+            NeedleMapType::NeedleMapInMemory,
+            ReplicaPlacement::default(),
+            Ttl::default(),
+            0,
+            NEEDLE_PADDING_SIZE,
+        )
+        .unwrap();
+        assert!(volume.sealed());
+
+        // unsealing keeps a readonly flag set for another reason
+        volume.set_no_write_can_delete(true);
+        volume.set_sealed(false).unwrap();
+        assert!(!Path::new(&volume.seal_filename()).exists());
+        assert!(volume.readonly());
+        volume.set_no_write_can_delete(false);
+        assert!(!volume.readonly());
+    }
+
     #[test]
     pub fn test_scan_volume_file() {
         let dir = Builder::new()
@@ -918,4 +1551,129 @@ pub mod tests {
         )
         .unwrap();
     }
+
+    #[test]
+    pub fn test_needle_alignment() {
+        let dir = Builder::new()
+            .prefix("needle_alignment")
+            .tempdir_in(".")
+            .unwrap();
+        let dir = FastStr::new(dir.path().to_str().unwrap());
+        let volume = setup_with_alignment(dir.clone(), 64);
+        assert_eq!(volume.super_block.data_start(), 64);
+
+        let fid = FileId::new(volume.id, 1, 0);
+        let mut needle = Needle {
+            id: fid.key,
+            ..Default::default()
+        };
+        volume.read_needle(&mut needle).unwrap();
+        assert_eq!(needle.data, Bytes::from_static(b"Hello World"));
+
+        // the alignment is kept in the super block, needles are scanned at multiples of it
+        let mut needles = 0;
+        scan_volume_file(
+            dir.clone(),
+            FastStr::empty(),
+            volume.id,
+            volume.needle_map_type,
+            false,
+            |super_block: &Arc<SuperBlock>| -> Result<(), VolumeError> {
+                assert_eq!(super_block.alignment, 64);
+                Ok(())
+            },
+            |_, offset| -> Result<(), VolumeError> {
+                assert_eq!(offset % 64, 0);
+                needles += 1;
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(needles, 1000);
+
+        let mut buf = SuperBlock::default().as_bytes();
+        assert_eq!(
+            SuperBlock::parse(buf).unwrap().alignment,
+            NEEDLE_PADDING_SIZE
+        );
+        buf[6] = 4;
+        assert!(SuperBlock::parse(buf).is_err());
+        assert!(Volume::new(
+            dir,
+            FastStr::empty(),
+            2,
+            NeedleMapType::NeedleMapInMemory,
+            ReplicaPlacement::default(),
+            Ttl::default(),
+            0,
+            12,
+        )
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_load_seaweedfs_volume() {
+        let dir = Builder::new()
+            .prefix("seaweedfs_volume")
+            .tempdir_in(".")
+            .unwrap();
+        let path = dir.path();
+
+        // version 3 super block followed by a 2 bytes extra block, the first needle is at 16
+        let mut dat = vec![3, 0, 0, 0, 0, 0, 0, 2, 0x08, 0x01];
+        dat.resize(16, 0);
+        let data = b"Hello SeaweedFS";
+        let mut body = Vec::new();
+        body.put_u32(data.len() as u32);
+        body.put_slice(data);
+        body.put_u8(FLAG_HAS_LAST_MODIFIED_DATE);
+        body.put_uint(1_700_000_000, SEAWEEDFS_LAST_MODIFIED_BYTES_LENGTH);
+        let size = Size(body.len() as i32);
+        dat.put_u32(0x1234);
+        dat.put_u64(7);
+        dat.put_i32(size.0);
+        dat.put_slice(&body);
+        dat.put_u32(crc::castagnoli(data));
+        // append time in nanoseconds
+        dat.put_u64(1_700_000_000_000_000_000);
+        dat.put_bytes(0, size.padding_len(NEEDLE_PADDING_SIZE) as usize);
+
+        let mut idx = Vec::new();
+        idx.put_u64(7);
+        idx.put_u32(2);
+        idx.put_i32(size.0);
+        std::fs::write(path.join("pictures_3.dat"), dat).unwrap();
+        std::fs::write(path.join("pictures_3.idx"), idx).unwrap();
+
+        let volume = Volume::new(
+            FastStr::new(path.to_str().unwrap()),
+            FastStr::new("pictures"),
+            3,
+            NeedleMapType::NeedleMapInMemory,
+            ReplicaPlacement::default(),
+            Ttl::default(),
+            0,
+            NEEDLE_PADDING_SIZE,
+        )
+        .unwrap();
+        assert_eq!(volume.version(), VERSION3);
+        assert_eq!(volume.super_block.extra_size, 2);
+        assert_eq!(volume.super_block.data_start(), 16);
+        assert!(volume.readonly());
+
+        let mut needle = Needle {
+            id: 7,
+            ..Default::default()
+        };
+        volume.read_needle(&mut needle).unwrap();
+        assert_eq!(needle.cookie, 0x1234);
+        assert_eq!(needle.data, Bytes::from_static(data));
+        assert_eq!(needle.last_modified, 1_700_000_000);
+        assert!(volume.verify_needle(7).unwrap().checksum_ok());
+        assert!(volume.write_needle(&mut needle).is_err());
+        assert!(volume.compact().is_err());
+
+        let super_block = SuperBlock::parse(volume.super_block.as_bytes()).unwrap();
+        assert_eq!(super_block.extra_size, 2);
+    }
 }
@@ -40,7 +40,7 @@ impl ReplicaPlacement {
     }
 
     pub fn new(s: &str) -> Result<ReplicaPlacement, VolumeError> {
-        if s.len() != 3 {
+        if s.len() != 3 || !s.bytes().all(|b| b.is_ascii_digit()) {
             return Err(VolumeError::ReplicaPlacement(String::from(s)));
         }
 
@@ -3,7 +3,6 @@ use std::{
     fs,
     fs::File,
     io::{Seek, SeekFrom},
-    os::unix::fs::{FileExt, OpenOptionsExt},
     sync::Arc,
 };
 
@@ -17,9 +16,11 @@ use tracing::{debug, error, info};
 use crate::{
     storage::{
         needle::{
-            read_index_entry, read_needle_blob, walk_index_file, NeedleMapper, NEEDLE_INDEX_SIZE,
-            NEEDLE_PADDING_SIZE,
+            compact_index_file, read_index_entry, read_needle_blob, IndexCompaction, NeedleMapper,
+            NEEDLE_INDEX_SIZE,
         },
+        types::Offset,
+        version::CURRENT_VERSION,
         volume::{
             append_needle_at,
             checking::{read_index_entry_at_offset, verify_index_file_integrity},
@@ -29,7 +30,10 @@ use crate::{
         Needle, NeedleError, NeedleValue, VolumeError, VolumeId,
     },
     topology::{volume_layout::VolumeLayoutRef, DataNodeRef},
-    util::time::now,
+    util::{
+        file::{FileExt, OpenOptionsExt},
+        time::now,
+    },
 };
 
 impl Volume {
@@ -41,6 +45,12 @@ impl Volume {
     }
 
     pub fn compact(&self) -> Result<(), VolumeError> {
+        if self.quarantined() {
+            return Err(VolumeError::Quarantined(self.id));
+        }
+        if self.version() != CURRENT_VERSION {
+            return Err(NeedleError::UnsupportedVersion(self.version()).into());
+        }
         let filename = self.filename();
         self.set_last_compact_index_offset(self.index_file_size()?);
         self.set_last_compact_revision(self.super_block.compact_revision());
@@ -68,14 +78,17 @@ impl Volume {
         Ok(())
     }
 
-    pub fn commit_compact(&mut self) -> Result<(), VolumeError> {
+    pub fn commit_compact(&mut self) -> Result<IndexCompaction, VolumeError> {
         let filename = self.filename();
         let compact_data_filename = format!("{}.{COMPACT_DATA_FILE_SUFFIX}", filename);
         let compact_index_filename = format!("{}.{COMPACT_IDX_FILE_SUFFIX}", filename);
         let data_filename = format!("{}.{DATA_FILE_SUFFIX}", filename);
         let index_filename = format!("{}.{IDX_FILE_SUFFIX}", filename);
+        let compacting_index_filename = format!("{}.compacting", index_filename);
+        let index_entries = self.index_file_size()? / NEEDLE_INDEX_SIZE as u64;
         self.set_is_compacting(true);
 
+        let mut compaction = IndexCompaction::default();
         {
             let _lock = self.data_file_lock.write();
 
@@ -87,11 +100,18 @@ impl Volume {
                 &index_filename,
             ) {
                 Ok(()) => {
+                    // makeup diff appends the writes and deletes happened during compaction, drop
+                    // the superseded entries and tombstones so the index only has live needles
+                    compaction =
+                        compact_index_file(&compact_index_filename, &compacting_index_filename)?;
+                    compaction.entries_before = index_entries;
                     fs::rename(&compact_data_filename, data_filename)?;
-                    fs::rename(compact_index_filename, index_filename)?;
+                    fs::rename(compacting_index_filename, index_filename)?;
+                    fs::remove_file(compact_index_filename)?;
                     info!(
                         "makeup diff in commit compaction success, filename: \
-                         {compact_data_filename}"
+                         {compact_data_filename}, index entries: {} -> {}",
+                        compaction.entries_before, compaction.entries_after
                     );
                 }
                 Err(err) => {
@@ -106,7 +126,7 @@ impl Volume {
         }
         self.load(false, true)?;
         self.set_is_compacting(false);
-        Ok(())
+        Ok(compaction)
     }
 
     pub fn cleanup_compact(&self) -> Result<(), std::io::Error> {
@@ -134,11 +154,11 @@ impl Volume {
 
         let old_compact_revision = fetch_compact_revision_from_data_file(&old_data_file)?;
         if old_compact_revision != self.last_compact_revision() {
-            return Err(VolumeError::String(format!(
-                "current old data file's compact revision {old_compact_revision} is not the \
-                 expected one {}",
-                self.last_compact_revision()
-            )));
+            return Err(VolumeError::CompactRevision {
+                volume: self.id,
+                expected: self.last_compact_revision(),
+                actual: old_compact_revision,
+            });
         }
 
         let mut incremented_has_updated_index_entry = HashMap::new();
@@ -151,7 +171,7 @@ impl Volume {
                     let (key, offset, size) = read_index_entry(&idx_entry);
                     incremented_has_updated_index_entry
                         .entry(key)
-                        .or_insert(NeedleValue { offset, size });
+                        .or_insert(NeedleValue::new(offset, size));
 
                     idx_offset -= NEEDLE_INDEX_SIZE as i64;
                 } else {
@@ -174,11 +194,11 @@ impl Volume {
 
             let new_compact_revision = fetch_compact_revision_from_data_file(&new_data_file)?;
             if old_compact_revision + 1 != new_compact_revision {
-                return Err(VolumeError::String(format!(
-                    "old data file {}'s compact revision is {old_compact_revision} while new data \
-                     file {}'s compact revision is {new_compact_revision}",
-                    old_data_filename, new_data_filename
-                )));
+                return Err(VolumeError::CompactRevision {
+                    volume: self.id,
+                    expected: old_compact_revision + 1,
+                    actual: new_compact_revision,
+                });
             }
 
             let mut index_entry_buf = [0u8; 16];
@@ -191,10 +211,10 @@ impl Volume {
                 (&mut index_entry_buf[8..12]).put_u32(value.offset.0);
                 (&mut index_entry_buf[12..16]).put_i32(value.size.0);
 
+                let alignment = self.alignment();
                 let mut offset = new_data_file.metadata()?.len();
-                if offset % NEEDLE_PADDING_SIZE as u64 != 0 {
-                    offset =
-                        offset + (NEEDLE_PADDING_SIZE as u64 - offset % NEEDLE_PADDING_SIZE as u64);
+                if offset % alignment as u64 != 0 {
+                    offset = offset.next_multiple_of(alignment as u64);
 
                     // There is no requirement to add a read lock since there is already a write
                     // lock in place.
@@ -202,9 +222,10 @@ impl Volume {
                 }
 
                 if value.offset != 0 && value.size != 0 {
-                    let needle_bytes = read_needle_blob(&old_data_file, value.offset, value.size)?;
+                    let needle_bytes =
+                        read_needle_blob(&old_data_file, value.offset, value.size, alignment)?;
                     new_data_file.write_all_at(&needle_bytes, offset)?;
-                    (&mut index_entry_buf[8..12]).put_u32(offset as u32 / NEEDLE_PADDING_SIZE);
+                    (&mut index_entry_buf[8..12]).put_u32(Offset::from_actual(offset, alignment).0);
                 } else {
                     let mut fake_del_needle = Needle {
                         id: key,
@@ -212,7 +233,7 @@ impl Volume {
                         ..Default::default()
                     };
                     let version = self.version();
-                    fake_del_needle.append(&new_data_file, offset, version)?;
+                    fake_del_needle.append(&new_data_file, offset, version, alignment)?;
                     (&mut index_entry_buf[8..12]).put_u32(0);
                 }
 
@@ -243,10 +264,11 @@ impl Volume {
             .mode(0o644)
             .open(compact_index_filename)?;
 
-        let mut compact_nm = NeedleMapper::new(self.id, self.needle_map_type);
+        let alignment = self.alignment();
+        let mut compact_nm = NeedleMapper::new(self.id, self.needle_map_type, alignment);
         compact_nm.load_index_file(compact_index_file)?;
 
-        let mut new_offset = SUPER_BLOCK_SIZE as u64;
+        let mut new_offset = self.super_block.data_start();
         let now = now().as_millis() as u64;
         let mut version = self.version();
 
@@ -270,16 +292,16 @@ impl Volume {
                     return Ok(());
                 }
                 if let Some(nv) = self.get_index(needle.id)? {
-                    if nv.offset.actual_offset() == offset && nv.size > 0 {
-                        let nv = NeedleValue {
-                            offset: new_offset.into(),
-                            size: needle.size,
-                        };
+                    if nv.offset.actual_offset(alignment) == offset && nv.size > 0 {
+                        let nv = NeedleValue::new(
+                            Offset::from_actual(new_offset, alignment),
+                            needle.size,
+                        );
                         compact_nm.set(needle.id, nv)?;
 
-                        let offset = append_needle_at(&dst)?;
-                        needle.append(&dst, offset, self.version())?;
-                        new_offset += needle.disk_size();
+                        let offset = append_needle_at(&dst, alignment)?;
+                        needle.append(&dst, offset, self.version(), alignment)?;
+                        new_offset += needle.disk_size(alignment);
                     }
                 }
                 Ok(())
@@ -306,44 +328,33 @@ impl Volume {
             .mode(0o644)
             .open(compact_index_filename)?;
 
-        let mut old_idx_file = fs::OpenOptions::new()
-            .read(true)
-            .mode(0o644)
-            .open(format!("{}.{IDX_FILE_SUFFIX}", self.filename()))?;
-
-        let mut compact_nm = NeedleMapper::new(self.id, self.needle_map_type);
+        let alignment = self.alignment();
+        let mut compact_nm = NeedleMapper::new(self.id, self.needle_map_type, alignment);
         compact_nm.load_index_file(compact_index_file)?;
 
         let now = now().as_millis() as u64;
 
         self.super_block.add_compact_revision(1);
         compact_data_file.write_all_at(&self.super_block.as_bytes(), 0)?;
-        let mut new_offset = SUPER_BLOCK_SIZE as u64;
+        let mut new_offset = self.super_block.data_start();
 
-        walk_index_file(
-            &mut old_idx_file,
-            |key, offset, size| -> Result<(), NeedleError> {
-                if offset == 0 {
+        // live needles are copied in offset order, so the data file is read sequentially
+        self.needle_mapper()?
+            .ascending_visit(|key, nv| -> Result<(), NeedleError> {
+                if nv.offset == 0 || nv.size.is_deleted() {
                     return Ok(());
                 }
 
-                let nv = match self
-                    .get_index(key)
-                    .map_err(|err| NeedleError::Box(err.into()))?
-                {
-                    Some(nv) => nv,
-                    None => return Ok(()),
-                };
-
                 let mut needle = Needle::default();
                 let version = self.version();
 
                 needle.read_data(
                     self.data_file()
                         .map_err(|err| NeedleError::Box(err.into()))?,
-                    offset,
-                    size,
+                    nv.offset,
+                    nv.size,
                     version,
+                    alignment,
                 )?;
 
                 if needle.has_ttl()
@@ -352,21 +363,16 @@ impl Volume {
                     return Ok(());
                 }
 
-                if nv.offset == offset && nv.size > 0 {
-                    let nv = NeedleValue {
-                        offset: new_offset.into(),
-                        size: needle.size,
-                    };
-                    compact_nm
-                        .set(needle.id, nv)
-                        .map_err(|err| NeedleError::Box(err.into()))?;
-                    needle.append(&compact_data_file, new_offset, version)?;
-                    new_offset += needle.disk_size();
-                }
+                let value =
+                    NeedleValue::new(Offset::from_actual(new_offset, alignment), needle.size);
+                compact_nm
+                    .set(key, value)
+                    .map_err(|err| NeedleError::Box(err.into()))?;
+                needle.append(&compact_data_file, new_offset, version, alignment)?;
+                new_offset += needle.disk_size(alignment);
 
                 Ok(())
-            },
-        )?;
+            })?;
 
         Ok(())
     }
@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     errors::Result,
-    storage::{ttl::Ttl, version::Version, volume::ReplicaPlacement, VolumeId},
+    storage::{ttl::Ttl, version::Version, volume::ReplicaPlacement, DiskType, VolumeId},
 };
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -18,6 +18,8 @@ pub struct VolumeInfo {
     pub delete_count: i64,
     pub delete_bytes: u64,
     pub read_only: bool,
+    pub disk_type: DiskType,
+    pub quarantined: bool,
 }
 
 impl VolumeInfo {
@@ -34,6 +36,8 @@ impl VolumeInfo {
             version: m.version as Version,
             ttl: Ttl::from_u32(m.ttl)?,
             replica_placement: rp,
+            disk_type: DiskType::new(&m.disk_type)?,
+            quarantined: m.quarantined,
         })
     }
 
@@ -47,6 +51,7 @@ impl VolumeInfo {
             version: m.version as Version,
             ttl: Ttl::from_u32(m.ttl)?,
             replica_placement: rp,
+            disk_type: DiskType::new(&m.disk_type)?,
             ..Default::default()
         })
     }
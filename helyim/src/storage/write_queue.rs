@@ -0,0 +1,251 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use bytes::Bytes;
+use dashmap::{mapref::entry::Entry, DashMap};
+use tokio::sync::{watch, Semaphore, SemaphorePermit};
+
+use crate::storage::{types::Cookie, Needle, NeedleId, VolumeError, VolumeId};
+
+/// max writes waiting or running against a single volume
+pub const MAX_PENDING_WRITES_PER_VOLUME: usize = 128;
+/// seconds a client is asked to wait when the write queue of a volume is full
+pub const WRITE_QUEUE_RETRY_AFTER_SECS: u64 = 1;
+
+/// Bounded FIFO queue which serializes appends to one volume, a write is rejected instead of
+/// queued when the queue is full.
+pub struct WriteQueue {
+    writer: Semaphore,
+    pending: AtomicUsize,
+    capacity: usize,
+}
+
+impl WriteQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            writer: Semaphore::new(1),
+            pending: AtomicUsize::new(0),
+            capacity,
+        }
+    }
+
+    /// wait for the turn of this write, fails fast if the queue is saturated
+    pub async fn enter(&self, vid: VolumeId) -> Result<WriteTicket<'_>, VolumeError> {
+        if self.pending.fetch_add(1, Ordering::AcqRel) >= self.capacity {
+            self.pending.fetch_sub(1, Ordering::AcqRel);
+            return Err(VolumeError::WriteQueueFull(vid));
+        }
+        // created before waiting, so the pending count is released if the caller is cancelled
+        let mut ticket = WriteTicket {
+            queue: self,
+            permit: None,
+        };
+        // the semaphore is never closed, tokio semaphore is fair, so writes are in arrival order
+        ticket.permit = self.writer.acquire().await.ok();
+        Ok(ticket)
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+}
+
+pub struct WriteTicket<'a> {
+    queue: &'a WriteQueue,
+    permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for WriteTicket<'_> {
+    fn drop(&mut self) {
+        self.queue.pending.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[derive(Default)]
+pub struct WriteQueues {
+    queues: DashMap<VolumeId, Arc<WriteQueue>>,
+}
+
+impl WriteQueues {
+    pub fn get(&self, vid: VolumeId) -> Arc<WriteQueue> {
+        self.queues
+            .entry(vid)
+            .or_insert_with(|| Arc::new(WriteQueue::new(MAX_PENDING_WRITES_PER_VOLUME)))
+            .clone()
+    }
+
+    pub fn remove(&self, vid: VolumeId) {
+        self.queues.remove(&vid);
+    }
+}
+
+/// outcome of a write, shared with the identical uploads waiting for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    Pending,
+    Written(usize),
+    Failed,
+}
+
+/// content of an upload being written
+struct InFlightWrite {
+    cookie: Cookie,
+    checksum: u32,
+    data: Bytes,
+    name: Bytes,
+    mime: Bytes,
+    pairs: Bytes,
+    outcome: watch::Receiver<WriteOutcome>,
+}
+
+impl InFlightWrite {
+    fn same_upload(&self, needle: &Needle) -> bool {
+        self.cookie == needle.cookie
+            && self.checksum == needle.checksum
+            && self.data == needle.data
+            && self.name == needle.name
+            && self.mime == needle.mime
+            && self.pairs == needle.pairs
+    }
+}
+
+pub enum InFlight<'a> {
+    /// this upload writes the needle and shares its outcome
+    Leader(InFlightGuard<'a>),
+    /// an identical upload is being written, wait for its outcome
+    Follower(watch::Receiver<WriteOutcome>),
+    /// an upload of the same file id with other content is being written
+    Other,
+}
+
+/// Uploads being written, an upload identical to one in flight waits for it instead of appending
+/// the same needle again, so a retry storm costs a single write.
+#[derive(Default)]
+pub struct InFlightWrites {
+    writes: DashMap<(VolumeId, NeedleId), InFlightWrite>,
+}
+
+impl InFlightWrites {
+    pub fn join(&self, vid: VolumeId, needle: &Needle) -> InFlight<'_> {
+        let key = (vid, needle.id);
+        match self.writes.entry(key) {
+            Entry::Occupied(entry) if entry.get().same_upload(needle) => {
+                InFlight::Follower(entry.get().outcome.clone())
+            }
+            Entry::Occupied(_) => InFlight::Other,
+            Entry::Vacant(entry) => {
+                let (tx, rx) = watch::channel(WriteOutcome::Pending);
+                entry.insert(InFlightWrite {
+                    cookie: needle.cookie,
+                    checksum: needle.checksum,
+                    data: needle.data.clone(),
+                    name: needle.name.clone(),
+                    mime: needle.mime.clone(),
+                    pairs: needle.pairs.clone(),
+                    outcome: rx,
+                });
+                InFlight::Leader(InFlightGuard {
+                    writes: self,
+                    key,
+                    outcome: tx,
+                })
+            }
+        }
+    }
+}
+
+pub struct InFlightGuard<'a> {
+    writes: &'a InFlightWrites,
+    key: (VolumeId, NeedleId),
+    outcome: watch::Sender<WriteOutcome>,
+}
+
+impl InFlightGuard<'_> {
+    pub fn finish(self, outcome: WriteOutcome) {
+        self.outcome.send_replace(outcome);
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.writes.writes.remove(&self.key);
+        // the waiting uploads of a cancelled write are written on their own
+        self.outcome.send_if_modified(|outcome| {
+            let pending = *outcome == WriteOutcome::Pending;
+            if pending {
+                *outcome = WriteOutcome::Failed;
+            }
+            pending
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::storage::{
+        write_queue::{InFlight, InFlightWrites, WriteOutcome, WriteQueue},
+        Needle, VolumeError,
+    };
+
+    #[tokio::test]
+    async fn test_write_queue_backpressure() {
+        let queue = WriteQueue::new(1);
+        let ticket = queue.enter(1).await.unwrap();
+        assert_eq!(queue.pending(), 1);
+
+        assert!(matches!(
+            queue.enter(1).await,
+            Err(VolumeError::WriteQueueFull(1))
+        ));
+        assert_eq!(queue.pending(), 1);
+
+        drop(ticket);
+        assert_eq!(queue.pending(), 0);
+        let _ticket = queue.enter(1).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_writes() {
+        let writes = InFlightWrites::default();
+        let needle = |data: &'static [u8]| Needle {
+            id: 1,
+            cookie: 2,
+            data: Bytes::from_static(data),
+            ..Default::default()
+        };
+
+        let InFlight::Leader(leader) = writes.join(1, &needle(b"hello")) else {
+            panic!("the first upload writes the needle");
+        };
+        let InFlight::Follower(mut outcome) = writes.join(1, &needle(b"hello")) else {
+            panic!("an identical upload waits for the first one");
+        };
+        assert!(matches!(writes.join(1, &needle(b"world")), InFlight::Other));
+        assert!(matches!(
+            writes.join(2, &needle(b"hello")),
+            InFlight::Leader(_)
+        ));
+
+        leader.finish(WriteOutcome::Written(5));
+        assert!(writes.writes.is_empty());
+        let written = *outcome
+            .wait_for(|outcome| *outcome != WriteOutcome::Pending)
+            .await
+            .unwrap();
+        assert_eq!(written, WriteOutcome::Written(5));
+
+        // the uploads waiting for a cancelled write are not acknowledged
+        let InFlight::Leader(leader) = writes.join(1, &needle(b"hello")) else {
+            panic!("the upload is not in flight anymore");
+        };
+        let InFlight::Follower(outcome) = writes.join(1, &needle(b"hello")) else {
+            panic!("an identical upload waits for the first one");
+        };
+        drop(leader);
+        assert_eq!(*outcome.borrow(), WriteOutcome::Failed);
+    }
+}
@@ -0,0 +1,341 @@
+//! An in-process cluster for integration tests.
+//!
+//! ```ignore
+//! let cluster = Cluster::builder().masters(3).volume_servers(2).start().await?;
+//! let leader = cluster.leader().await?;
+//! ```
+//!
+//! Every server listens on a random port of 127.0.0.1 and stores its data in a temporary
+//! directory, which is removed when the cluster is dropped.
+
+use std::{net::TcpListener, time::Duration};
+
+use clap::Parser;
+use faststr::FastStr;
+use tempfile::TempDir;
+use tokio::time::{sleep, Instant};
+
+use crate::{
+    directory::{DirectoryServer, Sequencer},
+    errors::{Error, Result},
+    storage::{NeedleMapType, VolumeServer},
+    util::{
+        args::{Command, MasterOptions, Opts, VolumeOptions},
+        grpc::grpc_port,
+    },
+};
+
+const HOST: &str = "127.0.0.1";
+
+#[derive(Debug, Clone)]
+pub struct ClusterBuilder {
+    masters: usize,
+    volume_servers: usize,
+    max_volumes: usize,
+    default_replication: FastStr,
+    pulse: u64,
+    timeout: Duration,
+}
+
+impl Default for ClusterBuilder {
+    fn default() -> Self {
+        Self {
+            masters: 1,
+            volume_servers: 1,
+            max_volumes: 7,
+            default_replication: FastStr::from_static_str("000"),
+            pulse: 1,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ClusterBuilder {
+    /// number of masters, a raft group of 1 to 3 nodes
+    pub fn masters(mut self, masters: usize) -> Self {
+        self.masters = masters;
+        self
+    }
+
+    pub fn volume_servers(mut self, volume_servers: usize) -> Self {
+        self.volume_servers = volume_servers;
+        self
+    }
+
+    /// max volumes of every volume server
+    pub fn max_volumes(mut self, max_volumes: usize) -> Self {
+        self.max_volumes = max_volumes;
+        self
+    }
+
+    pub fn default_replication(mut self, replication: &str) -> Self {
+        self.default_replication = FastStr::new(replication);
+        self
+    }
+
+    /// heartbeat interval of the servers in seconds
+    pub fn pulse(mut self, pulse: u64) -> Self {
+        self.pulse = pulse;
+        self
+    }
+
+    /// how long `start` waits for the cluster to be ready
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// boot the masters, wait for a leader, then boot the volume servers and wait for all of
+    /// them to join
+    pub async fn start(self) -> Result<Cluster> {
+        let dir = tempfile::Builder::new()
+            .prefix("helyim-cluster")
+            .tempdir()?;
+        let mut cluster = Cluster {
+            builder: self.clone(),
+            masters: Vec::new(),
+            volume_servers: Vec::new(),
+            dir,
+        };
+
+        let ports = (0..self.masters)
+            .map(|_| free_port())
+            .collect::<Result<Vec<_>>>()?;
+        let peers: Vec<FastStr> = ports
+            .iter()
+            .map(|port| FastStr::new(format!("{HOST}:{port}")))
+            .collect();
+        for port in ports {
+            let options = cluster.master_options(port, &peers)?;
+            let sequencer = Sequencer::from_options(&options.sequencer).await?;
+            let mut server = DirectoryServer::new(options, 0.3, sequencer).await?;
+            server.start().await?;
+            cluster.masters.push(Node {
+                addr: FastStr::new(format!("{HOST}:{port}")),
+                server: Some(server),
+            });
+        }
+        cluster.leader().await?;
+
+        for i in 0..self.volume_servers {
+            let port = free_port()?;
+            cluster.start_volume_server(i, port).await?;
+        }
+        cluster.wait_for_volume_servers(self.volume_servers).await?;
+        Ok(cluster)
+    }
+}
+
+pub struct Node<S> {
+    pub addr: FastStr,
+    server: Option<S>,
+}
+
+impl<S> Node<S> {
+    pub fn is_running(&self) -> bool {
+        self.server.is_some()
+    }
+}
+
+pub struct Cluster {
+    builder: ClusterBuilder,
+    pub masters: Vec<Node<DirectoryServer>>,
+    pub volume_servers: Vec<Node<VolumeServer>>,
+    dir: TempDir,
+}
+
+impl Cluster {
+    pub fn builder() -> ClusterBuilder {
+        ClusterBuilder::default()
+    }
+
+    /// address of the current leader, waits until one is elected
+    pub async fn leader(&self) -> Result<FastStr> {
+        let deadline = Instant::now() + self.builder.timeout;
+        loop {
+            for master in self.masters.iter() {
+                if let Some(server) = master.server.as_ref() {
+                    if let Ok(leader) = server.topology.current_leader().await {
+                        return Ok(leader);
+                    }
+                }
+            }
+            if Instant::now() > deadline {
+                return Err(Error::String("no leader elected".to_string()));
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// the master server which is currently the leader
+    pub async fn leader_server(&self) -> Result<&DirectoryServer> {
+        let leader = self.leader().await?;
+        self.masters
+            .iter()
+            .find(|master| master.addr == leader)
+            .and_then(|master| master.server.as_ref())
+            .ok_or_else(|| Error::String(format!("leader {leader} is not running")))
+    }
+
+    /// wait until the leader has seen `count` volume servers
+    pub async fn wait_for_volume_servers(&self, count: usize) -> Result<()> {
+        let deadline = Instant::now() + self.builder.timeout;
+        loop {
+            if let Ok(leader) = self.leader_server().await {
+                if leader.topology.data_nodes().len() >= count {
+                    return Ok(());
+                }
+            }
+            if Instant::now() > deadline {
+                return Err(Error::String(format!(
+                    "volume servers do not join in {:?}",
+                    self.builder.timeout
+                )));
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// stop a master, the others elect a new leader if it was the leader
+    pub async fn stop_master(&mut self, index: usize) -> Result<()> {
+        if let Some(server) = self.masters[index].server.take() {
+            server.stop().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn stop_volume_server(&mut self, index: usize) -> Result<()> {
+        if let Some(server) = self.volume_servers[index].server.take() {
+            server.stop().await?;
+        }
+        Ok(())
+    }
+
+    /// start a stopped volume server again on the same port and folder
+    pub async fn restart_volume_server(&mut self, index: usize) -> Result<()> {
+        self.stop_volume_server(index).await?;
+        let port = self.volume_servers[index]
+            .addr
+            .rsplit_once(':')
+            .map(|(_, port)| port.parse::<u16>())
+            .transpose()?
+            .unwrap_or_default();
+        self.start_volume_server(index, port).await
+    }
+
+    pub async fn shutdown(mut self) -> Result<()> {
+        for i in 0..self.volume_servers.len() {
+            self.stop_volume_server(i).await?;
+        }
+        for i in 0..self.masters.len() {
+            self.stop_master(i).await?;
+        }
+        Ok(())
+    }
+
+    fn master_options(&self, port: u16, peers: &[FastStr]) -> Result<MasterOptions> {
+        let meta_path = self.dir.path().join(format!("master-{port}"));
+        std::fs::create_dir_all(&meta_path)?;
+        let mut args = vec![
+            "helyim".to_string(),
+            "master".to_string(),
+            format!("--ip={HOST}"),
+            format!("--port={port}"),
+            format!("--meta-path={}", meta_path.display()),
+            format!("--pulse={}", self.builder.pulse),
+            format!("--default-replication={}", self.builder.default_replication),
+        ];
+        args.extend(peers.iter().map(|peer| format!("--peers={peer}")));
+        match Opts::try_parse_from(args).map_err(|err| Error::String(err.to_string()))? {
+            Opts {
+                command: Command::Master(options),
+                ..
+            } => Ok(options),
+            _ => unreachable!(),
+        }
+    }
+
+    fn volume_options(&self, index: usize, port: u16) -> Result<VolumeOptions> {
+        let folder = self.dir.path().join(format!("volume-{index}"));
+        std::fs::create_dir_all(&folder)?;
+        let master = self
+            .masters
+            .first()
+            .map(|master| master.addr.clone())
+            .unwrap_or_default();
+        let args = vec![
+            "helyim".to_string(),
+            "volume".to_string(),
+            format!("--ip={HOST}"),
+            format!("--port={port}"),
+            format!("--pulse={}", self.builder.pulse),
+            format!("--master-server={master}"),
+            format!(
+                "--folders={}:{}",
+                folder.display(),
+                self.builder.max_volumes
+            ),
+            format!("--default-replication={}", self.builder.default_replication),
+        ];
+        match Opts::try_parse_from(args).map_err(|err| Error::String(err.to_string()))? {
+            Opts {
+                command: Command::Volume(options),
+                ..
+            } => Ok(options),
+            _ => unreachable!(),
+        }
+    }
+
+    async fn start_volume_server(&mut self, index: usize, port: u16) -> Result<()> {
+        let options = self.volume_options(index, port)?;
+        let mut server =
+            VolumeServer::new(NeedleMapType::NeedleMapInMemory, options, false).await?;
+        server.start().await?;
+        let node = Node {
+            addr: FastStr::new(format!("{HOST}:{port}")),
+            server: Some(server),
+        };
+        match self.volume_servers.get_mut(index) {
+            Some(slot) => *slot = node,
+            None => self.volume_servers.push(node),
+        }
+        Ok(())
+    }
+}
+
+/// a free port whose grpc port is free as well
+fn free_port() -> Result<u16> {
+    for _ in 0..100 {
+        let port = TcpListener::bind((HOST, 0))?.local_addr()?.port();
+        if port.checked_add(10000).is_none() {
+            continue;
+        }
+        if TcpListener::bind((HOST, grpc_port(port))).is_ok() {
+            return Ok(port);
+        }
+    }
+    Err(Error::String("no free port found".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{operation::Assignment, testing::Cluster, util::http::get};
+
+    #[tokio::test]
+    async fn test_mini_cluster() {
+        let cluster = Cluster::builder().volume_servers(2).start().await.unwrap();
+        let leader = cluster.leader().await.unwrap();
+
+        let body = get(format!("http://{leader}/dir/assign"), &[])
+            .await
+            .unwrap();
+        let assignment: Assignment = serde_json::from_slice(&body).unwrap();
+        assert!(assignment.error.is_empty());
+        assert!(cluster
+            .volume_servers
+            .iter()
+            .any(|server| server.addr.as_str() == assignment.url));
+
+        cluster.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, HashSet};
+
+use faststr::FastStr;
+use tracing::{error, info};
+
+use crate::{
+    storage::{VolumeError, VolumeId, VolumeInfo},
+    topology::{node::Node, DataNodeRef, JobKind, Topology},
+};
+
+/// A volume to move from the fullest data node of a rack to the emptiest one.
+pub struct VolumeMove {
+    pub volume: VolumeInfo,
+    pub source: DataNodeRef,
+    pub target: DataNodeRef,
+}
+
+struct Usage {
+    data_node: DataNodeRef,
+    volumes: HashSet<VolumeId>,
+    max: i64,
+}
+
+impl Usage {
+    fn ratio(&self) -> f64 {
+        self.volumes.len() as f64 / self.max as f64
+    }
+}
+
+/// the moves which bring the volume usage of the data nodes of one rack within `threshold` of
+/// each other, only writable volumes below `volume_size_limit` are moved
+pub(super) fn plan_rack(
+    data_nodes: Vec<DataNodeRef>,
+    threshold: f64,
+    volume_size_limit: u64,
+) -> Vec<VolumeMove> {
+    let mut usages: Vec<Usage> = data_nodes
+        .into_iter()
+        .filter(|data_node| !data_node.is_decommissioning() && data_node.max_volume_count() > 0)
+        .map(|data_node| Usage {
+            volumes: data_node
+                .volumes
+                .iter()
+                .map(|volume| *volume.key())
+                .collect(),
+            max: data_node.max_volume_count(),
+            data_node,
+        })
+        .collect();
+    let mut moved = HashSet::new();
+    let mut moves = Vec::new();
+    loop {
+        if usages.len() < 2 {
+            break;
+        }
+        usages.sort_by(|a, b| a.ratio().total_cmp(&b.ratio()));
+        let (target, source) = (0, usages.len() - 1);
+        if usages[source].ratio() - usages[target].ratio() <= threshold {
+            break;
+        }
+        // moving a volume the target already holds would drop a replica, a read only, full or
+        // quarantined volume is left where it is
+        let volume = usages[source]
+            .volumes
+            .iter()
+            .filter(|vid| !moved.contains(*vid) && !usages[target].volumes.contains(*vid))
+            .filter_map(|vid| {
+                usages[source]
+                    .data_node
+                    .get_volume(*vid)
+                    .map(|volume| volume.clone())
+            })
+            .filter(|volume| {
+                !volume.read_only && !volume.quarantined && volume.size < volume_size_limit
+            })
+            .min_by_key(|volume| volume.id);
+        let Some(volume) = volume else {
+            // nothing of the fullest data node can move, balance the others
+            usages.pop();
+            continue;
+        };
+
+        moved.insert(volume.id);
+        usages[source].volumes.remove(&volume.id);
+        usages[target].volumes.insert(volume.id);
+        moves.push(VolumeMove {
+            volume,
+            source: usages[source].data_node.clone(),
+            target: usages[target].data_node.clone(),
+        });
+    }
+    moves
+}
+
+impl Topology {
+    /// plan the moves balancing the data nodes of every rack, volumes only move within their rack
+    /// so the replica placement is kept
+    pub async fn plan_balance(&self, threshold: f64) -> Vec<VolumeMove> {
+        let mut racks: BTreeMap<(FastStr, FastStr), Vec<DataNodeRef>> = BTreeMap::new();
+        for data_node in self.data_nodes() {
+            let rack = (data_node.data_center_id().await, data_node.rack_id().await);
+            racks.entry(rack).or_default().push(data_node);
+        }
+        racks
+            .into_values()
+            .flat_map(|data_nodes| plan_rack(data_nodes, threshold, self.volume_size_limit))
+            .collect()
+    }
+
+    /// move volumes until the data nodes of every rack are balanced within `threshold`, as a job
+    /// which can be paused or cancelled between volumes
+    pub async fn balance(&self, threshold: f64) {
+        if self.jobs.running(JobKind::Balance, "").is_some() {
+            return;
+        }
+        let moves = self.plan_balance(threshold).await;
+        if moves.is_empty() {
+            return;
+        }
+        info!("balance data nodes, {} volumes to move", moves.len());
+        let job = self
+            .jobs
+            .start(JobKind::Balance, FastStr::empty(), moves.len());
+        for VolumeMove {
+            volume,
+            source,
+            target,
+        } in moves
+        {
+            if !job.checkpoint().await {
+                break;
+            }
+            let result = if target.disk_free_space(volume.disk_type) > 0 {
+                self.move_volume(&volume, &source, &target).await
+            } else {
+                Err(VolumeError::NoFreeSpace(format!("{} is full", target.id())))
+            };
+            match result {
+                Ok(()) => job.step_done(),
+                Err(err) => {
+                    error!(
+                        "balance, move volume {} from {} to {} failed: {err}",
+                        volume.id,
+                        source.id(),
+                        target.id()
+                    );
+                    job.step_failed(volume.id);
+                }
+            }
+        }
+        job.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::topology::{node::Node, tests::setup_topo};
+
+    #[tokio::test]
+    async fn test_plan_balance() {
+        let topo = setup_topo().await;
+        let moves: Vec<(u32, String, String)> = topo
+            .plan_balance(0.2)
+            .await
+            .into_iter()
+            .map(|m| {
+                (
+                    m.volume.id,
+                    m.source.id().to_string(),
+                    m.target.id().to_string(),
+                )
+            })
+            .collect();
+
+        // server111 is full, server122 is empty, volumes never leave their rack
+        assert_eq!(moves.len(), 4);
+        assert!(moves.contains(&(1, "server111".into(), "server112".into())));
+        assert!(moves.contains(&(2, "server111".into(), "server112".into())));
+        assert!(moves.contains(&(4, "server121".into(), "server122".into())));
+        assert!(moves.contains(&(2, "server123".into(), "server122".into())));
+
+        // balanced within a loose threshold
+        assert!(topo.plan_balance(0.8).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_plan_balance_skips_unmovable() {
+        let topo = setup_topo().await;
+        let server111 = topo
+            .data_nodes()
+            .into_iter()
+            .find(|data_node| data_node.id() == "server111")
+            .unwrap();
+        for vid in 1..=3 {
+            let mut volume = server111.get_volume(vid).unwrap().clone();
+            match vid {
+                1 => volume.read_only = true,
+                2 => volume.quarantined = true,
+                _ => volume.size = 32 * 1024,
+            }
+            server111.add_or_update_volume(&volume).await;
+        }
+
+        // nothing of server111 can move, the other racks are still balanced
+        let moves: Vec<(u32, String)> = topo
+            .plan_balance(0.2)
+            .await
+            .into_iter()
+            .map(|m| (m.volume.id, m.source.id().to_string()))
+            .collect();
+        assert!(moves.iter().all(|(_, source)| source != "server111"));
+        assert!(moves.contains(&(4, "server121".into())));
+        assert!(moves.contains(&(2, "server123".into())));
+    }
+}
@@ -0,0 +1,134 @@
+use std::{fs::File, path::Path, sync::atomic::Ordering};
+
+use faststr::FastStr;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    storage::VolumeError,
+    topology::{node::Node, Topology},
+    util::time::now,
+};
+
+/// Data centers, racks and data nodes the cluster is expected to have, declared nodes are shown in
+/// the topology before they connect, and volume growth waits for them until the bootstrap timeout.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StaticTopology {
+    pub data_centers: Vec<StaticDataCenter>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StaticDataCenter {
+    pub name: FastStr,
+    pub racks: Vec<StaticRack>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StaticRack {
+    pub name: FastStr,
+    pub nodes: Vec<StaticDataNode>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StaticDataNode {
+    pub ip: FastStr,
+    pub port: u16,
+    #[serde(default)]
+    pub public_url: Option<FastStr>,
+}
+
+impl StaticTopology {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<StaticTopology, VolumeError> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+}
+
+impl Topology {
+    /// declare the expected data nodes, they have no capacity until their first heartbeat
+    pub async fn bootstrap(
+        &self,
+        topology: &StaticTopology,
+        timeout: u64,
+    ) -> Result<(), VolumeError> {
+        for dc in topology.data_centers.iter() {
+            let data_center = self.get_or_create_data_center(&dc.name).await?;
+            for r in dc.racks.iter() {
+                let rack = data_center.get_or_create_rack(&r.name).await?;
+                rack.set_parent(Some(data_center.clone())).await;
+                for node in r.nodes.iter() {
+                    let id = FastStr::new(format!("{}:{}", node.ip, node.port));
+                    let public_url = node.public_url.clone().unwrap_or_else(|| id.clone());
+                    let data_node = rack
+                        .get_or_create_data_node(id, node.ip.clone(), node.port, public_url, 0)
+                        .await;
+                    data_node.set_parent(Some(rack.clone())).await;
+                    info!("declare data node {}", data_node.id());
+                }
+            }
+        }
+        self.bootstrap_deadline
+            .store(now().as_secs() + timeout, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// declared data nodes which have not connected yet, empty once the bootstrap timed out
+    pub fn bootstrapping_nodes(&self) -> Vec<FastStr> {
+        if now().as_secs() >= self.bootstrap_deadline.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        self.data_nodes()
+            .into_iter()
+            .filter(|data_node| !data_node.is_connected())
+            .map(|data_node| FastStr::new(data_node.id()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        sequence::{MemorySequencer, Sequencer},
+        topology::{bootstrap::StaticTopology, node::Node, Topology},
+    };
+
+    #[tokio::test]
+    async fn test_bootstrap_static_topology() {
+        let declared: StaticTopology = serde_json::from_str(
+            r#"{
+                "dataCenters": [{
+                    "name": "dc1",
+                    "racks": [
+                        {"name": "rack1", "nodes": [{"ip": "10.0.0.1", "port": 8080}]},
+                        {"name": "rack2", "nodes": [{"ip": "10.0.0.2", "port": 8080}]}
+                    ]
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let topo = Arc::new(Topology::new(
+            Sequencer::Memory(MemorySequencer::new()),
+            1024,
+            5,
+        ));
+        assert!(topo.bootstrapping_nodes().is_empty());
+
+        topo.bootstrap(&declared, 60).await.unwrap();
+        assert_eq!(topo.data_nodes().len(), 2);
+        assert_eq!(topo.free_space(), 0);
+        assert_eq!(topo.bootstrapping_nodes().len(), 2);
+
+        let data_node = topo.find_data_node("10.0.0.1:8080").unwrap();
+        assert_eq!(data_node.rack_id().await, "rack1");
+        data_node.touch();
+        assert_eq!(topo.bootstrapping_nodes(), vec!["10.0.0.2:8080"]);
+
+        topo.bootstrap(&declared, 0).await.unwrap();
+        assert!(topo.bootstrapping_nodes().is_empty());
+    }
+}
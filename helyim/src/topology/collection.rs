@@ -5,9 +5,9 @@ use faststr::FastStr;
 use serde::Serialize;
 
 use crate::{
-    storage::{ReplicaPlacement, Ttl, VolumeId},
+    storage::{DiskType, ReplicaPlacement, Ttl, VolumeId},
     topology::{
-        volume_layout::{VolumeLayout, VolumeLayoutRef},
+        volume_layout::{AssignStrategy, VolumeLayout, VolumeLayoutRef},
         DataNodeRef,
     },
 };
@@ -16,6 +16,7 @@ use crate::{
 pub struct Collection {
     name: FastStr,
     volume_size_limit: u64,
+    assign_strategy: AssignStrategy,
     #[serde(skip)]
     pub volume_layouts: DashMap<FastStr, VolumeLayoutRef>,
 }
@@ -25,24 +26,38 @@ impl Collection {
         Collection {
             name,
             volume_size_limit,
+            assign_strategy: AssignStrategy::default(),
             volume_layouts: DashMap::new(),
         }
     }
 
+    pub fn with_assign_strategy(mut self, assign_strategy: AssignStrategy) -> Self {
+        self.assign_strategy = assign_strategy;
+        self
+    }
+
     pub fn get_or_create_volume_layout(
         &self,
         rp: ReplicaPlacement,
         ttl: Option<Ttl>,
+        disk_type: DiskType,
     ) -> VolumeLayoutRef {
-        let key = match ttl {
+        let mut key = match ttl {
             Some(ttl) => format!("{}{}", rp, ttl),
             None => rp.to_string(),
         };
+        // volumes on different disk types are never picked for the same write
+        if disk_type != DiskType::Hdd {
+            key.push_str(disk_type.as_str());
+        }
 
         match self.volume_layouts.get(key.as_str()) {
             Some(vl) => vl.value().clone(),
             None => {
-                let volume_layout = Arc::new(VolumeLayout::new(rp, ttl, self.volume_size_limit));
+                let volume_layout = Arc::new(
+                    VolumeLayout::new(rp, ttl, self.volume_size_limit)
+                        .with_strategy(self.assign_strategy),
+                );
                 self.volume_layouts
                     .insert(FastStr::new(key), volume_layout.clone());
                 volume_layout
@@ -69,7 +84,7 @@ mod tests {
     use faststr::FastStr;
 
     use crate::{
-        storage::{ReplicaPlacement, Ttl, VolumeInfo, CURRENT_VERSION},
+        storage::{DiskType, ReplicaPlacement, Ttl, VolumeInfo, CURRENT_VERSION},
         topology::{collection::Collection, data_node::DataNode},
     };
 
@@ -88,13 +103,13 @@ mod tests {
 
         let rp = ReplicaPlacement::new("000").unwrap();
         let ttl = Ttl::new("1d").unwrap();
-        let vl = collection.get_or_create_volume_layout(rp, Some(ttl));
-        let _vl1 = collection.get_or_create_volume_layout(rp, Some(ttl));
+        let vl = collection.get_or_create_volume_layout(rp, Some(ttl), DiskType::Hdd);
+        let _vl1 = collection.get_or_create_volume_layout(rp, Some(ttl), DiskType::Hdd);
 
         assert_eq!(Arc::strong_count(&vl), 3);
 
-        let vl = collection.get_or_create_volume_layout(rp, None);
-        let _vl1 = collection.get_or_create_volume_layout(rp, None);
+        let vl = collection.get_or_create_volume_layout(rp, None, DiskType::Hdd);
+        let _vl1 = collection.get_or_create_volume_layout(rp, None, DiskType::Hdd);
 
         assert_eq!(Arc::strong_count(&vl), 3);
     }
@@ -105,7 +120,7 @@ mod tests {
 
         let rp = ReplicaPlacement::new("000").unwrap();
         let ttl = Ttl::new("1d").unwrap();
-        let vl = collection.get_or_create_volume_layout(rp, Some(ttl));
+        let vl = collection.get_or_create_volume_layout(rp, Some(ttl), DiskType::Hdd);
 
         let volume_opt = collection.lookup(0).await;
         let volume1_opt = vl.lookup(0);
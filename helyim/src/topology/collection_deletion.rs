@@ -0,0 +1,309 @@
+use faststr::FastStr;
+use helyim_proto::volume::VolumeDeleteRequest;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::{
+    storage::VolumeId,
+    topology::{node::Node, Topology, TopologyError},
+    util::time::now,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CollectionDeleteAction {
+    /// mark the collection for deletion, no more file ids are assigned to it until the mark is
+    /// cancelled
+    #[default]
+    Mark,
+    /// delete the volumes of a marked collection
+    Purge,
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CollectionDeletionState {
+    Marked,
+    Purged,
+    Cancelled,
+}
+
+/// deletion mark of a collection, it can be purged from `purge_after` until `expires_at`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct CollectionMark {
+    pub purge_after: u64,
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionDeletion {
+    pub collection: FastStr,
+    pub state: CollectionDeletionState,
+    /// unix seconds from which the mark can be purged
+    pub purge_after: u64,
+    /// unix seconds until the mark can be purged
+    pub expires_at: u64,
+    pub deleted: Vec<VolumeId>,
+    /// volumes of which a replica could not be deleted, purging again retries them
+    pub failed: Vec<VolumeId>,
+}
+
+impl CollectionDeletion {
+    fn new(collection: FastStr, state: CollectionDeletionState, mark: CollectionMark) -> Self {
+        Self {
+            collection,
+            state,
+            purge_after: mark.purge_after,
+            expires_at: mark.expires_at,
+            deleted: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+}
+
+impl Topology {
+    /// whether assignments to `collection` are refused because it is about to be deleted
+    pub fn is_collection_marked(&self, collection: &str) -> bool {
+        self.collection_deletions
+            .get(collection)
+            .is_some_and(|mark| mark.expires_at > now().as_secs())
+    }
+
+    /// the collections marked for deletion, expired marks are dropped
+    pub fn collection_deletions(&self) -> Vec<CollectionDeletion> {
+        let now = now().as_secs();
+        self.collection_deletions
+            .retain(|_, mark| mark.expires_at > now);
+        self.collection_deletions
+            .iter()
+            .map(|mark| {
+                CollectionDeletion::new(
+                    mark.key().clone(),
+                    CollectionDeletionState::Marked,
+                    *mark.value(),
+                )
+            })
+            .collect()
+    }
+
+    /// set the mark of `collection` as the raft log orders it
+    pub fn apply_collection_deletion(
+        &self,
+        collection: FastStr,
+        purge_after: u64,
+        expires_at: u64,
+    ) {
+        if expires_at == 0 {
+            self.collection_deletions.remove(&collection);
+        } else {
+            let mark = CollectionMark {
+                purge_after,
+                expires_at,
+            };
+            self.collection_deletions.insert(collection, mark);
+        }
+    }
+
+    /// the first step of deleting a collection, the mark can be purged after `delay` seconds and
+    /// within `ttl` seconds from then. it is kept by every master, so it outlives a change of the
+    /// leader.
+    pub async fn mark_collection_deletion(
+        &self,
+        collection: &str,
+        delay: u64,
+        ttl: u64,
+        protected: &[FastStr],
+    ) -> Result<CollectionDeletion, TopologyError> {
+        if protected.iter().any(|name| name == collection) {
+            return Err(TopologyError::CollectionProtected(FastStr::new(collection)));
+        }
+        if !self.collections.contains_key(collection) {
+            return Err(TopologyError::CollectionNotFound(FastStr::new(collection)));
+        }
+        let collection = FastStr::new(collection);
+        let purge_after = now().as_secs() + delay;
+        let mark = CollectionMark {
+            purge_after,
+            expires_at: purge_after + ttl,
+        };
+        self.replicate_collection_deletion(collection.clone(), mark.purge_after, mark.expires_at)
+            .await?;
+        warn!(
+            "collection {collection} is marked for deletion, it can be purged from {} until {}",
+            mark.purge_after, mark.expires_at
+        );
+        Ok(CollectionDeletion::new(
+            collection,
+            CollectionDeletionState::Marked,
+            mark,
+        ))
+    }
+
+    pub async fn cancel_collection_deletion(
+        &self,
+        collection: &str,
+    ) -> Result<CollectionDeletion, TopologyError> {
+        let collection = FastStr::new(collection);
+        if !self.collection_deletions.contains_key(&collection) {
+            return Err(TopologyError::CollectionNotMarked(collection));
+        }
+        self.replicate_collection_deletion(collection.clone(), 0, 0)
+            .await?;
+        info!("deletion of collection {collection} is cancelled");
+        Ok(CollectionDeletion::new(
+            collection,
+            CollectionDeletionState::Cancelled,
+            CollectionMark::default(),
+        ))
+    }
+
+    /// the second step of deleting a collection, every replica of its volumes is deleted. a mark
+    /// can not be purged before its delay passed, so a mistaken mark can still be cancelled.
+    pub async fn purge_collection(
+        &self,
+        collection: &str,
+    ) -> Result<CollectionDeletion, TopologyError> {
+        let now = now().as_secs();
+        let mark = match self.collection_deletions.get(collection) {
+            Some(mark) if mark.expires_at > now => *mark,
+            _ => return Err(TopologyError::CollectionNotMarked(FastStr::new(collection))),
+        };
+        if mark.purge_after > now {
+            return Err(TopologyError::CollectionPurgeTooEarly(
+                FastStr::new(collection),
+                mark.purge_after,
+            ));
+        }
+        let mut deletion = CollectionDeletion::new(
+            FastStr::new(collection),
+            CollectionDeletionState::Purged,
+            mark,
+        );
+
+        for data_node in self.data_nodes() {
+            let volumes: Vec<VolumeId> = data_node
+                .volumes
+                .iter()
+                .filter(|volume| volume.collection == collection)
+                .map(|volume| volume.id)
+                .collect();
+            for volume_id in volumes {
+                match data_node
+                    .volume_delete(VolumeDeleteRequest { volume_id })
+                    .await
+                {
+                    Ok(_) => {
+                        if !deletion.deleted.contains(&volume_id) {
+                            deletion.deleted.push(volume_id);
+                        }
+                    }
+                    Err(err) => {
+                        error!(
+                            "delete volume {volume_id} of collection {collection} on {} failed: \
+                             {err}",
+                            data_node.id()
+                        );
+                        deletion.failed.push(volume_id);
+                    }
+                }
+            }
+        }
+        deletion
+            .deleted
+            .retain(|volume_id| !deletion.failed.contains(volume_id));
+
+        // a failed purge keeps the mark, so it can be retried before the mark expires
+        if deletion.failed.is_empty() {
+            self.collections.remove(collection);
+            self.replicate_collection_deletion(FastStr::new(collection), 0, 0)
+                .await?;
+        }
+        info!(
+            "purge collection {collection}, {} volumes deleted, {} failed",
+            deletion.deleted.len(),
+            deletion.failed.len()
+        );
+        Ok(deletion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use faststr::FastStr;
+
+    use crate::{
+        directory::Sequencer,
+        sequence::MemorySequencer,
+        topology::{collection::Collection, Topology, TopologyError},
+    };
+
+    #[tokio::test]
+    async fn test_collection_deletion() {
+        let topo = Topology::new(Sequencer::Memory(MemorySequencer::new()), 32 * 1024, 5);
+        let pictures = FastStr::new("pictures");
+        topo.collections
+            .insert(pictures.clone(), Collection::new(pictures.clone(), 1024));
+
+        let protected = [FastStr::new("pictures")];
+        assert!(matches!(
+            topo.mark_collection_deletion("pictures", 0, 60, &protected)
+                .await,
+            Err(TopologyError::CollectionProtected(_))
+        ));
+        assert!(matches!(
+            topo.mark_collection_deletion("videos", 0, 60, &[]).await,
+            Err(TopologyError::CollectionNotFound(_))
+        ));
+        assert!(matches!(
+            topo.purge_collection("pictures").await,
+            Err(TopologyError::CollectionNotMarked(_))
+        ));
+
+        topo.mark_collection_deletion("pictures", 0, 60, &[])
+            .await
+            .unwrap();
+        assert!(topo.is_collection_marked("pictures"));
+        assert_eq!(topo.collection_deletions().len(), 1);
+        topo.cancel_collection_deletion("pictures").await.unwrap();
+        assert!(!topo.is_collection_marked("pictures"));
+
+        // an expired mark can not be purged
+        topo.mark_collection_deletion("pictures", 0, 0, &[])
+            .await
+            .unwrap();
+        assert!(topo.collection_deletions().is_empty());
+        assert!(topo.purge_collection("pictures").await.is_err());
+
+        // a mark can not be purged right away
+        let mark = topo
+            .mark_collection_deletion("pictures", 60, 60, &[])
+            .await
+            .unwrap();
+        assert!(topo.is_collection_marked("pictures"));
+        match topo.purge_collection("pictures").await {
+            Err(TopologyError::CollectionPurgeTooEarly(_, purge_after)) => {
+                assert_eq!(purge_after, mark.purge_after)
+            }
+            other => panic!("unexpected purge: {other:?}"),
+        }
+        assert!(topo.collections.contains_key("pictures"));
+        topo.cancel_collection_deletion("pictures").await.unwrap();
+
+        topo.mark_collection_deletion("pictures", 0, 60, &[])
+            .await
+            .unwrap();
+        let deletion = topo.purge_collection("pictures").await.unwrap();
+        assert!(deletion.deleted.is_empty());
+        assert!(!topo.collections.contains_key("pictures"));
+        assert!(!topo.is_collection_marked("pictures"));
+
+        // marks replicated by the raft log of the leader
+        let videos = FastStr::new("videos");
+        topo.apply_collection_deletion(videos.clone(), 0, u64::MAX);
+        assert!(topo.is_collection_marked("videos"));
+        topo.apply_collection_deletion(videos, 0, 0);
+        assert!(!topo.is_collection_marked("videos"));
+    }
+}
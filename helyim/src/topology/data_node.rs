@@ -2,36 +2,72 @@ use std::{
     collections::HashMap,
     fmt::{Debug, Display, Formatter},
     result::Result as StdResult,
-    sync::{atomic::AtomicU64, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 use dashmap::{mapref::one::Ref, DashMap};
 use faststr::FastStr;
-use helyim_proto::volume::{
-    AllocateVolumeRequest, AllocateVolumeResponse, VacuumVolumeCheckRequest,
-    VacuumVolumeCheckResponse, VacuumVolumeCleanupRequest, VacuumVolumeCleanupResponse,
-    VacuumVolumeCommitRequest, VacuumVolumeCommitResponse, VacuumVolumeCompactRequest,
-    VacuumVolumeCompactResponse,
+use helyim_proto::{
+    directory::CollectionUsageMessage,
+    volume::{
+        AllocateVolumeRequest, AllocateVolumeResponse, VacuumVolumeCheckRequest,
+        VacuumVolumeCheckResponse, VacuumVolumeCleanupRequest, VacuumVolumeCleanupResponse,
+        VacuumVolumeCommitRequest, VacuumVolumeCommitResponse, VacuumVolumeCompactRequest,
+        VacuumVolumeCompactResponse, VolumeCopyRequest, VolumeCopyResponse, VolumeDeleteRequest,
+        VolumeDeleteResponse, VolumeMarkReadonlyRequest, VolumeMarkReadonlyResponse,
+        VolumeMarkWritableRequest, VolumeMarkWritableResponse,
+    },
 };
-use serde::Serialize;
+use parking_lot::RwLock;
+use serde::{Serialize, Serializer};
+use tracing::warn;
 
 use crate::{
-    storage::{erasure_coding::EcVolumeInfo, VolumeError, VolumeId, VolumeInfo},
+    storage::{erasure_coding::EcVolumeInfo, DiskType, VolumeError, VolumeId, VolumeInfo},
     topology::node::{Node, NodeImpl, NodeType},
-    util::grpc::volume_server_client,
+    util::{capability::Capabilities, grpc::volume_server_client, retry::retry, time::now},
 };
 
+/// heartbeat intervals without a heartbeat before a data node is considered dead, unless the
+/// master is told otherwise
+pub const DEFAULT_MAX_MISSED_HEARTBEATS: u64 = 3;
+
 #[derive(Serialize)]
 pub struct DataNode {
     pub ip: FastStr,
     pub port: u16,
     pub public_url: FastStr,
-    pub last_seen: i64,
+    /// unix seconds of the last heartbeat
+    pub last_seen: AtomicI64,
+    /// set while the volumes of this node are evacuated
+    pub decommissioning: AtomicBool,
+    /// set by the first heartbeat, declared nodes are not connected until then
+    connected: AtomicBool,
+    /// set when the node missed too many heartbeats and was removed from the topology
+    expired: AtomicBool,
     node: Arc<NodeImpl>,
 
     pub volumes: DashMap<VolumeId, VolumeInfo>,
+    /// max volume count per disk type, empty if the node does not report disk types
+    pub max_volume_counts: DashMap<DiskType, i64>,
     pub ec_shards: DashMap<VolumeId, EcVolumeInfo>,
     pub ec_shard_count: AtomicU64,
+
+    #[serde(serialize_with = "serialize_capabilities")]
+    capabilities: RwLock<Capabilities>,
+    /// requests per collection of the last full heartbeat
+    #[serde(skip)]
+    usages: RwLock<Vec<CollectionUsageMessage>>,
+}
+
+fn serialize_capabilities<S: Serializer>(
+    capabilities: &RwLock<Capabilities>,
+    serializer: S,
+) -> StdResult<S::Ok, S::Error> {
+    capabilities.read().serialize(serializer)
 }
 
 impl Debug for DataNode {
@@ -61,18 +97,108 @@ impl DataNode {
             ip,
             port,
             public_url,
-            last_seen: 0,
+            last_seen: AtomicI64::new(now().as_secs() as i64),
+            decommissioning: AtomicBool::new(false),
+            connected: AtomicBool::new(false),
+            expired: AtomicBool::new(false),
             node,
             volumes: DashMap::new(),
+            max_volume_counts: DashMap::new(),
             ec_shards: DashMap::new(),
             ec_shard_count: AtomicU64::new(0),
+            capabilities: RwLock::new(Capabilities::default()),
+            usages: RwLock::new(Vec::new()),
         }
     }
 
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities.read().clone()
+    }
+
+    pub fn set_capabilities(&self, capabilities: Capabilities) {
+        *self.capabilities.write() = capabilities;
+    }
+
+    pub fn usages(&self) -> Vec<CollectionUsageMessage> {
+        self.usages.read().clone()
+    }
+
+    pub fn set_usages(&self, usages: Vec<CollectionUsageMessage>) {
+        *self.usages.write() = usages;
+    }
+
+    pub fn supports_all<S: AsRef<str>>(&self, features: &[S]) -> bool {
+        self.capabilities.read().supports_all(features)
+    }
+
+    pub fn touch(&self) {
+        self.last_seen
+            .store(now().as_secs() as i64, Ordering::Relaxed);
+        self.connected.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    pub fn last_seen(&self) -> i64 {
+        self.last_seen.load(Ordering::Relaxed)
+    }
+
+    /// whether the last heartbeat is at most `timeout` seconds old
+    pub fn is_alive(&self, timeout: u64) -> bool {
+        now().as_secs() as i64 - self.last_seen() <= timeout as i64
+    }
+
+    pub fn expire(&self) {
+        self.expired.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    pub fn is_decommissioning(&self) -> bool {
+        self.decommissioning.load(Ordering::Relaxed)
+    }
+
     pub fn url(&self) -> String {
         format!("{}:{}", self.ip, self.port)
     }
 
+    pub fn set_max_volume_counts(&self, max_volume_counts: &HashMap<String, u32>) {
+        self.max_volume_counts.clear();
+        for (disk_type, count) in max_volume_counts {
+            match DiskType::new(disk_type) {
+                Ok(disk_type) => {
+                    *self.max_volume_counts.entry(disk_type).or_default() += *count as i64;
+                }
+                Err(err) => warn!("data node {} reports {err}", self.id()),
+            }
+        }
+    }
+
+    /// free volume slots on disks of `disk_type`, nodes which do not report disk types only have
+    /// hdd slots
+    pub fn disk_free_space(&self, disk_type: DiskType) -> i64 {
+        if self.max_volume_counts.is_empty() {
+            return match disk_type {
+                DiskType::Hdd => self.free_space(),
+                DiskType::Ssd => 0,
+            };
+        }
+        let max_volume_count = self
+            .max_volume_counts
+            .get(&disk_type)
+            .map_or(0, |count| *count);
+        let volume_count = self
+            .volumes
+            .iter()
+            .filter(|volume| volume.disk_type == disk_type)
+            .count() as i64;
+        (max_volume_count - volume_count).min(self.free_space())
+    }
+
     pub async fn delta_update_volumes(
         &self,
         new_volumes: &[VolumeInfo],
@@ -149,6 +275,11 @@ impl DataNode {
         is_new
     }
 
+    /// bytes of the volumes on the node as of the last heartbeat
+    pub fn used_bytes(&self) -> u64 {
+        self.volumes.iter().map(|volume| volume.size).sum()
+    }
+
     pub fn get_volume(&self, vid: VolumeId) -> Option<Ref<VolumeId, VolumeInfo>> {
         self.volumes.get(&vid)
     }
@@ -180,13 +311,56 @@ impl DataNode {
         Ok(response.into_inner())
     }
 
+    pub async fn volume_copy(
+        &self,
+        request: VolumeCopyRequest,
+    ) -> StdResult<VolumeCopyResponse, VolumeError> {
+        let addr = self.url();
+        let client = volume_server_client(&addr)?;
+        let response = client.volume_copy(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn volume_delete(
+        &self,
+        request: VolumeDeleteRequest,
+    ) -> StdResult<VolumeDeleteResponse, VolumeError> {
+        let addr = self.url();
+        let client = volume_server_client(&addr)?;
+        let response = client.volume_delete(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn volume_mark_readonly(
+        &self,
+        request: VolumeMarkReadonlyRequest,
+    ) -> StdResult<VolumeMarkReadonlyResponse, VolumeError> {
+        let addr = self.url();
+        let client = volume_server_client(&addr)?;
+        let response = client.volume_mark_readonly(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn volume_mark_writable(
+        &self,
+        request: VolumeMarkWritableRequest,
+    ) -> StdResult<VolumeMarkWritableResponse, VolumeError> {
+        let addr = self.url();
+        let client = volume_server_client(&addr)?;
+        let response = client.volume_mark_writable(request).await?;
+        Ok(response.into_inner())
+    }
+
     pub async fn vacuum_volume_check(
         &self,
         request: VacuumVolumeCheckRequest,
     ) -> StdResult<VacuumVolumeCheckResponse, VolumeError> {
         let addr = self.url();
-        let client = volume_server_client(&addr)?;
-        let response = client.vacuum_volume_check(request).await?;
+        let response = retry("vacuum volume check", || async {
+            let client = volume_server_client(&addr)?;
+            Ok::<_, VolumeError>(client.vacuum_volume_check(request.clone()).await?)
+        })
+        .await?;
         Ok(response.into_inner())
     }
 
@@ -227,10 +401,12 @@ pub type DataNodeRef = Arc<DataNode>;
 
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
+
     use faststr::FastStr;
 
     use crate::{
-        storage::{ReplicaPlacement, Ttl, VolumeId, VolumeInfo, CURRENT_VERSION},
+        storage::{DiskType, ReplicaPlacement, Ttl, VolumeId, VolumeInfo, CURRENT_VERSION},
         topology::{data_node::DataNode, node::Node},
     };
 
@@ -310,4 +486,20 @@ mod test {
         assert_eq!(new_volumes.len(), 0);
         assert_eq!(deleted_volumes.len(), 1);
     }
+
+    #[tokio::test]
+    pub async fn test_disk_free_space() {
+        let data_node = setup();
+        data_node.adjust_max_volume_count(3).await;
+        assert_eq!(data_node.disk_free_space(DiskType::Hdd), 4);
+        assert_eq!(data_node.disk_free_space(DiskType::Ssd), 0);
+
+        let max_volume_counts = HashMap::from([(String::from("hdd"), 2), (String::from("ssd"), 2)]);
+        data_node.set_max_volume_counts(&max_volume_counts);
+        let mut volume = volume_info(0);
+        volume.disk_type = DiskType::Ssd;
+        data_node.update_volumes(vec![volume]).await;
+        assert_eq!(data_node.disk_free_space(DiskType::Hdd), 2);
+        assert_eq!(data_node.disk_free_space(DiskType::Ssd), 1);
+    }
 }
@@ -0,0 +1,313 @@
+use std::sync::atomic::Ordering;
+
+use faststr::FastStr;
+use helyim_proto::{
+    directory::{TopologyEvent, TopologyEventKind},
+    volume::{
+        VolumeCopyRequest, VolumeDeleteRequest, VolumeMarkReadonlyRequest,
+        VolumeMarkWritableRequest,
+    },
+};
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::{
+    storage::{VolumeError, VolumeId, VolumeInfo},
+    topology::{new_event, node::Node, DataNodeRef, JobKind, JobState, Topology, TopologyRef},
+    util::time::now,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DecommissionState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecommissionProgress {
+    pub node: FastStr,
+    /// the admin job moving the volumes
+    pub job: u64,
+    pub state: DecommissionState,
+    pub total: usize,
+    pub moved: usize,
+    pub failed: Vec<VolumeId>,
+    /// quarantined volumes are not moved, their data is suspect. they stay on the node until the
+    /// quarantine is resolved
+    pub quarantined: Vec<VolumeId>,
+    pub started_at: u64,
+    pub finished_at: u64,
+}
+
+impl DecommissionProgress {
+    fn new(node: FastStr, job: u64, total: usize, quarantined: Vec<VolumeId>) -> Self {
+        Self {
+            node,
+            job,
+            state: DecommissionState::Running,
+            total,
+            moved: 0,
+            failed: Vec::new(),
+            quarantined,
+            started_at: now().as_secs(),
+            finished_at: 0,
+        }
+    }
+}
+
+impl Topology {
+    pub fn decommission_progress(&self, node: &str) -> Option<DecommissionProgress> {
+        self.decommissions
+            .get(node)
+            .map(|progress| progress.clone())
+    }
+
+    pub fn decommissions(&self) -> Vec<DecommissionProgress> {
+        self.decommissions
+            .iter()
+            .map(|progress| progress.value().clone())
+            .collect()
+    }
+
+    /// pick a node to host a copy of `vid`, nodes close to `source` are preferred so the replica
+    /// placement of the volume is kept.
+    pub async fn pick_move_target(
+        &self,
+        vid: VolumeId,
+        source: &DataNodeRef,
+    ) -> Option<DataNodeRef> {
+        let source_rack = source.rack_id().await;
+        let source_dc = source.data_center_id().await;
+        let disk_type = source
+            .get_volume(vid)
+            .map(|volume| volume.disk_type)
+            .unwrap_or_default();
+
+        let mut target: Option<((u8, i64), DataNodeRef)> = None;
+        for data_node in self.data_nodes() {
+            if data_node.id() == source.id()
+                || data_node.is_decommissioning()
+                || data_node.disk_free_space(disk_type) <= 0
+                || data_node.volumes.contains_key(&vid)
+            {
+                continue;
+            }
+            let closeness = if data_node.data_center_id().await != source_dc {
+                0
+            } else if data_node.rack_id().await != source_rack {
+                1
+            } else {
+                2
+            };
+            let score = (closeness, data_node.disk_free_space(disk_type));
+            if target.as_ref().map_or(true, |(best, _)| score > *best) {
+                target = Some((score, data_node));
+            }
+        }
+        target.map(|(_, data_node)| data_node)
+    }
+
+    /// copy a volume from `source` to `target` then drop it from `source`, the volume is readonly
+    /// on the source while it is copied and writable again if the copy fails.
+    pub async fn move_volume(
+        &self,
+        volume: &VolumeInfo,
+        source: &DataNodeRef,
+        target: &DataNodeRef,
+    ) -> Result<(), VolumeError> {
+        let layout = self.get_volume_layout(
+            volume.collection.clone(),
+            volume.replica_placement,
+            volume.ttl,
+            volume.disk_type,
+        );
+        let writable = layout.remove_from_writable(&volume.id).await;
+        let copied = match source
+            .volume_mark_readonly(VolumeMarkReadonlyRequest {
+                volume_id: volume.id,
+            })
+            .await
+        {
+            Ok(_) => target
+                .volume_copy(VolumeCopyRequest {
+                    volume_id: volume.id,
+                    collection: volume.collection.to_string(),
+                    source_data_node: source.url(),
+                    disk_type: volume.disk_type.as_str().to_string(),
+                })
+                .await
+                .map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = copied {
+            // the volume stays on the source, it takes writes again unless it was readonly before
+            if !volume.read_only {
+                if let Err(err) = source
+                    .volume_mark_writable(VolumeMarkWritableRequest {
+                        volume_id: volume.id,
+                    })
+                    .await
+                {
+                    error!(
+                        "mark volume {} writable on {} error: {err}",
+                        volume.id,
+                        source.url()
+                    );
+                }
+            }
+            if writable {
+                layout.set_volume_writable(volume.id).await;
+            }
+            return Err(err);
+        }
+        source
+            .volume_delete(VolumeDeleteRequest {
+                volume_id: volume.id,
+            })
+            .await?;
+        self.publish_event(TopologyEvent {
+            node: source.url(),
+            volume_id: volume.id,
+            collection: volume.collection.to_string(),
+            target: target.url(),
+            ..new_event(TopologyEventKind::VolumeMoved)
+        });
+        Ok(())
+    }
+}
+
+/// start evacuating all volumes of a data node, the node stops receiving new volumes at once and
+/// can be removed after the returned progress is completed. a cancelled decommission leaves the
+/// node draining, starting it again moves the remaining volumes.
+pub async fn start_decommission(
+    topology: TopologyRef,
+    node: &str,
+) -> Result<DecommissionProgress, VolumeError> {
+    let data_node = topology
+        .find_data_node(node)
+        .ok_or_else(|| VolumeError::DataNodeNotFound(FastStr::new(node)))?;
+    if let Some(progress) = topology.decommission_progress(node) {
+        if progress.state == DecommissionState::Running {
+            return Ok(progress);
+        }
+    }
+
+    data_node.decommissioning.store(true, Ordering::Relaxed);
+    let free_space = data_node.free_space();
+    if free_space > 0 {
+        data_node.adjust_max_volume_count(-free_space).await;
+    }
+
+    let (quarantined, volumes): (Vec<VolumeInfo>, Vec<VolumeInfo>) = data_node
+        .volumes
+        .iter()
+        .map(|volume| volume.value().clone())
+        .partition(|volume| volume.quarantined);
+    let quarantined: Vec<VolumeId> = quarantined.iter().map(|volume| volume.id).collect();
+    if !quarantined.is_empty() {
+        warn!("decommission {node}, quarantined volumes {quarantined:?} are not moved");
+    }
+    for volume in volumes.iter() {
+        topology
+            .get_volume_layout(
+                volume.collection.clone(),
+                volume.replica_placement,
+                volume.ttl,
+                volume.disk_type,
+            )
+            .remove_from_writable(&volume.id)
+            .await;
+    }
+
+    let node = FastStr::new(node);
+    let job = topology
+        .jobs
+        .start(JobKind::Decommission, node.clone(), volumes.len());
+    let progress = DecommissionProgress::new(node.clone(), job.id(), volumes.len(), quarantined);
+    topology
+        .decommissions
+        .insert(node.clone(), progress.clone());
+
+    tokio::spawn(async move {
+        info!("decommission {node}, {} volumes to move", volumes.len());
+        for volume in volumes {
+            if !job.checkpoint().await {
+                break;
+            }
+            let result = match topology.pick_move_target(volume.id, &data_node).await {
+                Some(target) => topology.move_volume(&volume, &data_node, &target).await,
+                None => Err(VolumeError::NoFreeSpace(format!(
+                    "no target for volume {}",
+                    volume.id
+                ))),
+            };
+
+            if result.is_ok() {
+                // keep the released slot from being used by volume growth
+                data_node.adjust_max_volume_count(-1).await;
+            }
+            if result.is_ok() {
+                job.step_done();
+            } else {
+                job.step_failed(volume.id);
+            }
+            if let Some(mut progress) = topology.decommissions.get_mut(&node) {
+                match result {
+                    Ok(()) => progress.moved += 1,
+                    Err(err) => {
+                        error!(
+                            "decommission {node}, move volume {} failed: {err}",
+                            volume.id
+                        );
+                        progress.failed.push(volume.id);
+                    }
+                }
+            }
+        }
+
+        let status = job.finish();
+        if let Some(mut progress) = topology.decommissions.get_mut(&node) {
+            progress.state = match status.state {
+                JobState::Cancelled => DecommissionState::Cancelled,
+                JobState::Failed => DecommissionState::Failed,
+                _ => DecommissionState::Completed,
+            };
+            progress.finished_at = now().as_secs();
+            info!(
+                "decommission {node} finished, moved: {}, failed: {:?}",
+                progress.moved, progress.failed
+            );
+        }
+    });
+
+    Ok(progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::topology::{node::Node, tests::setup_topo};
+
+    #[tokio::test]
+    async fn test_pick_move_target() {
+        let topo = setup_topo().await;
+
+        // prefer the node in the same rack
+        let source = topo.find_data_node("server111").unwrap();
+        let target = topo.pick_move_target(1, &source).await.unwrap();
+        assert_eq!(target.id(), "server112");
+
+        // nodes already holding the volume are skipped
+        let source = topo.find_data_node("server121").unwrap();
+        let target = topo.pick_move_target(4, &source).await.unwrap();
+        assert_eq!(target.id(), "server122");
+
+        // the only node in dc3, fall back to the node with most free space
+        let source = topo.find_data_node("server321").unwrap();
+        let target = topo.pick_move_target(3, &source).await.unwrap();
+        assert_eq!(target.id(), "server112");
+    }
+}
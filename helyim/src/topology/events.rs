@@ -0,0 +1,96 @@
+use helyim_proto::directory::{TopologyEvent, TopologyEventKind};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tracing::{info, warn};
+
+use crate::{raft::RaftServer, topology::Topology, util::time::now};
+
+/// events kept for observers falling behind, a slower observer loses the stream and has to
+/// watch again
+pub const TOPOLOGY_EVENT_CAPACITY: usize = 1024;
+
+pub(super) fn event_channel() -> Sender<TopologyEvent> {
+    broadcast::channel(TOPOLOGY_EVENT_CAPACITY).0
+}
+
+/// an event of `kind` at the current time, the other fields are left empty
+pub fn new_event(kind: TopologyEventKind) -> TopologyEvent {
+    TopologyEvent {
+        kind: kind as i32,
+        ts_ns: now().as_nanos() as u64,
+        ..Default::default()
+    }
+}
+
+impl Topology {
+    pub fn subscribe_events(&self) -> Receiver<TopologyEvent> {
+        self.events.subscribe()
+    }
+
+    /// send `event` to the current observers, it is dropped if there are none
+    pub fn publish_event(&self, event: TopologyEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// publish the leader of the raft cluster whenever it changes, the paused jobs are cancelled
+    /// when this node loses the leadership
+    pub(super) fn watch_leader(&self, raft: RaftServer) {
+        let events = self.events.clone();
+        let jobs = self.jobs.clone();
+        let mut metrics = raft.raft.metrics();
+        tokio::spawn(async move {
+            let mut leader = None;
+            while metrics.changed().await.is_ok() {
+                let current = metrics.borrow().current_leader;
+                if current == leader {
+                    continue;
+                }
+                if leader == Some(raft.id) {
+                    let cancelled = jobs.cancel_paused();
+                    if !cancelled.is_empty() {
+                        warn!("raft leadership is lost, paused jobs {cancelled:?} are cancelled");
+                    }
+                }
+                leader = current;
+                let address = raft.current_leader_address().await.unwrap_or_default();
+                info!("raft leader changed to {address}");
+                let _ = events.send(TopologyEvent {
+                    leader: address.to_string(),
+                    ..new_event(TopologyEventKind::LeaderChanged)
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use helyim_proto::directory::{TopologyEvent, TopologyEventKind};
+
+    use crate::{
+        directory::Sequencer,
+        sequence::MemorySequencer,
+        topology::{events::new_event, Topology},
+    };
+
+    #[tokio::test]
+    async fn test_topology_events() {
+        let topo = Topology::new(Sequencer::Memory(MemorySequencer::new()), 32 * 1024, 5);
+        // nobody is watching yet
+        topo.publish_event(new_event(TopologyEventKind::NodeJoined));
+
+        let mut events = topo.subscribe_events();
+        topo.publish_event(TopologyEvent {
+            node: "127.0.0.1:8080".to_string(),
+            ..new_event(TopologyEventKind::NodeLeft)
+        });
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.kind(), TopologyEventKind::NodeLeft);
+        assert_eq!(event.node, "127.0.0.1:8080");
+        assert!(events.try_recv().is_err());
+        // an event without a kind is no joined node
+        assert_eq!(
+            TopologyEvent::default().kind(),
+            TopologyEventKind::Unspecified
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use dashmap::DashMap;
+use faststr::FastStr;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::{storage::VolumeId, topology::TopologyError, util::time::now};
+
+/// how many finished jobs are kept for listing
+const FINISHED_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobKind {
+    Vacuum,
+    Decommission,
+    Balance,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+    Running,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Completed | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobAction {
+    Pause,
+    Resume,
+    Cancel,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub id: u64,
+    pub kind: JobKind,
+    /// what the job works on, the data node of a decommission, empty for cluster wide jobs
+    pub target: FastStr,
+    pub state: JobState,
+    pub total: usize,
+    pub done: usize,
+    pub failed: Vec<VolumeId>,
+    pub progress: f64,
+    pub started_at: u64,
+    pub finished_at: u64,
+}
+
+impl JobStatus {
+    fn update_progress(&mut self) {
+        self.progress = if self.total == 0 {
+            100.0
+        } else {
+            (self.done + self.failed.len()) as f64 * 100.0 / self.total as f64
+        };
+    }
+}
+
+/// A running admin job, the worker reports its progress and calls `checkpoint` between steps so
+/// the job can be paused or cancelled.
+pub struct Job {
+    status: Mutex<JobStatus>,
+    control: watch::Sender<JobState>,
+}
+
+pub type JobRef = Arc<Job>;
+
+impl Job {
+    pub fn status(&self) -> JobStatus {
+        self.status.lock().clone()
+    }
+
+    pub fn id(&self) -> u64 {
+        self.status.lock().id
+    }
+
+    /// wait while the job is paused, returns false if the job is cancelled and the worker should
+    /// stop
+    pub async fn checkpoint(&self) -> bool {
+        let mut rx = self.control.subscribe();
+        match rx.wait_for(|state| *state != JobState::Paused).await {
+            Ok(state) => *state != JobState::Cancelled,
+            Err(_) => false,
+        }
+    }
+
+    pub fn step_done(&self) {
+        let mut status = self.status.lock();
+        status.done += 1;
+        status.update_progress();
+    }
+
+    pub fn step_failed(&self, vid: VolumeId) {
+        let mut status = self.status.lock();
+        status.failed.push(vid);
+        status.update_progress();
+    }
+
+    /// mark the job completed, or failed if any step failed, a cancelled job stays cancelled
+    pub fn finish(&self) -> JobStatus {
+        let mut status = self.status.lock();
+        if !status.state.finished() {
+            status.state = if status.failed.is_empty() {
+                JobState::Completed
+            } else {
+                JobState::Failed
+            };
+            self.control.send_replace(status.state);
+        }
+        status.finished_at = now().as_secs();
+        status.clone()
+    }
+
+    fn control(&self, action: JobAction) -> Result<JobStatus, TopologyError> {
+        let mut status = self.status.lock();
+        if status.state.finished() {
+            return Err(TopologyError::JobFinished(status.id));
+        }
+        status.state = match action {
+            JobAction::Pause => JobState::Paused,
+            JobAction::Resume => JobState::Running,
+            JobAction::Cancel => JobState::Cancelled,
+        };
+        self.control.send_replace(status.state);
+        Ok(status.clone())
+    }
+}
+
+/// Every long running operation of the master runs as a job, so it can be listed, paused and
+/// cancelled through the admin api.
+#[derive(Default)]
+pub struct JobManager {
+    next_id: AtomicU64,
+    jobs: DashMap<u64, JobRef>,
+}
+
+impl JobManager {
+    pub fn start(&self, kind: JobKind, target: FastStr, total: usize) -> JobRef {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut status = JobStatus {
+            id,
+            kind,
+            target,
+            state: JobState::Running,
+            total,
+            done: 0,
+            failed: Vec::new(),
+            progress: 0.0,
+            started_at: now().as_secs(),
+            finished_at: 0,
+        };
+        status.update_progress();
+        let job = Arc::new(Job {
+            status: Mutex::new(status),
+            control: watch::Sender::new(JobState::Running),
+        });
+        self.jobs.insert(id, job.clone());
+        self.prune();
+        job
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobRef> {
+        self.jobs.get(&id).map(|job| job.clone())
+    }
+
+    /// the unfinished job of `kind` on `target`, a new one should not be started beside it
+    pub fn running(&self, kind: JobKind, target: &str) -> Option<JobRef> {
+        self.jobs
+            .iter()
+            .find(|job| {
+                let status = job.status.lock();
+                status.kind == kind && status.target == target && !status.state.finished()
+            })
+            .map(|job| job.value().clone())
+    }
+
+    pub fn list(&self) -> Vec<JobStatus> {
+        let mut jobs: Vec<JobStatus> = self.jobs.iter().map(|job| job.status()).collect();
+        jobs.sort_by_key(|job| job.id);
+        jobs
+    }
+
+    pub fn control(&self, id: u64, action: JobAction) -> Result<JobStatus, TopologyError> {
+        self.get(id)
+            .ok_or(TopologyError::JobNotFound(id))?
+            .control(action)
+    }
+
+    /// cancel the paused jobs, nobody can resume them once this master is no longer the leader.
+    /// returns the ids of the cancelled jobs
+    pub fn cancel_paused(&self) -> Vec<u64> {
+        self.jobs
+            .iter()
+            .filter(|job| job.status.lock().state == JobState::Paused)
+            .filter_map(|job| job.control(JobAction::Cancel).ok())
+            .map(|status| status.id)
+            .collect()
+    }
+
+    fn prune(&self) {
+        let mut finished: Vec<u64> = self
+            .jobs
+            .iter()
+            .filter(|job| job.status.lock().state.finished())
+            .map(|job| *job.key())
+            .collect();
+        if finished.len() > FINISHED_JOBS {
+            finished.sort_unstable();
+            for id in &finished[..finished.len() - FINISHED_JOBS] {
+                self.jobs.remove(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use faststr::FastStr;
+
+    use crate::topology::{
+        job::{JobAction, JobKind, JobManager, JobState},
+        TopologyError,
+    };
+
+    #[tokio::test]
+    async fn test_job_control() {
+        let manager = JobManager::default();
+        let job = manager.start(JobKind::Decommission, FastStr::new("node1"), 4);
+        assert!(manager.running(JobKind::Decommission, "node1").is_some());
+        assert!(manager.running(JobKind::Vacuum, "").is_none());
+
+        job.step_done();
+        job.step_failed(3);
+        let status = job.status();
+        assert_eq!(status.progress, 50.0);
+
+        // a paused job blocks at the next checkpoint until it is resumed
+        manager.control(job.id(), JobAction::Pause).unwrap();
+        let worker = tokio::spawn({
+            let job = job.clone();
+            async move { job.checkpoint().await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!worker.is_finished());
+        manager.control(job.id(), JobAction::Resume).unwrap();
+        assert!(worker.await.unwrap());
+
+        manager.control(job.id(), JobAction::Cancel).unwrap();
+        assert!(!job.checkpoint().await);
+        assert_eq!(job.finish().state, JobState::Cancelled);
+        assert!(matches!(
+            manager.control(job.id(), JobAction::Resume),
+            Err(TopologyError::JobFinished(_))
+        ));
+        assert!(matches!(
+            manager.control(100, JobAction::Cancel),
+            Err(TopologyError::JobNotFound(100))
+        ));
+
+        let job = manager.start(JobKind::Vacuum, FastStr::empty(), 1);
+        job.step_failed(1);
+        assert_eq!(job.finish().state, JobState::Failed);
+        assert_eq!(manager.list().len(), 2);
+
+        // only the paused jobs are cancelled when the leadership is lost
+        let running = manager.start(JobKind::Vacuum, FastStr::empty(), 1);
+        let paused = manager.start(JobKind::Decommission, FastStr::new("node2"), 1);
+        manager.control(paused.id(), JobAction::Pause).unwrap();
+        assert_eq!(manager.cancel_paused(), vec![paused.id()]);
+        assert!(!paused.checkpoint().await);
+        assert_eq!(running.status().state, JobState::Running);
+        assert!(manager.cancel_paused().is_empty());
+    }
+}
@@ -1,17 +1,46 @@
 #[macro_use]
 pub mod node;
 
+mod bootstrap;
+pub use bootstrap::StaticTopology;
+
+mod balance;
+pub use balance::VolumeMove;
+
 pub mod collection;
 
+mod collection_deletion;
+pub use collection_deletion::{
+    CollectionDeleteAction, CollectionDeletion, CollectionDeletionState,
+};
+
 mod data_center;
 
 mod data_node;
-pub use data_node::DataNodeRef;
+pub use data_node::{DataNodeRef, DEFAULT_MAX_MISSED_HEARTBEATS};
+
+mod decommission;
+pub use decommission::{start_decommission, DecommissionProgress, DecommissionState};
 
 mod erasure_coding;
 
+mod events;
+pub use events::{new_event, TOPOLOGY_EVENT_CAPACITY};
+
+mod job;
+pub use job::{JobAction, JobKind, JobRef, JobState, JobStatus};
+
+mod policy;
+pub use policy::{topology_maintenance_loop, MaintenancePolicy};
+
 mod rack;
 
+mod replication;
+pub use replication::topology_replication_loop;
+
+mod simulation;
+pub use simulation::{PlacementChange, SimulatedCopy, SimulatedNode, SimulationReport};
+
 mod topology;
 #[cfg(test)]
 pub(crate) use topology::tests;
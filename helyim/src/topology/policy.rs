@@ -0,0 +1,134 @@
+use std::{fs::File, path::Path, time::Duration};
+
+use faststr::FastStr;
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+use crate::{storage::VolumeError, topology::TopologyRef};
+
+fn default_interval() -> u64 {
+    15 * 60
+}
+
+/// Maintenance the master enforces on its own, loaded from the policy file and reloaded every
+/// round, so changes apply without a restart.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct MaintenancePolicy {
+    /// seconds between two rounds
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    /// volumes with more garbage than this ratio are vacuumed, no vacuum if absent
+    #[serde(default)]
+    pub max_garbage_ratio: Option<f64>,
+    /// volumes are moved until the usage of the data nodes of a rack differs less than this, no
+    /// balancing if absent
+    #[serde(default)]
+    pub balance_threshold: Option<f64>,
+}
+
+impl MaintenancePolicy {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MaintenancePolicy, VolumeError> {
+        let file = File::open(path)?;
+        let policy: MaintenancePolicy = serde_json::from_reader(file)?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    fn validate(&self) -> Result<(), VolumeError> {
+        if self.interval == 0 {
+            return Err(VolumeError::String(
+                "policy interval should be positive".to_string(),
+            ));
+        }
+        for (name, ratio) in [
+            ("maxGarbageRatio", self.max_garbage_ratio),
+            ("balanceThreshold", self.balance_threshold),
+        ] {
+            if let Some(ratio) = ratio {
+                if !(ratio > 0.0 && ratio <= 1.0) {
+                    return Err(VolumeError::String(format!(
+                        "policy {name} should be in (0, 1], got {ratio}"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// launch the admin jobs the policy asks for, replaces the fixed vacuum loop when a policy file
+/// is given
+pub async fn topology_maintenance_loop(
+    topology: TopologyRef,
+    path: FastStr,
+    mut policy: MaintenancePolicy,
+    preallocate: u64,
+    mut shutdown: async_broadcast::Receiver<()>,
+) {
+    info!("topology maintenance loop starting, policy: {policy:?}");
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(policy.interval)) => {
+                match MaintenancePolicy::load(path.as_str()) {
+                    Ok(latest) => {
+                        if latest != policy {
+                            info!("maintenance policy changed: {latest:?}");
+                            policy = latest;
+                        }
+                    }
+                    Err(err) => warn!("reload maintenance policy {path} failed, keep the last one: {err}"),
+                }
+                if !topology.is_leader().await {
+                    continue;
+                }
+                // jobs run in the background, a paused job must not block the loop
+                let topology = topology.clone();
+                let policy = policy.clone();
+                tokio::spawn(async move {
+                    if let Some(ratio) = policy.max_garbage_ratio {
+                        debug!("policy vacuum starting.");
+                        topology.vacuum(ratio, preallocate).await;
+                    }
+                    if let Some(threshold) = policy.balance_threshold {
+                        debug!("policy balance starting.");
+                        topology.balance(threshold).await;
+                    }
+                });
+            }
+            _ = shutdown.recv() => {
+                break;
+            }
+        }
+    }
+    info!("topology maintenance loop stopped")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::topology::policy::MaintenancePolicy;
+
+    fn load(content: &str) -> Result<MaintenancePolicy, String> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        MaintenancePolicy::load(file.path()).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_load_policy() {
+        let policy = load(r#"{"maxGarbageRatio": 0.3, "balanceThreshold": 0.1}"#).unwrap();
+        assert_eq!(policy.interval, 15 * 60);
+        assert_eq!(policy.max_garbage_ratio, Some(0.3));
+        assert_eq!(policy.balance_threshold, Some(0.1));
+
+        let policy = load(r#"{"interval": 60}"#).unwrap();
+        assert_eq!(policy.max_garbage_ratio, None);
+
+        assert!(load(r#"{"interval": 0}"#).is_err());
+        assert!(load(r#"{"maxGarbageRatio": 1.5}"#).is_err());
+        // unsupported settings are rejected instead of silently ignored
+        assert!(load(r#"{"ecAge": 86400}"#).is_err());
+    }
+}
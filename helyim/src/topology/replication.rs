@@ -0,0 +1,289 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use dashmap::DashSet;
+use faststr::FastStr;
+use helyim_proto::volume::VolumeCopyRequest;
+use tokio::sync::Semaphore;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    storage::{ReplicaPlacement, VolumeId, VolumeInfo},
+    topology::{node::Node, DataNodeRef, Topology, TopologyRef},
+    util::{args::ReplicationOptions, time::now},
+};
+
+/// Whether replicas at `locations`, the data center and rack of each, may be part of the layout
+/// `rp` asks for: the main rack holds at most `same_rack_count + 1` of them, at most
+/// `diff_rack_count` other racks of its data center and `diff_data_center_count` other data
+/// centers hold one each.
+pub(super) fn fits_placement(rp: &ReplicaPlacement, locations: &[(FastStr, FastStr)]) -> bool {
+    if locations.len() > rp.copy_count() {
+        return false;
+    }
+    if locations.is_empty() {
+        return true;
+    }
+    // a rack holding a replica is as good a main rack as any other
+    locations.iter().any(|(main_data_center, main_rack)| {
+        let mut other_data_centers: HashMap<&FastStr, usize> = HashMap::new();
+        let mut other_racks: HashMap<&FastStr, usize> = HashMap::new();
+        let mut same_rack = 0;
+        for (data_center, rack) in locations {
+            if data_center != main_data_center {
+                *other_data_centers.entry(data_center).or_default() += 1;
+            } else if rack != main_rack {
+                *other_racks.entry(rack).or_default() += 1;
+            } else {
+                same_rack += 1;
+            }
+        }
+        same_rack <= rp.same_rack_count as usize + 1
+            && other_racks.len() <= rp.diff_rack_count as usize
+            && other_racks.values().all(|count| *count == 1)
+            && other_data_centers.len() <= rp.diff_data_center_count as usize
+            && other_data_centers.values().all(|count| *count == 1)
+    })
+}
+
+impl Topology {
+    /// remove the data nodes which missed too many heartbeats, their replicas become missing
+    pub async fn expire_dead_nodes(&self) -> Vec<DataNodeRef> {
+        let mut expired = Vec::new();
+        for data_node in self.data_nodes() {
+            // declared data nodes are kept until they connect
+            if !data_node.is_connected() || data_node.is_alive(self.liveness_timeout()) {
+                continue;
+            }
+            warn!(
+                "data node {} missed heartbeats since {}, remove it from topology",
+                data_node.id(),
+                data_node.last_seen()
+            );
+            data_node.expire();
+            self.unregister_data_node(&data_node).await;
+            expired.push(data_node);
+        }
+        expired
+    }
+
+    /// volumes with less replicas than their replica placement requires
+    pub fn under_replicated_volumes(&self) -> Vec<(VolumeInfo, Vec<DataNodeRef>)> {
+        let mut volumes = Vec::new();
+        for collection in self.collections.iter() {
+            for layout in collection.volume_layouts.iter() {
+                let copy_count = layout.replica_placement().copy_count();
+                for locations in layout.locations.iter() {
+                    if locations.is_empty() || locations.len() >= copy_count {
+                        continue;
+                    }
+                    // the data of a quarantined replica is suspect, do not spread it even from the
+                    // other replicas until the quarantine is resolved
+                    let vid = *locations.key();
+                    if locations.iter().any(|data_node| {
+                        data_node
+                            .get_volume(vid)
+                            .is_some_and(|volume| volume.quarantined)
+                    }) {
+                        continue;
+                    }
+                    if let Some(volume) = locations[0].get_volume(vid) {
+                        let mut volume = volume.clone();
+                        // the layout decides how many replicas there should be and where
+                        volume.replica_placement = layout.replica_placement();
+                        volumes.push((volume, locations.value().clone()));
+                    }
+                }
+            }
+        }
+        volumes
+    }
+
+    /// pick a node for a new replica of `vid` which keeps its replica placement, racks which do
+    /// not host a replica yet are preferred
+    pub async fn pick_replica_target(
+        &self,
+        volume: &VolumeInfo,
+        holders: &[DataNodeRef],
+    ) -> Option<DataNodeRef> {
+        let (vid, disk_type, placement) = (volume.id, volume.disk_type, volume.replica_placement);
+        let mut locations = Vec::with_capacity(holders.len() + 1);
+        for holder in holders {
+            locations.push((holder.data_center_id().await, holder.rack_id().await));
+        }
+        let holder_racks: HashSet<(FastStr, FastStr)> = locations.iter().cloned().collect();
+
+        let mut target: Option<((bool, i64), DataNodeRef)> = None;
+        for data_node in self.data_nodes() {
+            if data_node.is_decommissioning()
+                || !data_node.is_alive(self.liveness_timeout())
+                || data_node.disk_free_space(disk_type) <= 0
+                || data_node.volumes.contains_key(&vid)
+            {
+                continue;
+            }
+            let location = (data_node.data_center_id().await, data_node.rack_id().await);
+            locations.push(location.clone());
+            let fits = fits_placement(&placement, &locations);
+            locations.pop();
+            if !fits {
+                continue;
+            }
+            let new_rack = !holder_racks.contains(&location);
+            let score = (new_rack, data_node.disk_free_space(disk_type));
+            if target.as_ref().map_or(true, |(best, _)| score > *best) {
+                target = Some((score, data_node));
+            }
+        }
+        target.map(|(_, data_node)| data_node)
+    }
+}
+
+/// Tracks under replicated volumes and re-creates the missing replicas once they have been
+/// missing for longer than the grace period.
+pub struct ReplicationChecker {
+    topology: TopologyRef,
+    grace_period: u64,
+    permits: Arc<Semaphore>,
+    missing_since: HashMap<VolumeId, u64>,
+    in_flight: Arc<DashSet<VolumeId>>,
+}
+
+impl ReplicationChecker {
+    pub fn new(topology: TopologyRef, options: &ReplicationOptions) -> Self {
+        Self {
+            topology,
+            grace_period: options.replica_grace_period,
+            permits: Arc::new(Semaphore::new(options.max_concurrent_replications)),
+            missing_since: HashMap::new(),
+            in_flight: Arc::new(DashSet::new()),
+        }
+    }
+
+    pub async fn check(&mut self) {
+        let now = now().as_secs();
+        let volumes = self.topology.under_replicated_volumes();
+
+        let missing: HashSet<VolumeId> = volumes.iter().map(|(volume, _)| volume.id).collect();
+        self.missing_since.retain(|vid, _| missing.contains(vid));
+
+        for (volume, holders) in volumes {
+            let since = *self.missing_since.entry(volume.id).or_insert(now);
+            if now - since < self.grace_period || self.in_flight.contains(&volume.id) {
+                continue;
+            }
+            let permit = match self.permits.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    debug!("too many replications in flight, wait for the next round");
+                    break;
+                }
+            };
+            let target = match self.topology.pick_replica_target(&volume, &holders).await {
+                Some(target) => target,
+                None => {
+                    warn!(
+                        "no data node can host a new replica of volume {}",
+                        volume.id
+                    );
+                    continue;
+                }
+            };
+
+            let source = holders[0].clone();
+            let in_flight = self.in_flight.clone();
+            in_flight.insert(volume.id);
+            tokio::spawn(async move {
+                let _permit = permit;
+                info!(
+                    "re-create replica of volume {} from {} to {}",
+                    volume.id,
+                    source.url(),
+                    target.url()
+                );
+                let request = VolumeCopyRequest {
+                    volume_id: volume.id,
+                    collection: volume.collection.to_string(),
+                    source_data_node: source.url(),
+                    disk_type: volume.disk_type.as_str().to_string(),
+                };
+                if let Err(err) = target.volume_copy(request).await {
+                    error!("re-create replica of volume {} failed: {err}", volume.id);
+                }
+                in_flight.remove(&volume.id);
+            });
+        }
+    }
+}
+
+pub async fn topology_replication_loop(
+    topology: TopologyRef,
+    options: ReplicationOptions,
+    mut shutdown: async_broadcast::Receiver<()>,
+) {
+    info!("topology replication loop starting");
+    let mut checker = ReplicationChecker::new(topology.clone(), &options);
+    let mut interval = tokio::time::interval(Duration::from_secs(topology.pulse()));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if topology.is_leader().await {
+                    topology.expire_dead_nodes().await;
+                    checker.check().await;
+                }
+            }
+            _ = shutdown.recv() => {
+                break;
+            }
+        }
+    }
+    info!("topology replication loop stopped")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        storage::{ReplicaPlacement, VolumeInfo, CURRENT_VERSION},
+        topology::{node::Node, replication::ReplicationChecker, tests::setup_topo},
+        util::args::ReplicationOptions,
+    };
+
+    #[tokio::test]
+    async fn test_under_replicated_volumes() {
+        let topo = setup_topo().await;
+        let holder = topo.find_data_node("server111").unwrap();
+        let volume = VolumeInfo {
+            id: 1,
+            size: 100,
+            replica_placement: ReplicaPlacement::new("010").unwrap(),
+            version: CURRENT_VERSION,
+            ..Default::default()
+        };
+        topo.register_volume_layout(&volume, &holder).await;
+
+        let volumes = topo.under_replicated_volumes();
+        assert_eq!(volumes.len(), 1);
+        assert_eq!(volumes[0].0.id, 1);
+
+        // server112 shares the rack with the holder, another rack is preferred
+        let target = topo
+            .pick_replica_target(&volumes[0].0, &volumes[0].1)
+            .await
+            .unwrap();
+        assert_ne!(target.rack_id().await, holder.rack_id().await);
+        assert!(!target.volumes.contains_key(&1));
+
+        let options = ReplicationOptions {
+            replica_grace_period: 3600,
+            ..Default::default()
+        };
+        let mut checker = ReplicationChecker::new(topo.clone(), &options);
+        checker.check().await;
+        // still in the grace period
+        assert!(checker.missing_since.contains_key(&1));
+        assert!(checker.in_flight.is_empty());
+    }
+}
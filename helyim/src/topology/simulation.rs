@@ -0,0 +1,347 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
+
+use faststr::FastStr;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    storage::{ReplicaPlacement, VolumeError, VolumeId, VolumeInfo},
+    topology::{
+        balance::plan_rack, data_node::DataNode, node::Node, replication::fits_placement,
+        DataNodeRef, Topology, VolumeMove,
+    },
+};
+
+fn default_threshold() -> f64 {
+    0.1
+}
+
+/// a data node the change adds
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedNode {
+    pub data_center: FastStr,
+    pub rack: FastStr,
+    pub max_volumes: i64,
+}
+
+/// A hypothetical change of the cluster, nothing of it is applied.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlacementChange {
+    #[serde(default)]
+    pub add_nodes: Vec<SimulatedNode>,
+    /// racks as `<data center>:<rack>`, their replicas are gone
+    #[serde(default)]
+    pub drop_racks: Vec<FastStr>,
+    /// the new replication of the volumes of `collection`
+    #[serde(default)]
+    pub replication: Option<FastStr>,
+    #[serde(default)]
+    pub collection: FastStr,
+    /// the balance threshold of the data nodes of a rack
+    #[serde(default = "default_threshold")]
+    pub threshold: f64,
+}
+
+impl Default for PlacementChange {
+    fn default() -> Self {
+        Self {
+            add_nodes: Vec::new(),
+            drop_racks: Vec::new(),
+            replication: None,
+            collection: FastStr::empty(),
+            threshold: default_threshold(),
+        }
+    }
+}
+
+/// a volume copied from `source` to `target`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedCopy {
+    pub volume: VolumeId,
+    pub collection: FastStr,
+    pub size: u64,
+    pub source: FastStr,
+    pub target: FastStr,
+}
+
+impl SimulatedCopy {
+    fn new(volume: &VolumeInfo, source: &DataNodeRef, target: &DataNodeRef) -> Self {
+        Self {
+            volume: volume.id,
+            collection: volume.collection.clone(),
+            size: volume.size,
+            source: FastStr::new(source.id()),
+            target: FastStr::new(target.id()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationReport {
+    /// new replicas of the volumes short of copies after the change
+    pub replications: Vec<SimulatedCopy>,
+    /// moves balancing the racks once the replicas are created
+    pub moves: Vec<SimulatedCopy>,
+    /// volumes without any replica left
+    pub lost: Vec<VolumeId>,
+    /// volumes still short of copies, no data node has room for them
+    pub unplaced: Vec<VolumeId>,
+    /// bytes of the replications and moves
+    pub bytes: u64,
+}
+
+/// a data node outside of the topology with the volumes of `data_node`
+async fn copy_data_node(data_node: &DataNode) -> DataNodeRef {
+    let copy = DataNode::new(
+        FastStr::new(data_node.id()),
+        data_node.ip.clone(),
+        data_node.port,
+        data_node.public_url.clone(),
+        data_node.max_volume_count(),
+    );
+    let volumes: Vec<VolumeInfo> = data_node
+        .volumes
+        .iter()
+        .map(|volume| volume.value().clone())
+        .collect();
+    for volume in volumes {
+        copy.add_or_update_volume(&volume).await;
+    }
+    Arc::new(copy)
+}
+
+/// the data node for a new replica of `vid` which keeps `placement`, like
+/// `Topology::pick_replica_target`, racks without a replica are preferred
+fn pick_target(
+    racks: &BTreeMap<(FastStr, FastStr), Vec<DataNodeRef>>,
+    vid: VolumeId,
+    placement: &ReplicaPlacement,
+) -> Option<DataNodeRef> {
+    let mut locations: Vec<(FastStr, FastStr)> = racks
+        .iter()
+        .flat_map(|(rack, data_nodes)| {
+            data_nodes
+                .iter()
+                .filter(|data_node| data_node.volumes.contains_key(&vid))
+                .map(move |_| rack.clone())
+        })
+        .collect();
+    let mut target: Option<((bool, i64), DataNodeRef)> = None;
+    for (rack, data_nodes) in racks.iter() {
+        let new_rack = !locations.contains(rack);
+        locations.push(rack.clone());
+        let fits = fits_placement(placement, &locations);
+        locations.pop();
+        if !fits {
+            continue;
+        }
+        for data_node in data_nodes {
+            if data_node.free_space() <= 0 || data_node.volumes.contains_key(&vid) {
+                continue;
+            }
+            let score = (new_rack, data_node.free_space());
+            if target.as_ref().map_or(true, |(best, _)| score > *best) {
+                target = Some((score, data_node.clone()));
+            }
+        }
+    }
+    target.map(|(_, data_node)| data_node)
+}
+
+impl Topology {
+    /// the replications and balance moves `change` would cause, worked out on copies of the data
+    /// nodes. Dead and decommissioning data nodes are left out, their replicas are leaving anyway.
+    pub async fn simulate(
+        &self,
+        change: &PlacementChange,
+    ) -> Result<SimulationReport, VolumeError> {
+        let replication = match &change.replication {
+            Some(replication) => Some(ReplicaPlacement::new(replication)?),
+            None => None,
+        };
+        let mut dropped = HashSet::new();
+        for rack in change.drop_racks.iter() {
+            match rack.split_once(':') {
+                Some((data_center, rack)) => {
+                    dropped.insert((FastStr::new(data_center), FastStr::new(rack)));
+                }
+                None => {
+                    return Err(VolumeError::String(format!(
+                        "rack {rack} should be <data center>:<rack>"
+                    )))
+                }
+            }
+        }
+
+        let mut volumes = BTreeMap::new();
+        let mut racks: BTreeMap<(FastStr, FastStr), Vec<DataNodeRef>> = BTreeMap::new();
+        let mut found = HashSet::new();
+        for data_node in self.data_nodes() {
+            for volume in data_node.volumes.iter() {
+                volumes
+                    .entry(volume.id)
+                    .or_insert_with(|| volume.value().clone());
+            }
+            let rack = (data_node.data_center_id().await, data_node.rack_id().await);
+            if dropped.contains(&rack) {
+                found.insert(rack);
+                continue;
+            }
+            if data_node.is_decommissioning() || !data_node.is_alive(self.liveness_timeout()) {
+                continue;
+            }
+            racks
+                .entry(rack)
+                .or_default()
+                .push(copy_data_node(&data_node).await);
+        }
+        if let Some((data_center, rack)) = dropped.difference(&found).next() {
+            return Err(VolumeError::String(format!(
+                "rack {data_center}:{rack} is not found"
+            )));
+        }
+        for (i, node) in change.add_nodes.iter().enumerate() {
+            let data_node = DataNode::new(
+                FastStr::new(format!("simulated-{}", i + 1)),
+                FastStr::empty(),
+                0,
+                FastStr::empty(),
+                node.max_volumes,
+            );
+            racks
+                .entry((node.data_center.clone(), node.rack.clone()))
+                .or_default()
+                .push(Arc::new(data_node));
+        }
+
+        let mut report = SimulationReport::default();
+        for volume in volumes.values() {
+            // the data of a quarantined volume is suspect, it is not spread
+            if volume.quarantined {
+                continue;
+            }
+            let placement = match &replication {
+                Some(replication) if volume.collection == change.collection => *replication,
+                _ => volume.replica_placement,
+            };
+            let copy_count = placement.copy_count();
+            let holders: Vec<DataNodeRef> = racks
+                .values()
+                .flatten()
+                .filter(|data_node| data_node.volumes.contains_key(&volume.id))
+                .cloned()
+                .collect();
+            if holders.is_empty() {
+                report.lost.push(volume.id);
+                continue;
+            }
+            for _ in holders.len()..copy_count {
+                let Some(target) = pick_target(&racks, volume.id, &placement) else {
+                    report.unplaced.push(volume.id);
+                    break;
+                };
+                target.add_or_update_volume(volume).await;
+                report
+                    .replications
+                    .push(SimulatedCopy::new(volume, &holders[0], &target));
+            }
+        }
+
+        for data_nodes in racks.into_values() {
+            for VolumeMove {
+                volume,
+                source,
+                target,
+            } in plan_rack(data_nodes, change.threshold, self.volume_size_limit)
+            {
+                report
+                    .moves
+                    .push(SimulatedCopy::new(&volume, &source, &target));
+            }
+        }
+        report.bytes = report
+            .replications
+            .iter()
+            .chain(report.moves.iter())
+            .map(|copy| copy.size)
+            .sum();
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use faststr::FastStr;
+
+    use crate::topology::{
+        node::Node,
+        simulation::{PlacementChange, SimulatedNode},
+        tests::setup_topo,
+    };
+
+    #[tokio::test]
+    async fn test_simulate() {
+        let topo = setup_topo().await;
+        let change = PlacementChange {
+            drop_racks: vec![FastStr::new("dc3:rack32")],
+            replication: Some(FastStr::new("001")),
+            add_nodes: vec![SimulatedNode {
+                data_center: FastStr::new("dc1"),
+                rack: FastStr::new("rack12"),
+                max_volumes: 10,
+            }],
+            threshold: 0.2,
+            ..Default::default()
+        };
+        let report = topo.simulate(&change).await.unwrap();
+
+        // volume 1 had its second copy in the dropped rack, 001 keeps the new one in its rack
+        let replications: Vec<(u32, &str, &str)> = report
+            .replications
+            .iter()
+            .map(|copy| (copy.volume, copy.source.as_str(), copy.target.as_str()))
+            .collect();
+        assert_eq!(replications, vec![(1, "server111", "server112")]);
+        assert!(report.lost.is_empty());
+        assert!(report.unplaced.is_empty());
+        assert!(report.moves.iter().any(|m| m.target == "simulated-1"));
+        let bytes: u64 = report.moves.iter().map(|m| m.size).sum();
+        assert_eq!(report.bytes, bytes + 12312);
+
+        // nothing is applied to the topology
+        assert!(topo
+            .data_nodes()
+            .iter()
+            .all(|data_node| data_node.id() != "simulated-1"
+                && (data_node.id() != "server122" || data_node.volumes.is_empty())));
+
+        // no other data center is left for the copy of 100
+        let change = PlacementChange {
+            drop_racks: vec![FastStr::new("dc3:rack32")],
+            replication: Some(FastStr::new("100")),
+            ..Default::default()
+        };
+        let report = topo.simulate(&change).await.unwrap();
+        assert!(report.replications.is_empty());
+        assert_eq!(report.unplaced, vec![1]);
+
+        let change = PlacementChange {
+            drop_racks: vec![FastStr::new("dc3:rack32"), FastStr::new("dc1:rack11")],
+            ..Default::default()
+        };
+        let report = topo.simulate(&change).await.unwrap();
+        assert_eq!(report.lost, vec![1]);
+
+        let change = PlacementChange {
+            drop_racks: vec![FastStr::new("dc2:rack21")],
+            ..Default::default()
+        };
+        assert!(topo.simulate(&change).await.is_err());
+    }
+}
@@ -1,43 +1,57 @@
-use std::{collections::BTreeMap, result::Result as StdResult, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashSet},
+    result::Result as StdResult,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use axum::{
-    http::{
-        header::{InvalidHeaderName, InvalidHeaderValue},
-        StatusCode,
-    },
+    http::header::{InvalidHeaderName, InvalidHeaderValue},
     response::{IntoResponse, Response},
-    Json,
 };
 use dashmap::DashMap;
 use faststr::FastStr;
 use helyim_proto::directory::{
-    VolumeInformationMessage, VolumeLocation, VolumeShortInformationMessage,
+    TopologyEvent, TopologyEventKind, VolumeInformationMessage, VolumeLocation,
+    VolumeShortInformationMessage,
 };
+use moka::sync::Cache;
 use serde::Serialize;
-use serde_json::json;
-use tokio::sync::{mpsc::UnboundedSender, RwLock};
+use tokio::sync::{broadcast, mpsc::UnboundedSender, RwLock};
 use tonic::Status;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use crate::{
+    errors::{Error, ErrorCode},
+    operation::{sequence::SequenceStatus, CollectionUsage, DataNodeStatus, QuarantinedVolume},
     raft::{types::NodeId, RaftServer},
-    sequence::{Sequence, Sequencer},
+    sequence::{is_path_key, path_file_key, FileKeyConflict, Sequence, Sequencer},
     storage::{
-        batch_vacuum_volume_check, batch_vacuum_volume_commit, batch_vacuum_volume_compact, FileId,
-        ReplicaPlacement, Ttl, VolumeError, VolumeId, VolumeInfo,
+        batch_vacuum_volume_check, batch_vacuum_volume_commit, batch_vacuum_volume_compact,
+        DiskType, FileId, ReplicaPlacement, Ttl, VolumeError, VolumeId, VolumeInfo,
     },
     topology::{
         collection::Collection,
+        collection_deletion::CollectionMark,
         data_center::{DataCenter, DataCenterRef},
-        data_node::DataNode,
+        data_node::{DataNode, DEFAULT_MAX_MISSED_HEARTBEATS},
+        decommission::DecommissionProgress,
         erasure_coding::EcShardLocations,
-        node::{downcast_data_center, Node, NodeImpl, NodeType},
+        events::{event_channel, new_event},
+        job::{JobKind, JobManager},
+        node::{downcast_data_center, downcast_node, Node, NodeImpl, NodeType},
         volume_grow::VolumeGrowOption,
-        volume_layout::VolumeLayoutRef,
+        volume_layout::{AssignStrategy, VolumeLayoutRef},
         DataNodeRef,
     },
 };
 
+/// missing volumes cached at most, bad fids of a buggy client do not grow the cache unbounded
+const LOOKUP_MISS_CAPACITY: u64 = 100_000;
+
 #[derive(Serialize)]
 pub struct Topology {
     node: Arc<NodeImpl>,
@@ -47,7 +61,32 @@ pub struct Topology {
     #[serde(skip)]
     pub ec_shards: DashMap<VolumeId, EcShardLocations>,
     pulse: u64,
+    #[serde(skip)]
+    max_missed_heartbeats: u64,
     volume_size_limit: u64,
+    /// unix seconds until volume growth waits for declared data nodes
+    #[serde(skip)]
+    pub(super) bootstrap_deadline: AtomicU64,
+    #[serde(skip)]
+    pub(super) decommissions: Arc<DashMap<FastStr, DecommissionProgress>>,
+    #[serde(skip)]
+    pub jobs: Arc<JobManager>,
+    /// `(collection, volume)` recently looked up without being found, so clients retrying bad
+    /// fids do not walk the collections again, a registered volume is removed at once
+    #[serde(skip)]
+    missing_volumes: Option<Cache<(FastStr, VolumeId), ()>>,
+    #[serde(skip)]
+    file_key_conflict: FileKeyConflict,
+    #[serde(skip)]
+    assign_strategy: AssignStrategy,
+    /// the largest file key reported beyond the sequencer, 0 if none
+    #[serde(skip)]
+    conflicting_file_key: AtomicU64,
+    /// collections marked for deletion, until the unix seconds they can be purged
+    #[serde(skip)]
+    pub(super) collection_deletions: Arc<DashMap<FastStr, CollectionMark>>,
+    #[serde(skip)]
+    pub(super) events: broadcast::Sender<TopologyEvent>,
 
     #[serde(skip)]
     raft: RwLock<Option<RaftServer>>,
@@ -61,7 +100,17 @@ impl Clone for Topology {
             collections: self.collections.clone(),
             ec_shards: self.ec_shards.clone(),
             pulse: self.pulse,
+            max_missed_heartbeats: self.max_missed_heartbeats,
             volume_size_limit: self.volume_size_limit,
+            bootstrap_deadline: AtomicU64::new(self.bootstrap_deadline.load(Ordering::Relaxed)),
+            decommissions: self.decommissions.clone(),
+            jobs: self.jobs.clone(),
+            missing_volumes: self.missing_volumes.clone(),
+            file_key_conflict: self.file_key_conflict,
+            assign_strategy: self.assign_strategy,
+            conflicting_file_key: AtomicU64::new(self.conflicting_file_key.load(Ordering::Relaxed)),
+            collection_deletions: self.collection_deletions.clone(),
+            events: self.events.clone(),
             raft: RwLock::new(None),
         }
     }
@@ -76,7 +125,17 @@ impl Topology {
             collections: DashMap::new(),
             ec_shards: DashMap::new(),
             pulse,
+            max_missed_heartbeats: DEFAULT_MAX_MISSED_HEARTBEATS,
             volume_size_limit,
+            bootstrap_deadline: AtomicU64::new(0),
+            decommissions: Arc::new(DashMap::new()),
+            jobs: Arc::new(JobManager::default()),
+            missing_volumes: None,
+            file_key_conflict: FileKeyConflict::Bump,
+            assign_strategy: AssignStrategy::default(),
+            conflicting_file_key: AtomicU64::new(0),
+            collection_deletions: Arc::new(DashMap::new()),
+            events: event_channel(),
             raft: RwLock::new(None),
         }
     }
@@ -95,7 +154,53 @@ impl Topology {
         }
     }
 
+    /// cache missing volumes for `ttl` seconds, 0 disables the cache
+    pub fn with_lookup_miss_ttl(mut self, ttl: u64) -> Self {
+        self.missing_volumes = (ttl > 0).then(|| {
+            Cache::builder()
+                .max_capacity(LOOKUP_MISS_CAPACITY)
+                .time_to_live(Duration::from_secs(ttl))
+                .build()
+        });
+        self
+    }
+
+    pub fn with_file_key_conflict(mut self, file_key_conflict: FileKeyConflict) -> Self {
+        self.file_key_conflict = file_key_conflict;
+        self
+    }
+
+    pub fn with_assign_strategy(mut self, assign_strategy: AssignStrategy) -> Self {
+        self.assign_strategy = assign_strategy;
+        self
+    }
+
+    pub fn with_max_missed_heartbeats(mut self, max_missed_heartbeats: u64) -> Self {
+        self.max_missed_heartbeats = max_missed_heartbeats;
+        self
+    }
+
     pub async fn lookup(&self, collection: &str, volume_id: VolumeId) -> Option<Vec<DataNodeRef>> {
+        let key = (FastStr::new(collection), volume_id);
+        if let Some(missing) = self.missing_volumes.as_ref() {
+            if missing.contains_key(&key) {
+                return None;
+            }
+        }
+        let data_nodes = self.lookup_collections(collection, volume_id).await;
+        if data_nodes.is_none() {
+            if let Some(missing) = self.missing_volumes.as_ref() {
+                missing.insert(key, ());
+            }
+        }
+        data_nodes
+    }
+
+    async fn lookup_collections(
+        &self,
+        collection: &str,
+        volume_id: VolumeId,
+    ) -> Option<Vec<DataNodeRef>> {
         if collection.is_empty() {
             for c in self.collections.iter() {
                 let data_node = c.lookup(volume_id).await;
@@ -118,34 +223,69 @@ impl Topology {
             option.collection.clone(),
             option.replica_placement,
             option.ttl,
+            option.disk_type,
         );
 
         let active_volume_count = vl.active_volume_count(option).await;
         active_volume_count > 0
     }
 
+    /// pick up to `candidates` writable volumes for one file key, the first one is assigned and
+    /// the others are fallbacks, the primary replica of a volume is the first of its nodes
     pub async fn pick_for_write(
         &self,
         count: u64,
         option: &VolumeGrowOption,
-    ) -> StdResult<(FileId, u64, DataNodeRef), VolumeError> {
+        candidates: usize,
+    ) -> StdResult<(u64, Vec<(FileId, Vec<DataNodeRef>)>), VolumeError> {
         let file_id = self
             .sequencer
             .next_file_id(count)
+            .await
             .map_err(|err| VolumeError::Box(Box::new(err)))?;
 
-        let (volume_id, node) = {
+        let picked = {
+            let layout = self.get_volume_layout(
+                option.collection.clone(),
+                option.replica_placement,
+                option.ttl,
+                option.disk_type,
+            );
+            layout
+                .pick_many_for_write(option, candidates.max(1))
+                .await?
+        };
+
+        // the same key and cookie on every candidate, only the volume differs
+        let cookie = rand::random::<u32>();
+        let picked = picked
+            .into_iter()
+            .map(|(volume_id, nodes)| (FileId::new(volume_id, file_id, cookie), nodes))
+            .collect();
+        Ok((count, picked))
+    }
+
+    /// the file id is derived from the path, the volume is picked by rendezvous hashing of it, so
+    /// uploading the same path gets the same file id as long as its volume stays writable.
+    pub async fn pick_for_write_by_path(
+        &self,
+        path: &str,
+        option: &VolumeGrowOption,
+    ) -> StdResult<(FileId, u64, Vec<DataNodeRef>), VolumeError> {
+        let (key, cookie) = path_file_key(path);
+
+        let (volume_id, nodes) = {
             let layout = self.get_volume_layout(
                 option.collection.clone(),
                 option.replica_placement,
                 option.ttl,
+                option.disk_type,
             );
-            let (vid, nodes) = layout.pick_for_write(option).await?;
-            (vid, nodes[0].clone())
+            layout.pick_for_write_with_hint(option, Some(key)).await?
         };
 
-        let file_id = FileId::new(volume_id, file_id, rand::random::<u32>());
-        Ok((file_id, count, node))
+        let file_id = FileId::new(volume_id, key, cookie);
+        Ok((file_id, 1, nodes))
     }
 
     pub async fn register_data_node(
@@ -178,6 +318,26 @@ impl Topology {
             }
         }
 
+        // volumes which turn readonly or full with this heartbeat
+        let sealed: Vec<&VolumeInfo> = volume_infos
+            .iter()
+            .filter(|volume| {
+                self.is_sealed(volume)
+                    && data_node
+                        .volumes
+                        .get(&volume.id)
+                        .is_some_and(|known| !self.is_sealed(&known))
+            })
+            .collect();
+        for volume in sealed {
+            self.publish_event(TopologyEvent {
+                node: data_node.url(),
+                volume_id: volume.id,
+                collection: volume.collection.to_string(),
+                ..new_event(TopologyEventKind::VolumeSealed)
+            });
+        }
+
         let (new_volumes, deleted_volumes) = data_node.update_volumes(volume_infos).await;
         for volume in new_volumes.iter() {
             self.register_volume_layout(volume, data_node).await;
@@ -189,6 +349,10 @@ impl Topology {
         (new_volumes, deleted_volumes)
     }
 
+    fn is_sealed(&self, volume: &VolumeInfo) -> bool {
+        volume.read_only || volume.size >= self.volume_size_limit
+    }
+
     pub async fn incremental_sync_data_node_registration(
         &self,
         new_volumes: &[VolumeShortInformationMessage],
@@ -228,10 +392,15 @@ impl Topology {
     }
 
     pub async fn register_volume_layout(&self, volume: &VolumeInfo, data_node: &DataNodeRef) {
+        if let Some(missing) = self.missing_volumes.as_ref() {
+            missing.invalidate(&(volume.collection.clone(), volume.id));
+            missing.invalidate(&(FastStr::empty(), volume.id));
+        }
         self.get_volume_layout(
             volume.collection.clone(),
             volume.replica_placement,
             volume.ttl,
+            volume.disk_type,
         )
         .register_volume(volume, data_node)
         .await
@@ -242,6 +411,7 @@ impl Topology {
             volume.collection.clone(),
             volume.replica_placement,
             volume.ttl,
+            volume.disk_type,
         )
         .unregister_volume(volume, data_node)
         .await;
@@ -253,6 +423,7 @@ impl Topology {
                 volume.collection.clone(),
                 volume.replica_placement,
                 volume.ttl,
+                volume.disk_type,
             )
             .set_volume_unavailable(volume.key(), data_node)
             .await;
@@ -286,55 +457,151 @@ impl Topology {
         Ok(next)
     }
 
+    /// set the deletion mark of `collection` on every master, a mark expiring at 0 is dropped.
+    /// without raft only this master keeps it.
+    pub(super) async fn replicate_collection_deletion(
+        &self,
+        collection: FastStr,
+        purge_after: u64,
+        expires_at: u64,
+    ) -> Result<(), TopologyError> {
+        match self.raft.read().await.as_ref() {
+            Some(raft) => {
+                raft.set_collection_deletion(collection, purge_after, expires_at)
+                    .await
+                    .map_err(|err| TopologyError::Box(err.into()))?;
+            }
+            None => self.apply_collection_deletion(collection, purge_after, expires_at),
+        }
+        Ok(())
+    }
+
     pub fn set_max_sequence(&self, seq: u64) {
         self.sequencer.set_max(seq);
     }
 
+    /// check the max file key a volume server loaded from its index files against the
+    /// sequencer, a key the sequencer may hand out again is alerted and bumped past unless the
+    /// policy is alert only
+    pub fn observe_max_file_key(&self, data_node: &str, max_file_key: u64) {
+        // the memory sequencer starts over on restart and recovers from heartbeats, snowflake
+        // ids are time based and never conflict
+        let recovering = matches!(self.sequencer, Sequencer::Memory(_));
+        self.observe_file_key(data_node, max_file_key, recovering);
+    }
+
+    fn observe_file_key(&self, data_node: &str, max_file_key: u64, recovering: bool) {
+        // older volume servers report path keys as well, they are never handed out
+        if is_path_key(max_file_key) {
+            return;
+        }
+        let conflict = self
+            .sequencer
+            .peek()
+            .filter(|next| !recovering && max_file_key >= *next);
+        if let Some(next) = conflict {
+            let last = self
+                .conflicting_file_key
+                .fetch_max(max_file_key, Ordering::Relaxed);
+            if max_file_key > last {
+                warn!(
+                    "data node {data_node} holds file key {max_file_key} but the sequencer would \
+                     hand out {next}, policy: {:?}",
+                    self.file_key_conflict
+                );
+            }
+            if self.file_key_conflict == FileKeyConflict::Alert {
+                return;
+            }
+        }
+        self.sequencer.set_max(max_file_key);
+    }
+
+    pub fn sequence_status(&self) -> SequenceStatus {
+        SequenceStatus {
+            sequencer: self.sequencer.name().to_string(),
+            next_file_id: self.sequencer.peek(),
+            conflicting_file_key: self.conflicting_file_key.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn pulse(&self) -> u64 {
+        self.pulse
+    }
+
+    /// seconds without a heartbeat before a data node is considered dead
+    pub fn liveness_timeout(&self) -> u64 {
+        self.pulse.saturating_mul(self.max_missed_heartbeats)
+    }
+
     pub fn topology(&self) -> Topology {
         self.clone()
     }
 
+    /// vacuum the volumes whose garbage exceeds `garbage_threshold`, as a job which can be paused
+    /// or cancelled between volumes. a round is skipped while the last one is unfinished.
     pub async fn vacuum(&self, garbage_threshold: f64, preallocate: u64) {
+        if self.jobs.running(JobKind::Vacuum, "").is_some() {
+            debug!("the last vacuum job is unfinished, skip this round.");
+            return;
+        }
+        let mut volumes = Vec::new();
         for collection in self.collections.iter() {
             for volume_layout in collection.volume_layouts.iter() {
-                let volume_layout = volume_layout.value().clone();
-                let locations = volume_layout.locations.clone();
-                for data_nodes in locations.iter() {
+                for data_nodes in volume_layout.locations.iter() {
                     let vid = *data_nodes.key();
-                    if volume_layout.readonly_volumes.contains_key(&vid) {
-                        continue;
+                    if !volume_layout.readonly_volumes.contains_key(&vid) {
+                        volumes.push((volume_layout.value().clone(), vid));
                     }
+                }
+            }
+        }
 
-                    if batch_vacuum_volume_check(vid, &data_nodes, garbage_threshold).await
-                        && batch_vacuum_volume_compact(
-                            &volume_layout,
-                            vid,
-                            &data_nodes,
-                            preallocate,
-                        )
-                        .await
-                    {
-                        batch_vacuum_volume_commit(&volume_layout, vid, &data_nodes).await;
-                        // let _ = batch_vacuum_volume_cleanup(vid, data_nodes).await;
-                    }
+        let job = self
+            .jobs
+            .start(JobKind::Vacuum, FastStr::empty(), volumes.len());
+        for (volume_layout, vid) in volumes {
+            if !job.checkpoint().await {
+                break;
+            }
+            let data_nodes = match volume_layout.locations.get(&vid) {
+                Some(data_nodes) => data_nodes.clone(),
+                None => {
+                    job.step_done();
+                    continue;
                 }
+            };
+            if batch_vacuum_volume_check(vid, &data_nodes, garbage_threshold).await {
+                if batch_vacuum_volume_compact(&volume_layout, vid, &data_nodes, preallocate).await
+                    && batch_vacuum_volume_commit(&volume_layout, vid, &data_nodes).await
+                {
+                    // let _ = batch_vacuum_volume_cleanup(vid, data_nodes).await;
+                    job.step_done();
+                } else {
+                    job.step_failed(vid);
+                }
+            } else {
+                job.step_done();
             }
         }
+        job.finish();
     }
 }
 
 impl Topology {
-    fn get_volume_layout(
+    pub(super) fn get_volume_layout(
         &self,
         collection_name: FastStr,
         rp: ReplicaPlacement,
         ttl: Ttl,
+        disk_type: DiskType,
     ) -> VolumeLayoutRef {
         match self.collections.get(&collection_name) {
-            Some(collection) => collection.get_or_create_volume_layout(rp, Some(ttl)),
+            Some(collection) => collection.get_or_create_volume_layout(rp, Some(ttl), disk_type),
             None => {
-                let collection = Collection::new(collection_name.clone(), self.volume_size_limit);
-                let vl = collection.get_or_create_volume_layout(rp, Some(ttl));
+                let collection = Collection::new(collection_name.clone(), self.volume_size_limit)
+                    .with_assign_strategy(self.assign_strategy);
+                let vl = collection.get_or_create_volume_layout(rp, Some(ttl), disk_type);
                 self.collections.insert(collection_name, collection);
                 vl
             }
@@ -344,6 +611,7 @@ impl Topology {
 
 impl Topology {
     pub async fn set_raft_server(&self, raft: RaftServer) {
+        self.watch_leader(raft.clone());
         *self.raft.write().await = Some(raft);
     }
 
@@ -427,6 +695,103 @@ impl Topology {
         locations
     }
 
+    pub fn data_nodes(&self) -> Vec<DataNodeRef> {
+        let mut data_nodes = Vec::new();
+        for data_center in self.children().iter() {
+            for rack in data_center.children().iter() {
+                for data_node in rack.children().iter() {
+                    match downcast_node(data_node.value().clone()) {
+                        Ok(data_node) => data_nodes.push(data_node),
+                        Err(err) => error!("list data nodes error: {err}"),
+                    }
+                }
+            }
+        }
+        data_nodes
+    }
+
+    pub fn find_data_node(&self, id: &str) -> Option<DataNodeRef> {
+        self.data_nodes()
+            .into_iter()
+            .find(|data_node| data_node.id() == id)
+    }
+
+    /// volumes with at least one quarantined replica
+    pub fn quarantined_volumes(&self) -> Vec<QuarantinedVolume> {
+        let mut volumes: BTreeMap<VolumeId, QuarantinedVolume> = BTreeMap::new();
+        for data_node in self.data_nodes() {
+            for volume in data_node.volumes.iter() {
+                if !volume.quarantined {
+                    continue;
+                }
+                volumes
+                    .entry(volume.id)
+                    .or_insert_with(|| QuarantinedVolume {
+                        id: volume.id,
+                        collection: volume.collection.clone(),
+                        nodes: Vec::new(),
+                    })
+                    .nodes
+                    .push(FastStr::new(data_node.id()));
+            }
+        }
+        volumes.into_values().collect()
+    }
+
+    /// stored bytes and requests per collection over all data nodes, the files of a volume are
+    /// counted once while its size is counted on every replica
+    pub fn collection_usages(&self) -> Vec<CollectionUsage> {
+        let mut usages: BTreeMap<FastStr, CollectionUsage> = BTreeMap::new();
+        let mut counted = HashSet::new();
+        for data_node in self.data_nodes() {
+            for volume in data_node.volumes.iter() {
+                let usage = usages
+                    .entry(volume.collection.clone())
+                    .or_insert_with(|| CollectionUsage::new(volume.collection.clone()));
+                usage.size += volume.size;
+                usage.deleted_bytes += volume.delete_bytes;
+                if counted.insert(volume.id) {
+                    usage.volumes += 1;
+                    usage.files += (volume.file_count - volume.delete_count).max(0) as u64;
+                }
+            }
+            for message in data_node.usages() {
+                let collection = FastStr::new(&message.collection);
+                usages
+                    .entry(collection.clone())
+                    .or_insert_with(|| CollectionUsage::new(collection))
+                    .add_requests(&message);
+            }
+        }
+        usages.into_values().collect()
+    }
+
+    pub async fn data_node_statuses(&self) -> Vec<DataNodeStatus> {
+        let mut statuses = Vec::new();
+        for data_node in self.data_nodes() {
+            let last_seen = data_node.last_seen();
+            let capabilities = data_node.capabilities();
+            statuses.push(DataNodeStatus {
+                id: FastStr::new(data_node.id()),
+                url: data_node.url(),
+                public_url: data_node.public_url.clone(),
+                data_center: data_node.data_center_id().await,
+                rack: data_node.rack_id().await,
+                last_seen,
+                connected: data_node.is_connected(),
+                alive: data_node.is_alive(self.liveness_timeout()),
+                volumes: data_node.volume_count(),
+                ec_shards: data_node.ec_shard_count(),
+                max_volumes: data_node.max_volume_count(),
+                free_space: data_node.free_space(),
+                version: capabilities.version,
+                features: capabilities.features,
+                decommissioning: data_node.is_decommissioning(),
+            });
+        }
+        statuses
+    }
+
     pub async fn link_data_center(&self, data_center: Arc<DataCenter>) {
         let topo_node = self.node.clone();
         topo_node.link_child_node(data_center).await;
@@ -453,16 +818,47 @@ pub enum TopologyError {
     InvalidHeaderName(#[from] InvalidHeaderName),
     #[error("Invalid uri: {0}")]
     InvalidUrl(#[from] hyper::http::uri::InvalidUri),
+
+    #[error("Job {0} is not found")]
+    JobNotFound(u64),
+    #[error("Job {0} is finished")]
+    JobFinished(u64),
+
+    #[error("Collection {0} is not found")]
+    CollectionNotFound(FastStr),
+    #[error("Collection {0} is protected from deletion")]
+    CollectionProtected(FastStr),
+    #[error("Collection {0} is not marked for deletion, or the mark expired")]
+    CollectionNotMarked(FastStr),
+    #[error("Collection {0} can not be purged before {1}")]
+    CollectionPurgeTooEarly(FastStr, u64),
+}
+
+impl TopologyError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            TopologyError::NoLeader => ErrorCode::NotLeader,
+            TopologyError::JobNotFound(_) => ErrorCode::JobNotFound,
+            TopologyError::JobFinished(_) | TopologyError::CollectionProtected(_) => {
+                ErrorCode::Conflict
+            }
+            TopologyError::CollectionNotFound(_) => ErrorCode::CollectionNotFound,
+            TopologyError::CollectionNotMarked(_) | TopologyError::CollectionPurgeTooEarly(..) => {
+                ErrorCode::PreconditionFailed
+            }
+            TopologyError::InvalidHeaderValue(_)
+            | TopologyError::InvalidHeaderName(_)
+            | TopologyError::InvalidUrl(_) => ErrorCode::BadRequest,
+            TopologyError::Box(_) | TopologyError::Io(_) | TopologyError::Hyper(_) => {
+                ErrorCode::Internal
+            }
+        }
+    }
 }
 
 impl IntoResponse for TopologyError {
     fn into_response(self) -> Response {
-        let error = self.to_string();
-        let error = json!({
-            "error": error
-        });
-        let response = (StatusCode::BAD_REQUEST, Json(error));
-        response.into_response()
+        Error::Topology(self).into_response()
     }
 }
 
@@ -478,9 +874,13 @@ pub async fn topology_vacuum_loop(
         tokio::select! {
             _ = interval.tick() => {
                 if topology.is_leader().await {
-                    debug!("topology vacuum starting.");
-                    topology.vacuum(garbage_threshold, preallocate).await;
-                    debug!("topology vacuum success.")
+                    // a paused job must not block the loop
+                    let topology = topology.clone();
+                    tokio::spawn(async move {
+                        debug!("topology vacuum starting.");
+                        topology.vacuum(garbage_threshold, preallocate).await;
+                        debug!("topology vacuum finished.")
+                    });
                 }
             }
             _ = shutdown.recv() => {
@@ -495,14 +895,19 @@ pub type TopologyRef = Arc<Topology>;
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use std::{collections::HashMap, fs::File, sync::Arc};
+    use std::{
+        collections::{HashMap, HashSet},
+        fs::File,
+        sync::Arc,
+    };
 
     use faststr::FastStr;
+    use helyim_proto::directory::CollectionUsageMessage;
     use serde::Deserialize;
 
     use crate::{
         directory::Sequencer,
-        sequence::MemorySequencer,
+        sequence::{path_file_key, FileKeyConflict, MemorySequencer},
         storage::{VolumeInfo, CURRENT_VERSION},
         topology::{
             data_center::DataCenter, data_node::DataNode, node::Node, rack::Rack, Topology,
@@ -566,4 +971,101 @@ pub(crate) mod tests {
 
         topo
     }
+
+    #[tokio::test]
+    async fn test_lookup_miss_cache() {
+        let topo = Topology::new(Sequencer::Memory(MemorySequencer::new()), 32 * 1024, 5)
+            .with_lookup_miss_ttl(60);
+        assert!(topo.lookup("", 1).await.is_none());
+        assert!(topo
+            .missing_volumes
+            .as_ref()
+            .unwrap()
+            .contains_key(&(FastStr::empty(), 1)));
+
+        // registering the volume drops the cached miss
+        let data_node = Arc::new(DataNode::new(
+            FastStr::new("server1"),
+            FastStr::empty(),
+            0,
+            FastStr::empty(),
+            1,
+        ));
+        let volume = VolumeInfo {
+            id: 1,
+            version: CURRENT_VERSION,
+            ..Default::default()
+        };
+        topo.register_volume_layout(&volume, &data_node).await;
+        assert_eq!(topo.lookup("", 1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_collection_usages() {
+        let topo = setup_topo().await;
+        let mut volumes = HashSet::new();
+        let mut size = 0;
+        for data_node in topo.data_nodes() {
+            for volume in data_node.volumes.iter() {
+                volumes.insert(volume.id);
+                size += volume.size;
+            }
+            data_node.set_usages(vec![CollectionUsageMessage {
+                collection: "pictures".to_string(),
+                reads: 2,
+                bytes_read: 10,
+                ..Default::default()
+            }]);
+        }
+        let data_nodes = topo.data_nodes().len() as u64;
+
+        let usages = topo.collection_usages();
+        assert_eq!(usages.len(), 2);
+        assert_eq!(usages[0].collection, "");
+        assert_eq!(usages[0].volumes, volumes.len() as u64);
+        assert_eq!(usages[0].size, size);
+        assert_eq!(usages[0].reads, 0);
+        assert_eq!(usages[1].collection, "pictures");
+        assert_eq!(usages[1].volumes, 0);
+        assert_eq!(usages[1].reads, 2 * data_nodes);
+        assert_eq!(usages[1].bytes_read, 10 * data_nodes);
+    }
+
+    #[test]
+    fn test_observe_max_file_key() {
+        let topo = Topology::new(Sequencer::Memory(MemorySequencer::new()), 32 * 1024, 5);
+        // the memory sequencer recovers from the heartbeats
+        topo.observe_max_file_key("127.0.0.1:8080", 100);
+        assert_eq!(topo.sequence_status().next_file_id, Some(101));
+        // path keys are never handed out
+        let (path_key, _) = path_file_key("/photos/a.jpg");
+        topo.observe_max_file_key("127.0.0.1:8080", path_key);
+        assert_eq!(topo.sequence_status().next_file_id, Some(101));
+        assert_eq!(topo.sequence_status().conflicting_file_key, 0);
+
+        // a key the sequencer would hand out again is bumped past
+        topo.observe_file_key("127.0.0.1:8080", 200, false);
+        let status = topo.sequence_status();
+        assert_eq!(
+            (status.next_file_id, status.conflicting_file_key),
+            (Some(201), 200)
+        );
+        topo.observe_file_key("127.0.0.1:8080", 150, false);
+        topo.observe_file_key("127.0.0.1:8080", path_key, false);
+        let status = topo.sequence_status();
+        assert_eq!(
+            (status.next_file_id, status.conflicting_file_key),
+            (Some(201), 200)
+        );
+
+        // only alerted, the operator bumps the sequencer
+        let topo = Topology::new(Sequencer::Memory(MemorySequencer::new()), 32 * 1024, 5)
+            .with_file_key_conflict(FileKeyConflict::Alert);
+        topo.observe_file_key("127.0.0.1:8080", 200, false);
+        let status = topo.sequence_status();
+        assert_eq!(
+            (status.next_file_id, status.conflicting_file_key),
+            (Some(1), 200)
+        );
+    }
 }
@@ -6,7 +6,9 @@ use rand::Rng;
 use tracing::{debug, error};
 
 use crate::{
-    storage::{ReplicaPlacement, Ttl, VolumeError, VolumeId, VolumeInfo, CURRENT_VERSION},
+    storage::{
+        DiskType, ReplicaPlacement, Ttl, VolumeError, VolumeId, VolumeInfo, CURRENT_VERSION,
+    },
     topology::{
         node::{downcast_node, Node},
         DataNodeRef, Topology,
@@ -42,6 +44,7 @@ impl VolumeGrowth {
     ) -> Result<Vec<DataNodeRef>, VolumeError> {
         let mut ret = vec![];
         let rp = option.replica_placement;
+        let disk_type = option.disk_type;
 
         let (main_data_center, other_centers) = randomly_pick_nodes(
             topology.children(),
@@ -53,14 +56,16 @@ impl VolumeGrowth {
                 if racks_len < rp.diff_rack_count as usize + 1 {
                     return false;
                 }
-                if node.free_space() < (rp.diff_rack_count + rp.same_rack_count) as i64 + 1 {
+                if disk_free_space(node, disk_type)
+                    < (rp.diff_rack_count + rp.same_rack_count) as i64 + 1
+                {
                     return false;
                 }
                 let mut possible_racks_count = 0;
                 for rack in node.children().iter() {
                     let mut possible_nodes_count = 0;
                     for dn in rack.children().iter() {
-                        if dn.free_space() >= 1 {
+                        if disk_free_space(dn.value(), disk_type) >= 1 {
                             possible_nodes_count += 1;
                         }
                     }
@@ -74,6 +79,7 @@ impl VolumeGrowth {
                 true
             },
             rp.diff_data_center_count as usize,
+            disk_type,
         )
         .await?;
 
@@ -83,7 +89,7 @@ impl VolumeGrowth {
                 if !option.rack.is_empty() && option.rack != node.id() {
                     return false;
                 }
-                if node.free_space() < rp.same_rack_count as i64 + 1 {
+                if disk_free_space(node, disk_type) < rp.same_rack_count as i64 + 1 {
                     return false;
                 }
                 let data_nodes_len = node.children().len();
@@ -92,7 +98,7 @@ impl VolumeGrowth {
                 }
                 let mut possible_nodes = 0;
                 for node in node.children().iter() {
-                    if node.free_space() >= 1 {
+                    if disk_free_space(node.value(), disk_type) >= 1 {
                         possible_nodes += 1;
                     }
                 }
@@ -102,6 +108,7 @@ impl VolumeGrowth {
                 true
             },
             rp.diff_rack_count as usize,
+            disk_type,
         )
         .await?;
 
@@ -112,12 +119,18 @@ impl VolumeGrowth {
                 if !option.data_node.is_empty() && option.data_node != *node_id {
                     return false;
                 }
-                if node.free_space() < 1 {
+                if disk_free_space(node, disk_type) < 1 {
                     return false;
                 }
+                if !option.required_features.is_empty() {
+                    return downcast_node(node.clone())
+                        .map(|dn| dn.supports_all(&option.required_features))
+                        .unwrap_or(false);
+                }
                 true
             },
             rp.same_rack_count as usize,
+            disk_type,
         )
         .await?;
 
@@ -127,14 +140,14 @@ impl VolumeGrowth {
         }
 
         for rack in other_racks.into_iter().flatten() {
-            let random = rand::thread_rng().gen_range(0..rack.free_space());
-            let node = rack.reserve_one_volume(random)?;
+            let random = rand::thread_rng().gen_range(0..disk_free_space(&rack, disk_type));
+            let node = reserve_one_volume(&rack, random, disk_type)?;
             ret.push(node);
         }
 
         for dc in other_centers.into_iter().flatten() {
-            let random = rand::thread_rng().gen_range(0..dc.free_space());
-            let node = dc.reserve_one_volume(random)?;
+            let random = rand::thread_rng().gen_range(0..disk_free_space(&dc, disk_type));
+            let node = reserve_one_volume(&dc, random, disk_type)?;
             ret.push(node);
         }
 
@@ -174,6 +187,14 @@ impl VolumeGrowth {
         topology: &Topology,
         nodes: Vec<DataNodeRef>,
     ) -> Result<(), VolumeError> {
+        for dn in nodes.iter() {
+            if !dn.supports_all(&option.required_features) {
+                return Err(VolumeError::UnsupportedFeatures(
+                    FastStr::new(dn.id()),
+                    option.required_features.clone(),
+                ));
+            }
+        }
         for dn in nodes {
             // FIXME: the follow macro maybe removed after tonic support hyper 1.0
             #[cfg(not(test))]
@@ -183,6 +204,8 @@ impl VolumeGrowth {
                 replication: option.replica_placement.to_string(),
                 ttl: option.ttl.to_string(),
                 preallocate: option.preallocate,
+                disk_type: option.disk_type.as_str().to_string(),
+                needle_alignment: option.needle_alignment,
             })
             .await?;
 
@@ -193,6 +216,7 @@ impl VolumeGrowth {
                 replica_placement: option.replica_placement,
                 ttl: option.ttl,
                 version: CURRENT_VERSION,
+                disk_type: option.disk_type,
                 ..Default::default()
             };
 
@@ -223,12 +247,58 @@ pub struct VolumeGrowOption {
     pub data_center: FastStr,
     pub rack: FastStr,
     pub data_node: FastStr,
+    /// features the volume servers must support to hold the volume
+    pub required_features: Vec<FastStr>,
+    pub disk_type: DiskType,
+    /// alignment of the needles of new volumes, 0 leaves it to the volume servers
+    pub needle_alignment: u32,
+}
+
+/// free volume slots on disks of `disk_type` under `node`
+fn disk_free_space(node: &Arc<dyn Node>, disk_type: DiskType) -> i64 {
+    if node.node_type().is_data_node() {
+        return downcast_node(node.clone()).map_or(0, |dn| dn.disk_free_space(disk_type));
+    }
+    node.children()
+        .iter()
+        .map(|child| disk_free_space(child.value(), disk_type))
+        .sum()
+}
+
+/// same as `Node::reserve_one_volume`, but only counts the slots on disks of `disk_type`
+fn reserve_one_volume(
+    node: &Arc<dyn Node>,
+    mut rand: i64,
+    disk_type: DiskType,
+) -> Result<DataNodeRef, VolumeError> {
+    for child in node.children().iter() {
+        let free_space = disk_free_space(child.value(), disk_type);
+        if free_space <= 0 {
+            continue;
+        }
+        if rand >= free_space {
+            rand -= free_space;
+        } else {
+            if child.node_type().is_data_node() {
+                return downcast_node(child.clone());
+            }
+            return reserve_one_volume(child.value(), rand, disk_type);
+        }
+    }
+
+    Err(VolumeError::NoFreeSpace(format!(
+        "no free {} volumes found {}, node type: {}",
+        disk_type.as_str(),
+        node.id(),
+        node.node_type()
+    )))
 }
 
 async fn randomly_pick_nodes<F>(
     children: &DashMap<FastStr, Arc<dyn Node>>,
     filter: F,
     nodes_num: usize,
+    disk_type: DiskType,
 ) -> Result<(Arc<dyn Node>, Vec<Option<Arc<dyn Node>>>), VolumeError>
 where
     F: Fn(&Arc<dyn Node>) -> bool,
@@ -258,7 +328,7 @@ where
         if node.id() == main_dn.id() {
             continue;
         }
-        if node.free_space() <= 0 {
+        if disk_free_space(node.value(), disk_type) <= 0 {
             continue;
         }
         candidates.push(node.clone());
@@ -364,6 +434,7 @@ mod tests {
                     true
                 },
                 option.replica_placement.same_rack_count as usize,
+                option.disk_type,
             )
             .await
             .unwrap();
@@ -405,4 +476,21 @@ mod tests {
             println!("assigned node: {}", server.id());
         }
     }
+
+    #[tokio::test]
+    pub async fn test_find_empty_slots_with_features() {
+        let topo = setup_topo().await;
+        let vg = VolumeGrowth {};
+
+        // the data nodes report the legacy features only
+        let mut vgo = VolumeGrowOption {
+            data_center: FastStr::new("dc1"),
+            required_features: vec![FastStr::new("ttl")],
+            ..Default::default()
+        };
+        assert!(vg.find_empty_slots(&vgo, &topo).await.is_ok());
+
+        vgo.required_features = vec![FastStr::new("conditional_write")];
+        assert!(vg.find_empty_slots(&vgo, &topo).await.is_err());
+    }
 }
@@ -1,7 +1,10 @@
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 
 use dashmap::{mapref::one::RefMut, DashMap};
-use rand::Rng;
+use rand::{seq::SliceRandom, Rng};
 use serde::Serialize;
 use tokio::sync::RwLock;
 
@@ -10,11 +13,41 @@ use crate::{
     topology::{data_node::DataNodeRef, node::Node, volume_grow::VolumeGrowOption},
 };
 
+/// the weight of `vid` for `key`, a key goes to the volume of the highest weight, so adding or
+/// removing other volumes does not move it
+fn rendezvous_weight(key: u64, vid: VolumeId) -> u64 {
+    // splitmix64 finalizer
+    let mut x = key ^ (vid as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// how a writable volume is picked for an assignment
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum AssignStrategy {
+    /// uniformly at random
+    #[default]
+    Random,
+    /// the writable volumes in turn
+    RoundRobin,
+    /// the volume whose fullest replica node stores the fewest bytes
+    LeastUsed,
+    /// at random, weighted by the volume capacity of the smallest replica node, so larger nodes
+    /// take a larger share of the writes
+    Weighted,
+}
+
 #[derive(Serialize)]
 pub struct VolumeLayout {
     rp: ReplicaPlacement,
     ttl: Option<Ttl>,
     volume_size_limit: u64,
+    strategy: AssignStrategy,
+    /// position of the next round robin pick
+    #[serde(skip)]
+    next_pick: AtomicUsize,
 
     #[serde(skip)]
     writable_volumes: RwLock<Vec<VolumeId>>,
@@ -30,6 +63,8 @@ impl VolumeLayout {
             rp,
             ttl,
             volume_size_limit,
+            strategy: AssignStrategy::default(),
+            next_pick: AtomicUsize::new(0),
             writable_volumes: RwLock::new(Vec::new()),
             readonly_volumes: DashMap::new(),
             oversize_volumes: DashMap::new(),
@@ -37,6 +72,15 @@ impl VolumeLayout {
         }
     }
 
+    pub fn with_strategy(mut self, strategy: AssignStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn replica_placement(&self) -> ReplicaPlacement {
+        self.rp
+    }
+
     pub async fn active_volume_count(&self, option: &VolumeGrowOption) -> i64 {
         if option.data_center.is_empty() {
             return self.writable_volumes.read().await.len() as i64;
@@ -65,23 +109,70 @@ impl VolumeLayout {
         &self,
         option: &VolumeGrowOption,
     ) -> Result<(VolumeId, Vec<DataNodeRef>), VolumeError> {
-        if self.writable_volumes.read().await.is_empty() {
+        self.pick_for_write_with_hint(option, None).await
+    }
+
+    /// pick a writable volume, the volume is picked by `hint` if it is set, otherwise by the
+    /// assign strategy
+    pub async fn pick_for_write_with_hint(
+        &self,
+        option: &VolumeGrowOption,
+        hint: Option<u64>,
+    ) -> Result<(VolumeId, Vec<DataNodeRef>), VolumeError> {
+        let candidates = self.write_candidates(option).await;
+        if candidates.is_empty() {
             return Err(VolumeError::NoWritableVolumes);
         }
+        let vid = match hint {
+            Some(hint) => *candidates
+                .iter()
+                .max_by_key(|vid| rendezvous_weight(hint, **vid))
+                .expect("candidates are not empty"),
+            None => self.order_candidates(candidates)[0],
+        };
+        match self.locations.get(&vid) {
+            Some(locations) => Ok((vid, locations.value().clone())),
+            None => Err(VolumeError::NotFound(vid)),
+        }
+    }
 
-        if option.data_center.is_empty() {
-            let len = self.writable_volumes.read().await.len();
-            let vid = self.writable_volumes.read().await[rand::thread_rng().gen_range(0..len)];
-            return match self.locations.get(&vid) {
-                Some(data_nodes) => Ok((vid, data_nodes.value().clone())),
-                None => Err(VolumeError::NotFound(vid)),
-            };
+    /// pick up to `count` distinct writable volumes in the order of the assign strategy, the ones
+    /// after the first are fallbacks for a client whose write to the first one fails
+    pub async fn pick_many_for_write(
+        &self,
+        option: &VolumeGrowOption,
+        count: usize,
+    ) -> Result<Vec<(VolumeId, Vec<DataNodeRef>)>, VolumeError> {
+        let candidates = self.write_candidates(option).await;
+        let candidates = self.order_candidates(candidates);
+
+        let mut picked: Vec<(VolumeId, Vec<DataNodeRef>)> = Vec::with_capacity(count);
+        for vid in candidates {
+            if picked.len() >= count {
+                break;
+            }
+            if picked.iter().any(|(picked, _)| *picked == vid) {
+                continue;
+            }
+            if let Some(locations) = self.locations.get(&vid) {
+                picked.push((vid, locations.value().clone()));
+            }
+        }
+        if picked.is_empty() {
+            return Err(VolumeError::NoWritableVolumes);
         }
+        Ok(picked)
+    }
 
-        let mut counter = 0;
-        let mut volume_id = 0;
-        let mut location_list = None;
+    /// the writable volumes matching the data center, rack and data node of `option`. with a data
+    /// center, a volume is listed once per matched replica, so volumes with more matched replicas
+    /// are more likely to be picked
+    async fn write_candidates(&self, option: &VolumeGrowOption) -> Vec<VolumeId> {
+        if option.data_center.is_empty() {
+            return self.writable_volumes.read().await.clone();
+        }
 
+        let mut candidates = vec![];
         for vid in self.writable_volumes.read().await.iter() {
             if let Some(locations) = self.locations.get(vid) {
                 for node in locations.iter() {
@@ -92,20 +183,56 @@ impl VolumeLayout {
                         if !option.data_node.is_empty() && node.id() != option.data_node {
                             continue;
                         }
-
-                        counter += 1;
-                        if rand::thread_rng().gen_range(0..counter) < 1 {
-                            volume_id = *vid;
-                            location_list = Some(locations.value().clone());
-                        }
+                        candidates.push(*vid);
                     }
                 }
             }
         }
+        candidates
+    }
+
+    /// the candidates in the order the assign strategy prefers them
+    fn order_candidates(&self, mut candidates: Vec<VolumeId>) -> Vec<VolumeId> {
+        let mut rng = rand::thread_rng();
+        match self.strategy {
+            AssignStrategy::Random => candidates.shuffle(&mut rng),
+            AssignStrategy::RoundRobin => {
+                if !candidates.is_empty() {
+                    let start = self.next_pick.fetch_add(1, Ordering::Relaxed) % candidates.len();
+                    candidates.rotate_left(start);
+                }
+            }
+            AssignStrategy::LeastUsed => {
+                // equally used volumes stay in random order
+                candidates.shuffle(&mut rng);
+                candidates.sort_by_cached_key(|vid| {
+                    self.replica_nodes_fold(*vid, 0, |used, node| used.max(node.used_bytes()))
+                });
+            }
+            AssignStrategy::Weighted => {
+                // weighted sampling without replacement, a volume gets the key u^(1/weight)
+                let mut keyed: Vec<(f64, VolumeId)> = candidates
+                    .iter()
+                    .map(|vid| {
+                        let capacity = self.replica_nodes_fold(*vid, i64::MAX, |capacity, node| {
+                            capacity.min(node.max_volume_count())
+                        });
+                        let weight = capacity.clamp(1, i64::MAX / 2) as f64;
+                        let u: f64 = rng.gen_range(f64::EPSILON..1.0);
+                        (u.powf(1.0 / weight), *vid)
+                    })
+                    .collect();
+                keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+                candidates = keyed.into_iter().map(|(_, vid)| vid).collect();
+            }
+        }
+        candidates
+    }
 
-        match location_list {
-            Some(locations) => Ok((volume_id, locations)),
-            None => Err(VolumeError::NoWritableVolumes),
+    fn replica_nodes_fold<T>(&self, vid: VolumeId, init: T, f: impl Fn(T, &DataNodeRef) -> T) -> T {
+        match self.locations.get(&vid) {
+            Some(nodes) => nodes.iter().fold(init, f),
+            None => init,
         }
     }
 
@@ -259,7 +386,9 @@ mod tests {
     use crate::{
         storage::{ReplicaPlacement, VolumeInfo, CURRENT_VERSION},
         topology::{
-            data_node::DataNode, volume_grow::VolumeGrowOption, volume_layout::VolumeLayout,
+            data_node::DataNode,
+            volume_grow::VolumeGrowOption,
+            volume_layout::{AssignStrategy, VolumeLayout},
         },
     };
 
@@ -373,8 +502,146 @@ mod tests {
         assert_eq!(vid, volume_info.id);
         assert_eq!(data_nodes.len(), 1);
 
+        let (hint_vid, _) = vl
+            .pick_for_write_with_hint(&option, Some(u64::MAX))
+            .await
+            .unwrap();
+        assert_eq!(hint_vid, volume_info.id);
+
         option.data_center = FastStr::new("default");
         let pick_for_write = vl.pick_for_write(&option).await;
         assert!(pick_for_write.is_err());
     }
+
+    #[tokio::test]
+    async fn test_pick_many_for_write() {
+        let vl = setup();
+        let option = VolumeGrowOption::default();
+        assert!(vl.pick_many_for_write(&option, 3).await.is_err());
+
+        let data_node = Arc::new(data_node());
+        for id in 1..=4 {
+            let volume_info = VolumeInfo {
+                id,
+                version: CURRENT_VERSION,
+                ..Default::default()
+            };
+            vl.register_volume(&volume_info, &data_node).await;
+        }
+
+        let picked = vl.pick_many_for_write(&option, 3).await.unwrap();
+        let mut vids: Vec<_> = picked.iter().map(|(vid, _)| *vid).collect();
+        vids.sort();
+        vids.dedup();
+        assert_eq!(vids.len(), 3);
+
+        // no more than the writable volumes
+        let picked = vl.pick_many_for_write(&option, 10).await.unwrap();
+        assert_eq!(picked.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_pick_with_hint_is_stable() {
+        let vl = setup();
+        let option = VolumeGrowOption::default();
+        let data_node = Arc::new(data_node());
+        for id in 1..=4 {
+            let volume_info = VolumeInfo {
+                id,
+                version: CURRENT_VERSION,
+                ..Default::default()
+            };
+            vl.register_volume(&volume_info, &data_node).await;
+        }
+        vl.remove_from_writable(&4).await;
+        let mut before = Vec::new();
+        for key in 0..64u64 {
+            let (vid, _) = vl
+                .pick_for_write_with_hint(&option, Some(key))
+                .await
+                .unwrap();
+            before.push(vid);
+        }
+        assert!((1..=3).all(|vid| before.contains(&vid)));
+
+        // a new writable volume only takes keys, the others keep their volume
+        vl.set_volume_writable(4).await;
+        for (key, vid) in before.iter().enumerate() {
+            let (picked, _) = vl
+                .pick_for_write_with_hint(&option, Some(key as u64))
+                .await
+                .unwrap();
+            assert!(picked == *vid || picked == 4);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_assign_strategy() {
+        let option = VolumeGrowOption::default();
+        let node = |port: u16, sizes: &[(u32, u64)]| {
+            let id = FastStr::new(format!("127.0.0.1:{port}"));
+            let data_node = DataNode::new(id.clone(), FastStr::new("127.0.0.1"), port, id, 8);
+            for (vid, size) in sizes {
+                data_node.volumes.insert(
+                    *vid,
+                    VolumeInfo {
+                        id: *vid,
+                        size: *size,
+                        version: CURRENT_VERSION,
+                        ..Default::default()
+                    },
+                );
+            }
+            Arc::new(data_node)
+        };
+        let full = node(8080, &[(1, 4096), (2, 4096)]);
+        let empty = node(8081, &[(3, 1024)]);
+
+        async fn register(vl: VolumeLayout, data_nodes: &[&Arc<DataNode>]) -> VolumeLayout {
+            for data_node in data_nodes {
+                let volumes: Vec<VolumeInfo> = data_node
+                    .volumes
+                    .iter()
+                    .map(|volume| volume.value().clone())
+                    .collect();
+                for volume in volumes {
+                    vl.register_volume(&volume, data_node).await;
+                }
+            }
+            vl
+        }
+
+        let vl = register(
+            setup().with_strategy(AssignStrategy::LeastUsed),
+            &[&full, &empty],
+        )
+        .await;
+        for _ in 0..8 {
+            let (vid, _) = vl.pick_for_write(&option).await.unwrap();
+            assert_eq!(vid, 3);
+        }
+        let picked = vl.pick_many_for_write(&option, 3).await.unwrap();
+        assert_eq!(picked[0].0, 3);
+
+        // every volume in turn
+        let vl = register(
+            setup().with_strategy(AssignStrategy::RoundRobin),
+            &[&full, &empty],
+        )
+        .await;
+        let mut vids = vec![];
+        for _ in 0..3 {
+            vids.push(vl.pick_for_write(&option).await.unwrap().0);
+        }
+        vids.sort();
+        assert_eq!(vids, vec![1, 2, 3]);
+
+        let vl = register(
+            setup().with_strategy(AssignStrategy::Weighted),
+            &[&full, &empty],
+        )
+        .await;
+        let picked = vl.pick_many_for_write(&option, 3).await.unwrap();
+        assert_eq!(picked.len(), 3);
+    }
 }
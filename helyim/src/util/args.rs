@@ -1,5 +1,19 @@
+use std::time::Duration;
+
 use clap::{Args, Parser, Subcommand};
 use faststr::FastStr;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    sequence::{FileKeyConflict, SequencerType},
+    storage::{DiskType, Durability, VolumeError, NEEDLE_PADDING_SIZE},
+    topology::{volume_layout::AssignStrategy, DEFAULT_MAX_MISSED_HEARTBEATS},
+    util::{
+        file::split_folder,
+        log::{LogOutput, LogRotation},
+        retry::RetryPolicy,
+    },
+};
 
 #[derive(Parser, Debug)]
 #[command(name = "helyim")]
@@ -15,6 +29,8 @@ pub struct Opts {
 pub enum Command {
     Master(MasterOptions),
     Volume(VolumeOptions),
+    Server(ServerOptions),
+    Cache(CacheOptions),
 }
 
 #[derive(Args, Debug, Clone)]
@@ -26,16 +42,73 @@ pub struct MasterOptions {
     pub port: u16,
     #[arg(long, default_value("./"))]
     pub meta_path: FastStr,
-    /// pulse in second
+    /// seconds between the heartbeats expected from the volume servers
     #[arg(long, default_value_t = 5)]
     pub pulse: u64,
+    /// heartbeats a volume server may miss before it is considered dead, raise it when the
+    /// network between the master and the volume servers is slow or lossy
+    #[arg(long, default_value_t = DEFAULT_MAX_MISSED_HEARTBEATS)]
+    pub max_missed_heartbeats: u64,
     #[arg(long, default_value_t = 30000)]
     pub volume_size_limit_mb: u64,
     /// default replication if not specified
     #[arg(long, default_value("000"))]
     pub default_replication: FastStr,
+    /// json file declaring the expected data centers, racks and data nodes
+    #[arg(long)]
+    pub topology_file: Option<FastStr>,
+    /// seconds volume growth waits for the declared data nodes to connect
+    #[arg(long, default_value_t = 300)]
+    pub topology_bootstrap_timeout: u64,
+    /// json file of the maintenance policy, vacuum and balancing are enforced by it instead of
+    /// the fixed vacuum loop
+    #[arg(long)]
+    pub policy_file: Option<FastStr>,
+    /// seconds a lookup of a missing volume is remembered, 0 disables it
+    #[arg(long, default_value_t = 5)]
+    pub lookup_miss_ttl: u64,
+    /// how writable volumes are picked for assignments
+    #[arg(long, value_enum, default_value_t = AssignStrategy::Random)]
+    pub assign_strategy: AssignStrategy,
+    /// data center of clients by source ip, `<dc>=<cidr>[,<cidr>...]`, lookups from these clients
+    /// list the replicas in their data center first
+    #[arg(long)]
+    pub data_center_ranges: Vec<FastStr>,
+    /// name of this cluster among the federated clusters
+    #[arg(long, default_value(""))]
+    pub region: FastStr,
+    /// masters of the other federated clusters, `<region>=<master>`, fids prefixed with
+    /// `<region>/` are looked up in that cluster
+    #[arg(long)]
+    pub federation: Vec<FastStr>,
+    /// shared secret signing the grpc calls, replication requests and admin calls between masters
+    /// and volume servers, every server of the cluster needs the same one
+    #[arg(long)]
+    pub cluster_secret: Option<FastStr>,
+    /// seconds after the mark before a collection marked for deletion can be purged
+    #[arg(long, default_value_t = 300)]
+    pub collection_delete_delay: u64,
+    /// seconds a collection marked for deletion can be purged after the delay, writes to it are
+    /// refused meanwhile
+    #[arg(long, default_value_t = 3600)]
+    pub collection_delete_ttl: u64,
+    /// collections which can not be deleted
+    #[arg(long)]
+    pub protected_collections: Vec<FastStr>,
     #[command(flatten)]
     pub raft: RaftOptions,
+    #[command(flatten)]
+    pub sequencer: SequencerOptions,
+    #[command(flatten)]
+    pub replication: ReplicationOptions,
+    #[command(flatten)]
+    pub timeout: TimeoutOptions,
+    #[command(flatten)]
+    pub retry: RetryOptions,
+    #[command(flatten)]
+    pub metrics_push: MetricsPushOptions,
+    #[command(flatten)]
+    pub runtime: RuntimeOptions,
 }
 
 impl MasterOptions {
@@ -55,6 +128,164 @@ pub struct RaftOptions {
     pub peers: Vec<FastStr>,
 }
 
+#[derive(Args, Debug, Clone)]
+pub struct RetryOptions {
+    /// attempts of idempotent internal calls, including the first one
+    #[arg(long, default_value_t = 3)]
+    pub retry_attempts: u32,
+    #[arg(long, default_value_t = 100)]
+    pub retry_initial_backoff_ms: u64,
+    #[arg(long, default_value_t = 2000)]
+    pub retry_max_backoff_ms: u64,
+}
+
+impl RetryOptions {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            attempts: self.retry_attempts.max(1),
+            initial_backoff: Duration::from_millis(self.retry_initial_backoff_ms),
+            max_backoff: Duration::from_millis(self.retry_max_backoff_ms),
+        }
+    }
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        Self {
+            retry_attempts: 3,
+            retry_initial_backoff_ms: 100,
+            retry_max_backoff_ms: 2000,
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RuntimeOptions {
+    /// worker threads of the async runtime, 0 starts one per cpu core
+    #[arg(long, default_value_t = 0)]
+    pub worker_threads: usize,
+    /// max threads of the blocking pool of the async runtime
+    #[arg(long, default_value_t = 512)]
+    pub max_blocking_threads: usize,
+}
+
+impl RuntimeOptions {
+    /// the runtime a server runs on, its threads are named after `role`
+    pub fn build(&self, role: &str) -> std::io::Result<Runtime> {
+        let mut builder = Builder::new_multi_thread();
+        if self.worker_threads > 0 {
+            builder.worker_threads(self.worker_threads);
+        }
+        builder
+            .max_blocking_threads(self.max_blocking_threads.max(1))
+            .thread_name(role)
+            .enable_all()
+            .build()
+    }
+}
+
+impl Default for RuntimeOptions {
+    fn default() -> Self {
+        Self {
+            worker_threads: 0,
+            max_blocking_threads: 512,
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct TimeoutOptions {
+    /// seconds before a read, write or lookup request is aborted
+    #[arg(long, default_value_t = 10)]
+    pub request_timeout: u64,
+    /// seconds before a long running admin request is aborted, e.g. ec encoding
+    #[arg(long, default_value_t = 600)]
+    pub admin_timeout: u64,
+}
+
+impl Default for TimeoutOptions {
+    fn default() -> Self {
+        Self {
+            request_timeout: 10,
+            admin_timeout: 600,
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct MetricsPushOptions {
+    /// prometheus pushgateway the metrics are pushed to, like `http://pushgateway:9091`, for
+    /// servers prometheus can not scrape
+    #[arg(long)]
+    pub metrics_push_gateway: Option<FastStr>,
+    /// seconds between two pushes
+    #[arg(long, default_value_t = 15)]
+    pub metrics_push_interval: u64,
+    /// job label of the pushed metrics, the instance label is the address of the server and the
+    /// role label is `master` or `volume`
+    #[arg(long, default_value("helyim"))]
+    pub metrics_push_job: FastStr,
+}
+
+impl Default for MetricsPushOptions {
+    fn default() -> Self {
+        Self {
+            metrics_push_gateway: None,
+            metrics_push_interval: 15,
+            metrics_push_job: FastStr::from_static_str("helyim"),
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ReplicationOptions {
+    /// seconds a replica may be missing before it is re-created on another node
+    #[arg(long, default_value_t = 300)]
+    pub replica_grace_period: u64,
+    /// max concurrent volume copies when re-creating replicas
+    #[arg(long, default_value_t = 2)]
+    pub max_concurrent_replications: usize,
+}
+
+impl Default for ReplicationOptions {
+    fn default() -> Self {
+        Self {
+            replica_grace_period: 300,
+            max_concurrent_replications: 2,
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct SequencerOptions {
+    /// how file ids are generated
+    #[arg(long, value_enum, default_value_t = SequencerType::Memory)]
+    pub sequencer: SequencerType,
+    /// etcd endpoints, only used by etcd sequencer
+    #[arg(long)]
+    pub etcd_endpoints: Vec<FastStr>,
+    #[arg(long, default_value("/helyim/sequence"))]
+    pub etcd_sequence_key: FastStr,
+    /// ids reserved from etcd at once
+    #[arg(long, default_value_t = 10000)]
+    pub etcd_sequence_batch: u64,
+    /// what to do when a volume server reports a file key beyond the sequencer
+    #[arg(long, value_enum, default_value_t = FileKeyConflict::Bump)]
+    pub file_key_conflict: FileKeyConflict,
+}
+
+impl Default for SequencerOptions {
+    fn default() -> Self {
+        Self {
+            sequencer: SequencerType::Memory,
+            etcd_endpoints: vec![],
+            etcd_sequence_key: FastStr::from_static_str("/helyim/sequence"),
+            etcd_sequence_batch: 10000,
+            file_key_conflict: FileKeyConflict::Bump,
+        }
+    }
+}
+
 #[derive(Args, Debug)]
 pub struct VolumeOptions {
     #[arg(long, default_value("127.0.0.1"))]
@@ -82,6 +313,40 @@ pub struct VolumeOptions {
     /// directories to store data files
     #[arg(long)]
     pub folders: Vec<FastStr>,
+    /// disk type of each folder, `hdd` or `ssd`, folders without one are hdd
+    #[arg(long)]
+    pub disk_types: Vec<FastStr>,
+    /// when a write is acknowledged, `sync` waits for fsync, which runs on dedicated threads
+    #[arg(long, value_enum, default_value_t = Durability::Buffered)]
+    pub durability: Durability,
+    /// reject writes to an existing fid with other content with 409, unless the upload asks for
+    /// `op=replace`
+    #[arg(long)]
+    pub protect_overwrite: bool,
+    /// keep a bloom filter of the keys of every volume, reads of absent keys are answered with
+    /// 404 without looking up the needle map, about 1.25 bytes per key
+    #[arg(long)]
+    pub read_bloom_filter: bool,
+    /// blocking threads of a runtime dedicated to reads and writes of needles, 0 shares the
+    /// blocking pool of the server runtime
+    #[arg(long, default_value_t = 0)]
+    pub disk_io_threads: usize,
+    /// alignment of the needles of new volumes, a power of two from 8 to 64, volumes can address
+    /// 4GiB times the alignment
+    #[arg(long, default_value_t = NEEDLE_PADDING_SIZE)]
+    pub needle_alignment: u32,
+    /// shared secret signing the grpc calls, replication requests and admin calls between masters
+    /// and volume servers, every server of the cluster needs the same one
+    #[arg(long)]
+    pub cluster_secret: Option<FastStr>,
+    #[command(flatten)]
+    pub timeout: TimeoutOptions,
+    #[command(flatten)]
+    pub retry: RetryOptions,
+    #[command(flatten)]
+    pub metrics_push: MetricsPushOptions,
+    #[command(flatten)]
+    pub runtime: RuntimeOptions,
 }
 
 impl VolumeOptions {
@@ -94,28 +359,158 @@ impl VolumeOptions {
     pub fn paths(&self) -> Vec<String> {
         self.folders
             .iter()
-            .map(|x| match x.rfind(':') {
-                Some(idx) => x[0..idx].to_string(),
-                None => x.to_string(),
-            })
+            .map(|x| split_folder(x).0.to_string())
             .collect()
     }
 
     pub fn max_volumes(&self) -> Vec<i64> {
         self.folders
             .iter()
-            .map(|x| match x.rfind(':') {
-                Some(idx) => x[idx + 1..].parse::<i64>().unwrap(),
+            .map(|x| match split_folder(x).1 {
+                Some(max) => max.parse::<i64>().unwrap(),
                 None => 7,
             })
             .collect()
     }
+
+    pub fn disk_types(&self) -> Result<Vec<DiskType>, VolumeError> {
+        (0..self.folders.len())
+            .map(|i| match self.disk_types.get(i) {
+                Some(disk_type) => DiskType::new(disk_type),
+                None => Ok(DiskType::Hdd),
+            })
+            .collect()
+    }
+}
+
+/// A master and a volume server in one process, for development and small deployments. They
+/// talk to each other over grpc and http on `ip` as separate servers do.
+#[derive(Args, Debug)]
+pub struct ServerOptions {
+    #[arg(long, default_value("127.0.0.1"))]
+    pub ip: FastStr,
+    #[arg(long, default_value_t = 9333)]
+    pub master_port: u16,
+    #[arg(long, default_value_t = 8080)]
+    pub volume_port: u16,
+    /// directory of the meta data of the master, the volumes are stored in its `volumes`
+    /// subdirectory
+    #[arg(long, default_value("./data"))]
+    pub dir: FastStr,
+    /// max volumes of the volume server
+    #[arg(long, default_value_t = 7)]
+    pub max_volumes: u32,
+    /// seconds between the heartbeats of the volume server
+    #[arg(long, default_value_t = 5)]
+    pub pulse: u64,
+    /// default replication if not specified
+    #[arg(long, default_value("000"))]
+    pub default_replication: FastStr,
+    #[arg(long, default_value_t = 30000)]
+    pub volume_size_limit_mb: u64,
+    /// both servers push their metrics
+    #[command(flatten)]
+    pub metrics_push: MetricsPushOptions,
+    #[command(flatten)]
+    pub runtime: RuntimeOptions,
+}
+
+impl ServerOptions {
+    fn parse(role: &str, args: Vec<String>) -> Result<Command, clap::Error> {
+        let argv = ["helyim".to_string(), role.to_string()]
+            .into_iter()
+            .chain(args);
+        Ok(Opts::try_parse_from(argv)?.command)
+    }
+
+    /// the options of the master, the other options keep their defaults
+    pub fn master_options(&self) -> Result<MasterOptions, clap::Error> {
+        let args = vec![
+            format!("--ip={}", self.ip),
+            format!("--port={}", self.master_port),
+            format!("--meta-path={}", self.dir),
+            format!("--pulse={}", self.pulse),
+            format!("--default-replication={}", self.default_replication),
+            format!("--volume-size-limit-mb={}", self.volume_size_limit_mb),
+        ];
+        match Self::parse("master", args)? {
+            Command::Master(mut options) => {
+                options.check_raft_peers();
+                options.metrics_push = self.metrics_push.clone();
+                Ok(options)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// the options of the volume server, it joins the master of the same process
+    pub fn volume_options(&self) -> Result<VolumeOptions, clap::Error> {
+        let args = vec![
+            format!("--ip={}", self.ip),
+            format!("--port={}", self.volume_port),
+            format!("--master-server={}:{}", self.ip, self.master_port),
+            format!("--folders={}/volumes:{}", self.dir, self.max_volumes),
+            format!("--pulse={}", self.pulse),
+            format!("--default-replication={}", self.default_replication),
+        ];
+        match Self::parse("volume", args)? {
+            Command::Volume(mut options) => {
+                options.metrics_push = self.metrics_push.clone();
+                Ok(options)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A read-through cache of the needles of a remote cluster, for branch offices and edges.
+#[derive(Args, Debug)]
+pub struct CacheOptions {
+    #[arg(long, default_value("127.0.0.1"))]
+    pub ip: FastStr,
+    #[arg(long, default_value_t = 8070)]
+    pub port: u16,
+    /// master of the remote cluster
+    #[arg(long, default_value("127.0.0.1:9333"))]
+    pub master_server: FastStr,
+    /// directory of the cached needles
+    #[arg(long, default_value("./cache"))]
+    pub dir: FastStr,
+    /// bytes of the cached needles in MiB, the least recently read are evicted beyond it
+    #[arg(long, default_value_t = 1024)]
+    pub capacity_mb: u64,
+    /// seconds a cached needle is served before it is read again from the remote cluster, 0
+    /// keeps it until it is evicted
+    #[arg(long, default_value_t = 3600)]
+    pub ttl: u64,
+    /// shared secret of the remote cluster
+    #[arg(long)]
+    pub cluster_secret: Option<FastStr>,
+    /// directory of the writes submitted while the remote cluster is unreachable, they are
+    /// uploaded once it is back. Without it such writes fail.
+    #[arg(long)]
+    pub offline_dir: Option<FastStr>,
+    #[command(flatten)]
+    pub timeout: TimeoutOptions,
+    #[command(flatten)]
+    pub retry: RetryOptions,
+    #[command(flatten)]
+    pub runtime: RuntimeOptions,
 }
 
 #[derive(Args, Debug, Clone)]
 pub struct LogOptions {
     #[arg(long, default_value("./target/logs"))]
     pub log_path: FastStr,
-    #[arg(long, default_value("stdout"))]
-    pub log_output: FastStr,
+    #[arg(long, value_enum, default_value_t = LogOutput::Stdout)]
+    pub log_output: LogOutput,
+    /// how often the log file is rotated
+    #[arg(long, value_enum, default_value_t = LogRotation::Daily)]
+    pub log_rotation: LogRotation,
+    /// number of rotated log files to keep, 0 keeps all of them
+    #[arg(long, default_value_t = 7)]
+    pub log_max_files: usize,
+    /// level of a single target, like `helyim::storage=debug`
+    #[arg(long)]
+    pub log_level: Vec<FastStr>,
 }
@@ -0,0 +1,162 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::storage::BUFFER_SIZE_LIMIT;
+
+const MAX_POOLED_BUFFERS: usize = 32;
+
+/// crate wide buffer pool used by the request handlers
+pub static BUFFER_POOL: Lazy<BufferPool> =
+    Lazy::new(|| BufferPool::new(MAX_POOLED_BUFFERS, BUFFER_SIZE_LIMIT));
+
+/// A freelist of byte buffers, buffers are returned to the pool when the `PooledBuffer` is
+/// dropped, buffers larger than `max_capacity` are never pooled.
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+    max_capacity: usize,
+
+    allocated: AtomicU64,
+    reused: AtomicU64,
+    released: AtomicU64,
+}
+
+impl BufferPool {
+    pub fn new(max_buffers: usize, max_capacity: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            max_buffers,
+            max_capacity,
+            allocated: AtomicU64::new(0),
+            reused: AtomicU64::new(0),
+            released: AtomicU64::new(0),
+        }
+    }
+
+    /// get a zeroed buffer with length `len`
+    pub fn get(&self, len: usize) -> PooledBuffer<'_> {
+        let buffer = if len <= self.max_capacity {
+            self.buffers.lock().pop()
+        } else {
+            None
+        };
+
+        let buffer = match buffer {
+            Some(mut buffer) => {
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                buffer.resize(len, 0);
+                buffer
+            }
+            None => {
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+                vec![0u8; len]
+            }
+        };
+
+        PooledBuffer {
+            pool: self,
+            buffer: Some(buffer),
+        }
+    }
+
+    fn put(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() > self.max_capacity {
+            self.released.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let mut buffers = self.buffers.lock();
+        if buffers.len() < self.max_buffers {
+            buffer.clear();
+            buffers.push(buffer);
+        } else {
+            self.released.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        BufferPoolStats {
+            pooled: self.buffers.lock().len(),
+            allocated: self.allocated.load(Ordering::Relaxed),
+            reused: self.reused.load(Ordering::Relaxed),
+            released: self.released.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub struct PooledBuffer<'a> {
+    pool: &'a BufferPool,
+    buffer: Option<Vec<u8>>,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        // buffer is only taken in drop
+        self.buffer.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.buffer.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.put(buffer);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BufferPoolStats {
+    pub pooled: usize,
+    pub allocated: u64,
+    pub reused: u64,
+    pub released: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::buffer::BufferPool;
+
+    #[test]
+    fn test_buffer_pool() {
+        let pool = BufferPool::new(1, 1024);
+        {
+            let mut buffer = pool.get(16);
+            assert_eq!(buffer.len(), 16);
+            buffer[0] = 1;
+
+            // the pool has no free buffer, allocate a new one
+            let _other = pool.get(16);
+        }
+        let stats = pool.stats();
+        assert_eq!(stats.pooled, 1);
+        assert_eq!(stats.allocated, 2);
+        assert_eq!(stats.released, 1);
+
+        let buffer = pool.get(32);
+        assert_eq!(buffer.len(), 32);
+        assert!(buffer.iter().all(|b| *b == 0));
+        assert_eq!(pool.stats().reused, 1);
+        drop(buffer);
+
+        // too large to be pooled
+        drop(pool.get(2048));
+        let stats = pool.stats();
+        assert_eq!(stats.pooled, 1);
+        assert_eq!(stats.released, 2);
+    }
+}
@@ -0,0 +1,117 @@
+use faststr::FastStr;
+use serde::Serialize;
+
+pub const FEATURE_EC: &str = "ec";
+pub const FEATURE_TTL: &str = "ttl";
+pub const FEATURE_GRPC_STREAMING: &str = "grpc_streaming";
+pub const FEATURE_CONTENT_CHECKSUM: &str = "content_checksum";
+pub const FEATURE_CONDITIONAL_WRITE: &str = "conditional_write";
+
+/// Version of the wire protocol between masters, volume servers and clients. Bump it on every
+/// incompatible change, and keep `MIN_PROTOCOL_VERSION` at the oldest version still understood.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// 0 is used by peers which predate protocol versioning
+pub const MIN_PROTOCOL_VERSION: u32 = 0;
+
+/// features supported by this build
+pub const SUPPORTED_FEATURES: [&str; 5] = [
+    FEATURE_EC,
+    FEATURE_TTL,
+    FEATURE_GRPC_STREAMING,
+    FEATURE_CONTENT_CHECKSUM,
+    FEATURE_CONDITIONAL_WRITE,
+];
+
+/// servers which do not report their capabilities predate the negotiation and only support these
+const LEGACY_FEATURES: [&str; 2] = [FEATURE_EC, FEATURE_TTL];
+
+pub fn server_version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/// every version is understood while `MIN_PROTOCOL_VERSION` is 0, the lower bound is checked
+/// here once it is raised
+pub fn check_protocol_version(_version: u32) -> Result<(), String> {
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    pub version: FastStr,
+    pub features: Vec<FastStr>,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            version: FastStr::empty(),
+            features: LEGACY_FEATURES
+                .iter()
+                .map(|f| FastStr::from_static_str(f))
+                .collect(),
+        }
+    }
+}
+
+impl Capabilities {
+    pub fn local() -> Self {
+        Self {
+            version: FastStr::from_static_str(server_version()),
+            features: SUPPORTED_FEATURES
+                .iter()
+                .map(|f| FastStr::from_static_str(f))
+                .collect(),
+        }
+    }
+
+    pub fn new(version: &str, features: &[String]) -> Self {
+        if version.is_empty() {
+            return Self::default();
+        }
+        Self {
+            version: FastStr::new(version),
+            features: features.iter().map(FastStr::new).collect(),
+        }
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    pub fn supports_all<S: AsRef<str>>(&self, features: &[S]) -> bool {
+        features.iter().all(|f| self.supports(f.as_ref()))
+    }
+
+    pub fn feature_list(&self) -> Vec<String> {
+        self.features.iter().map(|f| f.to_string()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::capability::{
+        check_protocol_version, Capabilities, FEATURE_CONTENT_CHECKSUM, FEATURE_EC, FEATURE_TTL,
+        PROTOCOL_VERSION,
+    };
+
+    #[test]
+    fn test_check_protocol_version() {
+        assert!(check_protocol_version(PROTOCOL_VERSION).is_ok());
+        // peers which predate protocol versioning
+        assert!(check_protocol_version(0).is_ok());
+        // newer peers are expected to keep talking to older ones
+        assert!(check_protocol_version(PROTOCOL_VERSION + 1).is_ok());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let legacy = Capabilities::new("", &[]);
+        assert!(legacy.supports_all(&[FEATURE_EC, FEATURE_TTL]));
+        assert!(!legacy.supports(FEATURE_CONTENT_CHECKSUM));
+
+        let local = Capabilities::local();
+        let remote = Capabilities::new(&local.version, &local.feature_list());
+        assert!(remote.supports(FEATURE_CONTENT_CHECKSUM));
+        assert!(remote.supports_all::<&str>(&[]));
+    }
+}
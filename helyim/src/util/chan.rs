@@ -2,12 +2,16 @@ use helyim_proto::directory::{VolumeEcShardInformationMessage, VolumeShortInform
 use kanal::{unbounded_async, AsyncReceiver, AsyncSender};
 use tracing::error;
 
+use crate::storage::VolumeId;
+
 #[derive(Clone)]
 pub struct DeltaVolumeInfoSender {
     pub new_volumes_tx: AsyncSender<VolumeShortInformationMessage>,
     pub deleted_volumes_tx: AsyncSender<VolumeShortInformationMessage>,
     pub new_ec_shards_tx: AsyncSender<VolumeEcShardInformationMessage>,
     pub deleted_ec_shards_tx: AsyncSender<VolumeEcShardInformationMessage>,
+    pub sealed_volumes_tx: AsyncSender<VolumeId>,
+    pub quarantined_volumes_tx: AsyncSender<VolumeId>,
 }
 
 impl DeltaVolumeInfoSender {
@@ -34,6 +38,28 @@ impl DeltaVolumeInfoSender {
             error!("delete delta ec shard info error: {err}");
         }
     }
+
+    /// the volume turned read only, the next heartbeat is sent right away
+    pub async fn seal_volume(&self, vid: VolumeId) {
+        if let Err(err) = self.sealed_volumes_tx.send(vid).await {
+            error!("seal volume info error: {err}");
+        }
+    }
+
+    /// the volume is quarantined or released from quarantine, the next heartbeat is sent right
+    /// away
+    pub async fn quarantine_volume(&self, vid: VolumeId) {
+        if let Err(err) = self.quarantined_volumes_tx.send(vid).await {
+            error!("quarantine volume info error: {err}");
+        }
+    }
+
+    /// `quarantine_volume` for callers which can not wait, the channel is unbounded
+    pub fn try_quarantine_volume(&self, vid: VolumeId) {
+        if let Err(err) = self.quarantined_volumes_tx.try_send(vid) {
+            error!("quarantine volume info error: {err}");
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -42,6 +68,8 @@ pub struct DeltaVolumeInfoReceiver {
     pub deleted_volumes_rx: AsyncReceiver<VolumeShortInformationMessage>,
     pub new_ec_shards_rx: AsyncReceiver<VolumeEcShardInformationMessage>,
     pub deleted_ec_shards_rx: AsyncReceiver<VolumeEcShardInformationMessage>,
+    pub sealed_volumes_rx: AsyncReceiver<VolumeId>,
+    pub quarantined_volumes_rx: AsyncReceiver<VolumeId>,
 }
 
 pub fn delta_volume_channel() -> (DeltaVolumeInfoSender, DeltaVolumeInfoReceiver) {
@@ -49,6 +77,8 @@ pub fn delta_volume_channel() -> (DeltaVolumeInfoSender, DeltaVolumeInfoReceiver
     let (deleted_volumes_tx, deleted_volumes_rx) = unbounded_async();
     let (new_ec_shards_tx, new_ec_shards_rx) = unbounded_async();
     let (deleted_ec_shards_tx, deleted_ec_shards_rx) = unbounded_async();
+    let (sealed_volumes_tx, sealed_volumes_rx) = unbounded_async();
+    let (quarantined_volumes_tx, quarantined_volumes_rx) = unbounded_async();
 
     (
         DeltaVolumeInfoSender {
@@ -56,12 +86,16 @@ pub fn delta_volume_channel() -> (DeltaVolumeInfoSender, DeltaVolumeInfoReceiver
             deleted_volumes_tx,
             new_ec_shards_tx,
             deleted_ec_shards_tx,
+            sealed_volumes_tx,
+            quarantined_volumes_tx,
         },
         DeltaVolumeInfoReceiver {
             new_volumes_rx,
             deleted_volumes_rx,
             new_ec_shards_rx,
             deleted_ec_shards_rx,
+            sealed_volumes_rx,
+            quarantined_volumes_rx,
         },
     )
 }
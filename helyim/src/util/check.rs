@@ -0,0 +1,443 @@
+//! Validation of the options before a server starts.
+//!
+//! Every problem is collected so they can be fixed at once, instead of the server failing on
+//! the first one, or later in the middle of a request.
+
+use std::{
+    fmt::Display,
+    fs,
+    net::{TcpListener, ToSocketAddrs},
+    path::Path,
+};
+
+use faststr::FastStr;
+
+use crate::{
+    errors::{Error, Result},
+    storage::{check_needle_alignment, DiskType, ReplicaPlacement},
+    topology::MaintenancePolicy,
+    util::{
+        args::{CacheOptions, MasterOptions, MetricsPushOptions, ServerOptions, VolumeOptions},
+        cidr::DataCenterRanges,
+        file::split_folder,
+    },
+};
+
+/// open files a server needs at least, every volume keeps its data and index file open
+pub const MIN_OPEN_FILES: u64 = 1024;
+
+#[derive(Debug, Default)]
+struct Checker {
+    problems: Vec<String>,
+}
+
+impl Checker {
+    fn check<E: Display>(&mut self, result: std::result::Result<(), E>) {
+        if let Err(err) = result {
+            self.problems.push(err.to_string());
+        }
+    }
+
+    fn replication(&mut self, replication: &str) {
+        self.check(
+            ReplicaPlacement::new(replication)
+                .map(|_| ())
+                .map_err(|_| format!("replication `{replication}` should be 3 digits, like `001`")),
+        );
+    }
+
+    fn metrics_push(&mut self, opts: &MetricsPushOptions) {
+        if opts.metrics_push_gateway.is_none() {
+            return;
+        }
+        self.positive("--metrics-push-interval", opts.metrics_push_interval);
+        if opts.metrics_push_job.is_empty() || opts.metrics_push_job.contains('/') {
+            self.problems.push(format!(
+                "metrics push job `{}` should be a name without `/`",
+                opts.metrics_push_job
+            ));
+        }
+    }
+
+    /// the http port and its grpc port are free on `ip`
+    fn ports(&mut self, ip: &str, port: u16) {
+        let Some(grpc) = port.checked_add(10000) else {
+            self.problems.push(format!(
+                "port {port} is too large, grpc listens on port + 10000"
+            ));
+            return;
+        };
+        for port in [port, grpc] {
+            self.port(ip, port);
+        }
+    }
+
+    fn port(&mut self, ip: &str, port: u16) {
+        self.check(
+            TcpListener::bind((ip, port))
+                .map(|_| ())
+                .map_err(|err| format!("cannot listen on {ip}:{port}: {err}")),
+        );
+    }
+
+    fn resolvable(&mut self, what: &str, addr: &str) {
+        self.check(
+            addr.to_socket_addrs()
+                .map_err(|err| err.to_string())
+                .and_then(|mut addrs| match addrs.next() {
+                    Some(_) => Ok(()),
+                    None => Err("no address found".to_string()),
+                })
+                .map_err(|err| format!("{what} `{addr}` cannot be resolved: {err}")),
+        );
+    }
+
+    /// `dir` exists or can be created, and a file can be written into it
+    fn writable(&mut self, what: &str, dir: &str) {
+        let probe = Path::new(dir).join(".helyim-check");
+        self.check(
+            fs::create_dir_all(dir)
+                .and_then(|_| fs::write(&probe, b""))
+                .and_then(|_| fs::remove_file(&probe))
+                .map_err(|err| format!("{what} `{dir}` is not writable: {err}")),
+        );
+    }
+
+    #[cfg(unix)]
+    fn open_files(&mut self) {
+        use rustix::process::{getrlimit, Resource};
+
+        if let Some(current) = getrlimit(Resource::Nofile).current {
+            if current < MIN_OPEN_FILES {
+                self.problems.push(format!(
+                    "open files limit {current} is lower than {MIN_OPEN_FILES}, raise it with \
+                     `ulimit -n`"
+                ));
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn open_files(&mut self) {}
+
+    fn positive(&mut self, what: &str, value: u64) {
+        if value == 0 {
+            self.problems
+                .push(format!("{what} should be greater than 0"));
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidOptions(self.problems))
+        }
+    }
+}
+
+/// the part of a federation entry or a peer after `=`, if any
+fn peer_addr(entry: &FastStr) -> &str {
+    entry
+        .split_once('=')
+        .map(|(_, addr)| addr)
+        .unwrap_or(entry.as_str())
+}
+
+pub fn check_master(opts: &MasterOptions) -> Result<()> {
+    let mut checker = Checker::default();
+    checker.ports(&opts.ip, opts.port);
+    checker.writable("meta path", &opts.meta_path);
+    checker.replication(&opts.default_replication);
+    checker.positive("--pulse", opts.pulse);
+    checker.positive("--max-missed-heartbeats", opts.max_missed_heartbeats);
+    checker.positive(
+        "--max-concurrent-replications",
+        opts.replication.max_concurrent_replications as u64,
+    );
+    // a replica is missing once its node misses its heartbeats, a shorter grace period would
+    // re-create the replicas of every node that is only slow
+    match opts.pulse.checked_mul(opts.max_missed_heartbeats) {
+        Some(dead_after) if opts.replication.replica_grace_period < dead_after => {
+            checker.problems.push(format!(
+                "--replica-grace-period {} should not be shorter than --pulse times \
+                 --max-missed-heartbeats, {dead_after} seconds",
+                opts.replication.replica_grace_period
+            ))
+        }
+        Some(_) => {}
+        None => checker
+            .problems
+            .push("--pulse times --max-missed-heartbeats should fit into 64 bits".to_string()),
+    }
+    checker.positive("--volume-size-limit-mb", opts.volume_size_limit_mb);
+    checker.positive("--request-timeout", opts.timeout.request_timeout);
+    for peer in opts.raft.peers.iter() {
+        checker.resolvable("peer", peer);
+    }
+    for entry in opts.federation.iter() {
+        checker.resolvable("federated master", peer_addr(entry));
+    }
+    checker.check(DataCenterRanges::parse(&opts.data_center_ranges).map(|_| ()));
+    checker.metrics_push(&opts.metrics_push);
+    if let Some(path) = opts.topology_file.as_ref() {
+        if !Path::new(path.as_str()).is_file() {
+            checker
+                .problems
+                .push(format!("topology file `{path}` does not exist"));
+        }
+    }
+    if let Some(path) = opts.policy_file.as_ref() {
+        checker.check(
+            MaintenancePolicy::load(path.as_str())
+                .map(|_| ())
+                .map_err(|err| format!("policy file `{path}`: {err}")),
+        );
+    }
+    checker.open_files();
+    checker.finish()
+}
+
+pub fn check_volume(opts: &VolumeOptions) -> Result<()> {
+    let mut checker = Checker::default();
+    checker.ports(&opts.ip, opts.port);
+    checker.replication(&opts.default_replication);
+    checker.positive("--pulse", opts.pulse);
+    checker.positive("--request-timeout", opts.timeout.request_timeout);
+    checker.resolvable("master server", &opts.master_server);
+    checker.check(check_needle_alignment(opts.needle_alignment));
+    checker.metrics_push(&opts.metrics_push);
+
+    if opts.folders.is_empty() {
+        checker
+            .problems
+            .push("no folder to store volumes, set `--folders <dir>[:<max volumes>]`".to_string());
+    }
+    for folder in opts.folders.iter() {
+        let dir = match split_folder(folder) {
+            (dir, Some(max)) => {
+                if max.parse::<u32>().is_err() {
+                    checker.problems.push(format!(
+                        "max volumes of folder `{folder}` should be a number, like `{dir}:7`"
+                    ));
+                }
+                dir
+            }
+            (dir, None) => dir,
+        };
+        checker.writable("folder", dir);
+    }
+    if opts.disk_types.len() > opts.folders.len() {
+        checker.problems.push(format!(
+            "{} disk types are given for {} folders",
+            opts.disk_types.len(),
+            opts.folders.len()
+        ));
+    }
+    for disk_type in opts.disk_types.iter() {
+        checker.check(DiskType::new(disk_type).map(|_| ()));
+    }
+    checker.open_files();
+    checker.finish()
+}
+
+pub fn check_cache(opts: &CacheOptions) -> Result<()> {
+    let mut checker = Checker::default();
+    // the cache serves http only
+    checker.port(&opts.ip, opts.port);
+    checker.writable("cache dir", &opts.dir);
+    if let Some(dir) = &opts.offline_dir {
+        checker.writable("offline dir", dir);
+    }
+    checker.positive("--capacity-mb", opts.capacity_mb);
+    checker.positive("--request-timeout", opts.timeout.request_timeout);
+    checker.resolvable("master server", &opts.master_server);
+    checker.open_files();
+    checker.finish()
+}
+
+/// the options of both servers of `helyim server`, a problem they share is reported once
+pub fn check_server(opts: &ServerOptions) -> Result<()> {
+    let master = opts
+        .master_options()
+        .map_err(|err| Error::String(err.to_string()))?;
+    let volume = opts
+        .volume_options()
+        .map_err(|err| Error::String(err.to_string()))?;
+    let mut problems: Vec<String> = Vec::new();
+    for result in [check_master(&master), check_volume(&volume)] {
+        match result {
+            Ok(()) => {}
+            Err(Error::InvalidOptions(found)) => {
+                for problem in found {
+                    if !problems.contains(&problem) {
+                        problems.push(problem);
+                    }
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidOptions(problems))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use clap::Parser;
+
+    use crate::{
+        errors::Error,
+        util::{
+            args::{Command, Opts},
+            check::{check_cache, check_master, check_server, check_volume},
+        },
+    };
+
+    fn problems(args: &[&str]) -> Vec<String> {
+        let opts = Opts::parse_from(args);
+        let result = match opts.command {
+            Command::Master(opts) => check_master(&opts),
+            Command::Volume(opts) => check_volume(&opts),
+            Command::Server(opts) => check_server(&opts),
+            Command::Cache(opts) => check_cache(&opts),
+        };
+        match result {
+            Ok(()) => Vec::new(),
+            Err(Error::InvalidOptions(problems)) => problems,
+            Err(err) => panic!("unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    fn test_check_options() {
+        let dir = tempfile::Builder::new()
+            .prefix("check")
+            .tempdir_in(".")
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let folder = format!("--folders={}:x", dir.path().display());
+        let port = format!("--port={port}");
+
+        let problems = problems(&[
+            "helyim",
+            "volume",
+            port.as_str(),
+            folder.as_str(),
+            "--default-replication=01",
+            "--pulse=0",
+            "--master-server=127.0.0.1:9333",
+        ]);
+        // the grpc port of a high random port does not fit into u16
+        assert!(problems
+            .iter()
+            .any(|p| p.contains("cannot listen") || p.contains("too large")));
+        assert!(problems.iter().any(|p| p.contains("max volumes")));
+        assert!(problems.iter().any(|p| p.contains("replication `01`")));
+        assert!(problems.iter().any(|p| p.contains("--pulse")));
+        assert!(!problems.iter().any(|p| p.contains("not writable")));
+        assert!(!problems.iter().any(|p| p.contains("master server")));
+    }
+
+    #[test]
+    fn test_check_master_liveness() {
+        let dir = tempfile::Builder::new()
+            .prefix("check")
+            .tempdir_in(".")
+            .unwrap();
+        let meta_path = format!("--meta-path={}", dir.path().display());
+
+        let invalid = problems(&[
+            "helyim",
+            "master",
+            meta_path.as_str(),
+            "--pulse=10",
+            "--max-missed-heartbeats=0",
+            "--max-concurrent-replications=0",
+        ]);
+        assert!(invalid
+            .iter()
+            .any(|p| p.contains("--max-missed-heartbeats")));
+        assert!(invalid
+            .iter()
+            .any(|p| p.contains("--max-concurrent-replications")));
+
+        // 5 missed pulses of a minute are the default grace period of 300 seconds
+        let valid = problems(&[
+            "helyim",
+            "master",
+            meta_path.as_str(),
+            "--pulse=60",
+            "--max-missed-heartbeats=5",
+        ]);
+        assert!(!valid.iter().any(|p| p.contains("--replica-grace-period")));
+        let invalid = problems(&[
+            "helyim",
+            "master",
+            meta_path.as_str(),
+            "--pulse=60",
+            "--max-missed-heartbeats=6",
+        ]);
+        assert!(invalid
+            .iter()
+            .any(|p| p.contains("--replica-grace-period 300")));
+    }
+
+    #[test]
+    fn test_check_metrics_push() {
+        let dir = tempfile::Builder::new()
+            .prefix("check")
+            .tempdir_in(".")
+            .unwrap();
+        let meta_path = format!("--meta-path={}", dir.path().display());
+
+        let invalid = problems(&[
+            "helyim",
+            "master",
+            meta_path.as_str(),
+            "--metrics-push-gateway=http://127.0.0.1:9091",
+            "--metrics-push-interval=0",
+            "--metrics-push-job=a/b",
+        ]);
+        assert!(invalid
+            .iter()
+            .any(|p| p.contains("--metrics-push-interval")));
+        assert!(invalid.iter().any(|p| p.contains("metrics push job")));
+
+        // nothing is pushed without a pushgateway
+        let valid = problems(&[
+            "helyim",
+            "master",
+            meta_path.as_str(),
+            "--metrics-push-interval=0",
+        ]);
+        assert!(!valid.iter().any(|p| p.contains("metrics")));
+    }
+
+    #[test]
+    fn test_check_server() {
+        let dir = tempfile::Builder::new()
+            .prefix("check")
+            .tempdir_in(".")
+            .unwrap();
+        let data = format!("--dir={}", dir.path().display());
+
+        let problems = problems(&["helyim", "server", data.as_str(), "--pulse=0"]);
+        // both servers share the pulse, it is reported once
+        assert_eq!(
+            problems
+                .iter()
+                .filter(|p| p.contains("--pulse should"))
+                .count(),
+            1
+        );
+        assert!(!problems.iter().any(|p| p.contains("not writable")));
+        assert!(dir.path().join("volumes").is_dir());
+    }
+}
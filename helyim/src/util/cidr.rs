@@ -0,0 +1,132 @@
+use std::net::IpAddr;
+
+use faststr::FastStr;
+
+use crate::errors::{Error, Result};
+
+/// An ip network in cidr notation, like `10.0.0.0/8` or `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    pub fn parse(range: &str) -> Result<Self> {
+        let (network, prefix_len) = match range.split_once('/') {
+            Some((network, prefix_len)) => (network.parse::<IpAddr>()?, prefix_len.parse::<u8>()?),
+            None => {
+                let network = range.parse::<IpAddr>()?;
+                (network, max_prefix_len(&network))
+            }
+        };
+        if prefix_len > max_prefix_len(&network) {
+            return Err(Error::String(format!("invalid prefix length of {range}")));
+        }
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => prefix_matches(
+                u32::from(network).into(),
+                u32::from(ip).into(),
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(network.into(), ip.into(), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn max_prefix_len(ip: &IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn prefix_matches(network: u128, ip: u128, bits: u8, prefix_len: u8) -> bool {
+    prefix_len == 0 || (network ^ ip) >> (bits - prefix_len) == 0
+}
+
+/// Maps client ips to the data center they are located in.
+#[derive(Debug, Clone, Default)]
+pub struct DataCenterRanges {
+    ranges: Vec<(FastStr, IpRange)>,
+}
+
+impl DataCenterRanges {
+    /// every entry is `<data center>=<cidr>[,<cidr>...]`
+    pub fn parse(entries: &[FastStr]) -> Result<Self> {
+        let mut ranges = Vec::new();
+        for entry in entries {
+            let (data_center, cidrs) = entry.split_once('=').ok_or_else(|| {
+                Error::String(format!("data center range {entry} should be <dc>=<cidr>"))
+            })?;
+            for cidr in cidrs.split(',').filter(|cidr| !cidr.is_empty()) {
+                ranges.push((FastStr::new(data_center), IpRange::parse(cidr.trim())?));
+            }
+        }
+        Ok(Self { ranges })
+    }
+
+    /// the data center of the most specific range containing `ip`
+    pub fn data_center(&self, ip: IpAddr) -> Option<FastStr> {
+        self.ranges
+            .iter()
+            .filter(|(_, range)| range.contains(ip))
+            .max_by_key(|(_, range)| range.prefix_len)
+            .map(|(data_center, _)| data_center.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use faststr::FastStr;
+
+    use crate::util::cidr::{DataCenterRanges, IpRange};
+
+    #[test]
+    fn test_ip_range() {
+        let range = IpRange::parse("10.1.0.0/16").unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("10.2.2.3".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.2.3".parse().unwrap()));
+
+        let range = IpRange::parse("fd00::/8").unwrap();
+        assert!(range.contains("fd12::1".parse().unwrap()));
+        assert!(!range.contains("fe80::1".parse().unwrap()));
+
+        assert!(IpRange::parse("10.0.0.0/33").is_err());
+    }
+
+    #[test]
+    fn test_data_center_ranges() {
+        let ranges = DataCenterRanges::parse(&[
+            FastStr::new("dc1=10.0.0.0/8"),
+            FastStr::new("dc2=10.2.0.0/16,192.168.0.0/16"),
+        ])
+        .unwrap();
+        assert_eq!(
+            ranges.data_center("10.1.0.1".parse().unwrap()).as_deref(),
+            Some("dc1")
+        );
+        assert_eq!(
+            ranges.data_center("10.2.0.1".parse().unwrap()).as_deref(),
+            Some("dc2")
+        );
+        assert!(ranges.data_center("172.16.0.1".parse().unwrap()).is_none());
+        assert!(DataCenterRanges::parse(&[FastStr::new("dc1")]).is_err());
+    }
+}
@@ -1,11 +1,126 @@
-use std::{fs::metadata, io::ErrorKind, os::unix::fs::MetadataExt, time::SystemTime};
+use std::{
+    fs::{metadata, File, Metadata, OpenOptions},
+    io::{self, ErrorKind},
+    time::SystemTime,
+};
+
+/// Positioned reads and writes, like `std::os::unix::fs::FileExt`, on unix and windows.
+///
+/// The file cursor is left alone on unix, windows moves it, storage never relies on it.
+pub trait FileExt {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize>;
+
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => break,
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if buf.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ))
+        }
+    }
+
+    fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write_at(buf, offset) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ));
+                }
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl FileExt for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::write_at(self, buf, offset)
+    }
+}
+
+#[cfg(windows)]
+impl FileExt for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_write(self, buf, offset)
+    }
+}
+
+/// Permission bits of created files, ignored on windows.
+pub trait OpenOptionsExt {
+    fn mode(&mut self, mode: u32) -> &mut Self;
+}
+
+impl OpenOptionsExt for OpenOptions {
+    #[cfg(unix)]
+    fn mode(&mut self, mode: u32) -> &mut Self {
+        std::os::unix::fs::OpenOptionsExt::mode(self, mode)
+    }
+
+    #[cfg(not(unix))]
+    fn mode(&mut self, _mode: u32) -> &mut Self {
+        self
+    }
+}
+
+/// whether the owner can read and write the file
+#[cfg(unix)]
+fn permissions(metadata: &Metadata) -> (bool, bool) {
+    use std::os::unix::fs::MetadataExt;
+
+    let mode = metadata.mode();
+    (mode & 0o400 != 0, mode & 0o200 != 0)
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &Metadata) -> (bool, bool) {
+    (true, !metadata.permissions().readonly())
+}
+
+/// split a `--folders` entry `<dir>[:<max volumes>]`, a colon of a windows drive like `C:\data`
+/// is part of the directory
+pub fn split_folder(folder: &str) -> (&str, Option<&str>) {
+    match folder.rsplit_once(':') {
+        Some((dir, max)) if !dir.is_empty() && !max.contains(['/', '\\']) => (dir, Some(max)),
+        _ => (folder, None),
+    }
+}
 
 pub fn check_file(filename: &str) -> Result<Option<(bool, bool, SystemTime, u64)>, std::io::Error> {
     match metadata(filename) {
         Ok(metadata) => {
-            let mode = metadata.mode();
-            let can_read = mode & 0o400 != 0;
-            let can_write = mode & 0o200 != 0;
+            let (can_read, can_write) = permissions(&metadata);
             let modified = metadata.modified()?;
             let filesize = metadata.len();
             Ok(Some((can_read, can_write, modified, filesize)))
@@ -42,6 +157,8 @@ mod tests {
         path::Path,
     };
 
+    use crate::util::file::{split_folder, FileExt};
+
     #[test]
     pub fn test_file_exist() {
         let metadata = metadata("/tmp/not_exist_path");
@@ -78,6 +195,27 @@ mod tests {
         assert_eq!(n, 0);
     }
 
+    #[test]
+    pub fn test_positioned_io() {
+        let file = tempfile::tempfile().unwrap();
+        file.write_all_at(b"hello helyim", 0).unwrap();
+        file.write_all_at(b"world", 6).unwrap();
+
+        let mut buf = [0u8; 11];
+        file.read_exact_at(&mut buf, 0).unwrap();
+        assert_eq!(&buf, b"hello world");
+        let err = file.read_exact_at(&mut buf, 6).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    pub fn test_split_folder() {
+        assert_eq!(split_folder("/data:7"), ("/data", Some("7")));
+        assert_eq!(split_folder("/data"), ("/data", None));
+        assert_eq!(split_folder("C:\\data"), ("C:\\data", None));
+        assert_eq!(split_folder("C:\\data:3"), ("C:\\data", Some("3")));
+    }
+
     #[test]
     pub fn test_file_ext() {
         let path = Path::new("/tmp/helyim.txt");
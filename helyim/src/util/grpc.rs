@@ -1,40 +1,119 @@
-use std::{collections::HashMap, ops::Deref};
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
+use dashmap::DashMap;
 use faststr::FastStr;
 use futures::executor::block_on;
 use ginepro::LoadBalancedChannel;
 use helyim_proto::{
-    directory::helyim_client::HelyimClient, volume::volume_server_client::VolumeServerClient,
+    directory::helyim_client::HelyimClient, filer::helyim_filer_client::HelyimFilerClient,
+    volume::volume_server_client::VolumeServerClient, FILE_DESCRIPTOR_SET,
 };
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
+use serde::Serialize;
+use tonic::{codegen::InterceptedService, server::NamedService};
+use tonic_health::server::{health_reporter, Health, HealthServer};
+use tonic_reflection::server::{ServerReflection, ServerReflectionServer};
 use tracing::info;
 
-use crate::{storage::VolumeError, util::parser::parse_host_port};
+use crate::{
+    storage::VolumeError,
+    util::{parser::parse_host_port, sign::SignInterceptor},
+};
 
 pub fn grpc_port(port: u16) -> u16 {
     port + 10000
 }
 
+/// the standard grpc health service reporting `S` as serving, and server reflection of the
+/// helyim protos, so grpc-health-probe and grpcurl work against every grpc server
+pub async fn standard_services<S: NamedService>() -> Result<
+    (
+        HealthServer<impl Health>,
+        ServerReflectionServer<impl ServerReflection>,
+    ),
+    VolumeError,
+> {
+    let (mut reporter, health) = health_reporter();
+    reporter.set_serving::<S>().await;
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build()
+        .map_err(|err| VolumeError::String(err.to_string()))?;
+    Ok((health, reflection))
+}
+
 static GRPC_CLIENT_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
-type VolumeServerClientMap = HashMap<FastStr, VolumeServerClient<LoadBalancedChannel>>;
+const GRPC_TIMEOUT: Duration = Duration::from_secs(30);
+const GRPC_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// interval to re-resolve the endpoints, unhealthy endpoints are removed from the channel
+const GRPC_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+static GRPC_CHANNEL_CREATED: AtomicU64 = AtomicU64::new(0);
+static GRPC_CHANNEL_REUSED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrpcPoolStats {
+    pub volume_server_channels: usize,
+    pub helyim_channels: usize,
+    pub filer_channels: usize,
+    pub created: u64,
+    pub reused: u64,
+}
+
+pub fn grpc_pool_stats() -> GrpcPoolStats {
+    let _lock = GRPC_CLIENT_LOCK.lock();
+    GrpcPoolStats {
+        volume_server_channels: VOLUME_SERVER_CLIENTS.len(),
+        helyim_channels: HELYIM_CLIENTS.len(),
+        filer_channels: FILER_CLIENTS.len(),
+        created: GRPC_CHANNEL_CREATED.load(Ordering::Relaxed),
+        reused: GRPC_CHANNEL_REUSED.load(Ordering::Relaxed),
+    }
+}
+
+/// channel of the internal grpc clients, every call is signed with the cluster secret
+pub type SignedChannel = InterceptedService<LoadBalancedChannel, SignInterceptor>;
+
+fn load_balanced_channel(ip: String, port: u16) -> Result<LoadBalancedChannel, VolumeError> {
+    let channel = block_on(
+        LoadBalancedChannel::builder((ip, port))
+            .timeout(GRPC_TIMEOUT)
+            .connect_timeout(GRPC_CONNECT_TIMEOUT)
+            .dns_probe_interval(GRPC_PROBE_INTERVAL)
+            .channel(),
+    )
+    .map_err(|err| VolumeError::Box(err.into()))?;
+    GRPC_CHANNEL_CREATED.fetch_add(1, Ordering::Relaxed);
+    Ok(channel)
+}
+
+type VolumeServerClientMap = HashMap<FastStr, VolumeServerClient<SignedChannel>>;
 static VOLUME_SERVER_CLIENTS: Lazy<VolumeServerClientMap> = Lazy::new(HashMap::new);
 
 pub fn volume_server_client(
     addr: &str,
-) -> Result<&mut VolumeServerClient<LoadBalancedChannel>, VolumeError> {
+) -> Result<&mut VolumeServerClient<SignedChannel>, VolumeError> {
     let clients =
         VOLUME_SERVER_CLIENTS.deref() as *const VolumeServerClientMap as *mut VolumeServerClientMap;
     match unsafe { (*clients).get_mut(addr) } {
-        Some(client) => Ok(client),
+        Some(client) => {
+            GRPC_CHANNEL_REUSED.fetch_add(1, Ordering::Relaxed);
+            Ok(client)
+        }
         None => {
             let (ip, port) = parse_host_port(addr)?;
             let grpc_port = grpc_port(port);
 
-            let channel = block_on(LoadBalancedChannel::builder((ip.clone(), grpc_port)).channel())
-                .map_err(|err| VolumeError::Box(err.into()))?;
-            let client = VolumeServerClient::new(channel);
+            let channel = load_balanced_channel(ip.clone(), grpc_port)?;
+            let client = VolumeServerClient::with_interceptor(channel, SignInterceptor);
             info!("create volume server client success, addr: {ip}:{grpc_port}");
 
             let _lock = GRPC_CLIENT_LOCK.lock();
@@ -45,20 +124,22 @@ pub fn volume_server_client(
     }
 }
 
-type HelyimClientMap = HashMap<FastStr, HelyimClient<LoadBalancedChannel>>;
+type HelyimClientMap = HashMap<FastStr, HelyimClient<SignedChannel>>;
 static HELYIM_CLIENTS: Lazy<HelyimClientMap> = Lazy::new(HashMap::new);
 
-pub fn helyim_client(addr: &str) -> Result<&mut HelyimClient<LoadBalancedChannel>, VolumeError> {
+pub fn helyim_client(addr: &str) -> Result<&mut HelyimClient<SignedChannel>, VolumeError> {
     let clients = HELYIM_CLIENTS.deref() as *const HelyimClientMap as *mut HelyimClientMap;
     match unsafe { (*clients).get_mut(addr) } {
-        Some(client) => Ok(client),
+        Some(client) => {
+            GRPC_CHANNEL_REUSED.fetch_add(1, Ordering::Relaxed);
+            Ok(client)
+        }
         None => {
             let (ip, port) = parse_host_port(addr)?;
             let grpc_port = grpc_port(port);
 
-            let channel = block_on(LoadBalancedChannel::builder((ip.clone(), grpc_port)).channel())
-                .map_err(|err| VolumeError::Box(err.into()))?;
-            let client = HelyimClient::new(channel);
+            let channel = load_balanced_channel(ip.clone(), grpc_port)?;
+            let client = HelyimClient::with_interceptor(channel, SignInterceptor);
 
             info!("create helyim client success, addr: {ip}:{grpc_port}");
 
@@ -69,3 +150,28 @@ pub fn helyim_client(addr: &str) -> Result<&mut HelyimClient<LoadBalancedChannel
         }
     }
 }
+
+static FILER_CLIENTS: Lazy<DashMap<FastStr, HelyimFilerClient<SignedChannel>>> =
+    Lazy::new(DashMap::new);
+
+/// a client of the filer at `addr`, the clones share the channel
+pub fn filer_client(addr: &str) -> Result<HelyimFilerClient<SignedChannel>, VolumeError> {
+    if let Some(client) = FILER_CLIENTS.get(addr) {
+        GRPC_CHANNEL_REUSED.fetch_add(1, Ordering::Relaxed);
+        return Ok(client.clone());
+    }
+    let (ip, port) = parse_host_port(addr)?;
+    let grpc_port = grpc_port(port);
+
+    let channel = load_balanced_channel(ip.clone(), grpc_port)?;
+    let client = HelyimFilerClient::with_interceptor(channel, SignInterceptor);
+
+    info!("create filer client success, addr: {ip}:{grpc_port}");
+
+    // WARN: addr is not the grpc addr
+    let client = FILER_CLIENTS
+        .entry(FastStr::new(addr))
+        .or_insert(client)
+        .clone();
+    Ok(client)
+}
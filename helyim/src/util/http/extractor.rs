@@ -20,12 +20,14 @@ use tracing::{error, info};
 
 use crate::{
     directory::DirectoryState,
+    operation::ReplicaSync,
     storage::VolumeId,
     topology::{TopologyError, TopologyRef},
 };
 
 #[derive(Debug, FromRequest)]
 pub struct GetOrHeadExtractor {
+    pub method: Method,
     pub uri: Uri,
     pub headers: HeaderMap,
 }
@@ -46,6 +48,7 @@ pub struct DeleteExtractor {
     // only the last field can implement `FromRequest`
     // other fields must only implement `FromRequestParts`
     pub uri: Uri,
+    pub headers: HeaderMap,
     #[from_request(via(TypedHeader))]
     pub host: Host,
     #[from_request(via(Query))]
@@ -60,6 +63,13 @@ pub struct StorageQuery {
     pub ttl: Option<FastStr>,
     // last modified
     pub ts: Option<u64>,
+    /// `replace` overwrites an existing needle when overwrites are protected
+    pub op: Option<FastStr>,
+    /// `all`, `quorum` or `one`, copies of a write to wait for, a write is rejected if fewer are
+    /// written
+    pub sync: Option<ReplicaSync>,
+    /// volume server a replica pulls a large needle from with ReadNeedleBlob
+    pub source: Option<FastStr>,
 }
 
 #[derive(Debug, FromRequest)]
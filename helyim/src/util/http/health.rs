@@ -0,0 +1,85 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub error: String,
+}
+
+/// result of the readiness probe, answered with 503 if any dependency check failed
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self {
+            ready: true,
+            checks: Vec::new(),
+        }
+    }
+}
+
+impl Readiness {
+    pub fn check<N: Into<String>, E: ToString>(&mut self, name: N, result: Result<(), E>) {
+        let (ok, error) = match result {
+            Ok(()) => (true, String::new()),
+            Err(err) => (false, err.to_string()),
+        };
+        self.ready &= ok;
+        self.checks.push(Check {
+            name: name.into(),
+            ok,
+            error,
+        });
+    }
+}
+
+impl IntoResponse for Readiness {
+    fn into_response(self) -> Response {
+        let status = if self.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+/// liveness probe, the process is able to serve http requests
+pub async fn healthz_handler() -> &'static str {
+    "ok"
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    use crate::util::http::health::Readiness;
+
+    #[test]
+    fn test_readiness() {
+        let mut readiness = Readiness::default();
+        readiness.check("leader", Ok::<(), String>(()));
+        assert!(readiness.ready);
+        assert_eq!(readiness.clone().into_response().status(), StatusCode::OK);
+
+        readiness.check("disk", Err("read-only file system"));
+        assert!(!readiness.ready);
+        assert!(readiness.checks[0].ok);
+        assert_eq!(readiness.checks[1].error, "read-only file system");
+        assert_eq!(
+            readiness.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
@@ -1,20 +1,54 @@
 pub mod extractor;
 
-use std::time::Duration;
+pub mod health;
 
-use axum::response::Html;
+pub mod pool;
+
+pub mod request_id;
+
+use std::{any::Any, time::Duration};
+
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    Json,
+};
 use bytes::Bytes;
+use faststr::FastStr;
 use once_cell::sync::Lazy;
-use reqwest::Body;
+use reqwest::{header::CONTENT_TYPE, Body, RequestBuilder};
+use serde_json::{json, Value};
+use tracing::error;
 use url::Url;
 
-use crate::{errors::Result, images::FAVICON_ICO, PHRASE};
+use crate::{
+    errors::{Error, ErrorBody, ErrorCode, Result},
+    images::FAVICON_ICO,
+    util::{
+        buffer::BUFFER_POOL,
+        grpc::grpc_pool_stats,
+        http::{
+            pool::{host_pool, pool_stats},
+            request_id::{current_request_id, REQUEST_ID_HEADER},
+        },
+        retry::{retry, retry_stats},
+        sign::sign_http,
+        sys::panic_message,
+    },
+    PHRASE,
+};
 
 pub const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+/// content type of the prometheus text exposition format
+pub const PROMETHEUS_TEXT_FORMAT: &str = "text/plain; version=0.0.4; charset=utf-8";
 
 pub async fn get<U: AsRef<str>>(url: U, params: &[(&str, &str)]) -> Result<Bytes> {
     let url = Url::parse_with_params(url.as_ref(), params)?;
-    Ok(HTTP_CLIENT.get(url).send().await?.bytes().await?)
+    let response = retry(&format!("GET {url}"), || {
+        send(&url, HTTP_CLIENT.get(url.clone()))
+    })
+    .await?;
+    api_result(response)
 }
 
 pub async fn post<U: AsRef<str>, B: Into<Body>>(
@@ -23,18 +57,101 @@ pub async fn post<U: AsRef<str>, B: Into<Body>>(
     body: B,
 ) -> Result<Bytes> {
     let url = Url::parse_with_params(url.as_ref(), params)?;
-    Ok(HTTP_CLIENT
-        .post(url)
-        .body(body)
-        .send()
-        .await?
-        .bytes()
-        .await?)
+    api_result(send(&url, HTTP_CLIENT.post(url.clone()).body(body)).await?)
+}
+
+/// post `body` as it is with its `content_type`, a multipart body keeps its boundary
+pub async fn post_with_content_type<U: AsRef<str>, B: Into<Body>>(
+    url: U,
+    content_type: &str,
+    body: B,
+) -> Result<Bytes> {
+    let url = Url::parse(url.as_ref())?;
+    let request = HTTP_CLIENT
+        .post(url.clone())
+        .header(CONTENT_TYPE, content_type)
+        .body(body);
+    api_result(send(&url, request).await?)
 }
 
 pub async fn delete<U: AsRef<str>>(url: U, params: &[(&str, &str)]) -> Result<Bytes> {
     let url = Url::parse_with_params(url.as_ref(), params)?;
-    Ok(HTTP_CLIENT.delete(url).send().await?.bytes().await?)
+    api_result(send(&url, HTTP_CLIENT.delete(url.clone())).await?)
+}
+
+/// send request through the shared connection pool, bounded by the per host permits, requests
+/// are signed with the cluster secret. requests to an ejected host fail without being sent
+async fn send(url: &Url, request: RequestBuilder) -> Result<(StatusCode, Bytes)> {
+    let pool = host_pool(url);
+    let Some(_permit) = pool.acquire().await else {
+        return Err(Error::HostEjected(FastStr::new(url.authority())));
+    };
+    // the request of a client carries its id to the other servers
+    let request = match current_request_id() {
+        Some(id) => request.header(REQUEST_ID_HEADER, id.as_str()),
+        None => request,
+    };
+    let mut request = request.build()?;
+    sign_http(&mut request);
+    match HTTP_CLIENT.execute(request).await {
+        Ok(response) => {
+            let status = response.status();
+            let body = match response.bytes().await {
+                Ok(body) => body,
+                Err(err) => {
+                    pool.record_failure();
+                    return Err(err.into());
+                }
+            };
+            if is_host_failure(status.as_u16(), &body) {
+                pool.record_failure();
+            } else {
+                pool.record_success();
+            }
+            Ok((status, body))
+        }
+        Err(err) => {
+            pool.record_failure();
+            Err(err.into())
+        }
+    }
+}
+
+/// Whether a response means the host itself is failing, like a proxy in front of a host which is
+/// down. An error answered by helyim, even a 5xx one, comes from a healthy host.
+fn is_host_failure(status: u16, body: &[u8]) -> bool {
+    matches!(status, 502..=504) && serde_json::from_slice::<ErrorBody>(body).is_err()
+}
+
+/// turn an error response carrying an error code into `Error::Api`, other bodies are returned
+/// as they are to keep the callers which decode the legacy `error` field working
+fn api_result((status, body): (StatusCode, Bytes)) -> Result<Bytes> {
+    if !status.is_success() {
+        if let Ok(error) = serde_json::from_slice::<ErrorBody>(&body) {
+            return Err(Error::Api(error));
+        }
+    }
+    Ok(body)
+}
+
+/// whether the value of an `If-Match` header matches the current etag, `None` means the resource
+/// does not exist
+pub fn etag_matches(if_match: &str, etag: Option<&str>) -> bool {
+    match etag {
+        Some(etag) => if_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            let tag = tag.strip_prefix("W/").unwrap_or(tag);
+            tag == "*" || tag.trim_matches('"') == etag
+        }),
+        None => false,
+    }
+}
+
+/// response of a request whose handler panicked, used with `CatchPanicLayer`
+pub fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = panic_message(panic.as_ref());
+    error!("request handler panicked: {message}");
+    ErrorBody::new(ErrorCode::Internal, format!("panicked: {message}")).into_response()
 }
 
 pub async fn default_handler() -> Html<&'static str> {
@@ -45,12 +162,78 @@ pub async fn favicon_handler<'a>() -> Result<&'a [u8]> {
     FAVICON_ICO.bytes()
 }
 
+pub async fn pool_stats_handler() -> Json<Value> {
+    Json(json!({
+        "http": pool_stats(),
+        "grpc": grpc_pool_stats(),
+        "buffer": BUFFER_POOL.stats(),
+        "retry": retry_stats(),
+    }))
+}
+
 pub static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
     reqwest::Client::builder()
         .timeout(Duration::from_secs(10))
         .connect_timeout(Duration::from_secs(10))
         .pool_idle_timeout(Duration::from_secs(30))
+        .pool_max_idle_per_host(pool::MAX_CONCURRENT_REQUESTS_PER_HOST)
+        .tcp_keepalive(Duration::from_secs(30))
+        .http2_keep_alive_interval(Duration::from_secs(30))
         .http2_keep_alive_timeout(Duration::from_secs(60))
+        .http2_keep_alive_while_idle(true)
         .build()
         .expect("HTTP CLIENT initialize failed")
 });
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use bytes::Bytes;
+
+    use crate::{
+        errors::{Error, ErrorCode},
+        util::http::{api_result, etag_matches, is_host_failure},
+    };
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches("\"1234\"", Some("1234")));
+        assert!(etag_matches("W/\"1234\"", Some("1234")));
+        assert!(etag_matches("\"abcd\", \"1234\"", Some("1234")));
+        assert!(etag_matches("*", Some("1234")));
+        assert!(!etag_matches("*", None));
+        assert!(!etag_matches("\"abcd\"", Some("1234")));
+    }
+
+    #[test]
+    fn test_is_host_failure() {
+        assert!(is_host_failure(502, b"<html>bad gateway</html>"));
+        assert!(is_host_failure(503, b""));
+        assert!(!is_host_failure(
+            503,
+            br#"{"code":"not_leader","error":"no leader","retryable":true}"#
+        ));
+        assert!(!is_host_failure(500, b"oops"));
+        assert!(!is_host_failure(404, b""));
+    }
+
+    #[test]
+    fn test_api_result() {
+        let body = Bytes::from_static(
+            br#"{"code":"write_queue_full","error":"busy","retryable":true,"volumeId":3}"#,
+        );
+        match api_result((StatusCode::TOO_MANY_REQUESTS, body)) {
+            Err(Error::Api(body)) => {
+                assert_eq!(body.code, ErrorCode::WriteQueueFull);
+                assert!(body.retryable);
+                assert_eq!(body.volume_id, Some(3));
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+
+        let body = Bytes::from_static(br#"{"error":"not found"}"#);
+        assert!(api_result((StatusCode::NOT_FOUND, body)).is_ok());
+        let body = Bytes::from_static(br#"{"code":"internal","error":"x","retryable":false}"#);
+        assert!(api_result((StatusCode::OK, body)).is_ok());
+    }
+}
@@ -0,0 +1,235 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+
+use dashmap::DashMap;
+use faststr::FastStr;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use url::Url;
+
+use crate::util::time::now;
+
+/// max concurrent in-flight requests from this process to one peer
+pub const MAX_CONCURRENT_REQUESTS_PER_HOST: usize = 64;
+/// consecutive failures after which a host is ejected from the lookup rotation
+pub const EJECT_FAILURE_THRESHOLD: u64 = 5;
+/// milliseconds a host stays ejected before a probe request is let through
+pub const EJECT_DURATION_MS: u64 = 30_000;
+
+/// Per peer request gate, bounds the concurrency towards a single host and records stats.
+///
+/// It also acts as a circuit breaker: after `EJECT_FAILURE_THRESHOLD` consecutive failures the
+/// host is ejected for `EJECT_DURATION_MS` and its requests are rejected. After that a single
+/// request probes it while the others are still rejected, a success admits the host again and a
+/// failure ejects it for another period.
+pub struct HostPool {
+    permits: Semaphore,
+    in_flight: AtomicU64,
+    requests: AtomicU64,
+    failures: AtomicU64,
+    consecutive_failures: AtomicU64,
+    ejected_until: AtomicU64,
+    probing: AtomicBool,
+}
+
+impl HostPool {
+    fn new(max_concurrent: usize) -> Self {
+        Self {
+            permits: Semaphore::new(max_concurrent),
+            in_flight: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            consecutive_failures: AtomicU64::new(0),
+            ejected_until: AtomicU64::new(0),
+            probing: AtomicBool::new(false),
+        }
+    }
+
+    /// `None` while the host is ejected, or while another request is probing it
+    pub async fn acquire(&self) -> Option<HostPermit<'_>> {
+        let probe = match self.ejected_until.load(Ordering::Relaxed) {
+            0 => false,
+            ejected_until if now().as_millis() as u64 >= ejected_until => {
+                // the eject period is over, only one request is let through to probe the host
+                self.probing
+                    .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+                    .ok()?;
+                true
+            }
+            _ => return None,
+        };
+        // the semaphore is never closed
+        let permit = self.permits.acquire().await.ok();
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        Some(HostPermit {
+            pool: self,
+            probe,
+            _permit: permit,
+        })
+    }
+
+    pub fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= EJECT_FAILURE_THRESHOLD {
+            self.ejected_until.store(
+                now().as_millis() as u64 + EJECT_DURATION_MS,
+                Ordering::Relaxed,
+            );
+        }
+    }
+
+    pub fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        self.ejected_until.store(0, Ordering::Relaxed);
+    }
+
+    /// whether requests should be routed to this host, a host being probed is not
+    pub fn is_available(&self) -> bool {
+        now().as_millis() as u64 >= self.ejected_until.load(Ordering::Relaxed)
+            && !self.probing.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self, host: FastStr) -> HostPoolStats {
+        HostPoolStats {
+            host,
+            available_permits: self.permits.available_permits(),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            consecutive_failures: self.consecutive_failures.load(Ordering::Relaxed),
+            ejected: !self.is_available(),
+        }
+    }
+}
+
+pub struct HostPermit<'a> {
+    pool: &'a HostPool,
+    probe: bool,
+    _permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for HostPermit<'_> {
+    fn drop(&mut self) {
+        self.pool.in_flight.fetch_sub(1, Ordering::Relaxed);
+        // the outcome of the probe is recorded by now, or the request was cancelled and the next
+        // one probes again
+        if self.probe {
+            self.pool.probing.store(false, Ordering::Release);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostPoolStats {
+    pub host: FastStr,
+    pub available_permits: usize,
+    pub in_flight: u64,
+    pub requests: u64,
+    pub failures: u64,
+    pub consecutive_failures: u64,
+    pub ejected: bool,
+}
+
+static HOST_POOLS: Lazy<DashMap<FastStr, Arc<HostPool>>> = Lazy::new(DashMap::new);
+
+pub fn host_pool(url: &Url) -> Arc<HostPool> {
+    let host = match (url.host_str(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => FastStr::new(format!("{host}:{port}")),
+        (Some(host), None) => FastStr::new(host),
+        _ => FastStr::empty(),
+    };
+    HOST_POOLS
+        .entry(host)
+        .or_insert_with(|| Arc::new(HostPool::new(MAX_CONCURRENT_REQUESTS_PER_HOST)))
+        .clone()
+}
+
+/// whether `host` in `host:port` form is not ejected, hosts never requested are available
+pub fn host_available(host: &str) -> bool {
+    HOST_POOLS
+        .get(host)
+        .map_or(true, |pool| pool.is_available())
+}
+
+pub fn pool_stats() -> Vec<HostPoolStats> {
+    HOST_POOLS
+        .iter()
+        .map(|pool| pool.value().stats(pool.key().clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use url::Url;
+
+    use crate::util::http::pool::{
+        host_available, host_pool, EJECT_FAILURE_THRESHOLD, MAX_CONCURRENT_REQUESTS_PER_HOST,
+    };
+
+    #[tokio::test]
+    async fn test_host_pool_permits() {
+        let url = Url::parse("http://127.0.0.1:18080/1,01637037d6").unwrap();
+        let pool = host_pool(&url);
+        {
+            let _permit = pool.acquire().await.unwrap();
+            let stats = pool.stats("127.0.0.1:18080".into());
+            assert_eq!(stats.in_flight, 1);
+            assert_eq!(
+                stats.available_permits,
+                MAX_CONCURRENT_REQUESTS_PER_HOST - 1
+            );
+        }
+        pool.record_failure();
+
+        let stats = pool.stats("127.0.0.1:18080".into());
+        assert_eq!(stats.in_flight, 0);
+        assert_eq!(stats.requests, 1);
+        assert_eq!(stats.failures, 1);
+        assert_eq!(stats.available_permits, MAX_CONCURRENT_REQUESTS_PER_HOST);
+    }
+
+    #[tokio::test]
+    async fn test_host_pool_ejection() {
+        let url = Url::parse("http://127.0.0.1:18081/1,01637037d6").unwrap();
+        let pool = host_pool(&url);
+        for _ in 1..EJECT_FAILURE_THRESHOLD {
+            pool.record_failure();
+        }
+        assert!(host_available("127.0.0.1:18081"));
+
+        pool.record_failure();
+        assert!(!host_available("127.0.0.1:18081"));
+        assert!(pool.stats("127.0.0.1:18081".into()).ejected);
+        assert!(pool.acquire().await.is_none());
+
+        // the eject period is over, a single request probes the host
+        pool.ejected_until.store(1, Ordering::Relaxed);
+        {
+            let probe = pool.acquire().await;
+            assert!(probe.is_some());
+            assert!(!host_available("127.0.0.1:18081"));
+            assert!(pool.acquire().await.is_none());
+            pool.record_failure();
+        }
+        assert!(pool.acquire().await.is_none());
+
+        // a successful probe admits the host again
+        pool.ejected_until.store(1, Ordering::Relaxed);
+        {
+            let _probe = pool.acquire().await.unwrap();
+            pool.record_success();
+        }
+        assert!(host_available("127.0.0.1:18081"));
+        assert!(pool.acquire().await.is_some());
+        assert!(pool.acquire().await.is_some());
+        assert!(host_available("127.0.0.1:18082"));
+    }
+}
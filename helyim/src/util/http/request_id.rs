@@ -0,0 +1,164 @@
+use std::task::{Context, Poll};
+
+use axum::{
+    body::Body,
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use faststr::FastStr;
+use futures::future::BoxFuture;
+use tonic::codegen::http as grpc_http;
+use tower::{Layer, Service};
+use tracing::{info_span, Instrument};
+
+/// name of the header, for the http 0.2 clients and the grpc metadata
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// header carrying the id of a request, taken from the client or generated
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static(REQUEST_ID_HEADER);
+
+/// longest id taken from a client, a longer one is replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: FastStr;
+}
+
+/// the id of the request being served, `None` outside of a request
+pub fn current_request_id() -> Option<FastStr> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+fn new_request_id() -> FastStr {
+    FastStr::new(format!("{:016x}", rand::random::<u64>()))
+}
+
+/// the id of the client if it is a sane one, a new id otherwise
+fn accept(id: Option<&str>) -> FastStr {
+    match id {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic()) =>
+        {
+            FastStr::new(id)
+        }
+        _ => new_request_id(),
+    }
+}
+
+/// Middleware of the http servers. The request is served within a span and scope of its id, so
+/// the logs and error bodies carry it, and the id is returned in the `x-request-id` header.
+pub async fn request_id(mut request: Request<Body>, next: Next) -> Response {
+    let id = accept(
+        request
+            .headers()
+            .get(&X_REQUEST_ID)
+            .and_then(|value| value.to_str().ok()),
+    );
+    let value = HeaderValue::from_str(&id).ok();
+    // forwarded along with the request when it is proxied
+    if let Some(value) = &value {
+        request.headers_mut().insert(X_REQUEST_ID, value.clone());
+    }
+    let span =
+        info_span!("request", id = %id, method = %request.method(), path = request.uri().path());
+    let mut response = REQUEST_ID
+        .scope(id, next.run(request).instrument(span))
+        .await;
+    if let Some(value) = value {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    response
+}
+
+/// layer of the grpc servers, does for a call what `request_id` does for a http request
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GrpcRequestIdLayer;
+
+impl<S> Layer<S> for GrpcRequestIdLayer {
+    type Service = GrpcRequestId<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcRequestId { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GrpcRequestId<S> {
+    inner: S,
+}
+
+impl<S, B, R> Service<grpc_http::Request<B>> for GrpcRequestId<S>
+where
+    S: Service<grpc_http::Request<B>, Response = grpc_http::Response<R>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: grpc_http::Request<B>) -> Self::Future {
+        let id = accept(
+            request
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok()),
+        );
+        let span = info_span!("grpc", id = %id, path = request.uri().path());
+        let future = self.inner.call(request);
+        Box::pin(
+            REQUEST_ID.scope(
+                id.clone(),
+                async move {
+                    let mut response = future.await?;
+                    // a failed call is answered in the headers as well
+                    if let Ok(value) = grpc_http::HeaderValue::from_str(&id) {
+                        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+                    }
+                    Ok(response)
+                }
+                .instrument(span),
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::to_bytes, response::IntoResponse};
+
+    use crate::{
+        errors::{ErrorBody, ErrorCode},
+        util::http::request_id::{accept, current_request_id, REQUEST_ID},
+    };
+
+    #[tokio::test]
+    async fn test_request_id() {
+        assert_eq!(accept(Some("abc-123")), "abc-123");
+        assert_eq!(accept(Some("")).len(), 16);
+        assert_ne!(accept(Some("with space")), "with space");
+        assert_eq!(accept(Some(&"x".repeat(200))).len(), 16);
+        assert_ne!(accept(None), accept(None));
+
+        assert!(current_request_id().is_none());
+        let id = REQUEST_ID
+            .scope("abc".into(), async { current_request_id() })
+            .await;
+        assert_eq!(id.as_deref(), Some("abc"));
+
+        let response = REQUEST_ID
+            .scope("abc".into(), async {
+                ErrorBody::new(ErrorCode::Internal, "oops").into_response()
+            })
+            .await;
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.request_id.as_deref(), Some("abc"));
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+
+use axum::{extract::Query, Json};
+use faststr::FastStr;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::{info, level_filters::LevelFilter, Level};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    filter::Directive, fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter,
+    Registry,
+};
+
+use crate::{
+    errors::{Error, Result},
+    util::args::LogOptions,
+};
+
+static LOG_FILTER: OnceCell<LogFilter> = OnceCell::new();
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogOutput {
+    Stdout,
+    File,
+    /// write to stdout and the log file
+    Both,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+/// Tracing filter which can be changed at runtime, the level of every target is kept so the
+/// filter can be rebuilt when one of them changes.
+struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    levels: Mutex<BTreeMap<FastStr, LevelFilter>>,
+}
+
+impl LogFilter {
+    fn build(levels: &BTreeMap<FastStr, LevelFilter>) -> Result<EnvFilter> {
+        let mut filter = EnvFilter::new("off");
+        for (target, level) in levels {
+            filter = filter.add_directive(directive(target, *level)?);
+        }
+        Ok(filter)
+    }
+
+    fn set_level(&self, target: FastStr, level: LevelFilter) -> Result<()> {
+        let mut levels = self.levels.lock();
+        let mut changed = levels.clone();
+        changed.insert(target, level);
+        let filter = Self::build(&changed)?;
+        self.handle
+            .reload(filter)
+            .map_err(|err| Error::String(err.to_string()))?;
+        *levels = changed;
+        Ok(())
+    }
+}
+
+fn directive(target: &str, level: LevelFilter) -> Result<Directive> {
+    let valid = !target.is_empty()
+        && target
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':');
+    if !valid {
+        return Err(Error::String(format!("invalid log target: {target}")));
+    }
+    format!("{target}={level}")
+        .parse()
+        .map_err(|err| Error::String(format!("invalid log directive: {err}")))
+}
+
+/// parse `<target>=<level>`
+fn parse_target_level(directive: &str) -> Result<(FastStr, LevelFilter)> {
+    let (target, level) = directive.split_once('=').ok_or_else(|| {
+        Error::String(format!("log level {directive} should be <target>=<level>"))
+    })?;
+    let level = level
+        .parse::<LevelFilter>()
+        .map_err(|err| Error::String(format!("invalid log level {level}: {err}")))?;
+    Ok((FastStr::new(target), level))
+}
+
+/// rolling appender writing `<log_path>/helyim-<prefix>.<date>.log`
+fn file_appender(opts: &LogOptions, prefix: &str) -> Result<RollingFileAppender> {
+    let mut builder = RollingFileAppender::builder()
+        .rotation(opts.log_rotation.into())
+        .filename_prefix(format!("helyim-{prefix}"))
+        .filename_suffix("log");
+    if opts.log_max_files > 0 {
+        builder = builder.max_log_files(opts.log_max_files);
+    }
+    builder.build(opts.log_path.as_str()).map_err(|err| {
+        Error::String(format!(
+            "create log file in {} failed: {err}",
+            opts.log_path
+        ))
+    })
+}
+
+/// install the global subscriber, helyim logs at `level` and `--log-level` overrides the level
+/// of single targets.
+///
+/// the returned guard flushes the log file when dropped, it must be held until exit.
+pub fn init(level: Level, opts: &LogOptions, prefix: &str) -> Result<Option<WorkerGuard>> {
+    let mut levels = BTreeMap::new();
+    levels.insert(
+        FastStr::from_static_str(env!("CARGO_PKG_NAME")),
+        LevelFilter::from_level(level),
+    );
+    for directive in opts.log_level.iter() {
+        let (target, level) = parse_target_level(directive)?;
+        levels.insert(target, level);
+    }
+
+    let stdout = match opts.log_output {
+        LogOutput::Stdout | LogOutput::Both => Some(
+            fmt::layer()
+                .with_target(true)
+                .with_level(true)
+                .with_ansi(true)
+                .with_line_number(true),
+        ),
+        LogOutput::File => None,
+    };
+    let (file, guard) = match opts.log_output {
+        LogOutput::File | LogOutput::Both => {
+            let (writer, guard) = tracing_appender::non_blocking(file_appender(opts, prefix)?);
+            let layer = fmt::layer()
+                .with_writer(writer)
+                .with_target(true)
+                .with_level(true)
+                .with_ansi(false)
+                .with_line_number(true);
+            (Some(layer), Some(guard))
+        }
+        LogOutput::Stdout => (None, None),
+    };
+
+    let (filter, handle) = reload::Layer::new(LogFilter::build(&levels)?);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(stdout)
+        .with(file)
+        .try_init()
+        .map_err(|err| Error::String(err.to_string()))?;
+
+    let _ = LOG_FILTER.set(LogFilter {
+        handle,
+        levels: Mutex::new(levels),
+    });
+    Ok(guard)
+}
+
+/// change the level of `target` and its children, like `helyim::storage`
+pub fn set_level(target: &str, level: &str) -> Result<()> {
+    let filter = LOG_FILTER
+        .get()
+        .ok_or_else(|| Error::String("log filter is not initialized".to_string()))?;
+    let (target, level) = parse_target_level(&format!("{target}={level}"))?;
+    info!("set log level of {target} to {level}");
+    filter.set_level(target, level)
+}
+
+pub fn levels() -> BTreeMap<FastStr, String> {
+    LOG_FILTER
+        .get()
+        .map(|filter| {
+            filter
+                .levels
+                .lock()
+                .iter()
+                .map(|(target, level)| (target.clone(), level.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogLevelRequest {
+    pub target: FastStr,
+    pub level: FastStr,
+}
+
+/// `GET /admin/log-level` answers with the levels of all targets
+pub async fn log_levels_handler() -> Json<BTreeMap<FastStr, String>> {
+    Json(levels())
+}
+
+/// `PUT /admin/log-level?target=helyim::storage&level=debug` changes the level of a target and
+/// answers with the levels of all targets
+pub async fn set_log_level_handler(
+    Query(request): Query<LogLevelRequest>,
+) -> Result<Json<BTreeMap<FastStr, String>>> {
+    set_level(&request.target, &request.level)?;
+    Ok(Json(levels()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use faststr::FastStr;
+    use tracing::level_filters::LevelFilter;
+
+    use crate::util::{
+        args::Opts,
+        log::{file_appender, parse_target_level, LogFilter},
+    };
+
+    #[test]
+    fn test_log_directive() {
+        assert_eq!(
+            parse_target_level("helyim::storage=debug").unwrap(),
+            (FastStr::new("helyim::storage"), LevelFilter::DEBUG)
+        );
+        assert!(parse_target_level("helyim::storage").is_err());
+        assert!(parse_target_level("helyim=loud").is_err());
+
+        let mut levels = BTreeMap::new();
+        levels.insert(FastStr::new("helyim"), LevelFilter::INFO);
+        levels.insert(FastStr::new("helyim::storage"), LevelFilter::DEBUG);
+        assert!(LogFilter::build(&levels).is_ok());
+        levels.insert(FastStr::new("helyim storage"), LevelFilter::DEBUG);
+        assert!(LogFilter::build(&levels).is_err());
+    }
+
+    #[test]
+    fn test_log_file_rotation() {
+        use std::io::Write;
+
+        use clap::Parser;
+
+        let dir = tempfile::Builder::new()
+            .prefix("logs")
+            .tempdir_in(".")
+            .unwrap();
+        let log_path = format!("--log-path={}", dir.path().display());
+        let opts = Opts::parse_from([
+            "helyim",
+            log_path.as_str(),
+            "--log-output=both",
+            "--log-rotation=never",
+            "master",
+        ]);
+        let mut appender = file_appender(&opts.log, "master").unwrap();
+        appender.write_all(b"hello\n").unwrap();
+        appender.flush().unwrap();
+
+        let content = std::fs::read_to_string(dir.path().join("helyim-master.log")).unwrap();
+        assert_eq!(content, "hello\n");
+    }
+}
@@ -3,19 +3,35 @@ use faststr::FastStr;
 
 pub mod args;
 
+pub mod buffer;
+
+pub mod capability;
+
 pub mod chan;
 
+pub mod check;
+
+pub mod cidr;
+
 pub mod file;
 
 pub mod grpc;
 
 pub mod http;
 
+pub mod log;
+
 #[macro_use]
 pub mod macros;
 
 pub mod parser;
 
+pub mod pushgateway;
+
+pub mod retry;
+
+pub mod sign;
+
 pub mod sys;
 
 pub mod time;
@@ -0,0 +1,140 @@
+//! Metrics pushed to a prometheus pushgateway, for servers prometheus can not scrape, like volume
+//! servers behind a NAT.
+//!
+//! Every server pushes the text it serves on `/metrics` to the group of its `job`, `instance` and
+//! `role`, a push replaces the previous one of the group. The role, `master` or `volume`, keeps
+//! the cluster sums of the master apart from the shares of the volume servers under one job. The
+//! group is deleted when the server stops, so a stopped server does not linger with its last
+//! samples.
+
+use std::{future::Future, time::Duration};
+
+use faststr::FastStr;
+use reqwest::header::CONTENT_TYPE;
+use tracing::{debug, warn};
+
+use crate::{
+    errors::{Error, Result},
+    util::{
+        args::MetricsPushOptions,
+        http::{HTTP_CLIENT, PROMETHEUS_TEXT_FORMAT},
+    },
+};
+
+/// the url of the group of `job`, `instance` and `role` on `gateway`
+fn group_url(gateway: &str, job: &str, instance: &str, role: &str) -> String {
+    let gateway = gateway.trim_end_matches('/');
+    let gateway = if gateway.contains("://") {
+        gateway.to_string()
+    } else {
+        format!("http://{gateway}")
+    };
+    format!("{gateway}/metrics/job/{job}/instance/{instance}/role/{role}")
+}
+
+async fn check_response(response: reqwest::Response) -> Result<()> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::String(format!(
+            "pushgateway answered {status}: {body}"
+        )));
+    }
+    Ok(())
+}
+
+/// push `metrics` in the prometheus text format, they replace the group of `job`, `instance` and
+/// `role`
+pub async fn push_metrics(
+    gateway: &str,
+    job: &str,
+    instance: &str,
+    role: &str,
+    metrics: String,
+) -> Result<()> {
+    let response = HTTP_CLIENT
+        .put(group_url(gateway, job, instance, role))
+        .header(CONTENT_TYPE, PROMETHEUS_TEXT_FORMAT)
+        .body(metrics)
+        .send()
+        .await?;
+    check_response(response).await
+}
+
+/// delete the group of `job`, `instance` and `role`
+pub async fn delete_metrics(gateway: &str, job: &str, instance: &str, role: &str) -> Result<()> {
+    let response = HTTP_CLIENT
+        .delete(group_url(gateway, job, instance, role))
+        .send()
+        .await?;
+    check_response(response).await
+}
+
+/// Push the metrics `collect` returns every interval of `options`, until shutdown. A `None` of
+/// `collect` deletes what was pushed, like for a master which is no longer the leader. Returns
+/// at once without a pushgateway.
+pub async fn push_loop<F, Fut>(
+    options: MetricsPushOptions,
+    instance: FastStr,
+    role: &'static str,
+    collect: F,
+    mut shutdown: async_broadcast::Receiver<()>,
+) where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Option<String>>,
+{
+    let Some(gateway) = options.metrics_push_gateway else {
+        return;
+    };
+    let job = options.metrics_push_job;
+    let mut interval = tokio::time::interval(Duration::from_secs(options.metrics_push_interval));
+    let mut pushed = false;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                match collect().await {
+                    Some(metrics) => match push_metrics(&gateway, &job, &instance, role, metrics).await {
+                        Ok(()) => pushed = true,
+                        Err(err) => warn!("push metrics to {gateway} failed: {err}"),
+                    },
+                    None if pushed => match delete_metrics(&gateway, &job, &instance, role).await {
+                        Ok(()) => pushed = false,
+                        Err(err) => warn!("delete metrics from {gateway} failed: {err}"),
+                    },
+                    None => {}
+                }
+            }
+            _ = shutdown.recv() => {
+                if pushed {
+                    match delete_metrics(&gateway, &job, &instance, role).await {
+                        Ok(()) => debug!("metrics of {instance} deleted from {gateway}"),
+                        Err(err) => warn!("delete metrics from {gateway} failed: {err}"),
+                    }
+                }
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::pushgateway::group_url;
+
+    #[test]
+    fn test_group_url() {
+        assert_eq!(
+            group_url(
+                "http://pushgateway:9091/",
+                "helyim",
+                "10.0.0.1:8080",
+                "volume"
+            ),
+            "http://pushgateway:9091/metrics/job/helyim/instance/10.0.0.1:8080/role/volume"
+        );
+        assert_eq!(
+            group_url("pushgateway:9091", "helyim", "10.0.0.1:9333", "master"),
+            "http://pushgateway:9091/metrics/job/helyim/instance/10.0.0.1:9333/role/master"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    fmt::Display,
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use rand::Rng;
+use serde::Serialize;
+use tracing::warn;
+
+static RETRY_POLICY: Lazy<RwLock<RetryPolicy>> = Lazy::new(|| RwLock::new(RetryPolicy::default()));
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+static RETRIES_EXHAUSTED: AtomicU64 = AtomicU64::new(0);
+
+/// How idempotent internal calls are retried, the backoff grows exponentially from
+/// `initial_backoff` up to `max_backoff` with full jitter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// total attempts including the first one
+    pub attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// upper bound of the sleep after the `attempt`th failure, starting from 0
+    pub fn max_backoff_of(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff)
+    }
+
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let max = self.max_backoff_of(attempt).as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(0..=max))
+    }
+}
+
+pub fn retry_policy() -> RetryPolicy {
+    *RETRY_POLICY.read()
+}
+
+pub fn set_retry_policy(policy: RetryPolicy) {
+    *RETRY_POLICY.write() = policy;
+}
+
+/// run `f` with the process wide retry policy, only use it for idempotent operations
+pub async fn retry<T, E, F, Fut>(name: &str, f: F) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    retry_with(name, &retry_policy(), f).await
+}
+
+pub async fn retry_with<T, E, F, Fut>(name: &str, policy: &RetryPolicy, mut f: F) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        match f().await {
+            Ok(value) => return Ok(value),
+            Err(err) => {
+                attempt += 1;
+                if attempt >= policy.attempts {
+                    if policy.attempts > 1 {
+                        RETRIES_EXHAUSTED.fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Err(err);
+                }
+                let backoff = policy.backoff(attempt - 1);
+                RETRIES.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "{name} failed: {err}, retry {attempt}/{} after {backoff:?}",
+                    policy.attempts - 1
+                );
+                tokio::time::sleep(backoff).await;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryStats {
+    pub retries: u64,
+    pub exhausted: u64,
+}
+
+pub fn retry_stats() -> RetryStats {
+    RetryStats {
+        retries: RETRIES.load(Ordering::Relaxed),
+        exhausted: RETRIES_EXHAUSTED.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        time::Duration,
+    };
+
+    use crate::util::retry::{retry_with, RetryPolicy};
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        assert_eq!(policy.max_backoff_of(0), Duration::from_millis(100));
+        assert_eq!(policy.max_backoff_of(2), Duration::from_millis(400));
+        assert_eq!(policy.max_backoff_of(3), Duration::from_millis(500));
+        assert_eq!(policy.max_backoff_of(64), Duration::from_millis(500));
+        for attempt in 0..8 {
+            assert!(policy.backoff(attempt) <= policy.max_backoff);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let policy = RetryPolicy {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        };
+
+        let calls = AtomicU32::new(0);
+        let ret: Result<u32, String> = retry_with("test", &policy, || async {
+            match calls.fetch_add(1, Ordering::Relaxed) {
+                0 => Err("unavailable".to_string()),
+                n => Ok(n),
+            }
+        })
+        .await;
+        assert_eq!(ret, Ok(1));
+
+        calls.store(0, Ordering::Relaxed);
+        let ret: Result<(), String> = retry_with("test", &policy, || async {
+            calls.fetch_add(1, Ordering::Relaxed);
+            Err("unavailable".to_string())
+        })
+        .await;
+        assert!(ret.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+    }
+}
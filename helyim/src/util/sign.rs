@@ -0,0 +1,455 @@
+//! Signing of the calls between masters and volume servers with a shared secret.
+//!
+//! A signature is `<unix seconds>:<hex hmac-sha256>` of the timestamp, the method, the scope and
+//! the sha256 of the body of the call. The scope of a http request is its path and query. The
+//! messages of a grpc call are not covered, its scope is the method path with a random nonce and
+//! each nonce is accepted once, so a signature seen on the wire can not be replayed. Without a
+//! secret nothing is signed or verified.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::ready,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::OriginalUri,
+    http::{HeaderMap, HeaderName, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use faststr::FastStr;
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use sha2::{Digest, Sha256};
+use tonic::{
+    body::BoxBody, codegen::http as grpc_http, server::NamedService, service::Interceptor,
+    GrpcMethod, Status,
+};
+use tower::Service;
+
+use crate::{
+    errors::{Error, ErrorBody, ErrorCode},
+    util::{
+        http::request_id::{current_request_id, REQUEST_ID_HEADER},
+        time::now,
+    },
+};
+
+const SIGNATURE_KEY: &str = "x-helyim-signature";
+const NONCE_KEY: &str = "x-helyim-nonce";
+pub const X_HELYIM_SIGNATURE: HeaderName = HeaderName::from_static(SIGNATURE_KEY);
+/// signatures further from the local clock are rejected, it bounds replays and tolerates skew
+pub const SIGNATURE_WINDOW_SECS: u64 = 300;
+
+/// grpc calls are http/2 posts
+const GRPC_METHOD: &str = "POST";
+/// largest body of a signed http request, like the body limit of the routers
+const MAX_SIGNED_BODY: usize = 1024 * 1024 * 50;
+
+static CLUSTER_SECRET: Lazy<RwLock<Option<FastStr>>> = Lazy::new(|| RwLock::new(None));
+/// nonces of the grpc calls verified within the signature window
+static SEEN_NONCES: Lazy<NonceCache> = Lazy::new(NonceCache::default);
+
+pub fn cluster_secret() -> Option<FastStr> {
+    CLUSTER_SECRET.read().clone()
+}
+
+pub fn set_cluster_secret(secret: Option<FastStr>) {
+    *CLUSTER_SECRET.write() = secret.filter(|secret| !secret.is_empty());
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    #[error("missing signature of the cluster secret")]
+    Missing,
+    #[error("malformed signature")]
+    Malformed,
+    #[error("signature of {0} is out of the window of {SIGNATURE_WINDOW_SECS} seconds")]
+    Expired(u64),
+    #[error("signature does not match the cluster secret")]
+    Mismatch,
+    #[error("signature is replayed")]
+    Replayed,
+}
+
+/// Nonces seen within the signature window, older ones are dropped once a second since their
+/// signatures are expired anyway.
+#[derive(Default)]
+struct NonceCache {
+    seen: Mutex<(HashMap<String, u64>, u64)>,
+}
+
+impl NonceCache {
+    /// record `nonce` of a signature made at `timestamp`, fails if it is seen before
+    fn check(&self, nonce: &str, timestamp: u64, now: u64) -> Result<(), SignatureError> {
+        let (seen, pruned_at) = &mut *self.seen.lock();
+        if seen.contains_key(nonce) {
+            return Err(SignatureError::Replayed);
+        }
+        if *pruned_at != now {
+            seen.retain(|_, timestamp| timestamp.abs_diff(now) <= SIGNATURE_WINDOW_SECS);
+            *pruned_at = now;
+        }
+        seen.insert(nonce.to_string(), timestamp);
+        Ok(())
+    }
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn digest(secret: &str, method: &str, scope: &str, body: &[u8], timestamp: u64) -> HmacSha256 {
+    let body = hex::encode(Sha256::digest(body));
+    // hmac takes keys of any length
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("hmac key");
+    mac.update(format!("{timestamp}\n{method}\n{scope}\n{body}").as_bytes());
+    mac
+}
+
+pub fn sign(secret: &str, method: &str, scope: &str, body: &[u8], timestamp: u64) -> String {
+    format!(
+        "{timestamp}:{}",
+        hex::encode(
+            digest(secret, method, scope, body, timestamp)
+                .finalize()
+                .into_bytes()
+        )
+    )
+}
+
+pub fn verify(
+    secret: &str,
+    method: &str,
+    scope: &str,
+    body: &[u8],
+    signature: Option<&str>,
+    now: u64,
+) -> Result<(), SignatureError> {
+    let signature = signature.ok_or(SignatureError::Missing)?;
+    let (timestamp, mac) = signature.split_once(':').ok_or(SignatureError::Malformed)?;
+    let timestamp: u64 = timestamp.parse().map_err(|_| SignatureError::Malformed)?;
+    let mac = hex::decode(mac).map_err(|_| SignatureError::Malformed)?;
+    if timestamp.abs_diff(now) > SIGNATURE_WINDOW_SECS {
+        return Err(SignatureError::Expired(timestamp));
+    }
+    // the comparison takes constant time, it does not tell how much of the mac is right
+    digest(secret, method, scope, body, timestamp)
+        .verify_slice(&mac)
+        .map_err(|_| SignatureError::Mismatch)
+}
+
+/// the scope of a http request to `path` with `query`
+fn http_scope(path: &str, query: Option<&str>) -> String {
+    match query {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    }
+}
+
+/// the scope of a grpc call of `method` with `nonce`
+fn grpc_scope(method: &str, nonce: &str) -> String {
+    format!("{method}#{nonce}")
+}
+
+/// verify the signature of a grpc call, its nonce is used up by a valid signature
+fn verify_grpc(
+    secret: &str,
+    method: &str,
+    nonce: Option<&str>,
+    signature: Option<&str>,
+    now: u64,
+) -> Result<(), SignatureError> {
+    let nonce = nonce.ok_or(SignatureError::Missing)?;
+    verify(
+        secret,
+        GRPC_METHOD,
+        &grpc_scope(method, nonce),
+        &[],
+        signature,
+        now,
+    )?;
+    // the signature is valid, so its timestamp is
+    let timestamp = signature
+        .and_then(|signature| signature.split_once(':'))
+        .and_then(|(timestamp, _)| timestamp.parse().ok())
+        .unwrap_or(now);
+    SEEN_NONCES.check(nonce, timestamp, now)
+}
+
+/// verify the signature of an http request against the cluster secret, if there is one
+pub fn verify_http(
+    headers: &HeaderMap,
+    method: &str,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+) -> Result<(), SignatureError> {
+    match cluster_secret() {
+        Some(secret) => {
+            let signature = headers
+                .get(X_HELYIM_SIGNATURE)
+                .and_then(|value| value.to_str().ok());
+            verify(
+                &secret,
+                method,
+                &http_scope(path, query),
+                body,
+                signature,
+                now().as_secs(),
+            )
+        }
+        None => Ok(()),
+    }
+}
+
+/// Middleware of the internal http routes, rejects requests without a valid signature. The body
+/// is read to be verified and handed on as it is.
+pub async fn verify_signature(request: Request<Body>, next: Next) -> Response {
+    if cluster_secret().is_none() {
+        return next.run(request).await;
+    }
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_SIGNED_BODY).await {
+        Ok(body) => body,
+        Err(err) => return ErrorBody::new(ErrorCode::BadRequest, err).into_response(),
+    };
+    // a nested router sees the uri without its prefix, the signature covers the whole one
+    let uri = match parts.extensions.get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri,
+        None => &parts.uri,
+    };
+    if let Err(err) = verify_http(
+        &parts.headers,
+        parts.method.as_str(),
+        uri.path(),
+        uri.query(),
+        &body,
+    ) {
+        return Error::from(err).into_response();
+    }
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+/// sign an internal http request, a streamed body is signed as an empty one
+pub fn sign_http(request: &mut reqwest::Request) {
+    if let Some(secret) = cluster_secret() {
+        let scope = http_scope(request.url().path(), request.url().query());
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .unwrap_or_default();
+        let signature = sign(
+            &secret,
+            request.method().as_str(),
+            &scope,
+            body,
+            now().as_secs(),
+        );
+        if let Ok(signature) = signature.parse() {
+            request.headers_mut().insert(SIGNATURE_KEY, signature);
+        }
+    }
+}
+
+/// interceptor of the grpc clients, adds the signature to the metadata
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignInterceptor;
+
+impl Interceptor for SignInterceptor {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        if let Some(secret) = cluster_secret() {
+            let method = request
+                .extensions()
+                .get::<GrpcMethod>()
+                .map(|method| format!("/{}/{}", method.service(), method.method()))
+                .unwrap_or_default();
+            let nonce = format!("{:016x}", rand::random::<u64>());
+            let signature = sign(
+                &secret,
+                GRPC_METHOD,
+                &grpc_scope(&method, &nonce),
+                &[],
+                now().as_secs(),
+            );
+            let signature = signature
+                .parse()
+                .map_err(|_| Status::internal("invalid signature metadata"))?;
+            request.metadata_mut().insert(SIGNATURE_KEY, signature);
+            let nonce = nonce
+                .parse()
+                .map_err(|_| Status::internal("invalid nonce metadata"))?;
+            request.metadata_mut().insert(NONCE_KEY, nonce);
+        }
+        // the request of a client carries its id to the other servers
+        if let Some(id) = current_request_id().and_then(|id| id.parse().ok()) {
+            request.metadata_mut().insert(REQUEST_ID_HEADER, id);
+        }
+        Ok(request)
+    }
+}
+
+/// grpc service rejecting calls without a valid signature, the standard health and reflection
+/// services are left unwrapped for the probes
+#[derive(Debug, Clone)]
+pub struct VerifyGrpc<S> {
+    inner: S,
+}
+
+impl<S> VerifyGrpc<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S: NamedService> NamedService for VerifyGrpc<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B> Service<grpc_http::Request<B>> for VerifyGrpc<S>
+where
+    S: Service<grpc_http::Request<B>, Response = grpc_http::Response<BoxBody>, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: grpc_http::Request<B>) -> Self::Future {
+        if let Some(secret) = cluster_secret() {
+            let header = |key: &str| {
+                request
+                    .headers()
+                    .get(key)
+                    .and_then(|value| value.to_str().ok())
+            };
+            if let Err(err) = verify_grpc(
+                &secret,
+                request.uri().path(),
+                header(NONCE_KEY),
+                header(SIGNATURE_KEY),
+                now().as_secs(),
+            ) {
+                let response = Status::unauthenticated(err.to_string()).to_http();
+                return Box::pin(ready(Ok(response)));
+            }
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::sign::{
+        grpc_scope, http_scope, sign, verify, verify_grpc, NonceCache, SignatureError, GRPC_METHOD,
+        SIGNATURE_WINDOW_SECS,
+    };
+
+    #[test]
+    fn test_verify() {
+        let signature = sign("secret", "POST", "/path", b"body", 1000);
+        // the mac is the hmac-sha256 of the timestamp, method, scope and body digest
+        assert_eq!(
+            signature,
+            "1000:837b07cdcf1e2ab661a533e474f57e13c90ac7efe48e0a0fcfd763d42b17b487"
+        );
+        assert!(verify("secret", "POST", "/path", b"body", Some(&signature), 1000).is_ok());
+        assert!(verify(
+            "secret",
+            "POST",
+            "/path",
+            b"body",
+            Some(&signature),
+            1000 + SIGNATURE_WINDOW_SECS
+        )
+        .is_ok());
+        assert_eq!(
+            verify(
+                "secret",
+                "POST",
+                "/path",
+                b"body",
+                Some(&signature),
+                1001 + SIGNATURE_WINDOW_SECS
+            ),
+            Err(SignatureError::Expired(1000))
+        );
+        assert_eq!(
+            verify("other", "POST", "/path", b"body", Some(&signature), 1000),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify("secret", "POST", "/other", b"body", Some(&signature), 1000),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify("secret", "POST", "/path", b"body", Some("1000:00"), 1000),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify("secret", "DELETE", "/path", b"body", Some(&signature), 1000),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify("secret", "POST", "/path", b"other", Some(&signature), 1000),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify("secret", "POST", "/path", b"body", Some("abc"), 1000),
+            Err(SignatureError::Malformed)
+        );
+        assert_eq!(
+            verify("secret", "POST", "/path", b"body", None, 1000),
+            Err(SignatureError::Missing)
+        );
+    }
+
+    #[test]
+    fn test_http_scope() {
+        assert_eq!(http_scope("/3,01637037d6", None), "/3,01637037d6");
+        assert_eq!(
+            http_scope("/admin/volume/vacuum", Some("volume=3")),
+            "/admin/volume/vacuum?volume=3"
+        );
+    }
+
+    #[test]
+    fn test_verify_grpc() {
+        let method = "/volume.VolumeServer/VolumeDelete";
+        let nonce = "5bdcc146bf60754e";
+        let signature = sign("secret", GRPC_METHOD, &grpc_scope(method, nonce), &[], 1000);
+        assert_eq!(
+            verify_grpc("secret", method, None, Some(&signature), 1000),
+            Err(SignatureError::Missing)
+        );
+        assert_eq!(
+            verify_grpc("secret", method, Some("other"), Some(&signature), 1000),
+            Err(SignatureError::Mismatch)
+        );
+        assert!(verify_grpc("secret", method, Some(nonce), Some(&signature), 1000).is_ok());
+        // a signature is accepted once
+        assert_eq!(
+            verify_grpc("secret", method, Some(nonce), Some(&signature), 1000),
+            Err(SignatureError::Replayed)
+        );
+    }
+
+    #[test]
+    fn test_nonce_cache() {
+        let cache = NonceCache::default();
+        assert!(cache.check("a", 1000, 1000).is_ok());
+        assert_eq!(cache.check("a", 1000, 1000), Err(SignatureError::Replayed));
+        assert!(cache.check("b", 1000, 1000 + SIGNATURE_WINDOW_SECS).is_ok());
+        assert_eq!(cache.seen.lock().0.len(), 2);
+        // expired nonces are dropped
+        assert!(cache.check("c", 1000, 1001 + SIGNATURE_WINDOW_SECS).is_ok());
+        assert_eq!(cache.seen.lock().0.len(), 1);
+    }
+}
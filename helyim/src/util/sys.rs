@@ -1,11 +1,32 @@
-use rustix::process::{getpid, kill_process, Signal};
+use std::any::Any;
+
 use tokio::signal;
 
 /// Exit by terminate signal
+#[cfg(unix)]
 pub fn exit() {
+    use rustix::process::{getpid, kill_process, Signal};
+
     let _ = kill_process(getpid(), Signal::Term);
 }
 
+/// Exit at once, windows has no terminate signal to shut down gracefully
+#[cfg(not(unix))]
+pub fn exit() {
+    std::process::exit(1);
+}
+
+/// the message of a caught panic payload
+pub fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
 pub async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
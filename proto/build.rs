@@ -1,4 +1,4 @@
-use std::{fs::read_dir, io::Result, path::PathBuf};
+use std::{env, fs::read_dir, io::Result, path::PathBuf};
 
 fn main() -> Result<()> {
     let includes = &[PathBuf::from("src/proto")];
@@ -15,7 +15,10 @@ fn main() -> Result<()> {
             }
         }
     }
+    // served by grpc reflection
+    let descriptor = PathBuf::from(env::var("OUT_DIR").unwrap()).join("helyim_descriptor.bin");
     tonic_build::configure()
+        .file_descriptor_set_path(descriptor)
         .type_attribute(
             "volume.RemoteFile",
             "#[derive(::serde::Serialize, ::serde::Deserialize)]",
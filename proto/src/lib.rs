@@ -1,3 +1,6 @@
+/// encoded descriptors of all helyim protos, for grpc server reflection
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("helyim_descriptor");
+
 pub mod directory {
     include!(concat!(env!("OUT_DIR"), "/helyim.rs"));
 